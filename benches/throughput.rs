@@ -0,0 +1,296 @@
+//! Throughput benchmarks over a synthetic workload.
+//! Run with: cargo bench --bench throughput
+//! Compare hashers with: cargo bench --bench throughput --no-default-features -- dispute_heavy
+//! Compare worker channel implementations with: cargo bench --bench throughput -- worker_channels
+//! Compare per-row and batched worker messages with: cargo bench --bench throughput -- batch_size
+//! Compare hot client helpers with: cargo bench --bench throughput -- hot_client
+//! Compare pinned and unpinned workers with:
+//! cargo bench --bench throughput --features affinity -- pinning
+//!
+//! Memory note: writing output used to merge every worker's map into one combined HashMap and then
+//! sort a copy of its keys, briefly holding the states in two tables. Output is now a K-way merge
+//! over the per-worker sorted shards, so the peak at the end of a run is the shards themselves.
+//! Compare with `/usr/bin/time -v target/release/payments_engine <file> > /dev/null` ("Maximum
+//! resident set size") on a file with tens of thousands of clients.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use payments_engine::{
+    EngineConfig, Transaction, TransactionType, process_file, process_transactions,
+};
+use std::fs::File;
+use std::io::Write;
+use tempfile::TempDir;
+
+/// Write `rows` deposits/withdrawals across 1000 clients and return the file path
+fn synthetic_file(dir: &TempDir, rows: u32) -> String {
+    let path = dir.path().join("bench.csv");
+    let mut file = File::create(&path).unwrap();
+    writeln!(file, "type,client,tx,amount").unwrap();
+    for i in 0..rows {
+        let tx_type = if i % 10 == 0 { "withdrawal" } else { "deposit" };
+        writeln!(file, "{},{},{},{}.25", tx_type, i % 1000, i, (i % 100) + 1).unwrap();
+    }
+    path.to_str().unwrap().to_string()
+}
+
+fn read_buffer_size(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let rows = 500_000;
+    let path = synthetic_file(&dir, rows);
+
+    let mut group = c.benchmark_group("read_buffer_size");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(rows as u64));
+
+    for (label, size) in [
+        ("4K", 4 * 1024),
+        ("64K", 64 * 1024),
+        ("1M", 1024 * 1024),
+        ("16M", 16 * 1024 * 1024),
+        ("64M", 64 * 1024 * 1024),
+    ] {
+        let config = EngineConfig {
+            read_buffer_size: size,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(label), &config, |b, config| {
+            b.iter(|| process_file(&path, config).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+/// Deposits across 50K clients, each followed by a dispute and a resolve of an earlier deposit,
+/// so client and transaction history lookups dominate
+fn dispute_heavy_transactions(rows: u32) -> Vec<Transaction> {
+    (0..rows)
+        .map(|i| {
+            let deposit = i - i % 3;
+            let (tx_type, tx, amount) = match i % 3 {
+                0 => (TransactionType::Deposit, i, Some(10.5)),
+                1 => (TransactionType::Dispute, deposit, None),
+                _ => (TransactionType::Resolve, deposit, None),
+            };
+            Transaction::try_new(tx_type, ((deposit / 3) % 50_000) as _, tx, amount).unwrap()
+        })
+        .collect()
+}
+
+fn dispute_heavy(c: &mut Criterion) {
+    let rows = 600_000;
+    let transactions = dispute_heavy_transactions(rows);
+    let config = EngineConfig {
+        workers: Some(1),
+        ..Default::default()
+    };
+
+    let mut group = c.benchmark_group("dispute_heavy");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(rows as u64));
+
+    let hasher = if cfg!(feature = "fast-hash") {
+        "fxhash"
+    } else {
+        "siphash"
+    };
+    group.bench_function(hasher, |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| process_transactions(transactions, &config).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+/// One producer round-robining small messages to 8 consumer threads over bounded channels,
+/// the shape of the router feeding its workers
+fn channel_round_robin<S, R>(
+    messages: u64,
+    channel: impl Fn() -> (S, R),
+    send: impl Fn(&S, u64),
+    drain: impl Fn(R) -> u64 + Send + Copy + 'static,
+) where
+    R: Send + 'static,
+{
+    let (senders, consumers): (Vec<S>, Vec<_>) = (0..8)
+        .map(|_| {
+            let (sender, receiver) = channel();
+            (sender, std::thread::spawn(move || drain(receiver)))
+        })
+        .unzip();
+    for i in 0..messages {
+        send(&senders[(i % 8) as usize], i);
+    }
+    drop(senders);
+    let received: u64 = consumers.into_iter().map(|c| c.join().unwrap()).sum();
+    assert_eq!(received, messages);
+}
+
+fn worker_channels(c: &mut Criterion) {
+    let messages = 1_000_000;
+    let capacity = 1024;
+
+    let mut group = c.benchmark_group("worker_channels");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(messages));
+
+    group.bench_function("std_sync_channel", |b| {
+        b.iter(|| {
+            channel_round_robin(
+                messages,
+                || std::sync::mpsc::sync_channel::<u64>(capacity),
+                |sender, i| sender.send(i).unwrap(),
+                |receiver| receiver.iter().count() as u64,
+            )
+        })
+    });
+    group.bench_function("crossbeam_bounded", |b| {
+        b.iter(|| {
+            channel_round_robin(
+                messages,
+                || crossbeam_channel::bounded::<u64>(capacity),
+                |sender, i| sender.send(i).unwrap(),
+                |receiver| receiver.iter().count() as u64,
+            )
+        })
+    });
+
+    // The whole engine with 8 workers and single-transaction messages
+    let rows = 600_000;
+    let transactions = dispute_heavy_transactions(rows);
+    let config = EngineConfig {
+        workers: Some(8),
+        channel_capacity: Some(capacity),
+        ..Default::default()
+    };
+    group.throughput(Throughput::Elements(rows as u64));
+    group.bench_function("engine_8_workers", |b| {
+        b.iter_batched(
+            || transactions.clone(),
+            |transactions| process_transactions(transactions, &config).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+/// The whole engine with 4 workers, sending one message per transaction against the batched
+/// default
+fn batch_size(c: &mut Criterion) {
+    let rows = 600_000;
+    let transactions = dispute_heavy_transactions(rows);
+
+    let mut group = c.benchmark_group("batch_size");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(rows as u64));
+
+    for size in [1, 16, 256] {
+        let config = EngineConfig {
+            workers: Some(4),
+            batch_size: size,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(size), &config, |b, config| {
+            b.iter_batched(
+                || transactions.clone(),
+                |transactions| process_transactions(transactions, config).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+/// The whole engine with 4 workers on the large synthetic file, with and without workers (and
+/// the router) pinned to cores; pinning mostly narrows the spread between samples
+fn pinning(c: &mut Criterion) {
+    if !cfg!(feature = "affinity") {
+        return;
+    }
+    let dir = TempDir::new().unwrap();
+    let rows = 2_000_000;
+    let path = synthetic_file(&dir, rows);
+
+    let mut group = c.benchmark_group("pinning");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(rows as u64));
+
+    for (label, pin_workers, pin_router) in [
+        ("unpinned", false, false),
+        ("workers", true, false),
+        ("workers_and_router", true, true),
+    ] {
+        let config = EngineConfig {
+            workers: Some(4),
+            pin_workers,
+            pin_router,
+            ..Default::default()
+        };
+        group.bench_with_input(BenchmarkId::from_parameter(label), &config, |b, config| {
+            b.iter(|| process_file(&path, config).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+/// Write `rows` rows with 80% deposits and withdrawals of client 1 and the rest spread over
+/// 1000 clients, with a dispute and resolve of client 1 every 1000 rows
+fn hot_client_file(dir: &TempDir, rows: u32) -> String {
+    let path = dir.path().join("hot_client.csv");
+    let mut file = File::create(&path).unwrap();
+    writeln!(file, "type,client,tx,amount").unwrap();
+    for i in 0..rows {
+        match i % 1000 {
+            500 => writeln!(file, "dispute,1,{},", i - 2).unwrap(),
+            501 => writeln!(file, "resolve,1,{},", i - 3).unwrap(),
+            _ if i % 5 == 0 => writeln!(file, "deposit,{},{},3.5", 2 + i % 1000, i).unwrap(),
+            _ if i % 10 == 1 => writeln!(file, "withdrawal,1,{},1.25", i).unwrap(),
+            _ => writeln!(file, "deposit,1,{},{}.75", i, (i % 100) + 1).unwrap(),
+        }
+    }
+    path.to_str().unwrap().to_string()
+}
+
+/// The whole engine with 4 workers on a file dominated by one client, parsing all of its rows
+/// on the router against handing them to helper threads
+fn hot_client(c: &mut Criterion) {
+    let dir = TempDir::new().unwrap();
+    let rows = 1_000_000;
+    let path = hot_client_file(&dir, rows);
+
+    let mut group = c.benchmark_group("hot_client");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(rows as u64));
+
+    for helpers in [0, 2, 4] {
+        let config = EngineConfig {
+            workers: Some(4),
+            hot_client_helpers: helpers,
+            ..Default::default()
+        };
+        group.bench_with_input(
+            BenchmarkId::from_parameter(helpers),
+            &config,
+            |b, config| b.iter(|| process_file(&path, config).unwrap()),
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    read_buffer_size,
+    dispute_heavy,
+    worker_channels,
+    batch_size,
+    pinning,
+    hot_client
+);
+criterion_main!(benches);
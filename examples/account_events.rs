@@ -0,0 +1,59 @@
+//! Print account events on a separate thread while a file settles.
+//! Run with: cargo run --example account_events -- tests/inputs/test_dispute_chargeback.csv
+//!
+//! The channel is bounded so a stalled notifier can't buffer without limit; the engine drops
+//! what doesn't fit and reports the count, rather than waiting for the notifier.
+
+use payments_engine::{AccountEvent, EngineConfig, process_file_with_report};
+use std::thread;
+
+fn main() {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("Usage: account_events <transactions.csv>");
+        std::process::exit(1);
+    };
+
+    let (sender, receiver) = crossbeam_channel::bounded(1024);
+    let notifier = thread::spawn(move || {
+        // Ends once the engine has finished and dropped every sender
+        for event in receiver {
+            match event {
+                AccountEvent::DisputeOpened { client, tx, amount } => {
+                    println!("client {}: dispute of tx {} ({})", client, tx, amount)
+                }
+                AccountEvent::ChargebackApplied { client, tx, amount } => {
+                    println!("client {}: chargeback of tx {} ({})", client, tx, amount)
+                }
+                AccountEvent::WentNegative {
+                    client,
+                    tx,
+                    available,
+                    ..
+                } => println!("client {}: available {} after tx {}", client, available, tx),
+                AccountEvent::Locked { client, tx, reason } => {
+                    println!("client {}: locked by tx {} ({})", client, tx, reason.name())
+                }
+            }
+        }
+    });
+
+    let config = EngineConfig {
+        events: Some(sender),
+        ..Default::default()
+    };
+    let report = match process_file_with_report(&path, &config) {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    drop(config);
+    notifier.join().unwrap();
+
+    println!(
+        "{} accounts settled, {} events dropped",
+        report.accounts.len(),
+        report.stats.dropped_events
+    );
+}
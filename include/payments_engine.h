@@ -0,0 +1,142 @@
+#ifndef PAYMENTS_ENGINE_H
+#define PAYMENTS_ENGINE_H
+
+/* Generated from src/ffi.rs by cbindgen; do not edit by hand. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// `tx_type` code of a deposit; the codes match the binary input format
+#define PE_DEPOSIT 0
+
+// `tx_type` code of a withdrawal
+#define PE_WITHDRAWAL 1
+
+// `tx_type` code of a dispute
+#define PE_DISPUTE 2
+
+// `tx_type` code of a resolve
+#define PE_RESOLVE 3
+
+// `tx_type` code of a chargeback
+#define PE_CHARGEBACK 4
+
+// `tx_type` code of an account closure
+#define PE_CLOSE 5
+
+// The transaction was applied
+#define PE_OK 0
+
+// The transaction changed nothing, e.g. a dispute of an unknown tx
+#define PE_IGNORED 1
+
+// The deposit or withdrawal's tx id was already applied (`idempotent` option)
+#define PE_ALREADY_APPLIED 2
+
+// `RejectReason::UnknownClient`
+#define PE_REJECTED_UNKNOWN_CLIENT 10
+
+// `RejectReason::AccountLocked`
+#define PE_REJECTED_ACCOUNT_LOCKED 11
+
+// `RejectReason::InsufficientFunds`
+#define PE_REJECTED_INSUFFICIENT_FUNDS 12
+
+// `RejectReason::DisputeUnfunded`
+#define PE_REJECTED_DISPUTE_UNFUNDED 13
+
+// `RejectReason::MissingCurrency`
+#define PE_REJECTED_MISSING_CURRENCY 14
+
+// `RejectReason::DisputeExpired`
+#define PE_REJECTED_DISPUTE_EXPIRED 15
+
+// `RejectReason::AmountBelowMinimum`
+#define PE_REJECTED_AMOUNT_BELOW_MINIMUM 16
+
+// `RejectReason::DuplicateTransaction`
+#define PE_REJECTED_DUPLICATE_TX 17
+
+// `RejectReason::MissingAmount`
+#define PE_REJECTED_MISSING_AMOUNT 18
+
+// `RejectReason::Overflow`
+#define PE_REJECTED_OVERFLOW 19
+
+// `RejectReason::AccountClosed`
+#define PE_REJECTED_ACCOUNT_CLOSED 20
+
+// A required pointer was NULL
+#define PE_ERR_NULL -1
+
+// An argument is out of range, e.g. an unknown type code or a non-finite amount
+#define PE_ERR_INVALID_ARGUMENT -2
+
+// The client has no account
+#define PE_ERR_NOT_FOUND -3
+
+// Writing the output failed (`EngineError::Io` or `EngineError::Csv`)
+#define PE_ERR_IO -4
+
+// Any other `EngineError`
+#define PE_ERR_ENGINE -5
+
+// The engine panicked; the engine should be freed and not used again
+#define PE_ERR_PANIC -6
+
+// Opaque engine handle owned by the caller until `pe_engine_free`
+typedef struct Engine Engine;
+
+// Balances of one client, filled in by `pe_engine_account`
+typedef struct PeAccount {
+  uint32_t client;
+  double available;
+  double held;
+  double total;
+  bool locked;
+} PeAccount;
+
+// Create an engine from a JSON object of options (see `FfiConfig`), or with the defaults
+// when `config_json` is NULL
+// Returns NULL if the JSON is not valid UTF-8, names an unknown option or value, or fails
+// `EngineConfig::validate`.
+//
+// # Safety
+// `config_json` must be NULL or point to a NUL-terminated string.
+Engine *pe_engine_new(const char *config_json);
+
+// Apply one transaction and return its status
+// `tx_type` is one of the `PE_DEPOSIT`..`PE_CHARGEBACK` codes; `amount` is ignored for
+// disputes, resolves and chargebacks.
+//
+// # Safety
+// `engine` must be NULL or a live pointer from `pe_engine_new`, not used concurrently.
+int pe_engine_apply(Engine *engine, uint8_t tx_type, uint32_t client, uint32_t tx, double amount);
+
+// Copy the balances of `client` into `out`
+// Returns `PE_ERR_NOT_FOUND` when no transaction has created the account yet.
+//
+// # Safety
+// `engine` must be NULL or a live pointer from `pe_engine_new`; `out` must be NULL or
+// point to writable memory for one `PeAccount`.
+int pe_engine_account(const Engine *engine, uint32_t client, PeAccount *out);
+
+// Render every account as CSV, sorted by client, in the same format as a file run
+// Like `snprintf`, at most `len - 1` bytes are written followed by a NUL, and the return
+// value is the length of the whole CSV without the NUL; a result of `len` or more means the
+// output was truncated. Pass NULL and 0 to query the size. Negative results are `PE_ERR_*`
+// codes. The engine stays usable and must still be freed.
+//
+// # Safety
+// `engine` must be NULL or a live pointer from `pe_engine_new`; `out_buf` must be NULL or
+// point to `len` writable bytes.
+ptrdiff_t pe_engine_finish_csv(const Engine *engine, char *out_buf, size_t len);
+
+// Free an engine returned by `pe_engine_new`; NULL is ignored
+//
+// # Safety
+// `engine` must be NULL or a pointer from `pe_engine_new` that has not been freed.
+void pe_engine_free(Engine *engine);
+
+#endif /* PAYMENTS_ENGINE_H */
@@ -1,35 +1,422 @@
-use serde::Serialize;
+use crate::{AmountScale, ClientId, Currency, TransactionType};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Debug, Default, Clone, Serialize)]
+sealed_fields! {
+/// Balances of one client, or of one client in one currency; read through the accessor
+/// methods, changed only by the engine so that `total` stays `available + held`, though a
+/// deserialized account keeps the `total` it was given
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ClientAccount {
-    pub client: u16,
+    client: ClientId,
     #[serde(serialize_with = "round_to_four_decimals")]
-    pub available: f64,
+    available: f64,
     #[serde(serialize_with = "round_to_four_decimals")]
-    pub held: f64,
+    held: f64,
     #[serde(serialize_with = "round_to_four_decimals")]
-    pub total: f64,
+    total: f64,
+    locked: bool,
+    /// Why `locked` is set, when the engine locked the account during the run; not part of
+    /// the default output
+    #[serde(skip)]
+    lock_reason: Option<LockReason>,
+    /// Set by a `close` transaction: the account takes no more deposits or withdrawals but
+    /// still settles disputes. Not part of the default output; may be given in
+    /// `--initial-balances`
+    #[serde(default, skip_serializing)]
+    closed: bool,
+    /// Currency of these balances in multi-currency mode, where a client has one account per
+    /// currency it used; `None` otherwise
+    #[serde(skip)]
+    currency: Option<Currency>,
+    /// Transactions of each type applied to these balances during the run; not part of the
+    /// default output
+    #[serde(skip)]
+    counts: TransactionCounts,
+    /// Sum of the amounts of the disputes open on these balances, whatever part of them is
+    /// held; not part of the default output
+    #[serde(skip)]
+    open_disputed_amount: f64,
+}
+}
+
+/// Why `ClientAccount::try_from_balances` refused to build an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountError {
+    /// A balance that is not finite or is beyond `MAX_MINOR_UNITS`
+    BalanceOutOfRange,
+    /// Held funds below zero
+    NegativeHeld,
+}
+
+impl fmt::Display for AccountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountError::BalanceOutOfRange => {
+                write!(f, "balances must be finite and within the exact f64 range")
+            }
+            AccountError::NegativeHeld => write!(f, "held funds can't be negative"),
+        }
+    }
+}
+
+impl std::error::Error for AccountError {}
+
+/// Number of applied transactions of each type, written by the count columns
+/// Rejected and ignored transactions are not counted
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TransactionCounts {
+    pub deposits: u64,
+    pub withdrawals: u64,
+    pub disputes: u64,
+    pub resolves: u64,
+    pub chargebacks: u64,
+}
+
+impl TransactionCounts {
+    /// Count one applied transaction of this type
+    pub fn record(&mut self, tx_type: &TransactionType) {
+        match tx_type {
+            TransactionType::Deposit => self.deposits += 1,
+            TransactionType::Withdrawal => self.withdrawals += 1,
+            TransactionType::Dispute => self.disputes += 1,
+            TransactionType::Resolve => self.resolves += 1,
+            TransactionType::Chargeback => self.chargebacks += 1,
+            // Closing is a state, shown by `ClientAccount::closed`
+            TransactionType::Close => {}
+        }
+    }
+}
+
+/// What locked an account
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockReason {
+    /// A chargeback
+    Chargeback,
+    /// `EngineConfig::max_open_disputes` or `EngineConfig::max_disputed_amount` was crossed
+    DisputeThreshold,
+}
+
+impl LockReason {
+    /// Name written in the `lock_reason` column
+    pub fn name(&self) -> &'static str {
+        match self {
+            LockReason::Chargeback => "chargeback",
+            LockReason::DisputeThreshold => "dispute-threshold",
+        }
+    }
+}
+
+/// Account balances as integer counts of 1/10000 units
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MinorUnitsAccount {
+    pub client: ClientId,
+    pub available: i64,
+    pub held: i64,
+    pub total: i64,
     pub locked: bool,
 }
 
+/// A column of the account output, selectable and orderable via `EngineConfig::columns`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountColumn {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    LockReason,
+    Closed,
+    Currency,
+    Deposits,
+    Withdrawals,
+    Disputes,
+    Resolves,
+    Chargebacks,
+    OpenDisputedAmount,
+}
+
+impl AccountColumn {
+    /// Every column in the default output order
+    pub const ALL: [AccountColumn; 5] = [
+        AccountColumn::Client,
+        AccountColumn::Available,
+        AccountColumn::Held,
+        AccountColumn::Total,
+        AccountColumn::Locked,
+    ];
+
+    /// Columns only written when selected through `EngineConfig::columns`
+    pub const EXTENDED: [AccountColumn; 9] = [
+        AccountColumn::LockReason,
+        AccountColumn::Closed,
+        AccountColumn::Currency,
+        AccountColumn::Deposits,
+        AccountColumn::Withdrawals,
+        AccountColumn::Disputes,
+        AccountColumn::Resolves,
+        AccountColumn::Chargebacks,
+        AccountColumn::OpenDisputedAmount,
+    ];
+
+    /// Per-client transaction counts, appended by `EngineConfig::extended_output`
+    pub const COUNTS: [AccountColumn; 5] = [
+        AccountColumn::Deposits,
+        AccountColumn::Withdrawals,
+        AccountColumn::Disputes,
+        AccountColumn::Resolves,
+        AccountColumn::Chargebacks,
+    ];
+
+    /// Header name of the column
+    pub fn name(&self) -> &'static str {
+        match self {
+            AccountColumn::Client => "client",
+            AccountColumn::Available => "available",
+            AccountColumn::Held => "held",
+            AccountColumn::Total => "total",
+            AccountColumn::Locked => "locked",
+            AccountColumn::LockReason => "lock_reason",
+            AccountColumn::Closed => "closed",
+            AccountColumn::Currency => "currency",
+            AccountColumn::Deposits => "deposits",
+            AccountColumn::Withdrawals => "withdrawals",
+            AccountColumn::Disputes => "disputes",
+            AccountColumn::Resolves => "resolves",
+            AccountColumn::Chargebacks => "chargebacks",
+            AccountColumn::OpenDisputedAmount => "open_disputed_amount",
+        }
+    }
+}
+
+impl FromStr for AccountColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        AccountColumn::ALL
+            .into_iter()
+            .chain(AccountColumn::EXTENDED)
+            .find(|column| column.name() == s)
+            .ok_or_else(|| format!("Unknown output column: {}", s))
+    }
+}
+
+/// Number of minor units in one unit of currency (4 decimal places)
+pub const MINOR_UNITS_PER_UNIT: f64 = 10_000.0;
+
+/// Largest balance magnitude, in minor units, an f64 still holds to the exact minor unit (2^53)
+pub const MAX_MINOR_UNITS: f64 = 9_007_199_254_740_992.0;
+
+/// Returns true if `value` is not a balance the engine can hold exactly: non-finite, or
+/// beyond `MAX_MINOR_UNITS`, where 4 decimal places no longer survive f64 arithmetic
+pub(crate) fn balance_out_of_range(value: f64) -> bool {
+    let scaled = value * MINOR_UNITS_PER_UNIT;
+    !scaled.is_finite() || scaled.abs() > MAX_MINOR_UNITS
+}
+
+/// How close to a half minor unit a scaled amount must be to be rounded on its decimal digits
+const HALF_TOLERANCE: f64 = 1e-3;
+
+/// Converts an f64 balance to the nearest whole number of minor units, halves away from zero
+pub fn to_minor_units(value: f64) -> i64 {
+    round_minor_units(value) as i64
+}
+
+/// Rounds an f64 balance to 4 decimal places, halves away from zero
+/// A balance that rounds to zero is always `+0.0`, so f64 residue below zero is never
+/// written as `-0.0`.
+pub(crate) fn round_to_four(value: f64) -> f64 {
+    let rounded = round_minor_units(value) / MINOR_UNITS_PER_UNIT;
+    if rounded == 0.0 { 0.0 } else { rounded }
+}
+
+/// `value` in minor units, rounded to a whole number
+/// Scaling is not exact in binary: `0.00005 * 10000` is `0.49999...`, which `round` takes
+/// down. A product that close to a half is rounded on the shortest decimal form of `value`
+/// instead, i.e. the digits it was parsed from.
+fn round_minor_units(value: f64) -> f64 {
+    let scaled = value * MINOR_UNITS_PER_UNIT;
+    if !scaled.is_finite() || ((scaled - scaled.trunc()).abs() - 0.5).abs() > HALF_TOLERANCE {
+        return scaled.round();
+    }
+
+    // `Display` writes the shortest digits that read back as `value`, never an exponent
+    let text = value.abs().to_string();
+    let (whole, fraction) = text.split_once('.').unwrap_or((&text, ""));
+    let mut digits = fraction.bytes().map(|digit| f64::from(digit - b'0'));
+    let mut minor = whole.parse::<f64>().unwrap_or(0.0) * MINOR_UNITS_PER_UNIT;
+    for scale in [1000.0, 100.0, 10.0, 1.0] {
+        minor += digits.next().unwrap_or(0.0) * scale;
+    }
+    if digits.next().is_some_and(|digit| digit >= 5.0) {
+        minor += 1.0;
+    }
+    minor.copysign(value)
+}
+
+/// Formats a balance rounded to 4 decimal places through `Display`, which never switches to
+/// scientific notation; whole values keep a trailing `.0` as in the serde output
+pub(crate) fn format_decimal(value: f64) -> String {
+    let rounded = round_to_four(value);
+    let mut text = rounded.to_string();
+    if rounded.is_finite() && !text.contains('.') {
+        text.push_str(".0");
+    }
+    text
+}
+
 /// Rounds f64 to 4 decimal places for serialization
-fn round_to_four_decimals<S>(value: &f64, serializer: S) -> Result<S::Ok, S::Error>
+pub(crate) fn round_to_four_decimals<S>(value: &f64, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: serde::Serializer,
 {
-    let rounded = (value * 10000.0).round() / 10000.0;
-    serializer.serialize_f64(rounded)
+    serializer.serialize_f64(round_to_four(*value))
 }
 
 impl ClientAccount {
-    pub fn new(client: u16) -> Self {
+    pub fn new(client: ClientId) -> Self {
         Self {
             client,
             available: 0.0,
             held: 0.0,
             total: 0.0,
             locked: false,
+            lock_reason: None,
+            closed: false,
+            currency: None,
+            counts: TransactionCounts::default(),
+            open_disputed_amount: 0.0,
+        }
+    }
+
+    /// A starting account with these balances, e.g. to seed a `ClientLedger`; `total` is
+    /// `available + held`. `available` may be negative, as after a chargeback of spent funds
+    pub fn try_from_balances(
+        client: ClientId,
+        available: f64,
+        held: f64,
+        locked: bool,
+    ) -> Result<Self, AccountError> {
+        let total = available + held;
+        if [available, held, total]
+            .into_iter()
+            .any(balance_out_of_range)
+        {
+            return Err(AccountError::BalanceOutOfRange);
         }
+        if to_minor_units(held) < 0 {
+            return Err(AccountError::NegativeHeld);
+        }
+        Ok(Self {
+            available,
+            held,
+            total,
+            locked,
+            ..Self::new(client)
+        })
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn available(&self) -> f64 {
+        self.available
+    }
+
+    pub fn held(&self) -> f64 {
+        self.held
+    }
+
+    /// Always `available + held`
+    pub fn total(&self) -> f64 {
+        self.total
+    }
+
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+
+    /// Why the engine locked the account during the run; `None` when it is unlocked or was
+    /// already locked in the starting balances
+    pub fn lock_reason(&self) -> Option<LockReason> {
+        self.lock_reason
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Currency of these balances in multi-currency mode; `None` otherwise
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    /// Transactions of each type applied to these balances during the run
+    pub fn counts(&self) -> TransactionCounts {
+        self.counts
+    }
+
+    /// Sum of the amounts of the disputes open on these balances
+    pub fn open_disputed_amount(&self) -> f64 {
+        self.open_disputed_amount
+    }
+
+    /// Returns the same account with balances expressed in minor units
+    pub fn to_minor_units(&self) -> MinorUnitsAccount {
+        MinorUnitsAccount {
+            client: self.client,
+            available: to_minor_units(self.available),
+            held: to_minor_units(self.held),
+            total: to_minor_units(self.total),
+            locked: self.locked,
+        }
+    }
+
+    /// Renders one column of this account as a CSV field
+    /// Decimal amounts match the serde output, except that large balances are written in full
+    /// rather than in scientific notation
+    pub fn field(&self, column: AccountColumn, amount_scale: AmountScale) -> String {
+        let amount = |value: f64| match amount_scale {
+            AmountScale::Decimal => format_decimal(value),
+            AmountScale::MinorUnits => to_minor_units(value).to_string(),
+        };
+
+        match column {
+            AccountColumn::Client => self.client.to_string(),
+            AccountColumn::Available => amount(self.available),
+            AccountColumn::Held => amount(self.held),
+            AccountColumn::Total => amount(self.total),
+            AccountColumn::Locked => if self.locked { "true" } else { "false" }.to_string(),
+            AccountColumn::LockReason => self
+                .lock_reason
+                .map_or("", |reason| reason.name())
+                .to_string(),
+            AccountColumn::Closed => if self.closed { "true" } else { "false" }.to_string(),
+            AccountColumn::Currency => self
+                .currency
+                .as_ref()
+                .map_or("", Currency::as_str)
+                .to_string(),
+            AccountColumn::Deposits => self.counts.deposits.to_string(),
+            AccountColumn::Withdrawals => self.counts.withdrawals.to_string(),
+            AccountColumn::Disputes => self.counts.disputes.to_string(),
+            AccountColumn::Resolves => self.counts.resolves.to_string(),
+            AccountColumn::Chargebacks => self.counts.chargebacks.to_string(),
+            AccountColumn::OpenDisputedAmount => amount(self.open_disputed_amount),
+        }
+    }
+
+    /// Renders this account as one row of the default output, without the header or a line
+    /// terminator
+    /// Amounts are rounded as in `field`, and `locked` is written as `true` or `false`.
+    pub fn to_csv_row(&self) -> String {
+        AccountColumn::ALL
+            .iter()
+            .map(|column| self.field(*column, AmountScale::Decimal))
+            .collect::<Vec<_>>()
+            .join(",")
     }
 }
 
@@ -54,9 +441,167 @@ mod tests {
             held: 0.0,
             total: 1.23456789,
             locked: false,
+            lock_reason: None,
+            closed: false,
+            currency: None,
+            counts: TransactionCounts::default(),
+            open_disputed_amount: 0.0,
         };
 
         let serialized = serde_json::to_string(&account).unwrap();
         assert!(serialized.contains("1.2346")); // Rounded to 4 decimals
     }
+
+    #[test]
+    fn test_rounding_at_the_half_minor_unit() {
+        // Each of these scales to just under a half in binary
+        for (value, expected) in [(0.00005, 0.0001), (0.12345, 0.1235), (2.00005, 2.0001)] {
+            assert_eq!(round_to_four(value), expected, "{}", value);
+            assert_eq!(round_to_four(-value), -expected, "{}", -value);
+            assert_eq!(format_decimal(value), expected.to_string());
+        }
+        assert_eq!(to_minor_units(0.00005), 1);
+        assert_eq!(format_decimal(-0.0), "0.0");
+        assert_eq!(format_decimal(-0.00004), "0.0");
+        assert_eq!(to_minor_units(2.00005), 20001);
+        assert_eq!(to_minor_units(0.00004999), 0);
+        assert_eq!(to_minor_units(1.2344), 12344);
+    }
+
+    #[test]
+    fn test_minor_units_matches_decimal() {
+        let account = ClientAccount {
+            client: 7,
+            available: 1.2345,
+            held: 0.5,
+            total: 1.7345,
+            locked: false,
+            lock_reason: None,
+            closed: false,
+            currency: None,
+            counts: TransactionCounts::default(),
+            open_disputed_amount: 0.0,
+        };
+
+        let mut decimal = csv::Writer::from_writer(vec![]);
+        decimal.serialize(&account).unwrap();
+        let decimal = String::from_utf8(decimal.into_inner().unwrap()).unwrap();
+
+        let mut minor = csv::Writer::from_writer(vec![]);
+        minor.serialize(account.to_minor_units()).unwrap();
+        let minor = String::from_utf8(minor.into_inner().unwrap()).unwrap();
+
+        assert_eq!(
+            decimal,
+            "client,available,held,total,locked\n7,1.2345,0.5,1.7345,false\n"
+        );
+        assert_eq!(
+            minor,
+            "client,available,held,total,locked\n7,12345,5000,17345,false\n"
+        );
+    }
+
+    #[test]
+    fn test_field_matches_serde() {
+        let account = ClientAccount {
+            client: 3,
+            available: 100.0,
+            held: 0.00004,
+            total: 1.23456789,
+            locked: true,
+            lock_reason: None,
+            closed: false,
+            currency: None,
+            counts: TransactionCounts::default(),
+            open_disputed_amount: 0.0,
+        };
+
+        let fields: Vec<String> = AccountColumn::ALL
+            .iter()
+            .map(|column| account.field(*column, AmountScale::Decimal))
+            .collect();
+
+        let mut serde = csv::Writer::from_writer(vec![]);
+        serde.serialize(&account).unwrap();
+        let serde = String::from_utf8(serde.into_inner().unwrap()).unwrap();
+
+        assert_eq!(serde.lines().nth(1).unwrap(), fields.join(","));
+    }
+
+    #[test]
+    fn test_to_csv_row() {
+        let account = ClientAccount {
+            client: 42,
+            available: 1.23456789,
+            held: 0.5,
+            total: 1.73456789,
+            locked: true,
+            lock_reason: Some(LockReason::Chargeback),
+            closed: false,
+            currency: None,
+            counts: TransactionCounts::default(),
+            open_disputed_amount: 0.0,
+        };
+
+        assert_eq!(account.to_csv_row(), "42,1.2346,0.5,1.7346,true");
+        assert_eq!(ClientAccount::new(1).to_csv_row(), "1,0.0,0.0,0.0,false");
+    }
+
+    #[test]
+    fn test_large_balances_without_exponent() {
+        let account = ClientAccount {
+            client: 1,
+            available: 123456789012.3456,
+            held: 1e16,
+            total: 1e16 + 123456789012.3456,
+            locked: false,
+            lock_reason: None,
+            closed: false,
+            currency: None,
+            counts: TransactionCounts::default(),
+            open_disputed_amount: 0.0,
+        };
+
+        assert_eq!(
+            account.field(AccountColumn::Available, AmountScale::Decimal),
+            "123456789012.3456"
+        );
+        assert_eq!(
+            account.field(AccountColumn::Held, AmountScale::Decimal),
+            "10000000000000000.0"
+        );
+        assert!(
+            !account
+                .field(AccountColumn::Total, AmountScale::Decimal)
+                .contains('e')
+        );
+    }
+
+    #[test]
+    fn test_column_names_round_trip() {
+        for column in AccountColumn::ALL
+            .into_iter()
+            .chain(AccountColumn::EXTENDED)
+        {
+            assert_eq!(column.name().parse::<AccountColumn>(), Ok(column));
+        }
+        assert!("balance".parse::<AccountColumn>().is_err());
+    }
+
+    #[test]
+    fn test_try_from_balances() {
+        let account = ClientAccount::try_from_balances(3, -2.5, 4.0, true).unwrap();
+        assert_eq!(
+            (account.client(), account.total(), account.locked()),
+            (3, 1.5, true)
+        );
+        assert_eq!(
+            ClientAccount::try_from_balances(3, 1.0, -0.5, false),
+            Err(AccountError::NegativeHeld)
+        );
+        assert_eq!(
+            ClientAccount::try_from_balances(3, f64::NAN, 0.0, false),
+            Err(AccountError::BalanceOutOfRange)
+        );
+    }
 }
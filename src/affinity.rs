@@ -0,0 +1,169 @@
+//! Pinning engine threads to CPU cores (`EngineConfig::pin_workers`, `affinity` feature).
+//!
+//! Each worker gets a core of its own, in the order the platform lists them, and with
+//! `pin_router` the routing thread gets the next one. When there are fewer cores than threads
+//! to pin, or the platform can't list them, nothing is pinned and the run goes on as usual.
+//!
+//! The routing thread is the caller's, so it only stays pinned for the run: the cores it was
+//! allowed before are put back afterwards. That needs Linux; elsewhere the router is left
+//! unpinned.
+
+use crate::EngineConfig;
+
+/// Cores a pool's threads are pinned to: worker `i` to `workers[i]`, the router to `router`
+/// Both are empty when pinning is off or skipped.
+#[derive(Debug, Default)]
+pub(crate) struct CorePins {
+    pub(crate) workers: Vec<usize>,
+    pub(crate) router: Option<usize>,
+}
+
+/// Pick the cores for `num_workers` workers under `config`, reporting the assignment on stderr
+/// with `config.verbose`
+pub(crate) fn plan(num_workers: usize, config: &EngineConfig, with_router: bool) -> CorePins {
+    if !config.pin_workers {
+        return CorePins::default();
+    }
+    let cores = core_ids();
+    let router = with_router && config.pin_router;
+    let needed = num_workers + usize::from(router);
+    if cores.len() < needed {
+        if config.verbose {
+            eprintln!(
+                "Not pinning workers: {} cores available for {} threads",
+                cores.len(),
+                needed
+            );
+        }
+        return CorePins::default();
+    }
+
+    let pins = CorePins {
+        workers: cores[..num_workers].to_vec(),
+        router: router.then(|| cores[num_workers]),
+    };
+    if config.verbose {
+        for (worker_id, core) in pins.workers.iter().enumerate() {
+            eprintln!("Pinning worker {} to core {}", worker_id, core);
+        }
+        if let Some(core) = pins.router {
+            eprintln!("Pinning router to core {}", core);
+        }
+    }
+    pins
+}
+
+/// Pin the calling thread to `core`, warning on stderr if the platform refuses
+pub(crate) fn pin_current(core: usize, thread: &str) {
+    if !set_for_current(core) {
+        eprintln!("Warning: could not pin {} to core {}", thread, core);
+    }
+}
+
+/// The caller's thread pinned to a core for the router, back on the cores it was allowed
+/// before once dropped
+pub(crate) struct RouterPin {
+    previous: Option<CoreMask>,
+}
+
+impl RouterPin {
+    pub(crate) fn new(core: usize) -> Self {
+        let previous = current_mask();
+        match previous {
+            Some(_) => pin_current(core, "router"),
+            None => eprintln!(
+                "Warning: not pinning the router to core {}: its cores could not be restored \
+                 after the run",
+                core
+            ),
+        }
+        Self { previous }
+    }
+}
+
+impl Drop for RouterPin {
+    fn drop(&mut self) {
+        if let Some(mask) = &self.previous
+            && !set_mask(mask)
+        {
+            eprintln!("Warning: could not restore the router thread's cores");
+        }
+    }
+}
+
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+type CoreMask = libc::cpu_set_t;
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+type CoreMask = ();
+
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+fn current_mask() -> Option<CoreMask> {
+    // SAFETY: `cpu_set_t` is plain data, valid when zeroed, and the kernel writes at most
+    // the size passed in
+    unsafe {
+        let mut mask: libc::cpu_set_t = std::mem::zeroed();
+        let read = libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut mask);
+        (read == 0).then_some(mask)
+    }
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+fn current_mask() -> Option<CoreMask> {
+    None
+}
+
+#[cfg(all(feature = "affinity", target_os = "linux"))]
+fn set_mask(mask: &CoreMask) -> bool {
+    // SAFETY: the kernel reads at most the size passed in from a valid `cpu_set_t`
+    unsafe { libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), mask) == 0 }
+}
+
+#[cfg(not(all(feature = "affinity", target_os = "linux")))]
+fn set_mask(_mask: &CoreMask) -> bool {
+    false
+}
+
+#[cfg(feature = "affinity")]
+fn core_ids() -> Vec<usize> {
+    core_affinity::get_core_ids()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|core| core.id)
+        .collect()
+}
+
+#[cfg(not(feature = "affinity"))]
+fn core_ids() -> Vec<usize> {
+    Vec::new()
+}
+
+#[cfg(feature = "affinity")]
+fn set_for_current(core: usize) -> bool {
+    core_affinity::set_for_current(core_affinity::CoreId { id: core })
+}
+
+#[cfg(not(feature = "affinity"))]
+fn set_for_current(_core: usize) -> bool {
+    false
+}
+
+#[cfg(all(test, feature = "affinity", target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    fn core_count() -> i32 {
+        // SAFETY: reads a mask returned by the kernel
+        unsafe { libc::CPU_COUNT(&current_mask().unwrap()) }
+    }
+
+    #[test]
+    fn test_router_pin_restores_cores() {
+        let before = core_count();
+        let core = core_ids()[0];
+        let pin = RouterPin::new(core);
+        assert_eq!(core_count(), 1);
+        drop(pin);
+        assert_eq!(core_count(), before);
+    }
+}
@@ -0,0 +1,293 @@
+//! Apache Arrow ingestion (feature `arrow`): transactions read straight out of `RecordBatch`es,
+//! for callers that already hold them in Arrow form (e.g. from Parquet or Flight).
+//!
+//! A batch needs `type`, `client` and `tx` columns and may have `amount` and `timestamp`
+//! columns, found by name with surrounding whitespace ignored as in a CSV header:
+//! - `type`: `Utf8`, `LargeUtf8` or a dictionary of either, holding the input type names
+//! - `client`, `tx`: any integer type, each value in range for a client id or a `u32`
+//! - `amount`: `Float64` or `Decimal128`, null for dispute actions
+//! - `timestamp`: any integer type, read as in CSV input
+//!
+//! The schema, and that `type`, `client` and `tx` have no nulls, is checked once per batch;
+//! rows are then turned into transactions in place, without allocating per row. Every row of
+//! a batch is converted before the first one is applied, so a bad row fails the batch whole.
+//!
+//! A `Decimal128` amount becomes the `f64` closest to its exact value, the one parsing it
+//! from CSV gives: value and scale factor are both exact in `f64` for up to 15 significant
+//! digits and scales up to 22, and IEEE division rounds correctly.
+
+use crate::ledger::ApplyOutcome;
+use crate::transaction::is_column;
+use crate::{ClientId, Engine, EngineError, PaymentEngine, Transaction, TransactionType};
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Decimal128Type, Float64Type, Int8Type, Int16Type, Int32Type, Int64Type, UInt8Type, UInt16Type,
+    UInt32Type, UInt64Type,
+};
+use arrow_array::{Array, ArrayRef, PrimitiveArray, RecordBatch};
+use arrow_schema::DataType;
+use std::collections::BTreeMap;
+
+/// What `apply_record_batch` did with the rows of one batch
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BatchOutcome {
+    pub rows: u64,
+    pub applied: u64,
+    /// Rows that changed nothing, e.g. a dispute of an unknown tx
+    pub ignored: u64,
+    /// Rejected rows keyed by `RejectReason::code`
+    pub rejected: BTreeMap<&'static str, u64>,
+}
+
+/// Apply every row of `batch` to `engine`, in row order
+/// Fails before applying anything if the batch doesn't have the expected schema or a row
+/// can't be converted, e.g. an unknown type name or a client id out of range.
+pub fn apply_record_batch(
+    engine: &mut PaymentEngine,
+    batch: &RecordBatch,
+) -> Result<BatchOutcome, EngineError> {
+    let transactions = Columns::new(batch)?.transactions()?;
+    let mut outcome = BatchOutcome::default();
+    for transaction in transactions {
+        outcome.rows += 1;
+        match engine.apply(transaction) {
+            ApplyOutcome::Applied => outcome.applied += 1,
+            ApplyOutcome::Ignored(_) => outcome.ignored += 1,
+            ApplyOutcome::Rejected(reason) => {
+                *outcome.rejected.entry(reason.code()).or_default() += 1
+            }
+        }
+    }
+    Ok(outcome)
+}
+
+/// Submit every row of `batches` to the worker pool of `engine`, returning the number of rows
+/// Each batch is checked as in `apply_record_batch` before any of its rows are submitted; on
+/// an error the batches before the failing one stay submitted.
+pub fn route_record_batches<'a, I>(engine: &Engine, batches: I) -> Result<u64, EngineError>
+where
+    I: IntoIterator<Item = &'a RecordBatch>,
+{
+    let mut rows = 0;
+    for batch in batches {
+        for transaction in Columns::new(batch)?.transactions()? {
+            engine.submit(transaction)?;
+            rows += 1;
+        }
+    }
+    Ok(rows)
+}
+
+/// The columns of one batch, downcast after checking the schema
+pub(crate) struct Columns<'a> {
+    tx_type: TypeColumn<'a>,
+    client: IntColumn<'a>,
+    tx: IntColumn<'a>,
+    amount: Option<AmountColumn<'a>>,
+    timestamp: Option<IntColumn<'a>>,
+    rows: usize,
+}
+
+enum TypeColumn<'a> {
+    Utf8(&'a dyn Array),
+    /// Dictionary values parsed once, indexed by the normalized keys
+    Dictionary {
+        keys: Vec<usize>,
+        values: Vec<TransactionType>,
+    },
+}
+
+/// An integer column of any width and signedness, widened to `i128` row by row
+enum IntColumn<'a> {
+    Int8(&'a PrimitiveArray<Int8Type>),
+    Int16(&'a PrimitiveArray<Int16Type>),
+    Int32(&'a PrimitiveArray<Int32Type>),
+    Int64(&'a PrimitiveArray<Int64Type>),
+    UInt8(&'a PrimitiveArray<UInt8Type>),
+    UInt16(&'a PrimitiveArray<UInt16Type>),
+    UInt32(&'a PrimitiveArray<UInt32Type>),
+    UInt64(&'a PrimitiveArray<UInt64Type>),
+}
+
+impl<'a> IntColumn<'a> {
+    fn new(name: &str, array: &'a ArrayRef) -> Result<Self, EngineError> {
+        Ok(match array.data_type() {
+            DataType::Int8 => IntColumn::Int8(array.as_primitive()),
+            DataType::Int16 => IntColumn::Int16(array.as_primitive()),
+            DataType::Int32 => IntColumn::Int32(array.as_primitive()),
+            DataType::Int64 => IntColumn::Int64(array.as_primitive()),
+            DataType::UInt8 => IntColumn::UInt8(array.as_primitive()),
+            DataType::UInt16 => IntColumn::UInt16(array.as_primitive()),
+            DataType::UInt32 => IntColumn::UInt32(array.as_primitive()),
+            DataType::UInt64 => IntColumn::UInt64(array.as_primitive()),
+            other => return Err(wrong_type(name, other)),
+        })
+    }
+
+    fn value(&self, row: usize) -> Option<i128> {
+        macro_rules! value {
+            ($array:expr) => {
+                $array.is_valid(row).then(|| i128::from($array.value(row)))
+            };
+        }
+        match self {
+            IntColumn::Int8(array) => value!(array),
+            IntColumn::Int16(array) => value!(array),
+            IntColumn::Int32(array) => value!(array),
+            IntColumn::Int64(array) => value!(array),
+            IntColumn::UInt8(array) => value!(array),
+            IntColumn::UInt16(array) => value!(array),
+            IntColumn::UInt32(array) => value!(array),
+            IntColumn::UInt64(array) => value!(array),
+        }
+    }
+
+    /// The value of a column checked to have no nulls, converted to `T`
+    fn required<T: TryFrom<i128>>(&self, name: &str, row: usize) -> Result<T, EngineError> {
+        let value = self.value(row).unwrap_or_default();
+        T::try_from(value)
+            .map_err(|_| invalid(format!("{} {} in row {} is out of range", name, value, row)))
+    }
+}
+
+enum AmountColumn<'a> {
+    Float64(&'a PrimitiveArray<Float64Type>),
+    /// Values with their scale, i.e. `value / 10^scale`
+    Decimal128(&'a PrimitiveArray<Decimal128Type>, i8),
+}
+
+impl<'a> Columns<'a> {
+    pub(crate) fn new(batch: &'a RecordBatch) -> Result<Self, EngineError> {
+        let optional = |name: &str| {
+            let index = batch
+                .schema_ref()
+                .fields()
+                .iter()
+                .position(|field| is_column(field.name().trim(), name))?;
+            Some(batch.column(index))
+        };
+        let column = |name: &str| {
+            optional(name).ok_or_else(|| invalid(format!("record batch has no `{}` column", name)))
+        };
+        let required = |name: &str| -> Result<&'a ArrayRef, EngineError> {
+            let array = column(name)?;
+            if array.null_count() > 0 {
+                return Err(invalid(format!("column `{}` has nulls", name)));
+            }
+            Ok(array)
+        };
+
+        let tx_type = required("type")?;
+        let tx_type = match tx_type.data_type() {
+            DataType::Utf8 | DataType::LargeUtf8 => TypeColumn::Utf8(tx_type.as_ref()),
+            DataType::Dictionary(_, value_type)
+                if matches!(**value_type, DataType::Utf8 | DataType::LargeUtf8) =>
+            {
+                let dictionary = tx_type
+                    .as_any_dictionary_opt()
+                    .ok_or_else(|| wrong_type("type", tx_type.data_type()))?;
+                let values = dictionary.values();
+                let values = (0..values.len())
+                    .map(|index| parse_type(string_at(values.as_ref(), index)))
+                    .collect::<Result<_, _>>()?;
+                TypeColumn::Dictionary {
+                    keys: dictionary.normalized_keys(),
+                    values,
+                }
+            }
+            other => return Err(wrong_type("type", other)),
+        };
+
+        let client = IntColumn::new("client", required("client")?)?;
+        let tx = IntColumn::new("tx", required("tx")?)?;
+        let timestamp = optional("timestamp")
+            .map(|timestamp| IntColumn::new("timestamp", timestamp))
+            .transpose()?;
+
+        let amount = match optional("amount") {
+            None => None,
+            Some(amount) => Some(match amount.data_type() {
+                DataType::Float64 => AmountColumn::Float64(amount.as_primitive()),
+                DataType::Decimal128(_, scale) => {
+                    AmountColumn::Decimal128(amount.as_primitive(), *scale)
+                }
+                other => return Err(wrong_type("amount", other)),
+            }),
+        };
+
+        Ok(Self {
+            tx_type,
+            client,
+            tx,
+            amount,
+            timestamp,
+            rows: batch.num_rows(),
+        })
+    }
+
+    /// The transactions of every row, or the error of the first row that fails
+    fn transactions(&self) -> Result<Vec<Transaction>, EngineError> {
+        (0..self.rows).map(|row| self.transaction(row)).collect()
+    }
+
+    /// The transaction in row `row`, which must be in the batch
+    pub(crate) fn transaction(&self, row: usize) -> Result<Transaction, EngineError> {
+        let tx_type = match &self.tx_type {
+            TypeColumn::Utf8(array) => {
+                let name = string_at(*array, row);
+                TransactionType::from_name(name).ok_or_else(|| {
+                    invalid(format!(
+                        "unknown transaction type `{}` in row {}",
+                        name, row
+                    ))
+                })?
+            }
+            TypeColumn::Dictionary { keys, values } => values[keys[row]],
+        };
+        let amount = match &self.amount {
+            Some(AmountColumn::Float64(array)) if array.is_valid(row) => Some(array.value(row)),
+            Some(AmountColumn::Decimal128(array, scale)) if array.is_valid(row) => {
+                Some(array.value(row) as f64 / 10f64.powi(i32::from(*scale)))
+            }
+            _ => None,
+        };
+        let timestamp = match &self.timestamp {
+            Some(timestamp) if timestamp.value(row).is_some() => {
+                Some(timestamp.required("timestamp", row)?)
+            }
+            _ => None,
+        };
+        Ok(Transaction {
+            tx_type,
+            client: self.client.required::<ClientId>("client", row)?,
+            tx: self.tx.required("tx", row)?,
+            amount,
+            currency: None,
+            timestamp,
+        })
+    }
+}
+
+/// Value `index` of a `Utf8` or `LargeUtf8` array
+fn string_at(array: &dyn Array, index: usize) -> &str {
+    match array.as_string_opt::<i32>() {
+        Some(strings) => strings.value(index),
+        None => array.as_string::<i64>().value(index),
+    }
+}
+
+fn parse_type(name: &str) -> Result<TransactionType, EngineError> {
+    TransactionType::from_name(name)
+        .ok_or_else(|| invalid(format!("unknown transaction type `{}`", name)))
+}
+
+fn wrong_type(column: &str, data_type: &DataType) -> EngineError {
+    invalid(format!(
+        "column `{}` has unsupported type {}",
+        column, data_type
+    ))
+}
+
+fn invalid(message: String) -> EngineError {
+    EngineError::InvalidInput(message)
+}
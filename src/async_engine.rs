@@ -0,0 +1,209 @@
+//! Async counterpart of `process_transactions` for services on tokio (feature `async`).
+//!
+//! The input is read from any `AsyncRead` and the workers are tokio tasks instead of OS
+//! threads, so a service can await a run without `spawn_blocking`. Clients are sharded with
+//! `EngineConfig::shard_strategy` and each worker applies its transactions in input order,
+//! exactly as in the thread pool, so both give the same accounts.
+
+use crate::processor::{
+    RowDecoder, WorkerState, check_empty_output, check_row_limit, read_seeds, release_held,
+    submit_to_client, worker_for,
+};
+use crate::{ClientAccount, ClientId, EngineConfig, EngineError, InputFormat, Transaction};
+use csv::ReaderBuilder;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// Batches queued per worker task when `EngineConfig::channel_capacity` is not set
+const DEFAULT_TASK_QUEUE: usize = 64;
+
+/// Input bytes gathered before a block of lines is parsed
+const PARSE_BLOCK_BYTES: usize = 64 * 1024;
+
+/// Process CSV transactions read from `reader` on tokio tasks and return the final accounts
+pub async fn process_reader_async<R>(
+    reader: R,
+) -> Result<HashMap<ClientId, ClientAccount>, EngineError>
+where
+    R: AsyncRead + Unpin,
+{
+    process_reader_async_with_config(reader, &EngineConfig::default()).await
+}
+
+/// Same as `process_reader_async` with explicit runtime options
+/// Must be called within a tokio runtime, which may be single-threaded. Starting balances
+/// (`initial_balances`, `resume_from`) are read with blocking I/O before the input.
+/// Options that write files (`manifest`, `snapshot`), input other than CSV and string client
+/// ids are refused.
+pub async fn process_reader_async_with_config<R>(
+    reader: R,
+    config: &EngineConfig,
+) -> Result<HashMap<ClientId, ClientAccount>, EngineError>
+where
+    R: AsyncRead + Unpin,
+{
+    config.validate()?;
+    if config.string_client_ids
+        || config.input_format != InputFormat::Csv
+        || config.hashes_input()
+        || config.snapshot.is_some()
+        || config.overlay.is_some()
+    {
+        return Err(EngineError::InvalidConfig(
+            "async processing takes CSV with numeric client ids, writes no manifest, \
+             provenance or snapshot and applies no overlay"
+                .to_string(),
+        ));
+    }
+    let num_workers = config.worker_count();
+
+    let states: Vec<WorkerState> = (0..num_workers).map(|_| WorkerState::default()).collect();
+    for ledger in read_seeds(config)? {
+        let client = ledger.account().client;
+        if config.processes_client(client) {
+            let worker_id = worker_for(client, num_workers, config)?;
+            states[worker_id].client_states.insert(ledger);
+        }
+    }
+
+    let capacity = config.channel_capacity.unwrap_or(DEFAULT_TASK_QUEUE);
+    let shared = Arc::new(config.clone());
+    let (senders, workers): (Vec<_>, Vec<_>) = states
+        .into_iter()
+        .map(|state| {
+            let (sender, receiver) = mpsc::channel(capacity);
+            (sender, spawn_worker(state, receiver, Arc::clone(&shared)))
+        })
+        .unzip();
+
+    let routed = route(reader, senders, config).await;
+
+    // The senders are gone by now, so every worker finishes its queue and exits
+    let mut accounts = HashMap::new();
+    for worker in workers {
+        let state = worker
+            .await
+            .map_err(|_| EngineError::Worker("Worker task panicked".to_string()))?;
+        accounts.extend(
+            state
+                .client_states
+                .take()
+                .into_iter()
+                .map(|ledger| (ledger.account().client, ledger.into_account())),
+        );
+    }
+
+    let rows = routed?;
+    check_empty_output(!accounts.is_empty(), rows, config)?;
+    Ok(accounts)
+}
+
+fn spawn_worker(
+    mut state: WorkerState,
+    mut receiver: mpsc::Receiver<Vec<Transaction>>,
+    config: Arc<EngineConfig>,
+) -> JoinHandle<WorkerState> {
+    tokio::spawn(async move {
+        while let Some(batch) = receiver.recv().await {
+            for transaction in batch {
+                submit_to_client(&mut state, transaction, &config);
+            }
+        }
+        release_held(&mut state, &config);
+        state
+    })
+}
+
+/// Read, parse and route the input in order, returning the number of rows read
+/// Consumes the senders so the workers see the end of their queues however this returns
+async fn route<R>(
+    reader: R,
+    senders: Vec<mpsc::Sender<Vec<Transaction>>>,
+    config: &EngineConfig,
+) -> Result<u64, EngineError>
+where
+    R: AsyncRead + Unpin,
+{
+    let mut reader = BufReader::with_capacity(config.read_buffer_size, reader);
+    let mut header = Vec::new();
+    reader.read_until(b'\n', &mut header).await?;
+    let headers = csv_block(&header, config)
+        .headers()
+        .cloned()
+        .map_err(EngineError::from)?;
+    let decoder = RowDecoder::new(headers, config, &["client", "tx"]);
+
+    let mut batches: Vec<Vec<Transaction>> = vec![Vec::new(); senders.len()];
+    let mut rows = 0u64;
+    // Set once `EngineConfig::last_row` rows are read, which ends the input there
+    let mut row_cap_reached = config.last_row() == Some(0);
+    let mut block = header.clone();
+    while !row_cap_reached {
+        let read = reader.read_until(b'\n', &mut block).await?;
+        if block.len() < PARSE_BLOCK_BYTES && read > 0 {
+            continue;
+        }
+
+        // Every block repeats the header, so field counts are checked against it; positions
+        // are shifted to lines of the whole input
+        let rows_before = rows;
+        for result in csv_block(&block, config).into_records() {
+            if row_cap_reached {
+                break;
+            }
+            let mut record = result?;
+            if let Some(position) = record.position() {
+                let mut line = position.clone();
+                line.set_line(rows_before + position.line());
+                record.set_position(Some(line));
+            }
+            let transaction: Transaction = decoder.decode(record)?;
+            rows += 1;
+            check_row_limit(rows, config)?;
+            row_cap_reached = config.last_row() == Some(rows);
+            if !config.processes_client(transaction.client) {
+                continue;
+            }
+
+            let worker_id = worker_for(transaction.client, senders.len(), config)?;
+            let batch = &mut batches[worker_id];
+            batch.push(transaction);
+            if batch.len() >= config.batch_size {
+                let full = std::mem::replace(batch, Vec::with_capacity(config.batch_size));
+                send(&senders[worker_id], full).await?;
+            }
+        }
+
+        if read == 0 {
+            break;
+        }
+        block.truncate(header.len());
+    }
+
+    for (sender, batch) in senders.iter().zip(batches) {
+        if !batch.is_empty() {
+            send(sender, batch).await?;
+        }
+    }
+    Ok(rows)
+}
+
+fn csv_block<'a>(bytes: &'a [u8], config: &EngineConfig) -> csv::Reader<&'a [u8]> {
+    ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(config.delimiter)
+        .from_reader(bytes)
+}
+
+async fn send(
+    sender: &mpsc::Sender<Vec<Transaction>>,
+    batch: Vec<Transaction>,
+) -> Result<(), EngineError> {
+    sender
+        .send(batch)
+        .await
+        .map_err(|e| EngineError::Worker(format!("Failed to send to worker: {}", e)))
+}
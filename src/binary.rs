@@ -0,0 +1,213 @@
+//! Fixed-width binary transaction input (`InputFormat::Binary`).
+//!
+//! The input is a plain sequence of 19-byte records with no header, all integers
+//! little-endian:
+//!
+//! | Offset | Size | Field |
+//! |---|---|---|
+//! | 0 | 1 | type: 0 deposit, 1 withdrawal, 2 dispute, 3 resolve, 4 chargeback |
+//! | 1 | 2 | client, `u16` |
+//! | 3 | 8 | tx, `u64`; must fit in `u32` |
+//! | 11 | 8 | amount, `i64` in minor units (1/10000); ignored for dispute, resolve and chargeback |
+//!
+//! Reading does no text parsing at all, so this is the fastest way to feed the engine.
+
+use crate::account::{MINOR_UNITS_PER_UNIT, to_minor_units};
+use crate::{EngineError, Transaction, TransactionType};
+use std::io::{self, Read};
+
+/// Size in bytes of one encoded transaction
+pub const RECORD_SIZE: usize = 19;
+
+fn type_code(tx_type: &TransactionType) -> u8 {
+    match tx_type {
+        TransactionType::Deposit => 0,
+        TransactionType::Withdrawal => 1,
+        TransactionType::Dispute => 2,
+        TransactionType::Resolve => 3,
+        TransactionType::Chargeback => 4,
+        TransactionType::Close => 5,
+    }
+}
+
+pub(crate) fn tx_type(code: u8) -> Option<TransactionType> {
+    match code {
+        0 => Some(TransactionType::Deposit),
+        1 => Some(TransactionType::Withdrawal),
+        2 => Some(TransactionType::Dispute),
+        3 => Some(TransactionType::Resolve),
+        4 => Some(TransactionType::Chargeback),
+        5 => Some(TransactionType::Close),
+        _ => None,
+    }
+}
+
+/// Append the binary record of `transaction` to `out`
+/// Fails for a client id above `u16::MAX`, which only `wide-client-ids` builds can hold
+// `ClientId` is `u16` unless `wide-client-ids` is enabled
+#[allow(clippy::useless_conversion)]
+pub fn encode_transaction(transaction: &Transaction, out: &mut Vec<u8>) -> Result<(), EngineError> {
+    let client = u16::try_from(transaction.client).map_err(|_| {
+        EngineError::InvalidInput(format!(
+            "client {} does not fit the binary format's u16 client field",
+            transaction.client
+        ))
+    })?;
+    let amount = transaction.amount.map(to_minor_units).unwrap_or(0);
+
+    out.push(type_code(&transaction.tx_type));
+    out.extend_from_slice(&client.to_le_bytes());
+    out.extend_from_slice(&u64::from(transaction.tx).to_le_bytes());
+    out.extend_from_slice(&amount.to_le_bytes());
+    Ok(())
+}
+
+/// Encode a sequence of transactions, e.g. to build binary fixtures from CSV ones
+pub fn encode_transactions<'a>(
+    transactions: impl IntoIterator<Item = &'a Transaction>,
+) -> Result<Vec<u8>, EngineError> {
+    let mut out = Vec::new();
+    for transaction in transactions {
+        encode_transaction(transaction, &mut out)?;
+    }
+    Ok(out)
+}
+
+#[allow(clippy::useless_conversion)]
+fn decode(record: &[u8; RECORD_SIZE], index: u64) -> Result<Transaction, EngineError> {
+    let invalid =
+        |what: String| EngineError::InvalidInput(format!("binary record {}: {}", index, what));
+
+    let tx_type =
+        tx_type(record[0]).ok_or_else(|| invalid(format!("unknown type {}", record[0])))?;
+    let client = u16::from_le_bytes([record[1], record[2]]);
+    let tx = u64::from_le_bytes(record[3..11].try_into().unwrap());
+    let tx = u32::try_from(tx).map_err(|_| invalid(format!("tx {} does not fit in u32", tx)))?;
+    let amount = i64::from_le_bytes(record[11..19].try_into().unwrap());
+
+    let transaction = Transaction {
+        tx_type,
+        client: client.into(),
+        tx,
+        amount: None,
+        currency: None,
+        timestamp: None,
+    };
+    let amount = transaction
+        .requires_amount()
+        .then(|| amount as f64 / MINOR_UNITS_PER_UNIT);
+    Ok(Transaction {
+        amount,
+        ..transaction
+    })
+}
+
+/// Stream transactions out of binary input
+/// A trailing partial record is an error rather than being silently dropped
+pub(crate) fn binary_transactions(
+    mut reader: impl Read,
+) -> impl Iterator<Item = Result<Transaction, EngineError>> {
+    let mut index = 0;
+    let mut failed = false;
+
+    std::iter::from_fn(move || {
+        if failed {
+            return None;
+        }
+        let mut record = [0u8; RECORD_SIZE];
+        let result = match read_record(&mut reader, &mut record) {
+            Ok(0) => return None,
+            Ok(RECORD_SIZE) => decode(&record, index),
+            Ok(read) => Err(EngineError::InvalidInput(format!(
+                "binary input ends with a partial record of {} bytes",
+                read
+            ))),
+            Err(e) => Err(e.into()),
+        };
+        index += 1;
+        failed = result.is_err();
+        Some(result)
+    })
+}
+
+/// Fill `record` as far as the input allows, returning the number of bytes read
+fn read_record(reader: &mut impl Read, record: &mut [u8; RECORD_SIZE]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < RECORD_SIZE {
+        match reader.read(&mut record[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ClientId;
+
+    fn tx(tx_type: TransactionType, client: ClientId, tx: u32, amount: Option<f64>) -> Transaction {
+        Transaction {
+            tx_type,
+            client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let transactions = vec![
+            tx(TransactionType::Deposit, 65535, 1, Some(1234.5678)),
+            tx(TransactionType::Withdrawal, 2, u32::MAX, Some(0.0001)),
+            tx(TransactionType::Dispute, 2, 1, None),
+            tx(TransactionType::Resolve, 2, 1, None),
+            tx(TransactionType::Chargeback, 2, 1, None),
+        ];
+        let encoded = encode_transactions(&transactions).unwrap();
+        assert_eq!(encoded.len(), transactions.len() * RECORD_SIZE);
+
+        let decoded: Vec<Transaction> = binary_transactions(encoded.as_slice())
+            .collect::<Result<_, _>>()
+            .unwrap();
+        for (decoded, original) in decoded.iter().zip(&transactions) {
+            assert_eq!(decoded.tx_type, original.tx_type);
+            assert_eq!(decoded.client, original.client);
+            assert_eq!(decoded.tx, original.tx);
+            assert_eq!(decoded.amount, original.amount);
+        }
+        assert_eq!(decoded.len(), transactions.len());
+    }
+
+    #[test]
+    fn test_malformed_records_rejected() {
+        let mut encoded =
+            encode_transactions(&[tx(TransactionType::Deposit, 1, 1, Some(1.0))]).unwrap();
+
+        // Trailing partial record
+        let mut truncated = encoded.clone();
+        truncated.extend_from_slice(&[0, 1, 0]);
+        let results: Vec<_> = binary_transactions(truncated.as_slice()).collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(EngineError::InvalidInput(_))));
+
+        // Unknown type code and a tx id beyond u32
+        encoded[0] = 9;
+        assert!(matches!(
+            binary_transactions(encoded.as_slice()).next(),
+            Some(Err(EngineError::InvalidInput(_)))
+        ));
+        encoded[0] = 0;
+        encoded[3..11].copy_from_slice(&(u64::from(u32::MAX) + 1).to_le_bytes());
+        assert!(matches!(
+            binary_transactions(encoded.as_slice()).next(),
+            Some(Err(EngineError::InvalidInput(_)))
+        ));
+    }
+}
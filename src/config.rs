@@ -0,0 +1,955 @@
+use crate::account::MINOR_UNITS_PER_UNIT;
+use crate::shard::{ModuloShard, ShardStrategy};
+use crate::{
+    AccountColumn, AccountEvent, ClientId, EngineError, RejectHandler, Transaction, TransactionType,
+};
+use crossbeam_channel::Sender;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default capacity of the buffered reader in front of the input (16 MB)
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 16 * 1024 * 1024;
+
+/// Largest accepted read buffer (1 GB)
+pub const MAX_READ_BUFFER_SIZE: usize = 1024 * 1024 * 1024;
+
+/// How monetary columns are written in the output CSV
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AmountScale {
+    /// Decimal values rounded to 4 places, e.g. `1.2345`
+    #[default]
+    Decimal,
+    /// Integer count of 1/10000 units, e.g. `12345` for `1.2345`
+    MinorUnits,
+}
+
+impl FromStr for AmountScale {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "decimal" => Ok(AmountScale::Decimal),
+            "minor-units" => Ok(AmountScale::MinorUnits),
+            _ => Err(format!("Unknown amount scale: {}", s)),
+        }
+    }
+}
+
+/// Which transactions may open an account for a client that has none yet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AccountCreationPolicy {
+    /// Any transaction creates the account
+    #[default]
+    Implicit,
+    /// Only a deposit creates an account; anything else for an unknown client is rejected
+    DepositsOnly,
+    /// Only accounts loaded from `initial_balances` or `resume_from` exist; nothing creates
+    /// new ones
+    SeededOnly,
+}
+
+impl AccountCreationPolicy {
+    /// Whether `transaction` may create the account of a client that has none
+    pub fn creates_account(&self, transaction: &Transaction) -> bool {
+        match self {
+            AccountCreationPolicy::Implicit => true,
+            AccountCreationPolicy::DepositsOnly => {
+                transaction.tx_type == TransactionType::Deposit && transaction.is_valid()
+            }
+            AccountCreationPolicy::SeededOnly => false,
+        }
+    }
+}
+
+impl FromStr for AccountCreationPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "implicit" => Ok(AccountCreationPolicy::Implicit),
+            "deposits-only" => Ok(AccountCreationPolicy::DepositsOnly),
+            "seeded-only" => Ok(AccountCreationPolicy::SeededOnly),
+            _ => Err(format!("Unknown account creation policy: {}", s)),
+        }
+    }
+}
+
+/// What happens to a deposit for an account locked by a chargeback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockedDepositPolicy {
+    /// The deposit is refused
+    #[default]
+    Reject,
+    /// The deposit increases `held` and `total`, so funds arrive but stay frozen
+    ToHeld,
+    /// The deposit increases `available` and `total` as on an unlocked account
+    ToAvailable,
+}
+
+impl FromStr for LockedDepositPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reject" => Ok(LockedDepositPolicy::Reject),
+            "to-held" => Ok(LockedDepositPolicy::ToHeld),
+            "to-available" => Ok(LockedDepositPolicy::ToAvailable),
+            _ => Err(format!("Unknown locked deposit policy: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for LockedDepositPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LockedDepositPolicy::Reject => "reject",
+            LockedDepositPolicy::ToHeld => "to-held",
+            LockedDepositPolicy::ToAvailable => "to-available",
+        })
+    }
+}
+
+/// What happens when a run would write no account rows at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyOutputPolicy {
+    /// Write just the header, as for any other output
+    #[default]
+    Ignore,
+    /// Write the header and print a warning to stderr
+    Warn,
+    /// Fail the run with `EngineError::EmptyOutput`
+    Error,
+}
+
+impl FromStr for EmptyOutputPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ignore" => Ok(EmptyOutputPolicy::Ignore),
+            "warn" => Ok(EmptyOutputPolicy::Warn),
+            "error" => Ok(EmptyOutputPolicy::Error),
+            _ => Err(format!("Unknown empty output policy: {}", s)),
+        }
+    }
+}
+
+/// What happens when the input has more rows than `EngineConfig::max_rows`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RowLimitPolicy {
+    /// Stop reading after `max_rows` rows, as if the input ended there, and finish the run
+    /// with a warning
+    #[default]
+    Truncate,
+    /// Fail the run with `EngineError::TooManyRows` on the first row past the limit
+    Error,
+}
+
+impl FromStr for RowLimitPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "truncate" => Ok(RowLimitPolicy::Truncate),
+            "error" => Ok(RowLimitPolicy::Error),
+            _ => Err(format!("Unknown row limit policy: {}", s)),
+        }
+    }
+}
+
+/// How a dispute holding funds out of `available` treats funds that were already spent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputeFundsPolicy {
+    /// Hold the full deposit, driving `available` negative if necessary
+    #[default]
+    AllowNegative,
+    /// Reject the dispute unless `available` covers the whole deposit
+    RejectIfInsufficient,
+    /// Hold whatever is available, up to the deposit; a chargeback removes only what was held
+    PartialHold,
+}
+
+impl FromStr for DisputeFundsPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "allow-negative" => Ok(DisputeFundsPolicy::AllowNegative),
+            "reject-if-insufficient" => Ok(DisputeFundsPolicy::RejectIfInsufficient),
+            "partial-hold" => Ok(DisputeFundsPolicy::PartialHold),
+            _ => Err(format!("Unknown dispute funds policy: {}", s)),
+        }
+    }
+}
+
+/// How a dispute of a withdrawal moves funds while it is open
+/// Both models end in the same balances: a resolve leaves the withdrawal standing and a
+/// chargeback returns the withdrawn amount to `available`, raising `total`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WithdrawalDisputeModel {
+    /// Reverse the withdrawal into `held`: `held` and `total` rise by the amount, `available`
+    /// is unchanged
+    #[default]
+    ReverseAndHold,
+    /// Freeze the amount out of the client's own funds: `available` falls and `held` rises,
+    /// `total` is unchanged. Funds already spent are treated as for a deposit dispute under
+    /// `EngineConfig::dispute_funds`
+    HoldFromAvailable,
+}
+
+impl FromStr for WithdrawalDisputeModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reverse-and-hold" => Ok(WithdrawalDisputeModel::ReverseAndHold),
+            "hold-from-available" => Ok(WithdrawalDisputeModel::HoldFromAvailable),
+            _ => Err(format!("Unknown withdrawal dispute model: {}", s)),
+        }
+    }
+}
+
+/// Encoding of the transaction input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum InputFormat {
+    /// CSV with a `type,client,tx,amount` header
+    #[default]
+    Csv,
+    /// Fixed-width binary records, see `binary` for the layout
+    Binary,
+    /// A local Parquet file with the CSV column names, read a row group at a time; needs the
+    /// `parquet` feature
+    Parquet,
+}
+
+impl InputFormat {
+    /// The format implied by the extension of `path`: `Parquet` for `.parquet`, else `Csv`
+    pub fn for_path(path: &str) -> InputFormat {
+        let is_parquet = std::path::Path::new(path)
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("parquet"));
+        if is_parquet {
+            InputFormat::Parquet
+        } else {
+            InputFormat::Csv
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "binary" => Ok(InputFormat::Binary),
+            "parquet" => Ok(InputFormat::Parquet),
+            _ => Err(format!("Unknown input format: {}", s)),
+        }
+    }
+}
+
+/// Where the workers keep client ledgers
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum StorageBackend {
+    /// Only in the workers' memory, for the length of the run
+    #[default]
+    Memory,
+    /// Written through to a sled database in this directory, which a later run continues
+    /// from; deposits and withdrawals leave memory once stored. Needs the `sled-storage`
+    /// feature
+    Sled(String),
+}
+
+/// How amounts are written in the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NumberFormat {
+    /// `1234.5678`
+    #[default]
+    Plain,
+    /// `1,234.5678`: comma thousands separators, dot decimal separator
+    CommaThousands,
+    /// `1 234,5678` or `1.234,5678`: space or dot thousands separators, comma decimal separator
+    European,
+}
+
+impl NumberFormat {
+    /// Whether amounts in this format contain commas, so the input can't be comma delimited
+    pub fn uses_comma(&self) -> bool {
+        !matches!(self, NumberFormat::Plain)
+    }
+
+    /// Rewrites an amount in this format to the plain `1234.5678` form
+    pub fn normalize(&self, amount: &str) -> String {
+        match self {
+            NumberFormat::Plain => amount.to_string(),
+            NumberFormat::CommaThousands => amount.replace(',', ""),
+            NumberFormat::European => amount
+                .chars()
+                .filter(|c| !matches!(c, ' ' | '\u{a0}' | '.'))
+                .map(|c| if c == ',' { '.' } else { c })
+                .collect(),
+        }
+    }
+}
+
+impl FromStr for NumberFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(NumberFormat::Plain),
+            "comma-thousands" => Ok(NumberFormat::CommaThousands),
+            "european" => Ok(NumberFormat::European),
+            _ => Err(format!("Unknown number format: {}", s)),
+        }
+    }
+}
+
+/// Default number of messages the router holds back per worker with a full channel
+pub const DEFAULT_SPILL_LIMIT: usize = 1024;
+
+/// Default number of transactions grouped into one worker message
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+/// Default `EngineConfig::withdrawal_epsilon`: half a minor unit, so a withdrawal and a balance
+/// that round to the same 4-decimal value compare equal
+pub const DEFAULT_WITHDRAWAL_EPSILON: f64 = 0.00005;
+
+/// Runtime options for a single engine run
+#[derive(Debug, Clone)]
+pub struct EngineConfig {
+    /// Extra attempts made when opening an HTTP(S) input fails transiently
+    /// (connection errors, 429 and 5xx responses). Only used with the `http-input` feature.
+    pub http_retries: u32,
+    /// Extra attempts made when a read of the transaction input fails transiently, e.g. a
+    /// connection reset or timeout on a network filesystem, before the run fails with
+    /// `EngineError::ReadRetriesExhausted`. Failures such as missing permissions are never
+    /// retried
+    pub read_retries: u32,
+    /// Number of worker threads; `None` uses one per CPU core
+    pub workers: Option<usize>,
+    /// Apply every transaction in input order on a single worker. Slower, but its output is
+    /// the canonical result that parallel runs are checked against
+    pub ordered: bool,
+    /// Make stdout and stderr byte-identical for the same input and options at any worker
+    /// count, by leaving out log lines whose content or order depends on thread scheduling
+    pub deterministic: bool,
+    /// Print a per-worker load table (rows, clients, busy and idle time) to stderr after the
+    /// run; left out in `deterministic` mode since it depends on the worker count and timing
+    pub verbose: bool,
+    /// Count the deposits and withdrawals each worker keeps for disputes
+    /// (`WorkerLoad::records`) and print the total to stderr after the run, with the peak
+    /// resident memory when built with the `peak-rss` feature, for sizing instances
+    pub profile: bool,
+    /// Print a histogram of the clients each worker owned (`EngineStats::client_histogram`)
+    /// to stderr after the run, to spot routing skew
+    pub clients_per_worker: bool,
+    /// Pin each worker thread to a core of its own; needs the `affinity` feature, and is
+    /// skipped when there are fewer cores than threads to pin
+    pub pin_workers: bool,
+    /// With `pin_workers`, also pin the thread routing a file or `process_transactions` run to
+    /// the next core. That is the caller's thread, which gets the cores it was allowed before
+    /// back once the run is over; only Linux can do that, so elsewhere the router is not pinned
+    pub pin_router: bool,
+    /// Which worker owns each client; `ModuloShard` by default
+    pub shard_strategy: Arc<dyn ShardStrategy>,
+    /// Representation of available/held/total in the output
+    pub amount_scale: AmountScale,
+    /// Columns written to the output, in order
+    pub columns: Vec<AccountColumn>,
+    /// Treat the client column as an arbitrary string (e.g. a UUID) interned into dense ids.
+    /// Output shows the original identifiers, ordered by first appearance in the input.
+    pub string_client_ids: bool,
+    /// Which transactions may create an account for an unknown client
+    pub account_creation: AccountCreationPolicy,
+    /// CSV of starting accounts in the output format (`client,available,held,total,locked`)
+    pub initial_balances: Option<String>,
+    /// What happens to deposits for accounts locked by a chargeback
+    pub locked_deposits: LockedDepositPolicy,
+    /// Let a resolved dispute be opened again. Off by default, so each transaction can be
+    /// disputed to completion once
+    pub allow_redispute: bool,
+    /// Skip a deposit or withdrawal whose tx id the client's ledger already holds, e.g. from
+    /// the snapshot in `resume_from`, so processing the same input twice applies it once.
+    /// Skipped transactions are ignored with `IgnoreReason::AlreadyApplied`; without the
+    /// option they are rejected with `RejectReason::DuplicateTransaction`
+    pub idempotent: bool,
+    /// What a dispute of a deposit, or of a withdrawal held from available funds, does when
+    /// part of the amount has already been spent
+    pub dispute_funds: DisputeFundsPolicy,
+    /// How far a withdrawal may exceed the available funds and still go through, emptying
+    /// the account; absorbs f64 noise below the 4th decimal. At least 0 and below one minor
+    /// unit (0.0001)
+    pub withdrawal_epsilon: f64,
+    /// How a dispute of a withdrawal moves funds until it is resolved or charged back; keep it
+    /// the same across `snapshot` and `resume_from` while withdrawal disputes are open
+    pub withdrawal_disputes: WithdrawalDisputeModel,
+    /// Keep separate balances per currency, taken from the input's `currency` column. Output
+    /// has one row per client and currency, with a `currency` column after `client`
+    pub multi_currency: bool,
+    /// Append the per-client transaction counts (`AccountColumn::COUNTS`) and the amount in
+    /// open disputes (`AccountColumn::OpenDisputedAmount`) to the output columns. Snapshots
+    /// carry the counts over; accounts from `initial_balances` start at zero
+    pub extended_output: bool,
+    /// Write each worker's accounts as soon as the worker finishes instead of merging every
+    /// worker's into one sorted output. Rows are then sorted by client within each worker's
+    /// block only, and blocks come in the order workers finish. Only affects the CSV written
+    /// by `start_engine_with_config`, and is not available with `deterministic` or
+    /// `string_client_ids`
+    pub stream_shards: bool,
+    /// Lock an account once it has this many disputes open at the same time
+    pub max_open_disputes: Option<usize>,
+    /// Lock an account once the amounts of its open disputes add up to more than this
+    pub max_disputed_amount: Option<f64>,
+    /// Reject a dispute arriving more than this many of the client's transactions after the
+    /// deposit or withdrawal it disputes, counting every transaction of the client and the
+    /// dispute itself, so 1 allows only a dispute that directly follows
+    pub max_dispute_age: Option<u64>,
+    /// Flag a client as suspicious the first time its held funds go above this amount, e.g.
+    /// through many disputes, listing it in `EngineStats::suspicious`; nothing is locked
+    pub held_threshold: Option<f64>,
+    /// Apply each client's transactions in `Transaction::timestamp` order rather than input
+    /// order, holding back up to this many per client to do so. A transaction arriving further
+    /// out of order is applied late and counted in `EngineStats::late`; one without a
+    /// timestamp applies where it appears. Held transactions are applied at the end of the
+    /// input, or whenever the accounts are read from a long-lived `Engine`
+    pub reorder_window: Option<usize>,
+    /// Called for every transaction the engine rejects, from the worker thread that rejected it
+    pub on_reject: Option<RejectHandler>,
+    /// Receives an `AccountEvent` for every dispute, chargeback, lock and drop below zero
+    /// available, from the worker thread that applied it. Events of one client arrive in
+    /// order; different clients' events interleave. Sends never wait, so a slow receiver
+    /// can't hold up settlement: an event that finds a bounded channel full, or the receiver
+    /// dropped, is discarded and counted in `EngineStats::dropped_events`
+    pub events: Option<Sender<AccountEvent>>,
+    /// Record every transaction of this client, applied or rejected, for `RunReport::explain`
+    pub explain: Option<ClientId>,
+    /// Only process these clients; `None` processes every client. Transactions of other
+    /// clients, disputes included, are dropped by the router before reaching a worker
+    pub include_clients: Option<HashSet<ClientId>>,
+    /// Never process these clients; cannot be combined with `include_clients`
+    pub exclude_clients: HashSet<ClientId>,
+    /// Write a JSON manifest of the run (input SHA-256, row counts, workers, time) to this path
+    pub manifest: Option<String>,
+    /// Work out the run's `Provenance` (run id, input SHA-256, engine version and options) and
+    /// return it in `RunReport::provenance`; the manifest always carries it
+    pub provenance: bool,
+    /// Write the `Provenance` as `#` comment lines above the CSV header. Off by default, as
+    /// CSV readers that don't skip comments would take them for rows
+    pub provenance_header: bool,
+    /// Write the final state, including the transaction history disputes refer to, to this
+    /// path as a snapshot a later run can resume from
+    pub snapshot: Option<String>,
+    /// Write every applied transaction, in the order it was applied, to this path as CSV that
+    /// `replay_journal` turns back into the same accounts
+    pub journal: Option<String>,
+    /// Start from the state in this snapshot instead of empty accounts
+    pub resume_from: Option<String>,
+    /// Where worker ledgers are kept; a store is both the state a run starts from and where
+    /// it writes every change, so it replaces `resume_from` and `snapshot`. Used by file runs
+    /// and `process_transactions`
+    pub storage: StorageBackend,
+    /// What-if corrections: once the input is processed, process this file, in the same
+    /// format, through the state it led to. Its transactions are counted in
+    /// `EngineStats::overlay` and marked `overlay` in the journal; the snapshot keeps the
+    /// state from before it
+    pub overlay: Option<String>,
+    /// With `overlay`, write the accounts after the overlay to this path and leave the output
+    /// with the accounts from before it
+    pub overlay_output: Option<String>,
+    /// Audit mode: the run writes no file besides its output. Loaded state is only read, and
+    /// `snapshot`, `journal`, `manifest` and `overlay_output` are refused rather than ignored
+    pub read_only: bool,
+    /// Preview the run (`--dry-run`): `dry_run` returns a `DryRunReport` instead of the
+    /// accounts and writes no snapshot. `start_engine_with_config` refuses it
+    pub dry_run: bool,
+    /// Only check the input (`--inspect`): `inspect` returns the `InputSchema` read from the
+    /// header and first rows instead of processing anything. `start_engine_with_config`
+    /// refuses it
+    pub inspect: bool,
+    /// Check every account's invariants once settled (`verify::Invariant`);
+    /// `start_engine_with_config` still writes the accounts, then fails the run with
+    /// `EngineError::Inconsistent` on any violation
+    pub verify: bool,
+    /// Read the output CSV back once written and check every row against the account it was
+    /// written from, amounts as parsed numbers at 4 decimal places; a mismatch fails the run
+    /// with `EngineError::OutputMismatch` before anything reaches the writer. The output is
+    /// held in memory until checked
+    pub verify_output: bool,
+    /// Whether a run that ends with no accounts, e.g. because every row was rejected or the
+    /// input had no data rows, is reported
+    pub empty_output: EmptyOutputPolicy,
+    /// Encoding of the transaction input; delimiter, number format and string client ids only
+    /// apply to CSV
+    pub input_format: InputFormat,
+    /// Field delimiter of the input CSV
+    pub delimiter: u8,
+    /// How amounts are written in the input; formats using commas need a non-comma `delimiter`
+    pub number_format: NumberFormat,
+    /// Capacity in bytes of the buffered reader in front of the input.
+    /// Must be between 1 byte and 1 GB.
+    pub read_buffer_size: usize,
+    /// Maximum number of messages queued per worker channel; `None` for unbounded.
+    /// A bounded channel makes the router wait for slow workers instead of buffering in memory.
+    pub channel_capacity: Option<usize>,
+    /// Messages the router holds back per worker while that worker's bounded channel is full,
+    /// so one slow worker doesn't stall routing to the others. Once a worker's spill buffer is
+    /// full the router waits on that worker; 0 always waits.
+    pub spill_limit: usize,
+    /// Number of transactions the router groups into a single message per worker, which
+    /// amortizes channel synchronization over the batch. 1 sends every transaction as its own
+    /// message. `channel_capacity` and `spill_limit` count messages, so batches scale them.
+    pub batch_size: usize,
+    /// Threads that parse the rows of a hot client, one making up at least half of recent
+    /// input, on behalf of the worker that owns it; the owner still applies them one at a time
+    /// in input order. 0 parses every row on the router. CSV with numeric client ids only.
+    pub hot_client_helpers: usize,
+    /// Refuse a local input file larger than this many bytes before reading any of it;
+    /// URL input has no length to check up front
+    pub max_bytes: Option<u64>,
+    /// Most data rows read from the input; what happens past it is set by `row_limit`
+    pub max_rows: Option<u64>,
+    /// Past `max_rows`, `Truncate` (the default) stops reading and finishes the run with a
+    /// warning, while `Error` fails it with `EngineError::TooManyRows`; unused without `max_rows`
+    pub row_limit: RowLimitPolicy,
+    /// Apply exactly the first this many data rows, not counting the header, and finish the
+    /// run with the accounts as they stood after them, for point-in-time queries. Unlike
+    /// `max_rows` this is the intended end of the run, so nothing is warned about
+    pub stop_after_row: Option<u64>,
+    /// Give up on input that hasn't been read to the end this long after the run started, e.g.
+    /// a pipe that never closes; the run fails with `EngineError::TimedOut`
+    pub timeout: Option<Duration>,
+    /// On `timeout`, finish the run with the rows read so far instead of failing it
+    pub allow_partial: bool,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            http_retries: 3,
+            read_retries: 3,
+            workers: None,
+            ordered: false,
+            deterministic: false,
+            verbose: false,
+            profile: false,
+            clients_per_worker: false,
+            pin_workers: false,
+            pin_router: false,
+            shard_strategy: Arc::new(ModuloShard),
+            amount_scale: AmountScale::Decimal,
+            columns: AccountColumn::ALL.to_vec(),
+            string_client_ids: false,
+            account_creation: AccountCreationPolicy::Implicit,
+            initial_balances: None,
+            locked_deposits: LockedDepositPolicy::Reject,
+            allow_redispute: false,
+            idempotent: false,
+            dispute_funds: DisputeFundsPolicy::AllowNegative,
+            withdrawal_epsilon: DEFAULT_WITHDRAWAL_EPSILON,
+            withdrawal_disputes: WithdrawalDisputeModel::ReverseAndHold,
+            multi_currency: false,
+            extended_output: false,
+            stream_shards: false,
+            max_open_disputes: None,
+            max_disputed_amount: None,
+            max_dispute_age: None,
+            held_threshold: None,
+            reorder_window: None,
+            on_reject: None,
+            events: None,
+            explain: None,
+            include_clients: None,
+            exclude_clients: HashSet::new(),
+            manifest: None,
+            snapshot: None,
+            journal: None,
+            resume_from: None,
+            storage: StorageBackend::Memory,
+            overlay: None,
+            overlay_output: None,
+            read_only: false,
+            provenance: false,
+            provenance_header: false,
+            dry_run: false,
+            inspect: false,
+            verify: false,
+            verify_output: false,
+            empty_output: EmptyOutputPolicy::Ignore,
+            input_format: InputFormat::Csv,
+            delimiter: b',',
+            number_format: NumberFormat::Plain,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            channel_capacity: None,
+            spill_limit: DEFAULT_SPILL_LIMIT,
+            batch_size: DEFAULT_BATCH_SIZE,
+            hot_client_helpers: 0,
+            max_bytes: None,
+            max_rows: None,
+            row_limit: RowLimitPolicy::Truncate,
+            stop_after_row: None,
+            timeout: None,
+            allow_partial: false,
+        }
+    }
+}
+
+impl EngineConfig {
+    /// Number of workers a run will use, never less than 1: `workers: Some(0)`, or a CPU
+    /// count of 0 in an odd container, gets one worker
+    pub fn worker_count(&self) -> usize {
+        if self.ordered {
+            1
+        } else {
+            self.workers.unwrap_or_else(num_cpus::get).max(1)
+        }
+    }
+
+    /// Columns actually written: `columns`, plus `currency` right after `client` in
+    /// multi-currency mode and the count and open disputed amount columns at the end with
+    /// `extended_output`, unless they were selected explicitly
+    pub fn output_columns(&self) -> Vec<AccountColumn> {
+        let mut columns = self.columns.clone();
+        if self.multi_currency && !columns.contains(&AccountColumn::Currency) {
+            let at = columns
+                .iter()
+                .position(|column| *column == AccountColumn::Client)
+                .map_or(0, |client| client + 1);
+            columns.insert(at, AccountColumn::Currency);
+        }
+        if self.extended_output {
+            let extended = AccountColumn::COUNTS.into_iter();
+            for column in extended.chain([AccountColumn::OpenDisputedAmount]) {
+                if !columns.contains(&column) {
+                    columns.push(column);
+                }
+            }
+        }
+        columns
+    }
+
+    /// Number of rows after which the input is treated as ended, under
+    /// `RowLimitPolicy::Truncate`
+    pub(crate) fn truncate_rows_at(&self) -> Option<u64> {
+        self.max_rows
+            .filter(|_| self.row_limit == RowLimitPolicy::Truncate)
+    }
+
+    /// Number of rows after which the input is treated as ended, by `stop_after_row` or
+    /// `truncate_rows_at`, whichever comes first
+    pub(crate) fn last_row(&self) -> Option<u64> {
+        match (self.stop_after_row, self.truncate_rows_at()) {
+            (Some(stop), Some(truncate)) => Some(stop.min(truncate)),
+            (stop, truncate) => stop.or(truncate),
+        }
+    }
+
+    /// Whether either dispute threshold is set
+    pub fn has_dispute_thresholds(&self) -> bool {
+        self.max_open_disputes.is_some() || self.max_disputed_amount.is_some()
+    }
+
+    /// Whether `include_clients` and `exclude_clients` let this client through
+    pub fn processes_client(&self, client: ClientId) -> bool {
+        self.include_clients
+            .as_ref()
+            .is_none_or(|include| include.contains(&client))
+            && !self.exclude_clients.contains(&client)
+    }
+
+    /// Whether the input is hashed as it is read, for the manifest or the provenance
+    pub(crate) fn hashes_input(&self) -> bool {
+        self.manifest.is_some() || self.provenance || self.provenance_header
+    }
+
+    /// Checks that all tuning knobs are within sane bounds
+    pub fn validate(&self) -> Result<(), EngineError> {
+        if self.ordered && self.workers.is_some_and(|workers| workers > 1) {
+            return Err(EngineError::InvalidConfig(
+                "ordered processing runs on a single worker".to_string(),
+            ));
+        }
+        #[cfg(not(feature = "affinity"))]
+        if self.pin_workers {
+            return Err(EngineError::InvalidConfig(
+                "pinning workers needs the `affinity` feature".to_string(),
+            ));
+        }
+        if self.pin_router && !self.pin_workers {
+            return Err(EngineError::InvalidConfig(
+                "pin_router is only used together with pin_workers".to_string(),
+            ));
+        }
+        #[cfg(not(feature = "sled-storage"))]
+        if matches!(self.storage, StorageBackend::Sled(_)) {
+            return Err(EngineError::InvalidConfig(
+                "a sled store needs the `sled-storage` feature".to_string(),
+            ));
+        }
+        if self.storage != StorageBackend::Memory {
+            let conflicts: Vec<&str> = [
+                ("resume_from", self.resume_from.is_some()),
+                ("initial_balances", self.initial_balances.is_some()),
+                ("snapshot", self.snapshot.is_some()),
+                ("overlay", self.overlay.is_some()),
+                ("multi_currency", self.multi_currency),
+                ("string_client_ids", self.string_client_ids),
+                ("read_only", self.read_only),
+                ("dry_run", self.dry_run),
+            ]
+            .into_iter()
+            .filter_map(|(option, set)| set.then_some(option))
+            .collect();
+            if !conflicts.is_empty() {
+                return Err(EngineError::InvalidConfig(format!(
+                    "a store holds numeric single-currency accounts, starts the run and takes \
+                     its every change, so it cannot be combined with: {}",
+                    conflicts.join(", ")
+                )));
+            }
+        }
+        if self.read_buffer_size == 0 || self.read_buffer_size > MAX_READ_BUFFER_SIZE {
+            return Err(EngineError::InvalidConfig(format!(
+                "read buffer size must be between 1 and {} bytes, got {}",
+                MAX_READ_BUFFER_SIZE, self.read_buffer_size
+            )));
+        }
+        if self.columns.is_empty() {
+            return Err(EngineError::InvalidConfig(
+                "at least one output column must be selected".to_string(),
+            ));
+        }
+        for (index, column) in self.columns.iter().enumerate() {
+            if self.columns[..index].contains(column) {
+                return Err(EngineError::InvalidConfig(format!(
+                    "output column {} selected more than once",
+                    column.name()
+                )));
+            }
+        }
+        if self.multi_currency && self.input_format == InputFormat::Binary {
+            return Err(EngineError::InvalidConfig(
+                "binary input has no currency field and cannot be used in multi-currency mode"
+                    .to_string(),
+            ));
+        }
+        if self.multi_currency && self.initial_balances.is_some() {
+            return Err(EngineError::InvalidConfig(
+                "initial balances have no currency and cannot be combined with multi-currency mode"
+                    .to_string(),
+            ));
+        }
+        if self.multi_currency && self.journal.is_some() {
+            return Err(EngineError::InvalidConfig(
+                "the journal has no currency column and cannot be combined with multi-currency mode"
+                    .to_string(),
+            ));
+        }
+        if self.multi_currency && self.max_disputed_amount.is_some() {
+            return Err(EngineError::InvalidConfig(
+                "max_disputed_amount adds amounts across currencies and cannot be combined with \
+                 multi-currency mode"
+                    .to_string(),
+            ));
+        }
+        if self.max_open_disputes == Some(0) {
+            return Err(EngineError::InvalidConfig(
+                "max_open_disputes must be at least 1".to_string(),
+            ));
+        }
+        if self.reorder_window == Some(0) {
+            return Err(EngineError::InvalidConfig(
+                "reorder_window must be at least 1".to_string(),
+            ));
+        }
+        if self.timeout == Some(Duration::ZERO) {
+            return Err(EngineError::InvalidConfig(
+                "timeout must be longer than zero".to_string(),
+            ));
+        }
+        if self.max_dispute_age == Some(0) {
+            return Err(EngineError::InvalidConfig(
+                "max_dispute_age must be at least 1".to_string(),
+            ));
+        }
+        if let Some(amount) = self.max_disputed_amount
+            && !(amount.is_finite() && amount > 0.0)
+        {
+            return Err(EngineError::InvalidConfig(format!(
+                "max_disputed_amount must be a positive amount, got {}",
+                amount
+            )));
+        }
+        if !(0.0..1.0 / MINOR_UNITS_PER_UNIT).contains(&self.withdrawal_epsilon) {
+            return Err(EngineError::InvalidConfig(format!(
+                "withdrawal_epsilon must be at least 0 and below one minor unit (0.0001), got {}",
+                self.withdrawal_epsilon
+            )));
+        }
+        if let Some(amount) = self.held_threshold
+            && !(amount.is_finite() && amount >= 0.0)
+        {
+            return Err(EngineError::InvalidConfig(format!(
+                "held_threshold must be a non-negative amount, got {}",
+                amount
+            )));
+        }
+        if self.account_creation == AccountCreationPolicy::SeededOnly
+            && self.initial_balances.is_none()
+            && self.resume_from.is_none()
+        {
+            return Err(EngineError::InvalidConfig(
+                "seeded-only account creation requires an initial balances file or a snapshot"
+                    .to_string(),
+            ));
+        }
+        if self.resume_from.is_some() && self.initial_balances.is_some() {
+            return Err(EngineError::InvalidConfig(
+                "a run starts from either initial balances or a snapshot, not both".to_string(),
+            ));
+        }
+        if (self.snapshot.is_some() || self.resume_from.is_some())
+            && (self.string_client_ids || self.multi_currency)
+        {
+            return Err(EngineError::InvalidConfig(
+                "snapshots hold numeric single-currency accounts and cannot be combined with \
+                 string client ids or multi-currency mode"
+                    .to_string(),
+            ));
+        }
+        if self.read_only {
+            let writes: Vec<&str> = [
+                ("snapshot", self.snapshot.is_some()),
+                ("journal", self.journal.is_some()),
+                ("manifest", self.manifest.is_some()),
+                ("overlay output", self.overlay_output.is_some()),
+            ]
+            .into_iter()
+            .filter_map(|(option, set)| set.then_some(option))
+            .collect();
+            if !writes.is_empty() {
+                return Err(EngineError::InvalidConfig(format!(
+                    "read-only mode writes no files, but these options would: {}",
+                    writes.join(", ")
+                )));
+            }
+        }
+        if self.overlay_output.is_some() && self.overlay.is_none() {
+            return Err(EngineError::InvalidConfig(
+                "an overlay output needs an overlay to apply".to_string(),
+            ));
+        }
+        if self.overlay.is_some() && (self.dry_run || self.inspect) {
+            return Err(EngineError::InvalidConfig(
+                "dry runs and inspection only look at the input and apply no overlay".to_string(),
+            ));
+        }
+        if self.overlay.is_some() && (self.stream_shards || self.string_client_ids) {
+            return Err(EngineError::InvalidConfig(
+                "an overlay is applied to the finished state of numeric client ids and cannot \
+                 be combined with streamed shards or string client ids"
+                    .to_string(),
+            ));
+        }
+        if self.verify_output && self.stream_shards {
+            return Err(EngineError::InvalidConfig(
+                "streamed shards go out as workers finish and are not held back to be read \
+                 back by the output check"
+                    .to_string(),
+            ));
+        }
+        if self.provenance_header && self.stream_shards {
+            return Err(EngineError::InvalidConfig(
+                "the provenance header needs the input's SHA-256, which streamed shards are \
+                 written before"
+                    .to_string(),
+            ));
+        }
+        if self.stream_shards && self.deterministic {
+            return Err(EngineError::InvalidConfig(
+                "streamed shards come out in the order workers finish, which deterministic \
+                 output cannot depend on"
+                    .to_string(),
+            ));
+        }
+        if self.stream_shards && self.string_client_ids {
+            return Err(EngineError::InvalidConfig(
+                "streamed shards carry numeric client ids and cannot be combined with string \
+                 client ids"
+                    .to_string(),
+            ));
+        }
+        if self.string_client_ids && self.initial_balances.is_some() {
+            return Err(EngineError::InvalidConfig(
+                "initial balances cannot be combined with string client ids".to_string(),
+            ));
+        }
+        if self.string_client_ids && self.explain.is_some() {
+            return Err(EngineError::InvalidConfig(
+                "explain takes a numeric client id and cannot be combined with string client ids"
+                    .to_string(),
+            ));
+        }
+        if self.include_clients.is_some() && !self.exclude_clients.is_empty() {
+            return Err(EngineError::InvalidConfig(
+                "include and exclude client filters cannot be combined; list only the clients \
+                 to include"
+                    .to_string(),
+            ));
+        }
+        if self.string_client_ids
+            && (self.include_clients.is_some() || !self.exclude_clients.is_empty())
+        {
+            return Err(EngineError::InvalidConfig(
+                "client filters take numeric client ids and cannot be combined with string \
+                 client ids"
+                    .to_string(),
+            ));
+        }
+        if self.input_format == InputFormat::Binary && self.string_client_ids {
+            return Err(EngineError::InvalidConfig(
+                "binary input has numeric client ids and cannot use string client ids".to_string(),
+            ));
+        }
+        if self.input_format == InputFormat::Parquet
+            && (self.string_client_ids
+                || self.multi_currency
+                || self.hashes_input()
+                || self.timeout.is_some())
+        {
+            return Err(EngineError::InvalidConfig(
+                "Parquet input has numeric client ids in a single currency and is read from a \
+                 local file, so it takes no manifest, provenance or timeout"
+                    .to_string(),
+            ));
+        }
+        if self.number_format.uses_comma() && self.delimiter == b',' {
+            return Err(EngineError::InvalidConfig(format!(
+                "number format {:?} needs a delimiter other than ','",
+                self.number_format
+            )));
+        }
+        if self.channel_capacity == Some(0) {
+            return Err(EngineError::InvalidConfig(
+                "channel capacity must be at least 1".to_string(),
+            ));
+        }
+        if self.batch_size == 0 {
+            return Err(EngineError::InvalidConfig(
+                "batch size must be at least 1".to_string(),
+            ));
+        }
+        if self.hot_client_helpers > 0
+            && (self.input_format != InputFormat::Csv || self.string_client_ids)
+        {
+            return Err(EngineError::InvalidConfig(
+                "hot client helpers parse CSV rows with numeric client ids".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
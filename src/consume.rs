@@ -0,0 +1,361 @@
+//! Consuming transactions from a message broker instead of a file (`payments_engine consume`).
+//!
+//! Each message carries one transaction, as a JSON object or a single CSV row. Messages are
+//! routed into a long-lived `Engine` as they arrive. Every `checkpoint_every` messages, and at
+//! least every `checkpoint_interval` while messages keep coming, the consumer waits for the
+//! workers to apply everything routed so far. It then writes the accounts to the snapshot
+//! file together with the next offset of every partition, and only after that commits those
+//! offsets to the source. A restart loads the snapshot and skips any message below its
+//! offsets, so a message the source redelivers because its commit was lost is not applied
+//! twice.
+//!
+//! The loop runs against the `MessageSource` trait; `KafkaSource` (feature `kafka`) is the
+//! rdkafka implementation.
+
+#[cfg(feature = "kafka")]
+use crate::ClientLedger;
+#[cfg(feature = "kafka")]
+use crate::processor::write_output;
+use crate::processor::{Engine, RowDecoder, read_seeds};
+use crate::snapshot::{SourceOffset, read_checkpoint, write_snapshot};
+use crate::{ClientAccount, ClientId, EngineConfig, EngineError, Transaction};
+use csv::{ReaderBuilder, StringRecord};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// How long one poll of the source waits for a message
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Columns of a CSV payload, in order; trailing ones may be left out
+const CSV_PAYLOAD_COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "currency", "timestamp"];
+
+/// How a message payload encodes its transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadFormat {
+    /// An object with the input columns as keys, e.g.
+    /// `{"type":"deposit","client":1,"tx":1,"amount":1.5}`
+    #[default]
+    Json,
+    /// One row without a header, e.g. `deposit,1,1,1.5`
+    Csv,
+}
+
+impl fmt::Display for PayloadFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PayloadFormat::Json => "json",
+            PayloadFormat::Csv => "csv",
+        })
+    }
+}
+
+impl FromStr for PayloadFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(PayloadFormat::Json),
+            "csv" => Ok(PayloadFormat::Csv),
+            _ => Err(format!("Unknown payload format: {}", s)),
+        }
+    }
+}
+
+/// Where to consume from and how often to checkpoint
+/// Engine rules come from the `EngineConfig` passed alongside, which must set `snapshot`
+#[derive(Debug, Clone)]
+pub struct ConsumeConfig {
+    /// Comma separated `host:port` list of brokers
+    pub brokers: String,
+    pub topic: String,
+    /// Consumer group whose offsets are committed
+    pub group_id: String,
+    pub payload: PayloadFormat,
+    /// Checkpoint after this many messages
+    pub checkpoint_every: u64,
+    /// Checkpoint at least this often while messages keep arriving
+    pub checkpoint_interval: Duration,
+    /// Stop once no message has arrived for this long; `None` consumes until the process is
+    /// stopped
+    pub idle_timeout: Option<Duration>,
+}
+
+impl Default for ConsumeConfig {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            topic: String::new(),
+            group_id: "payments_engine".to_string(),
+            payload: PayloadFormat::default(),
+            checkpoint_every: 10_000,
+            checkpoint_interval: Duration::from_secs(5),
+            idle_timeout: None,
+        }
+    }
+}
+
+/// One message read from a source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMessage {
+    pub partition: i32,
+    pub offset: i64,
+    pub payload: Vec<u8>,
+}
+
+/// A partitioned, offset-addressed stream of messages such as a Kafka topic
+pub trait MessageSource {
+    /// Wait up to `timeout` for the next message, returning `None` if none arrived
+    fn poll(&mut self, timeout: Duration) -> Result<Option<SourceMessage>, EngineError>;
+
+    /// Record `offsets`, the next offset to read per partition, as consumed
+    fn commit(&mut self, offsets: &[SourceOffset]) -> Result<(), EngineError>;
+}
+
+/// Consume transactions from `source` until `consume.idle_timeout` passes without a message,
+/// then checkpoint a last time and return the accounts keyed by client id
+///
+/// Starts from the snapshot at `config.snapshot` if the file exists, and otherwise from
+/// `resume_from` or `initial_balances` as a file run would. Messages that fail to decode are
+/// reported on stderr and skipped, so one bad payload cannot stall the partition.
+pub fn consume<S: MessageSource>(
+    source: &mut S,
+    consume: &ConsumeConfig,
+    config: &EngineConfig,
+) -> Result<HashMap<ClientId, ClientAccount>, EngineError> {
+    let path = check_config(consume, config)?;
+    let (seeds, offsets) = if Path::new(path).exists() {
+        read_checkpoint(path)?
+    } else {
+        (read_seeds(config)?, Vec::new())
+    };
+    let mut positions: BTreeMap<i32, i64> = offsets
+        .iter()
+        .map(|position| (position.partition, position.offset))
+        .collect();
+    let mut engine = Engine::with_seeds(config, seeds)?;
+    let decoder = PayloadDecoder::new(consume.payload, config);
+
+    let mut pending = 0u64;
+    let mut last_checkpoint = Instant::now();
+    let mut last_message = Instant::now();
+    loop {
+        match source.poll(POLL_TIMEOUT)? {
+            Some(message) => {
+                last_message = Instant::now();
+                // Already applied before the snapshot was written; the commit that followed
+                // it never reached the source
+                if positions
+                    .get(&message.partition)
+                    .is_some_and(|&next| message.offset < next)
+                {
+                    continue;
+                }
+                positions.insert(message.partition, message.offset + 1);
+                pending += 1;
+                match decoder.decode(&message.payload) {
+                    Ok(transaction) => engine.submit(transaction)?,
+                    Err(e) => eprintln!(
+                        "Skipping message at partition {} offset {}: {}",
+                        message.partition, message.offset, e
+                    ),
+                }
+            }
+            None => {
+                if consume
+                    .idle_timeout
+                    .is_some_and(|timeout| last_message.elapsed() >= timeout)
+                {
+                    break;
+                }
+            }
+        }
+
+        if pending >= consume.checkpoint_every
+            || (pending > 0 && last_checkpoint.elapsed() >= consume.checkpoint_interval)
+        {
+            checkpoint(&engine, path, &positions, source)?;
+            pending = 0;
+            last_checkpoint = Instant::now();
+        }
+    }
+
+    if pending > 0 {
+        checkpoint(&engine, path, &positions, source)?;
+    }
+    engine.drain_and_reset()
+}
+
+/// Check the options of a consumer, returning the snapshot path it checkpoints to
+fn check_config<'a>(
+    consume: &ConsumeConfig,
+    config: &'a EngineConfig,
+) -> Result<&'a str, EngineError> {
+    config.validate()?;
+    let Some(path) = &config.snapshot else {
+        return Err(EngineError::InvalidConfig(
+            "consume checkpoints to the snapshot file, so `snapshot` must be set".to_string(),
+        ));
+    };
+    if config.string_client_ids {
+        return Err(EngineError::InvalidConfig(
+            "consume takes numeric client ids".to_string(),
+        ));
+    }
+    if consume.checkpoint_every == 0 {
+        return Err(EngineError::InvalidConfig(
+            "checkpoint_every must be at least 1".to_string(),
+        ));
+    }
+    Ok(path)
+}
+
+/// Consume from Kafka until `consume.idle_timeout`, then write the accounts to stdout as CSV
+#[cfg(feature = "kafka")]
+pub fn start_consumer(consume: &ConsumeConfig, config: &EngineConfig) -> Result<(), EngineError> {
+    // Check before connecting so a bad option fails without touching the brokers
+    check_config(consume, config)?;
+    let mut source = KafkaSource::connect(consume)?;
+    let accounts = self::consume(&mut source, consume, config)?;
+    let mut shard: Vec<ClientLedger> = accounts
+        .into_values()
+        .map(ClientLedger::from_account)
+        .collect();
+    shard.sort_unstable_by_key(|ledger| ledger.account().client);
+    write_output(vec![shard], config, None, std::io::stdout())
+}
+
+/// Save the applied state with the positions it covers, then commit those positions
+/// A crash mid-write leaves the previous checkpoint intact, see `write_snapshot`
+fn checkpoint<S: MessageSource>(
+    engine: &Engine,
+    path: &str,
+    positions: &BTreeMap<i32, i64>,
+    source: &mut S,
+) -> Result<(), EngineError> {
+    // Replies only once every worker has applied all it was sent
+    let ledgers = engine.ledgers()?;
+    let offsets: Vec<SourceOffset> = positions
+        .iter()
+        .map(|(&partition, &offset)| SourceOffset { partition, offset })
+        .collect();
+
+    write_snapshot(path, ledgers.iter(), &offsets)?;
+    source.commit(&offsets)
+}
+
+/// Turns message payloads into transactions
+struct PayloadDecoder {
+    format: PayloadFormat,
+    delimiter: u8,
+    rows: RowDecoder,
+}
+
+impl PayloadDecoder {
+    fn new(format: PayloadFormat, config: &EngineConfig) -> Self {
+        Self {
+            format,
+            delimiter: config.delimiter,
+            rows: RowDecoder::new(
+                StringRecord::from(CSV_PAYLOAD_COLUMNS.to_vec()),
+                config,
+                &["client", "tx"],
+            ),
+        }
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Transaction, EngineError> {
+        match self.format {
+            PayloadFormat::Json => serde_json::from_slice(payload)
+                .map_err(|e| EngineError::InvalidInput(format!("bad JSON payload: {}", e))),
+            PayloadFormat::Csv => {
+                let mut reader = ReaderBuilder::new()
+                    .has_headers(false)
+                    .flexible(true)
+                    .trim(csv::Trim::All)
+                    .delimiter(self.delimiter)
+                    .from_reader(payload);
+                let mut record = StringRecord::new();
+                if !reader.read_record(&mut record)? {
+                    return Err(EngineError::InvalidInput("empty CSV payload".to_string()));
+                }
+                self.rows.decode(record)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub use kafka::KafkaSource;
+
+#[cfg(feature = "kafka")]
+mod kafka {
+    use super::{ConsumeConfig, MessageSource, SourceMessage};
+    use crate::EngineError;
+    use crate::snapshot::SourceOffset;
+    use rdkafka::consumer::{BaseConsumer, CommitMode, Consumer};
+    use rdkafka::error::KafkaError;
+    use rdkafka::{ClientConfig, Message, Offset, TopicPartitionList};
+    use std::time::Duration;
+
+    fn source_error(e: KafkaError) -> EngineError {
+        EngineError::Source(e.to_string())
+    }
+
+    /// A Kafka consumer group member subscribed to `ConsumeConfig::topic`
+    /// Auto-commit is off: offsets are committed only by checkpoints
+    pub struct KafkaSource {
+        consumer: BaseConsumer,
+        topic: String,
+    }
+
+    impl KafkaSource {
+        pub fn connect(consume: &ConsumeConfig) -> Result<Self, EngineError> {
+            let consumer: BaseConsumer = ClientConfig::new()
+                .set("bootstrap.servers", &consume.brokers)
+                .set("group.id", &consume.group_id)
+                .set("enable.auto.commit", "false")
+                .set("auto.offset.reset", "earliest")
+                .create()
+                .map_err(source_error)?;
+            consumer
+                .subscribe(&[&consume.topic])
+                .map_err(source_error)?;
+            Ok(Self {
+                consumer,
+                topic: consume.topic.clone(),
+            })
+        }
+    }
+
+    impl MessageSource for KafkaSource {
+        fn poll(&mut self, timeout: Duration) -> Result<Option<SourceMessage>, EngineError> {
+            match self.consumer.poll(timeout) {
+                None => Ok(None),
+                Some(Err(e)) => Err(source_error(e)),
+                Some(Ok(message)) => Ok(Some(SourceMessage {
+                    partition: message.partition(),
+                    offset: message.offset(),
+                    payload: message.payload().unwrap_or_default().to_vec(),
+                })),
+            }
+        }
+
+        fn commit(&mut self, offsets: &[SourceOffset]) -> Result<(), EngineError> {
+            let mut list = TopicPartitionList::new();
+            for position in offsets {
+                list.add_partition_offset(
+                    &self.topic,
+                    position.partition,
+                    Offset::Offset(position.offset),
+                )
+                .map_err(source_error)?;
+            }
+            self.consumer
+                .commit(&list, CommitMode::Sync)
+                .map_err(source_error)
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::account::{format_decimal, to_minor_units};
+use crate::input::read_initial_balances;
+use crate::{AccountColumn, ClientAccount, ClientId, EngineConfig, EngineError};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// One discrepancy between two account outputs, as found by `diff_outputs`
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccountDiff {
+    /// The client has a row in the first output only
+    OnlyInLeft(ClientId),
+    /// The client has a row in the second output only
+    OnlyInRight(ClientId),
+    /// Both outputs have the client, but this column differs by more than the tolerance
+    Field {
+        client: ClientId,
+        column: AccountColumn,
+        left: String,
+        right: String,
+    },
+}
+
+impl fmt::Display for AccountDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AccountDiff::OnlyInLeft(client) => {
+                write!(f, "client {}: only in the first output", client)
+            }
+            AccountDiff::OnlyInRight(client) => {
+                write!(f, "client {}: only in the second output", client)
+            }
+            AccountDiff::Field {
+                client,
+                column,
+                left,
+                right,
+            } => write!(
+                f,
+                "client {}: {} {} != {}",
+                client,
+                column.name(),
+                left,
+                right
+            ),
+        }
+    }
+}
+
+/// Compare two account CSVs in the default output format, matching rows by client id
+///
+/// Balances differing by no more than `tolerance` count as equal; both sides are rounded to
+/// four decimal places first, so a tolerance of zero still ignores differences the output
+/// can't show. `locked` must match exactly. Differences are returned sorted by client, and a
+/// client appearing twice in one file or a negative tolerance is an error.
+pub fn diff_outputs(
+    left: &str,
+    right: &str,
+    tolerance: f64,
+) -> Result<Vec<AccountDiff>, EngineError> {
+    if !tolerance.is_finite() || tolerance < 0.0 {
+        return Err(EngineError::InvalidConfig(format!(
+            "diff tolerance must be zero or more, got {}",
+            tolerance
+        )));
+    }
+    let left = read_accounts(left)?;
+    let mut right = read_accounts(right)?;
+    let tolerance = to_minor_units(tolerance).unsigned_abs();
+
+    let mut diffs = Vec::new();
+    for (client, left) in left {
+        let Some(right) = right.remove(&client) else {
+            diffs.push(AccountDiff::OnlyInLeft(client));
+            continue;
+        };
+        for (column, a, b) in [
+            (AccountColumn::Available, left.available, right.available),
+            (AccountColumn::Held, left.held, right.held),
+            (AccountColumn::Total, left.total, right.total),
+        ] {
+            if to_minor_units(a).abs_diff(to_minor_units(b)) > tolerance {
+                diffs.push(AccountDiff::Field {
+                    client,
+                    column,
+                    left: format_decimal(a),
+                    right: format_decimal(b),
+                });
+            }
+        }
+        if left.locked != right.locked {
+            diffs.push(AccountDiff::Field {
+                client,
+                column: AccountColumn::Locked,
+                left: left.locked.to_string(),
+                right: right.locked.to_string(),
+            });
+        }
+    }
+    diffs.extend(right.into_keys().map(AccountDiff::OnlyInRight));
+    diffs.sort_by_key(|diff| match diff {
+        AccountDiff::OnlyInLeft(client) | AccountDiff::OnlyInRight(client) => *client,
+        AccountDiff::Field { client, .. } => *client,
+    });
+    Ok(diffs)
+}
+
+fn read_accounts(path: &str) -> Result<BTreeMap<ClientId, ClientAccount>, EngineError> {
+    let mut accounts = BTreeMap::new();
+    for account in read_initial_balances(path, &EngineConfig::default())? {
+        let client = account.client;
+        if accounts.insert(client, account).is_some() {
+            return Err(EngineError::InvalidInput(format!(
+                "client {} appears more than once in {}",
+                client, path
+            )));
+        }
+    }
+    Ok(accounts)
+}
@@ -0,0 +1,120 @@
+use crate::account::{format_decimal, to_minor_units};
+use crate::processor::read_seeds;
+use crate::{ClientAccount, ClientId, EngineConfig, EngineError, process_file_with_report};
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+
+/// What a run would do, produced by `dry_run` without writing accounts or a snapshot
+#[derive(Debug, Clone, PartialEq)]
+pub struct DryRunReport {
+    /// Transactions read from the input
+    pub rows: u64,
+    /// Transactions that would change a ledger
+    pub applied: u64,
+    /// Transactions that would be rejected, keyed by `RejectReason::code`
+    pub rejected: BTreeMap<&'static str, u64>,
+    /// Clients that would end up locked but were not locked in the starting state, sorted
+    pub newly_locked: Vec<ClientId>,
+    /// Accounts that would end with a negative available, held or total balance, sorted by
+    /// client (and currency in multi-currency mode)
+    pub negative: Vec<ClientAccount>,
+}
+
+impl DryRunReport {
+    /// Whether the run would complete without rejections or negative balances
+    pub fn is_clean(&self) -> bool {
+        self.rejected.is_empty() && self.negative.is_empty()
+    }
+}
+
+impl fmt::Display for DryRunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Dry run: no accounts written and no state saved")?;
+        writeln!(f, "rows: {}", self.rows)?;
+        writeln!(f, "applied: {}", self.applied)?;
+        writeln!(f, "rejected: {}", self.rejected.values().sum::<u64>())?;
+        for (code, count) in &self.rejected {
+            writeln!(f, "  {}: {}", code, count)?;
+        }
+        let locked: Vec<String> = self.newly_locked.iter().map(ToString::to_string).collect();
+        writeln!(f, "newly locked: {}", locked.len())?;
+        if !locked.is_empty() {
+            writeln!(f, "  clients {}", locked.join(", "))?;
+        }
+        write!(f, "negative balances: {}", self.negative.len())?;
+        for account in &self.negative {
+            write!(f, "\n  client {}", account.client)?;
+            if let Some(currency) = account.currency {
+                write!(f, " {}", currency)?;
+            }
+            write!(
+                f,
+                ": available {} held {} total {}",
+                format_decimal(account.available),
+                format_decimal(account.held),
+                format_decimal(account.total)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Run the full pipeline against a throwaway copy of the starting state and report the
+/// outcome. `config.resume_from` and `config.initial_balances` are read but
+/// neither `config.snapshot` nor `config.journal` is written, and no accounts are output.
+pub fn dry_run(path: &str, config: &EngineConfig) -> Result<DryRunReport, EngineError> {
+    let config = EngineConfig {
+        snapshot: None,
+        journal: None,
+        // Lets `validate` refuse a store
+        dry_run: true,
+        ..config.clone()
+    };
+    config.validate()?;
+    let locked_before: HashSet<ClientId> = read_seeds(&config)?
+        .iter()
+        .map(|ledger| ledger.account())
+        .filter(|account| account.locked)
+        .map(|account| account.client)
+        .collect();
+
+    let report = process_file_with_report(path, &config)?;
+
+    let mut newly_locked: Vec<ClientId> = report
+        .accounts
+        .values()
+        .filter(|account| account.locked && !locked_before.contains(&account.client))
+        .map(|account| account.client)
+        .collect();
+    newly_locked.sort_unstable();
+
+    let is_negative = |account: &&ClientAccount| {
+        [account.available, account.held, account.total]
+            .into_iter()
+            .any(|balance| to_minor_units(balance) < 0)
+    };
+    let mut negative: Vec<ClientAccount> = if config.multi_currency {
+        report
+            .currency_accounts
+            .iter()
+            .filter(is_negative)
+            .cloned()
+            .collect()
+    } else {
+        report
+            .accounts
+            .values()
+            .filter(is_negative)
+            .cloned()
+            .collect()
+    };
+    negative.sort_unstable_by_key(|account| (account.client, account.currency));
+
+    Ok(DryRunReport {
+        rows: report.stats.rows,
+        applied: report.stats.applied,
+        rejected: report.stats.rejected,
+        newly_locked,
+        negative,
+    })
+}
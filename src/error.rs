@@ -0,0 +1,202 @@
+use crate::input::{DeadlineExceeded, RetriesExhausted};
+use crate::verify::Violation;
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+/// Errors that abort an engine run
+#[derive(Debug)]
+pub enum EngineError {
+    /// Failure opening or reading the input source
+    Io(io::Error),
+    /// The input path is an empty string
+    EmptyPath,
+    /// The input path names a directory rather than a file
+    IsDirectory(String),
+    /// No file exists at the input path
+    NotFound(String),
+    /// Malformed CSV record that could not be deserialized
+    Csv(csv::Error),
+    /// HTTP(S) input answered with a status other than 200 OK
+    Http { url: String, status: u16 },
+    /// A worker thread panicked or its channel closed unexpectedly
+    Worker(String),
+    /// An `EngineConfig` value is out of bounds
+    InvalidConfig(String),
+    /// Input that parses but cannot be processed
+    InvalidInput(String),
+    /// A `client` or `tx` field written with a sign, e.g. `+5` or `-1`
+    SignedId {
+        column: &'static str,
+        value: String,
+        line: u64,
+    },
+    /// A snapshot file that is corrupt or of an unsupported format version
+    InvalidSnapshot(String),
+    /// The run produced no accounts and `EmptyOutputPolicy::Error` is set; `rows` is the
+    /// number of data rows read
+    EmptyOutput { rows: u64 },
+    /// The input file is `size` bytes, more than `EngineConfig::max_bytes`
+    InputTooLarge { size: u64, max_bytes: u64 },
+    /// The input has more than `EngineConfig::max_rows` data rows; processing stopped at
+    /// data row `row`
+    TooManyRows { row: u64, max_rows: u64 },
+    /// A message source such as a Kafka consumer failed to deliver or commit
+    Source(String),
+    /// The input was not read to the end within `EngineConfig::timeout`
+    TimedOut { timeout: Duration },
+    /// Reading the input failed transiently `attempts` times in a row, the last with `error`,
+    /// using up `EngineConfig::read_retries`
+    ReadRetriesExhausted { attempts: u32, error: io::Error },
+    /// Parquet input that is malformed or could not be decoded
+    Parquet(String),
+    /// Settled accounts broke the invariants checked by `EngineConfig::verify`
+    Inconsistent(Vec<Violation>),
+    /// The written output did not read back as the accounts it was written from
+    /// (`EngineConfig::verify_output`)
+    OutputMismatch(String),
+    /// The `EngineConfig::storage` store failed, or holds data this engine can't read
+    Store(String),
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Io(e) => write!(f, "I/O error: {}", e),
+            EngineError::EmptyPath => write!(f, "Input path is empty"),
+            EngineError::IsDirectory(path) => {
+                write!(f, "Input path {} is a directory, not a file", path)
+            }
+            EngineError::NotFound(path) => write!(f, "Input file {} not found", path),
+            // csv prefixes every kind of error but I/O
+            EngineError::Csv(e) => match e.kind() {
+                csv::ErrorKind::Io(_) => write!(f, "CSV error: {}", e),
+                _ => write!(f, "{}", e),
+            },
+            EngineError::Http { url, status } => {
+                write!(f, "HTTP request to {} returned status {}", url, status)
+            }
+            EngineError::Worker(msg) => write!(f, "Worker error: {}", msg),
+            EngineError::InvalidConfig(msg) => write!(f, "Invalid configuration: {}", msg),
+            EngineError::InvalidInput(msg) => write!(f, "Invalid input: {}", msg),
+            EngineError::SignedId {
+                column,
+                value,
+                line,
+            } => write!(
+                f,
+                "Invalid {} `{}` on line {}: ids are unsigned integers written without a sign",
+                column, value, line
+            ),
+            EngineError::InvalidSnapshot(reason) => write!(f, "Invalid snapshot: {}", reason),
+            EngineError::EmptyOutput { rows } => write!(f, "{}", empty_output_message(*rows)),
+            EngineError::InputTooLarge { size, max_bytes } => write!(
+                f,
+                "Input is {} bytes, over the max_bytes limit of {} bytes",
+                size, max_bytes
+            ),
+            EngineError::TooManyRows { row, max_rows } => write!(
+                f,
+                "Input exceeds the max_rows limit of {} rows: stopped at data row {}",
+                max_rows, row
+            ),
+            EngineError::Source(msg) => write!(f, "Message source error: {}", msg),
+            EngineError::TimedOut { timeout } => {
+                write!(
+                    f,
+                    "Input not read to the end within the timeout of {:?}",
+                    timeout
+                )
+            }
+            EngineError::ReadRetriesExhausted { attempts, error } => write!(
+                f,
+                "Reading the input failed {} times in a row, giving up: {}",
+                attempts, error
+            ),
+            EngineError::Parquet(msg) => write!(f, "Parquet error: {}", msg),
+            EngineError::Inconsistent(violations) => write!(
+                f,
+                "Verification found {} invariant violations",
+                violations.len()
+            ),
+            EngineError::OutputMismatch(reason) => {
+                write!(f, "Output does not read back as written: {}", reason)
+            }
+            EngineError::Store(msg) => write!(f, "Store error: {}", msg),
+        }
+    }
+}
+
+/// Why a run ended without accounts, shared by the warning and the error
+pub(crate) fn empty_output_message(rows: u64) -> String {
+    if rows == 0 {
+        "Input has no data rows, so no accounts were produced".to_string()
+    } else {
+        format!(
+            "All {} input rows were processed but no accounts were produced",
+            rows
+        )
+    }
+}
+
+impl std::error::Error for EngineError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EngineError::Io(e) => Some(e),
+            EngineError::Csv(e) => Some(e),
+            EngineError::ReadRetriesExhausted { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EngineError {
+    fn from(e: io::Error) -> Self {
+        if let Some(timeout) = deadline_exceeded(&e) {
+            return EngineError::TimedOut { timeout };
+        }
+        if !is_retries_exhausted(&e) {
+            return EngineError::Io(e);
+        }
+        match e
+            .into_inner()
+            .map(|inner| inner.downcast::<RetriesExhausted>())
+        {
+            Some(Ok(exhausted)) => EngineError::ReadRetriesExhausted {
+                attempts: exhausted.attempts,
+                error: exhausted.last,
+            },
+            _ => unreachable!("checked to be `RetriesExhausted` above"),
+        }
+    }
+}
+
+impl From<csv::Error> for EngineError {
+    fn from(e: csv::Error) -> Self {
+        // Giving up on the input is reported as such, not as a CSV error
+        let gave_up = match e.kind() {
+            csv::ErrorKind::Io(io) => deadline_exceeded(io).is_some() || is_retries_exhausted(io),
+            _ => false,
+        };
+        if !gave_up {
+            return EngineError::Csv(e);
+        }
+        match e.into_kind() {
+            csv::ErrorKind::Io(io) => io.into(),
+            _ => unreachable!("checked to be an I/O error above"),
+        }
+    }
+}
+
+/// The timeout, if `e` is a `DeadlineReader` giving up rather than a failure of the input
+fn deadline_exceeded(e: &io::Error) -> Option<Duration> {
+    e.get_ref()?
+        .downcast_ref::<DeadlineExceeded>()
+        .map(|exceeded| exceeded.0)
+}
+
+/// Whether `e` is a `RetryingReader` giving up after `EngineConfig::read_retries`
+fn is_retries_exhausted(e: &io::Error) -> bool {
+    e.get_ref()
+        .is_some_and(|inner| inner.is::<RetriesExhausted>())
+}
@@ -0,0 +1,110 @@
+use crate::account::to_minor_units;
+use crate::ledger::ClientLedger;
+use crate::{ClientId, Currency, LockReason, Transaction, TransactionType};
+use crossbeam_channel::Sender;
+
+/// A change to an account, published to `EngineConfig::events` as the transaction causing it
+/// is applied
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccountEvent {
+    /// Transaction `tx` was disputed; `amount` is the disputed transaction's amount
+    DisputeOpened {
+        client: ClientId,
+        tx: u32,
+        amount: f64,
+    },
+    /// Transaction `tx` was charged back; `amount` is the charged back transaction's amount
+    ChargebackApplied {
+        client: ClientId,
+        tx: u32,
+        amount: f64,
+    },
+    /// Transaction `tx` took the available balance from zero or more to below zero
+    /// `currency` is set in multi-currency mode, where each currency is watched on its own
+    WentNegative {
+        client: ClientId,
+        tx: u32,
+        currency: Option<Currency>,
+        available: f64,
+    },
+    /// Transaction `tx` locked the account
+    Locked {
+        client: ClientId,
+        tx: u32,
+        reason: LockReason,
+    },
+}
+
+/// What a client looked like before a transaction, for telling which events it caused
+pub(crate) struct Baseline {
+    locked: bool,
+    /// Currencies (`None` outside multi-currency mode) whose available balance was negative
+    negative: Vec<Option<Currency>>,
+}
+
+impl Baseline {
+    /// `ledger` is `None` for a client without an account yet
+    pub(crate) fn of(ledger: Option<&ClientLedger>) -> Self {
+        let Some(ledger) = ledger else {
+            return Self {
+                locked: false,
+                negative: Vec::new(),
+            };
+        };
+        Self {
+            locked: ledger.account().locked,
+            negative: ledger
+                .output_accounts()
+                .filter(|account| to_minor_units(account.available) < 0)
+                .map(|account| account.currency)
+                .collect(),
+        }
+    }
+}
+
+/// Send the events of an applied `transaction` to `events`, given the client's `ledger`
+/// after it and its `baseline` before
+///
+/// Never waits: an event that doesn't fit in the channel, or finds the receiver gone, is
+/// dropped and counted in `dropped`.
+pub(crate) fn publish(
+    events: &Sender<AccountEvent>,
+    baseline: &Baseline,
+    ledger: &ClientLedger,
+    transaction: &Transaction,
+    dropped: &mut u64,
+) {
+    let client = transaction.client;
+    let tx = transaction.tx;
+    let mut send = |event| {
+        if events.try_send(event).is_err() {
+            *dropped += 1;
+        }
+    };
+
+    let amount = ledger.transaction_amount(tx).unwrap_or_default();
+    match transaction.tx_type {
+        TransactionType::Dispute => send(AccountEvent::DisputeOpened { client, tx, amount }),
+        TransactionType::Chargeback => send(AccountEvent::ChargebackApplied { client, tx, amount }),
+        _ => {}
+    }
+    for account in ledger.output_accounts() {
+        if to_minor_units(account.available) < 0 && !baseline.negative.contains(&account.currency) {
+            send(AccountEvent::WentNegative {
+                client,
+                tx,
+                currency: account.currency,
+                available: account.available,
+            });
+        }
+    }
+    let account = ledger.account();
+    if account.locked && !baseline.locked {
+        send(AccountEvent::Locked {
+            client,
+            tx,
+            // Every lock records its reason
+            reason: account.lock_reason.unwrap_or(LockReason::Chargeback),
+        });
+    }
+}
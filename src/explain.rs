@@ -0,0 +1,39 @@
+use crate::account::format_decimal;
+use crate::{AccountColumn, AmountScale, ClientAccount, RejectReason, Transaction};
+use std::fmt;
+
+/// One transaction of the client selected by `EngineConfig::explain` and its effect
+#[derive(Debug, Clone)]
+pub struct ExplainStep {
+    pub transaction: Transaction,
+    /// The account right after the transaction, or why the transaction was rejected
+    pub outcome: Result<ClientAccount, RejectReason>,
+}
+
+impl fmt::Display for ExplainStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let transaction = &self.transaction;
+        write!(f, "{} tx {}", transaction.tx_type.name(), transaction.tx)?;
+        if let Some(amount) = transaction.amount {
+            write!(f, " amount {}", format_decimal(amount))?;
+        }
+
+        match &self.outcome {
+            Ok(account) => {
+                let field = |column| account.field(column, AmountScale::Decimal);
+                write!(
+                    f,
+                    " -> available {} held {} total {}",
+                    field(AccountColumn::Available),
+                    field(AccountColumn::Held),
+                    field(AccountColumn::Total)
+                )?;
+                if account.locked {
+                    write!(f, " locked")?;
+                }
+                Ok(())
+            }
+            Err(reason) => write!(f, " -> rejected: {}", reason),
+        }
+    }
+}
@@ -0,0 +1,362 @@
+//! C ABI for embedding the engine in non-Rust services (feature `ffi`).
+//!
+//! The declarations are in `include/payments_engine.h`, generated from this module with
+//! `cbindgen --config cbindgen.toml --output include/payments_engine.h`. Build a linkable
+//! library with `cargo rustc --release --lib --features ffi --crate-type cdylib` (or `staticlib`).
+//!
+//! An engine applies transactions one call at a time on the calling thread, under the same
+//! rules as a file run. Functions never unwind into C: a panic is caught at the boundary and
+//! reported as `PE_ERR_PANIC` (or a NULL engine). Statuses are `PE_OK`, `PE_IGNORED` (or
+//! `PE_ALREADY_APPLIED` for a repeated tx id under the `idempotent` option), a positive
+//! `PE_REJECTED_*` code per `RejectReason`, or a negative `PE_ERR_*` code per `EngineError`
+//! kind.
+
+use crate::binary::tx_type;
+use crate::ledger::{ApplyOutcome, IgnoreReason};
+use crate::processor::{WorkerState, apply_to_client, write_output};
+use crate::{
+    AccountColumn, ClientId, EngineConfig, EngineError, RejectReason, Transaction, TransactionType,
+};
+use serde::Deserialize;
+use std::ffi::{CStr, c_char, c_int};
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::ptr;
+use std::str::FromStr;
+
+/// `tx_type` code of a deposit; the codes match the binary input format
+pub const PE_DEPOSIT: u8 = 0;
+/// `tx_type` code of a withdrawal
+pub const PE_WITHDRAWAL: u8 = 1;
+/// `tx_type` code of a dispute
+pub const PE_DISPUTE: u8 = 2;
+/// `tx_type` code of a resolve
+pub const PE_RESOLVE: u8 = 3;
+/// `tx_type` code of a chargeback
+pub const PE_CHARGEBACK: u8 = 4;
+/// `tx_type` code of an account closure
+pub const PE_CLOSE: u8 = 5;
+
+/// The transaction was applied
+pub const PE_OK: c_int = 0;
+/// The transaction changed nothing, e.g. a dispute of an unknown tx
+pub const PE_IGNORED: c_int = 1;
+/// The deposit or withdrawal's tx id was already applied (`idempotent` option)
+pub const PE_ALREADY_APPLIED: c_int = 2;
+/// `RejectReason::UnknownClient`
+pub const PE_REJECTED_UNKNOWN_CLIENT: c_int = 10;
+/// `RejectReason::AccountLocked`
+pub const PE_REJECTED_ACCOUNT_LOCKED: c_int = 11;
+/// `RejectReason::InsufficientFunds`
+pub const PE_REJECTED_INSUFFICIENT_FUNDS: c_int = 12;
+/// `RejectReason::DisputeUnfunded`
+pub const PE_REJECTED_DISPUTE_UNFUNDED: c_int = 13;
+/// `RejectReason::MissingCurrency`
+pub const PE_REJECTED_MISSING_CURRENCY: c_int = 14;
+/// `RejectReason::DisputeExpired`
+pub const PE_REJECTED_DISPUTE_EXPIRED: c_int = 15;
+/// `RejectReason::AmountBelowMinimum`
+pub const PE_REJECTED_AMOUNT_BELOW_MINIMUM: c_int = 16;
+/// `RejectReason::DuplicateTransaction`
+pub const PE_REJECTED_DUPLICATE_TX: c_int = 17;
+/// `RejectReason::MissingAmount`
+pub const PE_REJECTED_MISSING_AMOUNT: c_int = 18;
+/// `RejectReason::Overflow`
+pub const PE_REJECTED_OVERFLOW: c_int = 19;
+/// `RejectReason::AccountClosed`
+pub const PE_REJECTED_ACCOUNT_CLOSED: c_int = 20;
+/// A required pointer was NULL
+pub const PE_ERR_NULL: c_int = -1;
+/// An argument is out of range, e.g. an unknown type code or a non-finite amount
+pub const PE_ERR_INVALID_ARGUMENT: c_int = -2;
+/// The client has no account
+pub const PE_ERR_NOT_FOUND: c_int = -3;
+/// Writing the output failed (`EngineError::Io` or `EngineError::Csv`)
+pub const PE_ERR_IO: c_int = -4;
+/// Any other `EngineError`
+pub const PE_ERR_ENGINE: c_int = -5;
+/// The engine panicked; the engine should be freed and not used again
+pub const PE_ERR_PANIC: c_int = -6;
+
+/// Opaque engine handle owned by the caller until `pe_engine_free`
+pub struct Engine {
+    config: EngineConfig,
+    state: WorkerState,
+}
+
+/// Balances of one client, filled in by `pe_engine_account`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PeAccount {
+    pub client: u32,
+    pub available: f64,
+    pub held: f64,
+    pub total: f64,
+    pub locked: bool,
+}
+
+/// Engine options accepted by `pe_engine_new`, named as the `EngineConfig` fields
+/// Policies are given by their CLI names, e.g. `{"account_creation": "deposits-only"}`
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FfiConfig {
+    amount_scale: Option<String>,
+    columns: Option<Vec<String>>,
+    account_creation: Option<String>,
+    locked_deposits: Option<String>,
+    dispute_funds: Option<String>,
+    withdrawal_disputes: Option<String>,
+    withdrawal_epsilon: Option<f64>,
+    allow_redispute: Option<bool>,
+    idempotent: Option<bool>,
+    max_open_disputes: Option<usize>,
+    max_disputed_amount: Option<f64>,
+    max_dispute_age: Option<u64>,
+}
+
+impl FfiConfig {
+    fn into_config(self) -> Result<EngineConfig, EngineError> {
+        let defaults = EngineConfig::default();
+        let config = EngineConfig {
+            amount_scale: parse_or(self.amount_scale, defaults.amount_scale)?,
+            columns: match self.columns {
+                Some(names) => names
+                    .iter()
+                    .map(|name| AccountColumn::from_str(name))
+                    .collect::<Result<_, _>>()
+                    .map_err(EngineError::InvalidConfig)?,
+                None => defaults.columns.clone(),
+            },
+            account_creation: parse_or(self.account_creation, defaults.account_creation)?,
+            locked_deposits: parse_or(self.locked_deposits, defaults.locked_deposits)?,
+            dispute_funds: parse_or(self.dispute_funds, defaults.dispute_funds)?,
+            withdrawal_disputes: parse_or(self.withdrawal_disputes, defaults.withdrawal_disputes)?,
+            withdrawal_epsilon: self
+                .withdrawal_epsilon
+                .unwrap_or(defaults.withdrawal_epsilon),
+            allow_redispute: self.allow_redispute.unwrap_or(defaults.allow_redispute),
+            idempotent: self.idempotent.unwrap_or(defaults.idempotent),
+            max_open_disputes: self.max_open_disputes,
+            max_disputed_amount: self.max_disputed_amount,
+            max_dispute_age: self.max_dispute_age,
+            ..defaults
+        };
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+fn parse_or<T: FromStr<Err = String>>(value: Option<String>, default: T) -> Result<T, EngineError> {
+    value.map_or(Ok(default), |value| {
+        value.parse().map_err(EngineError::InvalidConfig)
+    })
+}
+
+fn reject_status(reason: &RejectReason) -> c_int {
+    match reason {
+        RejectReason::UnknownClient => PE_REJECTED_UNKNOWN_CLIENT,
+        RejectReason::AccountLocked => PE_REJECTED_ACCOUNT_LOCKED,
+        RejectReason::AccountClosed => PE_REJECTED_ACCOUNT_CLOSED,
+        RejectReason::InsufficientFunds { .. } => PE_REJECTED_INSUFFICIENT_FUNDS,
+        RejectReason::DisputeUnfunded { .. } => PE_REJECTED_DISPUTE_UNFUNDED,
+        RejectReason::MissingCurrency => PE_REJECTED_MISSING_CURRENCY,
+        RejectReason::DisputeExpired { .. } => PE_REJECTED_DISPUTE_EXPIRED,
+        RejectReason::AmountBelowMinimum => PE_REJECTED_AMOUNT_BELOW_MINIMUM,
+        RejectReason::DuplicateTransaction => PE_REJECTED_DUPLICATE_TX,
+        RejectReason::MissingAmount => PE_REJECTED_MISSING_AMOUNT,
+        RejectReason::Overflow => PE_REJECTED_OVERFLOW,
+    }
+}
+
+fn error_status(error: &EngineError) -> c_int {
+    match error {
+        EngineError::Io(_) | EngineError::Csv(_) => PE_ERR_IO,
+        _ => PE_ERR_ENGINE,
+    }
+}
+
+/// Run `f`, turning a panic into `on_panic` so it never unwinds across the C boundary
+fn guard<T>(on_panic: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(on_panic)
+}
+
+/// Create an engine from a JSON object of options (see `FfiConfig`), or with the defaults
+/// when `config_json` is NULL
+/// Returns NULL if the JSON is not valid UTF-8, names an unknown option or value, or fails
+/// `EngineConfig::validate`.
+///
+/// # Safety
+/// `config_json` must be NULL or point to a NUL-terminated string.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pe_engine_new(config_json: *const c_char) -> *mut Engine {
+    guard(ptr::null_mut(), || {
+        let config = if config_json.is_null() {
+            Ok(EngineConfig::default())
+        } else {
+            // SAFETY: the caller passes a NUL-terminated string
+            let json = unsafe { CStr::from_ptr(config_json) };
+            json.to_str()
+                .map_err(|e| EngineError::InvalidConfig(e.to_string()))
+                .and_then(|json| {
+                    serde_json::from_str::<FfiConfig>(json)
+                        .map_err(|e| EngineError::InvalidConfig(e.to_string()))
+                })
+                .and_then(FfiConfig::into_config)
+        };
+        match config {
+            Ok(config) => Box::into_raw(Box::new(Engine {
+                config,
+                state: WorkerState::default(),
+            })),
+            Err(_) => ptr::null_mut(),
+        }
+    })
+}
+
+/// Apply one transaction and return its status
+/// `tx_type` is one of the `PE_DEPOSIT`..`PE_CHARGEBACK` codes; `amount` is ignored for
+/// disputes, resolves and chargebacks.
+///
+/// # Safety
+/// `engine` must be NULL or a live pointer from `pe_engine_new`, not used concurrently.
+// `ClientId` is `u16` unless `wide-client-ids` is enabled
+#[allow(clippy::unnecessary_fallible_conversions)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pe_engine_apply(
+    engine: *mut Engine,
+    tx_type: u8,
+    client: u32,
+    tx: u32,
+    amount: f64,
+) -> c_int {
+    guard(PE_ERR_PANIC, || {
+        // SAFETY: the caller passes NULL or a live engine with no other references
+        let Some(engine) = (unsafe { engine.as_mut() }) else {
+            return PE_ERR_NULL;
+        };
+        let (Some(tx_type), Ok(client)) = (self::tx_type(tx_type), ClientId::try_from(client))
+        else {
+            return PE_ERR_INVALID_ARGUMENT;
+        };
+        let needs_amount = matches!(
+            tx_type,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        );
+        if needs_amount && !amount.is_finite() {
+            return PE_ERR_INVALID_ARGUMENT;
+        }
+
+        let transaction = Transaction {
+            tx_type,
+            client,
+            tx,
+            amount: needs_amount.then_some(amount),
+            currency: None,
+            timestamp: None,
+        };
+        match apply_to_client(&mut engine.state, transaction, &engine.config) {
+            ApplyOutcome::Applied => PE_OK,
+            ApplyOutcome::Ignored(IgnoreReason::AlreadyApplied) => PE_ALREADY_APPLIED,
+            ApplyOutcome::Ignored(_) => PE_IGNORED,
+            ApplyOutcome::Rejected(reason) => reject_status(&reason),
+        }
+    })
+}
+
+/// Copy the balances of `client` into `out`
+/// Returns `PE_ERR_NOT_FOUND` when no transaction has created the account yet.
+///
+/// # Safety
+/// `engine` must be NULL or a live pointer from `pe_engine_new`; `out` must be NULL or
+/// point to writable memory for one `PeAccount`.
+// `ClientId` is `u16` unless `wide-client-ids` is enabled
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pe_engine_account(
+    engine: *const Engine,
+    client: u32,
+    out: *mut PeAccount,
+) -> c_int {
+    guard(PE_ERR_PANIC, || {
+        // SAFETY: the caller passes NULL or a live engine
+        let Some(engine) = (unsafe { engine.as_ref() }) else {
+            return PE_ERR_NULL;
+        };
+        if out.is_null() {
+            return PE_ERR_NULL;
+        }
+        let Some(client) = ClientId::try_from(client).ok() else {
+            return PE_ERR_INVALID_ARGUMENT;
+        };
+        let ledgers = engine.state.client_states.read();
+        let Some(ledger) = ledgers.lock(client) else {
+            return PE_ERR_NOT_FOUND;
+        };
+
+        let account = ledger.account();
+
+        // SAFETY: `out` is non-null and the caller guarantees it is writable
+        unsafe {
+            out.write(PeAccount {
+                client: account.client.into(),
+                available: account.available,
+                held: account.held,
+                total: account.total,
+                locked: account.locked,
+            })
+        };
+        PE_OK
+    })
+}
+
+/// Render every account as CSV, sorted by client, in the same format as a file run
+/// Like `snprintf`, at most `len - 1` bytes are written followed by a NUL, and the return
+/// value is the length of the whole CSV without the NUL; a result of `len` or more means the
+/// output was truncated. Pass NULL and 0 to query the size. Negative results are `PE_ERR_*`
+/// codes. The engine stays usable and must still be freed.
+///
+/// # Safety
+/// `engine` must be NULL or a live pointer from `pe_engine_new`; `out_buf` must be NULL or
+/// point to `len` writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pe_engine_finish_csv(
+    engine: *const Engine,
+    out_buf: *mut c_char,
+    len: usize,
+) -> isize {
+    guard(PE_ERR_PANIC as isize, || {
+        // SAFETY: the caller passes NULL or a live engine
+        let Some(engine) = (unsafe { engine.as_ref() }) else {
+            return PE_ERR_NULL as isize;
+        };
+
+        let shard = engine.state.client_states.sorted();
+        let mut csv = Vec::new();
+        if let Err(e) = write_output(vec![shard], &engine.config, None, &mut csv) {
+            return error_status(&e) as isize;
+        }
+
+        if !out_buf.is_null() && len > 0 {
+            let copied = csv.len().min(len - 1);
+            // SAFETY: `out_buf` holds `len` bytes and at most `len` are written
+            unsafe {
+                ptr::copy_nonoverlapping(csv.as_ptr(), out_buf.cast::<u8>(), copied);
+                out_buf.add(copied).write(0);
+            }
+        }
+        csv.len() as isize
+    })
+}
+
+/// Free an engine returned by `pe_engine_new`; NULL is ignored
+///
+/// # Safety
+/// `engine` must be NULL or a pointer from `pe_engine_new` that has not been freed.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn pe_engine_free(engine: *mut Engine) {
+    guard((), || {
+        if !engine.is_null() {
+            // SAFETY: the pointer came from `Box::into_raw` in `pe_engine_new`
+            drop(unsafe { Box::from_raw(engine) });
+        }
+    })
+}
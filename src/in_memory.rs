@@ -0,0 +1,115 @@
+//! Single-threaded processing of CSV held in memory, with no worker threads and no file I/O.
+//!
+//! This is the engine core the `wasm` feature exposes to JavaScript as `processCsvBytes`,
+//! for builds where threads and `std::fs` are unavailable (`wasm32-unknown-unknown`). The
+//! same rules apply as in the worker pool, and the output is byte for byte what a command line
+//! run writes to stdout.
+
+use crate::ledger::ApplyOutcome;
+use crate::processor::{
+    RowDecoder, WorkerState, apply_to_client, check_empty_output, check_row_limit, release_held,
+    rows_to_read, submit_to_client, write_output,
+};
+use crate::{ClientAccount, EngineConfig, EngineError, EngineStats, InputFormat, Transaction};
+use csv::ReaderBuilder;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Process CSV transactions and return the accounts as output CSV, with the default options
+/// Errors are returned as their message, which is what JavaScript callers receive.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = processCsvBytes))]
+pub fn process_csv_bytes(input: &[u8]) -> Result<Vec<u8>, String> {
+    process_csv_bytes_with_config(input, &EngineConfig::default()).map_err(|e| e.to_string())
+}
+
+/// Same as `process_csv_bytes` with explicit options
+/// Options that read or write files (`initial_balances`, `resume_from`, `manifest`,
+/// `snapshot`), input other than CSV and string client ids are refused; worker options are
+/// ignored.
+pub fn process_csv_bytes_with_config(
+    input: &[u8],
+    config: &EngineConfig,
+) -> Result<Vec<u8>, EngineError> {
+    config.validate()?;
+    if config.string_client_ids
+        || config.input_format != InputFormat::Csv
+        || config.initial_balances.is_some()
+        || config.resume_from.is_some()
+        || config.hashes_input()
+        || config.snapshot.is_some()
+        || config.overlay.is_some()
+    {
+        return Err(EngineError::InvalidConfig(
+            "in-memory processing takes CSV with numeric client ids, reads or writes no other \
+             files and has no provenance"
+                .to_string(),
+        ));
+    }
+
+    let mut reader = ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(config.delimiter)
+        .from_reader(input);
+    let decoder = RowDecoder::new(reader.headers()?.clone(), config, &["client", "tx"]);
+
+    let mut state = WorkerState::default();
+    let mut rows = 0u64;
+    for record in reader.into_records().take(rows_to_read(config)) {
+        let transaction: Transaction = decoder.decode(record?)?;
+        rows += 1;
+        check_row_limit(rows, config)?;
+        if config.processes_client(transaction.client) {
+            submit_to_client(&mut state, transaction, config);
+        }
+    }
+    release_held(&mut state, config);
+    check_empty_output(!state.client_states.is_empty(), rows, config)?;
+
+    let mut output = Vec::new();
+    write_output(
+        vec![state.client_states.take_sorted()],
+        config,
+        None,
+        &mut output,
+    )?;
+    Ok(output)
+}
+
+/// Applies transactions one at a time on the calling thread, under the same rules as a file
+/// run, for callers that already hold their transactions in memory
+/// Options that read or write files, the client filters and `reorder_window` are not used.
+pub struct PaymentEngine {
+    config: EngineConfig,
+    state: WorkerState,
+}
+
+impl PaymentEngine {
+    pub fn new(config: &EngineConfig) -> Result<Self, EngineError> {
+        config.validate()?;
+        Ok(Self {
+            config: config.clone(),
+            state: WorkerState::default(),
+        })
+    }
+
+    /// Apply a transaction to its client's account, creating the account if the
+    /// account creation policy allows it
+    pub fn apply(&mut self, transaction: Transaction) -> ApplyOutcome {
+        apply_to_client(&mut self.state, transaction, &self.config)
+    }
+
+    /// Counters so far; rows and per-worker load are not tracked
+    pub fn stats(&self) -> &EngineStats {
+        &self.state.stats
+    }
+
+    /// Accounts so far, sorted by client
+    pub fn accounts(&self) -> Vec<ClientAccount> {
+        self.state
+            .client_states
+            .sorted()
+            .into_iter()
+            .map(|ledger| ledger.into_account())
+            .collect()
+    }
+}
@@ -0,0 +1,205 @@
+use crate::processor::TransactionIter;
+use crate::{ClientAccount, EngineConfig, EngineError, InputFormat};
+use csv::ReaderBuilder;
+use std::cell::Cell;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::rc::Rc;
+
+pub(crate) use deadline::DeadlineExceeded;
+use deadline::DeadlineReader;
+pub(crate) use retry::RetriesExhausted;
+use retry::RetryingReader;
+
+mod deadline;
+mod directory;
+#[cfg(feature = "http-input")]
+mod http;
+#[cfg(feature = "parquet")]
+mod parquet;
+mod retry;
+
+/// Returns true if the input argument should be fetched over HTTP(S) rather than opened as a file
+fn is_url(path: &str) -> bool {
+    path.starts_with("http://") || path.starts_with("https://")
+}
+
+/// Open the input argument as a byte stream, either a local file or an HTTP(S) URL
+fn open_input(path: &str, config: &EngineConfig) -> Result<Box<dyn Read + Send>, EngineError> {
+    if is_url(path) {
+        return open_url(path, config);
+    }
+    Ok(Box::new(open_file(path)?))
+}
+
+#[cfg(test)]
+thread_local! {
+    /// Source the next `open_transaction_input` on this thread returns in place of opening its
+    /// path, for tests that run the engine over a reader that fails
+    pub(crate) static TEST_SOURCE: Cell<Option<Box<dyn Read + Send>>> = Cell::default();
+}
+
+/// Open the transaction input, first checking a local file's length against
+/// `config.max_bytes` so an oversized file is refused before anything is read
+/// A directory is read as the concatenation of the `.csv` files in it
+/// A read that fails transiently is tried again up to `config.read_retries` times. With
+/// `config.timeout`, reading fails with `EngineError::TimedOut` once the timeout has passed
+/// since the input was opened
+pub(crate) fn open_transaction_input(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<Box<dyn Read>, EngineError> {
+    let mut reader = open_transaction_source(path, config)?;
+    if config.read_retries > 0 {
+        reader = Box::new(RetryingReader::new(
+            reader,
+            config.read_retries,
+            config.verbose,
+        ));
+    }
+    Ok(match config.timeout {
+        Some(timeout) => Box::new(DeadlineReader::new(reader, timeout)),
+        None => reader,
+    })
+}
+
+fn open_transaction_source(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<Box<dyn Read + Send>, EngineError> {
+    #[cfg(test)]
+    if let Some(source) = TEST_SOURCE.take() {
+        return Ok(source);
+    }
+    if is_url(path) {
+        return open_url(path, config);
+    }
+    let (reader, size): (Box<dyn Read + Send>, u64) = if fs::metadata(path)
+        .is_ok_and(|m| m.is_dir())
+    {
+        if config.input_format == InputFormat::Binary {
+            return Err(EngineError::InvalidConfig(
+                "a directory input is read as CSV; binary input takes a single file".to_string(),
+            ));
+        }
+        directory::open(path)?
+    } else {
+        let file = open_file(path)?;
+        let size = file.metadata()?.len();
+        (Box::new(file), size)
+    };
+    check_size(size, config)?;
+    Ok(reader)
+}
+
+fn check_size(size: u64, config: &EngineConfig) -> Result<(), EngineError> {
+    match config.max_bytes {
+        Some(max_bytes) if size > max_bytes => Err(EngineError::InputTooLarge { size, max_bytes }),
+        _ => Ok(()),
+    }
+}
+
+/// Stream the transactions of a local Parquet file, first checking its length against
+/// `config.max_bytes`
+#[cfg(feature = "parquet")]
+pub(crate) fn parquet_transactions(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<TransactionIter, EngineError> {
+    if is_url(path) {
+        return Err(EngineError::InvalidConfig(format!(
+            "{} is a URL; Parquet input is read from a local file",
+            path
+        )));
+    }
+    let file = open_file(path)?;
+    check_size(file.metadata()?.len(), config)?;
+    Ok(Box::new(parquet::transactions(file)?))
+}
+
+#[cfg(not(feature = "parquet"))]
+pub(crate) fn parquet_transactions(
+    path: &str,
+    _config: &EngineConfig,
+) -> Result<TransactionIter, EngineError> {
+    Err(EngineError::InvalidConfig(format!(
+        "{} is Parquet input but the engine was built without the `parquet` feature",
+        path
+    )))
+}
+
+/// Open a local file, telling an empty path, a directory and a missing file apart
+/// Opening a directory succeeds on some platforms and only fails on the first read
+fn open_file(path: &str) -> Result<File, EngineError> {
+    if path.is_empty() {
+        return Err(EngineError::EmptyPath);
+    }
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Err(EngineError::IsDirectory(path.to_string())),
+        Ok(_) => Ok(File::open(path)?),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            Err(EngineError::NotFound(path.to_string()))
+        }
+        Err(e) => Err(EngineError::Io(e)),
+    }
+}
+
+/// Bytes read so far through a `CountingReader`
+pub(crate) type InputBytes = Rc<Cell<u64>>;
+
+/// Counts the bytes read through it, for the throughput summary
+/// The count moves once per `read`, which the buffered readers on top make in large chunks.
+pub(crate) struct CountingReader {
+    inner: Box<dyn Read>,
+    bytes: InputBytes,
+}
+
+impl CountingReader {
+    pub(crate) fn wrap(inner: Box<dyn Read>) -> (Self, InputBytes) {
+        let bytes = InputBytes::default();
+        let reader = Self {
+            inner,
+            bytes: Rc::clone(&bytes),
+        };
+        (reader, bytes)
+    }
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes.set(self.bytes.get() + read as u64);
+        Ok(read)
+    }
+}
+
+/// Load starting accounts from a CSV in the output format
+pub(crate) fn read_initial_balances(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<Vec<ClientAccount>, EngineError> {
+    let reader = open_input(path, config)?;
+
+    ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+        .into_deserialize()
+        .map(|result| result.map_err(EngineError::from))
+        .collect()
+}
+
+#[cfg(feature = "http-input")]
+fn open_url(url: &str, config: &EngineConfig) -> Result<Box<dyn Read + Send>, EngineError> {
+    http::open(url, config.http_retries, config.verbose)
+}
+
+#[cfg(not(feature = "http-input"))]
+fn open_url(url: &str, _config: &EngineConfig) -> Result<Box<dyn Read + Send>, EngineError> {
+    Err(EngineError::Io(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        format!(
+            "{} is a URL but the engine was built without the `http-input` feature",
+            url
+        ),
+    )))
+}
@@ -0,0 +1,101 @@
+//! Input that must be read to the end by a deadline (`EngineConfig::timeout`).
+
+use crossbeam_channel::{Receiver, RecvTimeoutError, bounded};
+use std::fmt;
+use std::io::{self, Read};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Bytes the reading thread fetches per chunk
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks read ahead of the engine
+const CHUNKS_AHEAD: usize = 4;
+
+/// Payload of the `io::ErrorKind::TimedOut` error a `DeadlineReader` returns once its
+/// deadline has passed, so the error can be told apart from a timeout of the source itself
+#[derive(Debug)]
+pub(crate) struct DeadlineExceeded(pub(crate) Duration);
+
+impl fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "input not read to the end within {:?}", self.0)
+    }
+}
+
+impl std::error::Error for DeadlineExceeded {}
+
+/// Reads the source on its own thread, so a read that never returns, e.g. from a pipe nothing
+/// writes to or closes, still gives up at the deadline
+///
+/// A thread blocked in such a read can't be stopped; it is left to end with the process, or
+/// whenever the read returns and finds this reader dropped.
+pub(crate) struct DeadlineReader {
+    chunks: Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    position: usize,
+    deadline: Instant,
+    timeout: Duration,
+    done: bool,
+}
+
+impl DeadlineReader {
+    pub(crate) fn new(mut source: Box<dyn Read + Send>, timeout: Duration) -> Self {
+        let (sender, chunks) = bounded(CHUNKS_AHEAD);
+        thread::spawn(move || {
+            loop {
+                let mut chunk = vec![0; CHUNK_SIZE];
+                let result = match source.read(&mut chunk) {
+                    Ok(read) => {
+                        chunk.truncate(read);
+                        Ok(chunk)
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => Err(e),
+                };
+                // An empty chunk is the end of the input
+                let last = !result.as_ref().is_ok_and(|chunk| !chunk.is_empty());
+                if sender.send(result).is_err() || last {
+                    break;
+                }
+            }
+        });
+        Self {
+            chunks,
+            chunk: Vec::new(),
+            position: 0,
+            deadline: Instant::now() + timeout,
+            timeout,
+            done: false,
+        }
+    }
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.position == self.chunk.len() {
+            if self.done {
+                return Ok(0);
+            }
+            match self.chunks.recv_deadline(self.deadline) {
+                Ok(Ok(chunk)) => {
+                    self.done = chunk.is_empty();
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Ok(Err(e)) => return Err(e),
+                Err(RecvTimeoutError::Timeout) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        DeadlineExceeded(self.timeout),
+                    ));
+                }
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            }
+        }
+        let read = buf.len().min(self.chunk.len() - self.position);
+        buf[..read].copy_from_slice(&self.chunk[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
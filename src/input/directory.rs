@@ -0,0 +1,118 @@
+use crate::EngineError;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+
+/// Open every `.csv` file directly inside `dir` as one stream, in file name order
+///
+/// The first file's header starts the stream; every later file must have the same header,
+/// which is dropped so the rows follow on as if from one file. Other files and
+/// subdirectories are ignored. Returns the reader with the combined size of the files.
+pub(crate) fn open(dir: &str) -> Result<(Box<dyn Read + Send>, u64), EngineError> {
+    let mut files = Vec::new();
+    let mut size = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let metadata = fs::metadata(&path)?;
+        if metadata.is_file() && path.extension() == Some(OsStr::new("csv")) {
+            size += metadata.len();
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let reader = DirectoryReader {
+        files: files.into_iter(),
+        current: None,
+        pending: Vec::new(),
+        pending_pos: 0,
+        header: None,
+        ends_with_newline: true,
+    };
+    Ok((Box::new(reader), size))
+}
+
+struct DirectoryReader {
+    files: std::vec::IntoIter<PathBuf>,
+    current: Option<BufReader<File>>,
+    /// Bytes to hand out before reading on: the first header, or a newline a file lacked
+    pending: Vec<u8>,
+    pending_pos: usize,
+    /// The first file and its header line, which every other file must repeat
+    header: Option<(PathBuf, String)>,
+    ends_with_newline: bool,
+}
+
+impl DirectoryReader {
+    /// Open the next file and read past its header, keeping it only for the first file
+    fn open_next(&mut self, path: PathBuf) -> io::Result<()> {
+        let mut file = BufReader::new(File::open(&path)?);
+        let mut line = Vec::new();
+        file.read_until(b'\n', &mut line)?;
+        // An empty file adds no header and no rows
+        if line.is_empty() {
+            return Ok(());
+        }
+
+        let header = String::from_utf8_lossy(&line).trim_end().to_string();
+        match &self.header {
+            None => {
+                self.header = Some((path, header));
+                self.pending = line;
+                self.pending_pos = 0;
+            }
+            Some((first, expected)) if *expected != header => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} has header `{}` but {} has `{}`",
+                        path.display(),
+                        header,
+                        first.display(),
+                        expected
+                    ),
+                ));
+            }
+            Some(_) => {}
+        }
+        self.current = Some(file);
+        Ok(())
+    }
+}
+
+impl Read for DirectoryReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.pending_pos < self.pending.len() {
+                let pending = &self.pending[self.pending_pos..];
+                let n = pending.len().min(buf.len());
+                buf[..n].copy_from_slice(&pending[..n]);
+                self.pending_pos += n;
+                self.ends_with_newline = buf[n - 1] == b'\n';
+                return Ok(n);
+            }
+            if let Some(file) = &mut self.current {
+                let n = file.read(buf)?;
+                if n > 0 {
+                    self.ends_with_newline = buf[n - 1] == b'\n';
+                    return Ok(n);
+                }
+                self.current = None;
+                // Keep the next file's first row off this file's last line
+                if !self.ends_with_newline {
+                    self.pending = b"\n".to_vec();
+                    self.pending_pos = 0;
+                    continue;
+                }
+            }
+            match self.files.next() {
+                Some(path) => self.open_next(path)?,
+                None => return Ok(0),
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use crate::EngineError;
+use std::io::Read;
+use std::thread;
+use std::time::Duration;
+
+/// Open `url` and return a reader streaming its response body
+///
+/// Redirects are not followed: any status other than 200 surfaces as `EngineError::Http`.
+/// Connection failures, 429 and 5xx responses are retried up to `retries` times with a linear backoff,
+/// each retry noted on stderr with `verbose`.
+/// A gzip `Content-Encoding` is decoded transparently by ureq.
+pub(crate) fn open(
+    url: &str,
+    retries: u32,
+    verbose: bool,
+) -> Result<Box<dyn Read + Send>, EngineError> {
+    let agent: ureq::Agent = ureq::Agent::config_builder()
+        .max_redirects(0)
+        .http_status_as_error(false)
+        .build()
+        .into();
+
+    let mut attempt = 0;
+    loop {
+        let result = agent.get(url).call();
+
+        let transient = match &result {
+            Ok(response) => is_transient_status(response.status().as_u16()),
+            Err(e) => is_transient_error(e),
+        };
+        if transient && attempt < retries {
+            attempt += 1;
+            if verbose {
+                eprintln!("Retrying {} (attempt {} of {})", url, attempt, retries);
+            }
+            thread::sleep(Duration::from_millis(100 * attempt as u64));
+            continue;
+        }
+
+        let response = result.map_err(|e| EngineError::Io(e.into_io()))?;
+        let status = response.status().as_u16();
+        if status != 200 {
+            return Err(EngineError::Http {
+                url: url.to_string(),
+                status,
+            });
+        }
+
+        return Ok(Box::new(response.into_body().into_reader()));
+    }
+}
+
+fn is_transient_status(status: u16) -> bool {
+    status == 429 || (500..600).contains(&status)
+}
+
+fn is_transient_error(error: &ureq::Error) -> bool {
+    matches!(
+        error,
+        ureq::Error::Io(_) | ureq::Error::Timeout(_) | ureq::Error::ConnectionFailed
+    )
+}
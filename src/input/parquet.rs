@@ -0,0 +1,34 @@
+//! Parquet transaction input (`InputFormat::Parquet`, feature `parquet`).
+
+use crate::arrow::Columns;
+use crate::{EngineError, Transaction};
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use std::fmt;
+use std::fs::File;
+
+/// Stream the transactions of a Parquet file, one record batch at a time
+/// Row groups are decoded as the batches reach them, so memory use is bounded by the largest
+/// row group rather than the file. Columns are mapped as in `arrow::apply_record_batch`.
+pub(crate) fn transactions(
+    file: File,
+) -> Result<impl Iterator<Item = Result<Transaction, EngineError>>, EngineError> {
+    let batches = ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.build())
+        .map_err(parquet_error)?;
+    Ok(batches.flat_map(|batch| {
+        let transactions: Vec<_> = match batch.map_err(parquet_error) {
+            Ok(batch) => match Columns::new(&batch) {
+                Ok(columns) => (0..batch.num_rows())
+                    .map(|row| columns.transaction(row))
+                    .collect(),
+                Err(e) => vec![Err(e)],
+            },
+            Err(e) => vec![Err(e)],
+        };
+        transactions
+    }))
+}
+
+fn parquet_error(e: impl fmt::Display) -> EngineError {
+    EngineError::Parquet(e.to_string())
+}
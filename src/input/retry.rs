@@ -0,0 +1,201 @@
+//! Retries of transient read errors from the input (`EngineConfig::read_retries`).
+
+use std::fmt;
+use std::io::{self, Read};
+use std::thread;
+use std::time::Duration;
+
+/// Wait before the first retry of a read; each later retry of the same read waits twice as
+/// long as the one before, up to `MAX_RETRY_DELAY`
+const FIRST_RETRY_DELAY: Duration = Duration::from_millis(50);
+
+/// Longest wait between two attempts of a read, however many retries are allowed
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Payload of the error a `RetryingReader` returns once a read has failed transiently on every
+/// attempt, so the engine can report it as `EngineError::ReadRetriesExhausted`
+#[derive(Debug)]
+pub(crate) struct RetriesExhausted {
+    pub(crate) attempts: u32,
+    pub(crate) last: io::Error,
+}
+
+impl fmt::Display for RetriesExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "read failed {} times: {}", self.attempts, self.last)
+    }
+}
+
+impl std::error::Error for RetriesExhausted {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.last)
+    }
+}
+
+/// Whether a read failing with `e` may succeed if tried again, as when a network filesystem
+/// or connection drops out for a moment
+/// The end of the input, missing permissions and bad data are not.
+fn is_transient(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::ResourceBusy
+    )
+}
+
+/// Tries a read that failed transiently up to `retries` more times, backing off between
+/// attempts, before giving up with `RetriesExhausted`
+/// The count starts over with every read, so only failures in a row add up. Any other error
+/// is returned as it is, and an interrupted read is repeated without counting. With `verbose`
+/// each retry is noted on stderr.
+pub(crate) struct RetryingReader<R> {
+    inner: R,
+    retries: u32,
+    verbose: bool,
+}
+
+impl<R: Read> RetryingReader<R> {
+    pub(crate) fn new(inner: R, retries: u32, verbose: bool) -> Self {
+        Self {
+            inner,
+            retries,
+            verbose,
+        }
+    }
+}
+
+/// Wait before retry number `retry` of a read, counting from 1
+fn retry_delay(retry: u32) -> Duration {
+    FIRST_RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(retry - 1))
+        .min(MAX_RETRY_DELAY)
+}
+
+impl<R: Read> Read for RetryingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut failures = 0;
+        loop {
+            match self.inner.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if is_transient(&e) && failures < self.retries => {
+                    failures += 1;
+                    if self.verbose {
+                        eprintln!(
+                            "Reading input failed ({}), retry {} of {}",
+                            e, failures, self.retries
+                        );
+                    }
+                    thread::sleep(retry_delay(failures));
+                }
+                Err(e) if is_transient(&e) => {
+                    return Err(io::Error::new(
+                        e.kind(),
+                        RetriesExhausted {
+                            attempts: failures + 1,
+                            last: e,
+                        },
+                    ));
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EngineError;
+
+    /// Fails each read with the next of `errors` until they run out, then reads `data`
+    struct FlakyReader {
+        errors: Vec<io::ErrorKind>,
+        data: &'static [u8],
+    }
+
+    impl Read for FlakyReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.errors.is_empty() {
+                return Err(io::Error::from(self.errors.remove(0)));
+            }
+            self.data.read(buf)
+        }
+    }
+
+    fn flaky(errors: &[io::ErrorKind]) -> FlakyReader {
+        FlakyReader {
+            errors: errors.to_vec(),
+            data: b"type,client,tx,amount\n",
+        }
+    }
+
+    #[test]
+    fn test_transient_failure_is_retried() {
+        let mut reader = RetryingReader::new(flaky(&[io::ErrorKind::ConnectionReset]), 2, false);
+        let mut text = String::new();
+        reader.read_to_string(&mut text).unwrap();
+        assert_eq!(text, "type,client,tx,amount\n");
+    }
+
+    #[test]
+    fn test_retries_exhausted() {
+        let mut reader = RetryingReader::new(flaky(&[io::ErrorKind::TimedOut; 3]), 2, false);
+        let e = reader.read(&mut [0; 8]).unwrap_err();
+        let exhausted = e.get_ref().unwrap().downcast_ref::<RetriesExhausted>();
+        assert_eq!(exhausted.map(|exhausted| exhausted.attempts), Some(3));
+        assert_eq!(
+            EngineError::from(csv::Error::from(e)).to_string(),
+            "Reading the input failed 3 times in a row, giving up: timed out"
+        );
+    }
+
+    #[test]
+    fn test_retry_delay_capped() {
+        assert_eq!(retry_delay(1), FIRST_RETRY_DELAY);
+        assert_eq!(retry_delay(3), FIRST_RETRY_DELAY * 4);
+        assert_eq!(retry_delay(20), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_file_run_retries_its_input() {
+        let config = crate::EngineConfig {
+            read_retries: 2,
+            ..Default::default()
+        };
+        let run = |errors: &[io::ErrorKind]| {
+            let source = FlakyReader {
+                errors: errors.to_vec(),
+                data: b"type,client,tx,amount\ndeposit,1,1,2.5\n",
+            };
+            crate::input::TEST_SOURCE.set(Some(Box::new(source)));
+            crate::process_file("flaky.csv", &config)
+        };
+
+        let accounts = run(&[io::ErrorKind::ConnectionReset, io::ErrorKind::TimedOut]).unwrap();
+        assert_eq!(accounts[&1].available(), 2.5);
+        assert!(matches!(
+            run(&[io::ErrorKind::TimedOut; 3]),
+            Err(EngineError::ReadRetriesExhausted { attempts: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_lasting_failures_are_not_retried() {
+        for kind in [
+            io::ErrorKind::PermissionDenied,
+            io::ErrorKind::UnexpectedEof,
+        ] {
+            let mut reader = RetryingReader::new(flaky(&[kind, kind]), 2, false);
+            let e = reader.read(&mut [0; 8]).unwrap_err();
+            assert_eq!(e.kind(), kind);
+            assert!(e.get_ref().is_none());
+            // The reader was asked once, so its second error is still waiting
+            assert_eq!(reader.read(&mut [0; 8]).unwrap_err().kind(), kind);
+        }
+    }
+}
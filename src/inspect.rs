@@ -0,0 +1,190 @@
+//! Input pre-flight (`--inspect`): the schema of a CSV input from its header and first rows.
+
+use crate::input::open_transaction_input;
+use crate::transaction::is_column;
+use crate::{EngineConfig, EngineError, InputFormat};
+use csv::{ReaderBuilder, StringRecord};
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+
+/// Data rows read after the header
+pub const INSPECT_SAMPLE_ROWS: usize = 10;
+
+/// Delimiters the header is tried against
+const CANDIDATE_DELIMITERS: [u8; 4] = [b',', b';', b'\t', b'|'];
+
+/// Columns the engine reads; a row needs the first three
+const KNOWN_COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "currency", "timestamp"];
+const REQUIRED_COLUMNS: usize = 3;
+
+/// What `inspect` found in the header and first rows of an input
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputSchema {
+    /// The delimiter the header splits on into the most columns, among `,` `;` tab and `|`;
+    /// `config.delimiter` when none of them appears
+    pub delimiter: u8,
+    /// Header columns in order
+    pub columns: Vec<String>,
+    /// Columns the engine reads, as `(input name, column)` in header order; the names differ
+    /// where an alias such as `client_id` matched
+    pub recognized: Vec<(String, &'static str)>,
+    /// Header columns the engine ignores
+    pub unknown: Vec<String>,
+    /// `type`, `client` or `tx` when the header has neither it nor its alias
+    pub missing: Vec<&'static str>,
+    /// Data rows read, at most `INSPECT_SAMPLE_ROWS`
+    pub sample_rows: usize,
+    /// Non-empty amounts among them
+    pub amounts: usize,
+    /// Sampled amounts that are not numbers with at most 4 decimal places, as written
+    pub irregular_amounts: Vec<String>,
+}
+
+impl InputSchema {
+    /// Whether the input looks processable: every required column is there and every
+    /// sampled amount is a 4-decimal number
+    pub fn is_clean(&self) -> bool {
+        self.missing.is_empty() && self.irregular_amounts.is_empty()
+    }
+}
+
+impl fmt::Display for InputSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let delimiter = match self.delimiter {
+            b'\t' => "tab".to_string(),
+            other => format!("'{}'", other as char),
+        };
+        writeln!(f, "delimiter: {}", delimiter)?;
+        writeln!(f, "columns: {}", self.columns.join(", "))?;
+        for (name, column) in &self.recognized {
+            if name == column {
+                writeln!(f, "  {}", column)?;
+            } else {
+                writeln!(f, "  {} (alias {})", column, name)?;
+            }
+        }
+        if !self.unknown.is_empty() {
+            writeln!(f, "ignored columns: {}", self.unknown.join(", "))?;
+        }
+        if !self.missing.is_empty() {
+            writeln!(f, "missing columns: {}", self.missing.join(", "))?;
+        }
+        writeln!(f, "sample rows: {}", self.sample_rows)?;
+        write!(
+            f,
+            "amounts: {} sampled, {} not at most 4 decimal places",
+            self.amounts,
+            self.irregular_amounts.len()
+        )?;
+        if !self.irregular_amounts.is_empty() {
+            write!(f, " ({})", self.irregular_amounts.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Read the header and up to `INSPECT_SAMPLE_ROWS` rows of the CSV input at `path` and report
+/// its schema; nothing is processed. `config.number_format` applies to the sampled amounts.
+pub fn inspect(path: &str, config: &EngineConfig) -> Result<InputSchema, EngineError> {
+    if config.input_format != InputFormat::Csv {
+        return Err(EngineError::InvalidConfig(
+            "inspect reads CSV input only".to_string(),
+        ));
+    }
+    let mut reader = BufReader::new(open_transaction_input(path, config)?);
+    let mut header = String::new();
+    reader.read_line(&mut header)?;
+    let header = header.trim_end_matches(['\r', '\n']);
+
+    let delimiter = CANDIDATE_DELIMITERS
+        .into_iter()
+        .map(|delimiter| {
+            (
+                header.bytes().filter(|&b| b == delimiter).count(),
+                delimiter,
+            )
+        })
+        .filter(|&(count, _)| count > 0)
+        .max_by_key(|&(count, delimiter)| {
+            // Prefer the configured delimiter on a tie
+            (count, delimiter == config.delimiter)
+        })
+        .map_or(config.delimiter, |(_, delimiter)| delimiter);
+
+    let columns: Vec<String> = csv_reader(header.as_bytes(), delimiter)
+        .records()
+        .next()
+        .transpose()?
+        .map(|record| record.iter().map(str::to_string).collect())
+        .unwrap_or_default();
+    let column_of = |name: &str| {
+        KNOWN_COLUMNS
+            .into_iter()
+            .find(|&column| is_column(name, column))
+    };
+    let recognized: Vec<(String, &'static str)> = columns
+        .iter()
+        .filter_map(|name| Some((name.clone(), column_of(name)?)))
+        .collect();
+    let unknown = columns
+        .iter()
+        .filter(|name| column_of(name).is_none())
+        .cloned()
+        .collect();
+    let missing = KNOWN_COLUMNS[..REQUIRED_COLUMNS]
+        .iter()
+        .copied()
+        .filter(|column| !recognized.iter().any(|(_, found)| found == column))
+        .collect();
+
+    let amount_index = columns
+        .iter()
+        .position(|name| column_of(name) == Some("amount"));
+    let mut sample_rows = 0;
+    let mut amounts = 0;
+    let mut irregular_amounts = Vec::new();
+    let mut record = StringRecord::new();
+    let mut rows = csv_reader(reader, delimiter);
+    while sample_rows < INSPECT_SAMPLE_ROWS && rows.read_record(&mut record)? {
+        sample_rows += 1;
+        let Some(amount) = amount_index.and_then(|index| record.get(index)) else {
+            continue;
+        };
+        if amount.is_empty() {
+            continue;
+        }
+        amounts += 1;
+        if !is_four_decimal(&config.number_format.normalize(amount)) {
+            irregular_amounts.push(amount.to_string());
+        }
+    }
+
+    Ok(InputSchema {
+        delimiter,
+        columns,
+        recognized,
+        unknown,
+        missing,
+        sample_rows,
+        amounts,
+        irregular_amounts,
+    })
+}
+
+/// Header-less reader of the rows in `reader`, which may differ in length
+fn csv_reader<R: Read>(reader: R, delimiter: u8) -> csv::Reader<R> {
+    ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader)
+}
+
+/// Whether `amount` is a plain number with at most 4 decimal places
+fn is_four_decimal(amount: &str) -> bool {
+    let decimals = amount.split_once('.').map_or("", |(_, decimals)| decimals);
+    amount.parse::<f64>().is_ok_and(f64::is_finite)
+        && decimals.len() <= 4
+        && decimals.bytes().all(|b| b.is_ascii_digit())
+}
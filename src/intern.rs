@@ -0,0 +1,113 @@
+use crate::{ClientId, Currency, EngineError, Transaction, TransactionType};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::mem::size_of;
+
+/// Input row whose client column is an arbitrary string, such as a UUID
+#[derive(Debug, Deserialize)]
+pub(crate) struct StringClientRecord {
+    #[serde(rename = "type", alias = "transaction_type")]
+    tx_type: TransactionType,
+    #[serde(alias = "client_id")]
+    client: String,
+    #[serde(alias = "transaction_id")]
+    tx: u32,
+    #[serde(default, alias = "value")]
+    amount: Option<f64>,
+    #[serde(default)]
+    currency: Option<Currency>,
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+
+impl StringClientRecord {
+    /// Converts the row to a `Transaction`, interning its client identifier
+    pub(crate) fn into_transaction(
+        self,
+        interner: &mut ClientInterner,
+    ) -> Result<Transaction, EngineError> {
+        Ok(Transaction {
+            tx_type: self.tx_type,
+            client: interner.intern(&self.client)?,
+            tx: self.tx,
+            amount: self.amount,
+            currency: self.currency,
+            timestamp: self.timestamp,
+        })
+    }
+}
+
+/// Assigns dense `ClientId`s to string client identifiers in order of first appearance,
+/// keeping the reverse mapping so output can show the original identifiers
+#[derive(Debug, Default)]
+pub struct ClientInterner {
+    ids: HashMap<String, ClientId>,
+    names: Vec<String>,
+}
+
+impl ClientInterner {
+    /// Returns the id for `name`, assigning the next free id on first sight
+    pub fn intern(&mut self, name: &str) -> Result<ClientId, EngineError> {
+        if let Some(id) = self.ids.get(name) {
+            return Ok(*id);
+        }
+
+        let id = ClientId::try_from(self.names.len()).map_err(|_| {
+            EngineError::InvalidInput(format!(
+                "more than {} distinct client identifiers (build with `wide-client-ids` for more)",
+                self.names.len()
+            ))
+        })?;
+        self.ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        Ok(id)
+    }
+
+    /// Original identifier of an interned client
+    pub fn name(&self, id: ClientId) -> Option<&str> {
+        self.names.get(id as usize).map(String::as_str)
+    }
+
+    /// Number of distinct identifiers interned so far
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// Approximate heap bytes used by both directions of the mapping
+    pub fn memory_bytes(&self) -> usize {
+        let string_bytes: usize = self.names.iter().map(String::capacity).sum();
+        let map_entry = size_of::<String>() + size_of::<ClientId>() + size_of::<u64>();
+
+        // Each identifier is stored twice: once as a map key, once in `names`
+        2 * string_bytes
+            + self.ids.capacity() * map_entry
+            + self.names.capacity() * size_of::<String>()
+    }
+
+    /// Consumes the table, returning identifiers indexed by their interned id
+    pub fn into_names(self) -> Vec<String> {
+        self.names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intern_is_dense_and_stable() {
+        let mut interner = ClientInterner::default();
+
+        assert_eq!(interner.intern("b7c1").unwrap(), 0);
+        assert_eq!(interner.intern("a9f0").unwrap(), 1);
+        assert_eq!(interner.intern("b7c1").unwrap(), 0);
+
+        assert_eq!(interner.len(), 2);
+        assert_eq!(interner.name(1), Some("a9f0"));
+        assert!(interner.memory_bytes() > 0);
+    }
+}
@@ -0,0 +1,149 @@
+//! Journal of applied transactions (`--journal`).
+//!
+//! The journal is CSV with the `type,client,tx,amount` header of the input, one row per
+//! transaction that changed a ledger, in the order it was applied. Rejected and ignored
+//! transactions are left out, and so are timestamps and the reordering they caused: rows are
+//! already in application order. Each worker's transactions are written in one block, so a
+//! client's rows keep their order while different clients' rows are grouped by worker.
+//!
+//! Workers append their rows as they apply them, each to a part file of its own beside the
+//! journal (`PATH.0`, `PATH.1`, ..., `PATH.overlay.0`, ... for the overlay), so memory does not
+//! grow with the input. The parts are journals in their own right, header included; once the
+//! run is complete they are joined into `PATH` in worker order and removed. A run that fails
+//! or is killed part way leaves them behind, holding what was applied up to that point.
+//!
+//! A run with an overlay adds a `source` column, `input` or `overlay`, and lists the overlay's
+//! transactions after all of the input's. Replaying ignores the column.
+//!
+//! Amounts are written with the shortest decimal form that parses back to the same `f64`, so
+//! `replay_journal` under the same options, starting from the same `resume_from` or
+//! `initial_balances`, rebuilds identical accounts.
+
+use crate::processor::csv_transactions;
+use crate::{
+    ClientAccount, ClientId, EngineConfig, EngineError, Transaction, process_transactions,
+};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+/// Header row of every journal
+const JOURNAL_HEADER: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Extra column of a journal written with an overlay
+const SOURCE_COLUMN: &str = "source";
+
+/// One worker's part of the journal, appended to as the worker applies transactions
+pub(crate) struct JournalPart {
+    path: String,
+    writer: csv::Writer<File>,
+    /// Value of the `source` column, for a run with `EngineConfig::overlay`
+    source: Option<&'static str>,
+    /// The first write that failed; later rows are dropped and the run fails at the end
+    error: Option<csv::Error>,
+}
+
+impl JournalPart {
+    /// Start the part of worker `worker_id`, replacing any file already there
+    /// `overlay` is `None` for a run without `EngineConfig::overlay`, else whether this part
+    /// belongs to the overlay's pool
+    pub(crate) fn create(
+        journal: &str,
+        worker_id: usize,
+        overlay: Option<bool>,
+    ) -> Result<Self, EngineError> {
+        let path = match overlay {
+            Some(true) => format!("{}.overlay.{}", journal, worker_id),
+            _ => format!("{}.{}", journal, worker_id),
+        };
+        let mut writer = csv::Writer::from_writer(File::create(&path)?);
+        let source = overlay.map(|overlay| if overlay { "overlay" } else { "input" });
+        match source {
+            Some(_) => writer.write_record(JOURNAL_HEADER.iter().chain([&SOURCE_COLUMN]))?,
+            None => writer.write_record(JOURNAL_HEADER)?,
+        }
+        Ok(Self {
+            path,
+            writer,
+            source,
+            error: None,
+        })
+    }
+
+    pub(crate) fn append(&mut self, transaction: &Transaction) {
+        if self.error.is_some() {
+            return;
+        }
+        let row = journal_row(transaction);
+        let written = match self.source {
+            Some(source) => self
+                .writer
+                .write_record(row.iter().map(String::as_str).chain([source])),
+            None => self.writer.write_record(row),
+        };
+        self.error = written.err();
+    }
+
+    /// Flush the part, returning its path or the first write that failed
+    fn finish(mut self) -> Result<String, EngineError> {
+        if let Some(e) = self.error {
+            return Err(e.into());
+        }
+        self.writer.flush()?;
+        Ok(self.path)
+    }
+}
+
+/// Join `parts` into the journal at `path`, replacing any file already there, and remove them
+/// The header comes from the first part and every part's rows follow in the order given
+pub(crate) fn write_journal(path: &str, parts: Vec<JournalPart>) -> Result<(), EngineError> {
+    let parts = parts
+        .into_iter()
+        .map(JournalPart::finish)
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut out = BufWriter::new(File::create(path)?);
+    for (index, part) in parts.iter().enumerate() {
+        let mut rows = BufReader::new(File::open(part)?);
+        if index > 0 {
+            rows.read_line(&mut String::new())?;
+        }
+        std::io::copy(&mut rows, &mut out)?;
+    }
+    out.flush()?;
+    for part in parts {
+        std::fs::remove_file(part)?;
+    }
+    Ok(())
+}
+
+fn journal_row(transaction: &Transaction) -> [String; 4] {
+    let amount = transaction
+        .amount
+        .map(|amount| amount.to_string())
+        .unwrap_or_default();
+    [
+        transaction.tx_type.name().to_string(),
+        transaction.client.to_string(),
+        transaction.tx.to_string(),
+        amount,
+    ]
+}
+
+/// Apply the journal at `path` through `process_transactions` and return the accounts it
+/// leads to
+/// The journal is always read as plain comma-separated CSV, whatever `config.delimiter` and
+/// `config.number_format` say, and replaying never writes a journal of its own.
+pub fn replay_journal(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<HashMap<ClientId, ClientAccount>, EngineError> {
+    let config = EngineConfig {
+        journal: None,
+        delimiter: EngineConfig::default().delimiter,
+        number_format: Default::default(),
+        ..config.clone()
+    };
+    let transactions =
+        csv_transactions(Box::new(File::open(path)?), &config).collect::<Result<Vec<_>, _>>()?;
+    process_transactions(transactions, &config)
+}
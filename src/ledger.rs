@@ -0,0 +1,2040 @@
+//! Settlement rules for a single client, independent of the worker pool.
+//!
+//! The engine applies every transaction through `apply_transaction_with_config`; the same
+//! functions can be used directly to replay or simulate transactions against one account.
+
+use crate::account::{
+    LockReason, MINOR_UNITS_PER_UNIT, balance_out_of_range, round_to_four, to_minor_units,
+};
+use crate::storage::StoredAccount;
+use crate::verify::{Invariant, Violation};
+use crate::{
+    ClientAccount, ClientId, Currency, DisputeFundsPolicy, EngineConfig, LockedDepositPolicy,
+    RejectReason, Transaction, TransactionType, WithdrawalDisputeModel,
+};
+use std::fmt;
+
+/// Map used for per-worker client and transaction history tables
+/// With `fast-hash` (default) this is FxHash, which is several times cheaper than SipHash on
+/// integer keys but not resistant to crafted collisions: input that picks client and tx ids to
+/// collide can degrade lookups to linear time. Build without default features to use SipHash.
+#[cfg(feature = "fast-hash")]
+pub(crate) type FastHashMap<K, V> = rustc_hash::FxHashMap<K, V>;
+#[cfg(not(feature = "fast-hash"))]
+pub(crate) type FastHashMap<K, V> = std::collections::HashMap<K, V>;
+
+/// Set counterpart of `FastHashMap`, with the same hasher
+#[cfg(feature = "fast-hash")]
+pub(crate) type FastHashSet<T> = rustc_hash::FxHashSet<T>;
+#[cfg(not(feature = "fast-hash"))]
+pub(crate) type FastHashSet<T> = std::collections::HashSet<T>;
+
+/// Where a deposit or withdrawal stands in the dispute process
+///
+/// Legal transitions are `None -> Open` (dispute), `Open -> Resolved` (resolve),
+/// `Open -> ChargedBack` (chargeback) and, with `EngineConfig::allow_redispute`,
+/// `Resolved -> Open`. `ChargedBack` is final.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DisputeState {
+    /// Never disputed
+    #[default]
+    None,
+    /// Disputed; funds are held until it is resolved or charged back
+    Open,
+    /// A dispute was resolved in the client's favour
+    Resolved,
+    /// A dispute ended in a chargeback
+    ChargedBack,
+}
+
+impl DisputeState {
+    /// Whether no dispute action can change the transaction any more
+    pub fn is_final(self) -> bool {
+        self == DisputeState::ChargedBack
+    }
+}
+
+/// Transaction record stored for dispute handling
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct TransactionRecord {
+    pub(crate) amount: f64,
+    pub(crate) dispute: DisputeState,
+    pub(crate) dispute_held: f64, //amount held by the latest dispute, less than `amount` after a partial hold
+    pub(crate) kind: TransactionType, //the deposit or withdrawal that created the record
+    pub(crate) frozen: bool, //deposit landed in held because the account was locked (`LockedDepositPolicy::ToHeld`)
+    pub(crate) currency: Option<Currency>, //balances the transaction moved in multi-currency mode
+    pub(crate) seq: u64,     //client's sequence number of the transaction, for `max_dispute_age`
+}
+
+impl TransactionRecord {
+    pub(crate) fn is_deposit(&self) -> bool {
+        self.kind == TransactionType::Deposit
+    }
+
+    /// Funds this record keeps in held: the amount held by an open dispute, or a frozen
+    /// deposit that wasn't charged back
+    fn held(&self) -> f64 {
+        match (self.frozen, self.dispute) {
+            (true, DisputeState::ChargedBack) => 0.0,
+            (true, _) => self.amount,
+            (false, DisputeState::Open) => self.dispute_held,
+            (false, _) => 0.0,
+        }
+    }
+
+    /// Only deposits and withdrawals can be disputed; should a record of any other kind be
+    /// stored, dispute actions treat its tx id as unknown
+    fn is_disputable(&self) -> bool {
+        matches!(
+            self.kind,
+            TransactionType::Deposit | TransactionType::Withdrawal
+        )
+    }
+}
+
+/// The record a dispute, resolve or chargeback of `tx` acts on, if it is disputable
+fn disputable_record(
+    tx_history: &mut FastHashMap<u32, TransactionRecord>,
+    tx: u32,
+) -> Option<&mut TransactionRecord> {
+    tx_history
+        .get_mut(&tx)
+        .filter(|record| record.is_disputable())
+}
+
+/// Disputes currently open on an account, checked against the dispute thresholds
+#[derive(Debug, Clone, Copy, Default)]
+struct OpenDisputes {
+    count: usize,
+    amount: f64,
+}
+
+impl OpenDisputes {
+    fn close(&mut self, amount: f64) {
+        self.count -= 1;
+        // Reset rather than carry f64 residue once nothing is open
+        self.amount = if self.count == 0 {
+            0.0
+        } else {
+            self.amount - amount
+        };
+    }
+
+    /// Whether `EngineConfig::max_open_disputes` or `max_disputed_amount` is reached
+    fn over_threshold(&self, config: &EngineConfig) -> bool {
+        config
+            .max_open_disputes
+            .is_some_and(|max| self.count >= max)
+            || config
+                .max_disputed_amount
+                .is_some_and(|max| to_minor_units(self.amount) > to_minor_units(max))
+    }
+}
+
+/// A client's account together with the deposits and withdrawals disputes can refer to
+#[derive(Debug, Clone)]
+pub struct ClientLedger {
+    account: ClientAccount,
+    /// Per-currency balances in multi-currency mode, sorted by currency; `account` then only
+    /// carries the client-wide lock
+    currencies: Vec<ClientAccount>,
+    tx_history: FastHashMap<u32, TransactionRecord>,
+    open: OpenDisputes,
+    /// Number of transactions given to this ledger, whatever their outcome
+    seq: u64,
+    /// Held funds the ledger started with that no record accounts for, e.g. from
+    /// `initial_balances`; taken as given by `violations`
+    opening_held: f64,
+    /// Whether the account was already locked when the ledger was created
+    opening_locked: bool,
+}
+
+impl ClientLedger {
+    /// Empty ledger for a new client
+    pub fn new(client_id: ClientId) -> Self {
+        Self::from_account(ClientAccount::new(client_id))
+    }
+
+    /// Ledger starting from existing balances, with no transaction history
+    pub fn from_account(account: ClientAccount) -> Self {
+        Self {
+            opening_held: account.held,
+            opening_locked: account.locked,
+            account,
+            currencies: Vec::new(),
+            tx_history: FastHashMap::default(),
+            open: OpenDisputes::default(),
+            seq: 0,
+        }
+    }
+
+    /// Ledger restored from a snapshot; the open dispute totals, and the account's
+    /// `open_disputed_amount`, are rebuilt from the records
+    pub(crate) fn from_history(
+        account: ClientAccount,
+        seq: u64,
+        history: impl IntoIterator<Item = (u32, TransactionRecord)>,
+    ) -> Self {
+        let mut ledger = Self::from_account(account);
+        ledger.seq = seq;
+        ledger.tx_history.extend(history);
+        for record in ledger.tx_history.values() {
+            if record.dispute == DisputeState::Open {
+                ledger.open.count += 1;
+                ledger.open.amount += record.amount;
+                ledger.account.open_disputed_amount += record.amount;
+            }
+        }
+        ledger.opening_held -= ledger.records_held(ledger.account.currency);
+        ledger
+    }
+
+    /// The account and dispute counters as a `LedgerStore` keeps them, without the records
+    pub(crate) fn to_stored(&self) -> StoredAccount {
+        StoredAccount {
+            account: self.account.clone(),
+            seq: self.seq,
+            open_disputes: self.open.count as u64,
+            disputed_amount: self.open.amount,
+        }
+    }
+
+    /// Ledger loaded from a `LedgerStore` with none of its records in memory; the store's
+    /// records are brought back with `restore_record` as transactions refer to them
+    pub(crate) fn from_stored(stored: StoredAccount) -> Self {
+        let mut ledger = Self::from_account(stored.account);
+        ledger.seq = stored.seq;
+        ledger.open = OpenDisputes {
+            count: stored.open_disputes as usize,
+            amount: stored.disputed_amount,
+        };
+        ledger
+    }
+
+    /// Put a record read back from the store in memory; what it holds no longer counts as
+    /// held funds no record accounts for
+    pub(crate) fn restore_record(&mut self, tx: u32, record: TransactionRecord) {
+        self.opening_held -= record.held();
+        self.tx_history.insert(tx, record);
+    }
+
+    /// Drop every record from memory once the store has them, counting what they hold as held
+    /// funds no record accounts for until they are restored
+    pub(crate) fn evict_records(&mut self) {
+        self.opening_held += self.records_held(self.account.currency);
+        self.tx_history = FastHashMap::default();
+    }
+
+    /// The record of an applied deposit or withdrawal, if it is in memory
+    pub(crate) fn record(&self, tx: u32) -> Option<&TransactionRecord> {
+        self.tx_history.get(&tx)
+    }
+
+    /// Applied deposits and withdrawals, sorted by tx id
+    pub(crate) fn history(&self) -> Vec<(u32, &TransactionRecord)> {
+        let mut history: Vec<_> = self
+            .tx_history
+            .iter()
+            .map(|(tx, record)| (*tx, record))
+            .collect();
+        history.sort_unstable_by_key(|(tx, _)| *tx);
+        history
+    }
+
+    pub fn account(&self) -> &ClientAccount {
+        &self.account
+    }
+
+    pub fn into_account(self) -> ClientAccount {
+        self.account
+    }
+
+    /// Balances per currency in multi-currency mode, sorted by currency
+    pub fn currency_accounts(&self) -> &[ClientAccount] {
+        &self.currencies
+    }
+
+    /// Held funds of the client's single account or, in multi-currency mode, of the currency
+    /// holding the most
+    pub fn max_held(&self) -> f64 {
+        self.currencies
+            .iter()
+            .map(|account| account.held)
+            .fold(self.account.held, f64::max)
+    }
+
+    /// The rows this client contributes to the output: one per currency in multi-currency
+    /// mode, otherwise (or before any currency was used) the single account
+    pub fn output_accounts(&self) -> impl Iterator<Item = &ClientAccount> {
+        let single = self.currencies.is_empty().then_some(&self.account);
+        single.into_iter().chain(&self.currencies)
+    }
+
+    /// Owned form of `output_accounts`
+    pub fn into_output_accounts(self) -> Vec<ClientAccount> {
+        if self.currencies.is_empty() {
+            vec![self.account]
+        } else {
+            self.currencies
+        }
+    }
+
+    /// Whether a deposit or withdrawal with this id has been applied
+    pub fn has_transaction(&self, tx: u32) -> bool {
+        self.tx_history.contains_key(&tx)
+    }
+
+    /// Number of deposits and withdrawals kept for disputes
+    pub fn record_count(&self) -> usize {
+        self.tx_history.len()
+    }
+
+    /// Number of disputes currently open
+    pub fn open_disputes(&self) -> usize {
+        self.open.count
+    }
+
+    /// Sum of the amounts of the disputes currently open
+    pub fn disputed_amount(&self) -> f64 {
+        self.open.amount
+    }
+
+    /// Number of transactions given to this ledger, applied or not; the sequence number of
+    /// the latest one
+    pub fn sequence(&self) -> u64 {
+        self.seq
+    }
+
+    /// Amount of an applied deposit or withdrawal
+    pub fn transaction_amount(&self, tx: u32) -> Option<f64> {
+        self.tx_history.get(&tx).map(|record| record.amount)
+    }
+
+    /// Dispute state of an applied deposit or withdrawal
+    pub fn dispute_state(&self, tx: u32) -> Option<DisputeState> {
+        self.tx_history.get(&tx).map(|record| record.dispute)
+    }
+
+    /// Invariants the client's balances break, as checked by `EngineConfig::verify`
+    pub fn violations(&self) -> Vec<Violation> {
+        let mut violations = Vec::new();
+        for account in self.output_accounts() {
+            let mut report = |invariant| {
+                violations.push(Violation {
+                    client: account.client,
+                    currency: account.currency,
+                    invariant,
+                })
+            };
+            let held = to_minor_units(account.held);
+            if to_minor_units(account.total) != to_minor_units(account.available + account.held) {
+                report(Invariant::TotalMismatch {
+                    available: account.available,
+                    held: account.held,
+                    total: account.total,
+                });
+            }
+            if held < 0 {
+                report(Invariant::NegativeHeld { held: account.held });
+            }
+            // Currency accounts are never seeded, so only the single account has an opening
+            let opening = if self.currencies.is_empty() {
+                self.opening_held
+            } else {
+                0.0
+            };
+            let expected = opening + self.records_held(account.currency);
+            if held != to_minor_units(expected) {
+                report(Invariant::HeldMismatch {
+                    held: account.held,
+                    expected,
+                });
+            }
+            if account.locked && account.lock_reason.is_none() && !self.opening_locked {
+                report(Invariant::MissingLockCause);
+            }
+        }
+        violations
+    }
+
+    /// Held funds of `currency` the records account for: the amount held by each open
+    /// dispute, and every frozen deposit that wasn't charged back
+    fn records_held(&self, currency: Option<Currency>) -> f64 {
+        self.tx_history
+            .values()
+            .filter(|record| record.currency == currency)
+            .map(TransactionRecord::held)
+            .sum()
+    }
+
+    /// Part of a disputed transaction's amount that could not be held
+    /// (`DisputeFundsPolicy::PartialHold`), for an open or charged back dispute
+    pub fn dispute_shortfall(&self, tx: u32) -> Option<f64> {
+        let record = self.tx_history.get(&tx)?;
+        matches!(
+            record.dispute,
+            DisputeState::Open | DisputeState::ChargedBack
+        )
+        .then(|| record.amount - record.dispute_held)
+    }
+}
+
+/// Why a transaction left the ledger unchanged without counting as a rejection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IgnoreReason {
+    /// A deposit or withdrawal whose amount isn't a positive number
+    InvalidAmount,
+    /// A dispute, resolve or chargeback referring to a transaction not in this ledger
+    UnknownTransaction,
+    /// A dispute of a transaction that is already disputed
+    AlreadyDisputed,
+    /// A resolve or chargeback of a transaction that isn't disputed
+    NotDisputed,
+    /// A dispute of a resolved transaction without `EngineConfig::allow_redispute`
+    AlreadyResolved,
+    /// Any dispute action on a transaction that has been charged back
+    AlreadyChargedBack,
+    /// A deposit or withdrawal whose tx id was already applied, with
+    /// `EngineConfig::idempotent`
+    AlreadyApplied,
+    /// A close of an account that is already closed
+    AlreadyClosed,
+}
+
+impl fmt::Display for IgnoreReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            IgnoreReason::InvalidAmount => "invalid amount",
+            IgnoreReason::UnknownTransaction => "unknown transaction",
+            IgnoreReason::AlreadyDisputed => "already disputed",
+            IgnoreReason::NotDisputed => "not disputed",
+            IgnoreReason::AlreadyResolved => "already resolved",
+            IgnoreReason::AlreadyChargedBack => "already charged back",
+            IgnoreReason::AlreadyApplied => "already applied",
+            IgnoreReason::AlreadyClosed => "already closed",
+        })
+    }
+}
+
+/// Result of applying one transaction to a ledger
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApplyOutcome {
+    /// The ledger was updated
+    Applied,
+    /// Nothing changed; the input is malformed or doesn't apply to the ledger's current state
+    Ignored(IgnoreReason),
+    /// Nothing changed; the transaction was refused and counts as a rejection
+    Rejected(RejectReason),
+}
+
+/// Apply a transaction to a ledger under the default engine rules
+///
+/// ```
+/// use payments_engine::ledger::{ApplyOutcome, ClientLedger, apply_transaction};
+/// use payments_engine::{RejectReason, Transaction, TransactionType};
+///
+/// let mut ledger = ClientLedger::new(1);
+/// let deposit = Transaction::try_new(TransactionType::Deposit, 1, 1, Some(5.0)).unwrap();
+/// assert_eq!(apply_transaction(&mut ledger, &deposit), ApplyOutcome::Applied);
+///
+/// let withdrawal = Transaction::try_new(TransactionType::Withdrawal, 1, 2, Some(8.0)).unwrap();
+/// assert_eq!(
+///     apply_transaction(&mut ledger, &withdrawal),
+///     ApplyOutcome::Rejected(RejectReason::InsufficientFunds { shortfall: 3.0 })
+/// );
+/// assert_eq!(ledger.account().available(), 5.0);
+/// ```
+pub fn apply_transaction(ledger: &mut ClientLedger, transaction: &Transaction) -> ApplyOutcome {
+    apply_transaction_with_config(ledger, transaction, &EngineConfig::default())
+}
+
+/// Same as `apply_transaction`, with the policies of `config` (e.g. `locked_deposits`)
+///
+/// Invalid transactions are ignored; an over-withdrawal is rejected with
+/// `RejectReason::InsufficientFunds`, and a locked account rejects everything but dispute
+/// actions and, depending on `config.locked_deposits`, deposits.
+///
+/// With `config.multi_currency`, deposits and withdrawals move the balances of their own
+/// currency and are rejected with `RejectReason::MissingCurrency` without one; dispute actions
+/// use the currency of the transaction they refer to. Locks apply to the whole client.
+///
+/// ```
+/// use payments_engine::ledger::{ApplyOutcome, ClientLedger, apply_transaction_with_config};
+/// use payments_engine::{ClientAccount, EngineConfig, LockedDepositPolicy, Transaction, TransactionType};
+///
+/// let locked = ClientAccount::try_from_balances(1, 0.0, 0.0, true).unwrap();
+/// let mut ledger = ClientLedger::from_account(locked);
+/// let config = EngineConfig { locked_deposits: LockedDepositPolicy::ToHeld, ..Default::default() };
+///
+/// let deposit = Transaction::try_new(TransactionType::Deposit, 1, 1, Some(5.0)).unwrap();
+/// assert_eq!(apply_transaction_with_config(&mut ledger, &deposit, &config), ApplyOutcome::Applied);
+/// assert_eq!(ledger.account().held(), 5.0);
+/// ```
+pub fn apply_transaction_with_config(
+    ledger: &mut ClientLedger,
+    transaction: &Transaction,
+    config: &EngineConfig,
+) -> ApplyOutcome {
+    apply_normalized(ledger, transaction.clone().normalize().as_ref(), config)
+}
+
+/// `apply_transaction_with_config` for a transaction its owner has already normalized, given
+/// as the outcome of `Transaction::normalize`; a rejection still takes a sequence number
+pub(crate) fn apply_normalized(
+    ledger: &mut ClientLedger,
+    transaction: Result<&Transaction, &RejectReason>,
+    config: &EngineConfig,
+) -> ApplyOutcome {
+    ledger.seq += 1;
+    let transaction = match transaction {
+        Ok(transaction) => transaction,
+        Err(reason) => return ApplyOutcome::Rejected(reason.clone()),
+    };
+    if !transaction.is_valid() {
+        return ApplyOutcome::Ignored(IgnoreReason::InvalidAmount);
+    }
+    if !config.multi_currency {
+        return apply_to_account(
+            &mut ledger.account,
+            &mut ledger.tx_history,
+            &mut ledger.open,
+            ledger.seq,
+            transaction,
+            config,
+        );
+    }
+
+    if transaction.tx_type == TransactionType::Close {
+        // Closes the client as a whole, in every currency it holds
+        let outcome = apply_to_account(
+            &mut ledger.account,
+            &mut ledger.tx_history,
+            &mut ledger.open,
+            ledger.seq,
+            transaction,
+            config,
+        );
+        if outcome == ApplyOutcome::Applied {
+            for account in &mut ledger.currencies {
+                account.closed = true;
+            }
+        }
+        return outcome;
+    }
+
+    let currency = if transaction.is_dispute_action() {
+        ledger
+            .tx_history
+            .get(&transaction.tx)
+            .and_then(|record| record.currency)
+    } else {
+        match transaction.currency {
+            Some(currency) => Some(currency),
+            None => return ApplyOutcome::Rejected(RejectReason::MissingCurrency),
+        }
+    };
+    let Some(currency) = currency else {
+        return ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction);
+    };
+
+    // Work on a copy so a currency is only added once something moves in it
+    let position = ledger
+        .currencies
+        .binary_search_by_key(&Some(currency), |account| account.currency);
+    let mut account = match position {
+        Ok(index) => ledger.currencies[index].clone(),
+        Err(_) => ClientAccount {
+            currency: Some(currency),
+            ..ClientAccount::new(ledger.account.client)
+        },
+    };
+    account.locked = ledger.account.locked;
+    account.lock_reason = ledger.account.lock_reason;
+    account.closed = ledger.account.closed;
+
+    let outcome = apply_to_account(
+        &mut account,
+        &mut ledger.tx_history,
+        &mut ledger.open,
+        ledger.seq,
+        transaction,
+        config,
+    );
+    if outcome == ApplyOutcome::Applied {
+        let newly_locked = account.locked && !ledger.account.locked;
+        ledger.account.locked = account.locked;
+        ledger.account.lock_reason = account.lock_reason;
+        match position {
+            Ok(index) => ledger.currencies[index] = account,
+            Err(index) => ledger.currencies.insert(index, account),
+        }
+        if newly_locked {
+            // Every currency row reports the client's lock
+            for other in &mut ledger.currencies {
+                other.locked = true;
+                other.lock_reason = ledger.account.lock_reason;
+            }
+        }
+    }
+    outcome
+}
+
+/// Move `amount` of available funds from `source` to `destination`, checked as a withdrawal
+/// from one and a deposit to the other: both accounts must be open and unlocked, `source`
+/// must cover the amount within `config.withdrawal_epsilon`, and `destination` must stay in
+/// range. Neither side records a transaction, so a transfer can't be disputed. Only
+/// single-currency ledgers are moved
+pub(crate) fn apply_transfer(
+    source: &mut ClientLedger,
+    destination: &mut ClientLedger,
+    amount: f64,
+    config: &EngineConfig,
+) -> ApplyOutcome {
+    if !amount.is_finite() || amount <= 0.0 {
+        return ApplyOutcome::Ignored(IgnoreReason::InvalidAmount);
+    }
+    if to_minor_units(amount) == 0 {
+        return ApplyOutcome::Rejected(RejectReason::AmountBelowMinimum);
+    }
+    let (source, destination) = (&mut source.account, &mut destination.account);
+    if source.closed || destination.closed {
+        return ApplyOutcome::Rejected(RejectReason::AccountClosed);
+    }
+    if source.locked || destination.locked {
+        return ApplyOutcome::Rejected(RejectReason::AccountLocked);
+    }
+    let shortfall = amount - source.available;
+    if shortfall > config.withdrawal_epsilon {
+        return ApplyOutcome::Rejected(RejectReason::InsufficientFunds {
+            shortfall: round_to_four(shortfall),
+        });
+    }
+    // As with a withdrawal, exactly the available balance moves when the amount is over it by
+    // no more than the epsilon, so no f64 residue is left behind; the destination gets what
+    // left the source, never more than was asked for
+    let moved = if shortfall > 0.0 {
+        source.available
+    } else {
+        amount
+    };
+    if overflows(&[destination.available + moved, destination.total + moved]) {
+        return ApplyOutcome::Rejected(RejectReason::Overflow);
+    }
+    source.available -= moved;
+    source.total -= moved;
+    destination.available += moved;
+    destination.total += moved;
+    ApplyOutcome::Applied
+}
+
+/// Apply a validated transaction to one set of balances
+fn apply_to_account(
+    account: &mut ClientAccount,
+    tx_history: &mut FastHashMap<u32, TransactionRecord>,
+    open: &mut OpenDisputes,
+    seq: u64,
+    transaction: &Transaction,
+    config: &EngineConfig,
+) -> ApplyOutcome {
+    // Dispute actions need no check: the dispute state already makes a repeat a no-op. A
+    // repeated deposit or withdrawal must not replace the record disputes refer to.
+    if transaction.requires_amount() && tx_history.contains_key(&transaction.tx) {
+        return if config.idempotent {
+            ApplyOutcome::Ignored(IgnoreReason::AlreadyApplied)
+        } else {
+            ApplyOutcome::Rejected(RejectReason::DuplicateTransaction)
+        };
+    }
+
+    if account.closed && transaction.requires_amount() {
+        return ApplyOutcome::Rejected(RejectReason::AccountClosed);
+    }
+
+    // A locked account can still be closed
+    if account.locked
+        && transaction.requires_amount()
+        && !(transaction.tx_type == TransactionType::Deposit
+            && config.locked_deposits != LockedDepositPolicy::Reject)
+    {
+        return ApplyOutcome::Rejected(RejectReason::AccountLocked);
+    }
+
+    if transaction.is_dispute_action()
+        && disputable_record(tx_history, transaction.tx)
+            .is_some_and(|record| record.dispute.is_final())
+    {
+        // A chargeback is terminal: later disputes, resolves and chargebacks of the same
+        // transaction never touch the balances or the lock again
+        return ApplyOutcome::Ignored(IgnoreReason::AlreadyChargedBack);
+    }
+
+    // Deposits and withdrawals always carry an amount once validated
+    let amount = transaction.amount.unwrap_or_default();
+
+    match transaction.tx_type {
+        TransactionType::Deposit => {
+            // A frozen account still receives funds, but can't spend them
+            let frozen = account.locked && config.locked_deposits == LockedDepositPolicy::ToHeld;
+            let credited = if frozen {
+                account.held
+            } else {
+                account.available
+            };
+            if overflows(&[credited + amount, account.total + amount]) {
+                return ApplyOutcome::Rejected(RejectReason::Overflow);
+            }
+            if frozen {
+                account.held += amount;
+            } else {
+                account.available += amount;
+            }
+            account.total += amount;
+
+            tx_history.insert(
+                transaction.tx,
+                TransactionRecord {
+                    amount,
+                    dispute: DisputeState::None,
+                    dispute_held: 0.0,
+                    kind: TransactionType::Deposit,
+                    frozen,
+                    currency: account.currency,
+                    seq,
+                },
+            );
+        }
+
+        TransactionType::Withdrawal => {
+            // Allow `config.withdrawal_epsilon` of slack so accumulated f64 error can't refuse
+            // a withdrawal of exactly the available balance
+            let shortfall = amount - account.available;
+            if shortfall > config.withdrawal_epsilon {
+                return ApplyOutcome::Rejected(RejectReason::InsufficientFunds {
+                    shortfall: round_to_four(shortfall),
+                });
+            }
+
+            // Slightly over the balance empties the account exactly rather than leaving f64
+            // residue behind; the record keeps what was debited, so a dispute or chargeback
+            // moves the same amount back
+            let debited = if shortfall > 0.0 {
+                account.available
+            } else {
+                amount
+            };
+            account.available -= debited;
+            account.total -= debited;
+
+            tx_history.insert(
+                transaction.tx,
+                TransactionRecord {
+                    amount: debited,
+                    dispute: DisputeState::None,
+                    dispute_held: 0.0,
+                    kind: TransactionType::Withdrawal,
+                    frozen: false,
+                    currency: account.currency,
+                    seq,
+                },
+            );
+        }
+
+        TransactionType::Dispute => {
+            let Some(record) = disputable_record(tx_history, transaction.tx) else {
+                return ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction);
+            };
+            match record.dispute {
+                DisputeState::None => {}
+                DisputeState::Resolved if config.allow_redispute => {}
+                DisputeState::Open => {
+                    return ApplyOutcome::Ignored(IgnoreReason::AlreadyDisputed);
+                }
+                DisputeState::Resolved => {
+                    return ApplyOutcome::Ignored(IgnoreReason::AlreadyResolved);
+                }
+                DisputeState::ChargedBack => {
+                    return ApplyOutcome::Ignored(IgnoreReason::AlreadyChargedBack);
+                }
+            }
+            // Counted in the client's own transactions, the dispute included
+            let age = seq - record.seq;
+            if config.max_dispute_age.is_some_and(|max| age > max) {
+                return ApplyOutcome::Rejected(RejectReason::DisputeExpired { age });
+            }
+            if !record.is_deposit() && account.locked {
+                // A locked account can't start reclaiming withdrawn funds; disputes opened
+                // before the lock still run to completion
+                return ApplyOutcome::Rejected(RejectReason::AccountLocked);
+            }
+
+            if record.frozen {
+                // Disputing a frozen deposit: funds are already held, nothing moves
+                record.dispute_held = record.amount;
+            } else if record.is_deposit() {
+                // Disputing a deposit: hold the deposited funds, or as much of them as
+                // `config.dispute_funds` allows when some were already spent
+                // available decreases, held increases, total unchanged
+                let hold = match dispute_hold(account.available, record.amount, config) {
+                    Ok(hold) => hold,
+                    Err(reason) => return ApplyOutcome::Rejected(reason),
+                };
+                if overflows(&[account.available - hold, account.held + hold]) {
+                    return ApplyOutcome::Rejected(RejectReason::Overflow);
+                }
+                account.available -= hold;
+                account.held += hold;
+                record.dispute_held = hold;
+            } else if config.withdrawal_disputes == WithdrawalDisputeModel::HoldFromAvailable {
+                // Disputing a withdrawal, held from available: freeze the client's own funds
+                // available decreases, held increases, total unchanged
+                let hold = match dispute_hold(account.available, record.amount, config) {
+                    Ok(hold) => hold,
+                    Err(reason) => return ApplyOutcome::Rejected(reason),
+                };
+                if overflows(&[account.available - hold, account.held + hold]) {
+                    return ApplyOutcome::Rejected(RejectReason::Overflow);
+                }
+                account.available -= hold;
+                account.held += hold;
+                record.dispute_held = hold;
+            } else {
+                // Disputing a withdrawal: reverse the withdrawal but hold funds
+                // available unchanged, held increases, total increases
+                if overflows(&[account.held + record.amount, account.total + record.amount]) {
+                    return ApplyOutcome::Rejected(RejectReason::Overflow);
+                }
+                account.held += record.amount;
+                account.total += record.amount;
+                record.dispute_held = record.amount;
+            }
+            record.dispute = DisputeState::Open;
+            account.open_disputed_amount += record.amount;
+
+            open.count += 1;
+            open.amount += record.amount;
+            if !account.locked && open.over_threshold(config) {
+                // Freeze before any chargeback arrives; the lock stays if disputes later close
+                account.locked = true;
+                account.lock_reason = Some(LockReason::DisputeThreshold);
+            }
+        }
+
+        TransactionType::Resolve => {
+            let Some(record) = disputable_record(tx_history, transaction.tx) else {
+                return ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction);
+            };
+            if let Some(reason) = not_open(record.dispute) {
+                return ApplyOutcome::Ignored(reason);
+            }
+
+            if record.frozen {
+                // Resolving a frozen deposit dispute: funds stay held with the account
+            } else if record.is_deposit()
+                || config.withdrawal_disputes == WithdrawalDisputeModel::HoldFromAvailable
+            {
+                // Resolving a deposit dispute, or a withdrawal dispute held from available:
+                // release held funds
+                // available increases, held decreases, total unchanged
+                account.available += record.dispute_held;
+                account.held -= record.dispute_held;
+            } else {
+                // Resolving a withdrawal dispute: withdrawal was legitimate
+                // available unchanged, held decreases, total decreases
+                account.held -= record.dispute_held;
+                account.total -= record.dispute_held;
+            }
+            record.dispute = DisputeState::Resolved;
+            open.close(record.amount);
+            close_disputed(account, record.amount);
+        }
+
+        TransactionType::Chargeback => {
+            let Some(record) = disputable_record(tx_history, transaction.tx) else {
+                return ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction);
+            };
+            if let Some(reason) = not_open(record.dispute) {
+                return ApplyOutcome::Ignored(reason);
+            }
+
+            if record.is_deposit() {
+                // Chargeback on deposit: remove the funds the dispute held
+                // held decreases, total decreases, lock account
+                account.held -= record.dispute_held;
+                account.total -= record.dispute_held;
+            } else if config.withdrawal_disputes == WithdrawalDisputeModel::HoldFromAvailable {
+                // Chargeback on withdrawal held from available: release the hold and return
+                // the withdrawn funds
+                // held decreases, available increases by both, total increases, lock account
+                let available = account.available + record.dispute_held + record.amount;
+                if overflows(&[available, account.total + record.amount]) {
+                    return ApplyOutcome::Rejected(RejectReason::Overflow);
+                }
+                account.held -= record.dispute_held;
+                account.available = available;
+                account.total += record.amount;
+            } else {
+                // Chargeback on withdrawal: withdrawal was fraudulent, return funds
+                // held decreases, available increases, total unchanged, lock account
+                account.held -= record.dispute_held;
+                account.available += record.dispute_held;
+            }
+            record.dispute = DisputeState::ChargedBack;
+            open.close(record.amount);
+            close_disputed(account, record.amount);
+            account.locked = true;
+            account.lock_reason = Some(LockReason::Chargeback);
+        }
+
+        TransactionType::Close => {
+            if account.closed {
+                return ApplyOutcome::Ignored(IgnoreReason::AlreadyClosed);
+            }
+            account.closed = true;
+        }
+    }
+
+    account.counts.record(&transaction.tx_type);
+    ApplyOutcome::Applied
+}
+
+/// Take a closed dispute's amount off `account.open_disputed_amount`
+fn close_disputed(account: &mut ClientAccount, amount: f64) {
+    account.open_disputed_amount -= amount;
+    // Reset rather than carry f64 residue once nothing is open
+    if to_minor_units(account.open_disputed_amount) == 0 {
+        account.open_disputed_amount = 0.0;
+    }
+}
+
+/// Amount a dispute of a deposit holds out of `available` under `config.dispute_funds`
+fn dispute_hold(available: f64, amount: f64, config: &EngineConfig) -> Result<f64, RejectReason> {
+    if config.dispute_funds == DisputeFundsPolicy::AllowNegative {
+        return Ok(amount);
+    }
+
+    // Compare in minor units, so f64 error can't refuse holding exactly the available balance
+    let available_units = to_minor_units(available).max(0);
+    let requested = to_minor_units(amount);
+    if available_units >= requested {
+        return Ok(amount);
+    }
+
+    match config.dispute_funds {
+        DisputeFundsPolicy::RejectIfInsufficient => Err(RejectReason::DisputeUnfunded {
+            shortfall: (requested - available_units) as f64 / MINOR_UNITS_PER_UNIT,
+        }),
+        _ => Ok(available.max(0.0)),
+    }
+}
+
+/// Returns true if any of the balances a transaction would leave is out of range, checked
+/// before anything moves so a rejected transaction leaves the account as it was
+fn overflows(balances: &[f64]) -> bool {
+    balances.iter().copied().any(balance_out_of_range)
+}
+
+/// Why a resolve or chargeback can't close a dispute in `state`, if it can't
+fn not_open(state: DisputeState) -> Option<IgnoreReason> {
+    match state {
+        DisputeState::Open => None,
+        DisputeState::None | DisputeState::Resolved => Some(IgnoreReason::NotDisputed),
+        DisputeState::ChargedBack => Some(IgnoreReason::AlreadyChargedBack),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DEFAULT_WITHDRAWAL_EPSILON;
+
+    fn apply(
+        ledger: &mut ClientLedger,
+        tx_type: TransactionType,
+        tx: u32,
+        amount: Option<f64>,
+    ) -> ApplyOutcome {
+        let transaction = Transaction {
+            tx_type,
+            client: ledger.account().client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        };
+        apply_transaction(ledger, &transaction)
+    }
+
+    fn balances(ledger: &ClientLedger) -> (f64, f64, f64, bool) {
+        let account = ledger.account();
+        (
+            account.available,
+            account.held,
+            account.total,
+            account.locked,
+        )
+    }
+
+    /// Ledger with a 100.0 deposit (tx 1) and a 30.0 withdrawal (tx 2)
+    fn funded() -> ClientLedger {
+        let mut ledger = ClientLedger::new(1);
+        apply(&mut ledger, TransactionType::Deposit, 1, Some(100.0));
+        apply(&mut ledger, TransactionType::Withdrawal, 2, Some(30.0));
+        ledger
+    }
+
+    #[test]
+    fn test_deposit() {
+        let mut ledger = ClientLedger::new(1);
+
+        let outcome = apply(&mut ledger, TransactionType::Deposit, 1, Some(12.5));
+
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        assert_eq!(balances(&ledger), (12.5, 0.0, 12.5, false));
+        assert!(ledger.has_transaction(1));
+    }
+
+    #[test]
+    fn test_invalid_amounts_ignored() {
+        let mut ledger = funded();
+
+        for (tx_type, amount) in [
+            (TransactionType::Deposit, Some(-1.0)),
+            (TransactionType::Withdrawal, Some(0.0)),
+        ] {
+            assert_eq!(
+                apply(&mut ledger, tx_type, 9, amount),
+                ApplyOutcome::Ignored(IgnoreReason::InvalidAmount)
+            );
+        }
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Deposit, 9, None),
+            ApplyOutcome::Rejected(RejectReason::MissingAmount)
+        );
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+        assert!(!ledger.has_transaction(9));
+    }
+
+    #[test]
+    fn test_withdrawal() {
+        let ledger = funded();
+
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+        assert!(ledger.has_transaction(2));
+    }
+
+    #[test]
+    fn test_withdrawal_of_exact_balance() {
+        let mut ledger = ClientLedger::new(1);
+        apply(&mut ledger, TransactionType::Deposit, 1, Some(100.0));
+
+        let outcome = apply(&mut ledger, TransactionType::Withdrawal, 2, Some(100.0));
+
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        assert_eq!(balances(&ledger), (0.0, 0.0, 0.0, false));
+    }
+
+    #[test]
+    fn test_withdrawal_of_exact_accumulated_balance() {
+        // 0.7 + 0.1 sums to 0.7999999999999999 in f64, just below 0.8
+        let mut ledger = ClientLedger::new(1);
+        apply(&mut ledger, TransactionType::Deposit, 1, Some(0.7));
+        apply(&mut ledger, TransactionType::Deposit, 2, Some(0.1));
+        apply(&mut ledger, TransactionType::Deposit, 3, Some(5.0));
+        apply(&mut ledger, TransactionType::Dispute, 3, None);
+        assert!(ledger.account().available < 0.8);
+
+        let outcome = apply(&mut ledger, TransactionType::Withdrawal, 4, Some(0.8));
+
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        assert_eq!(balances(&ledger), (0.0, 5.0, 5.0, false));
+    }
+
+    #[test]
+    fn test_withdrawal_epsilon() {
+        // Balances and withdrawals differing only below the 4th decimal
+        let withdraw = |deposits: &[f64], amount: f64, withdrawal_epsilon: f64| {
+            let config = EngineConfig {
+                withdrawal_epsilon,
+                ..Default::default()
+            };
+            let mut ledger = ClientLedger::new(1);
+            let mut tx = 0;
+            let mut transaction = |tx_type, amount| {
+                tx += 1;
+                let transaction = Transaction {
+                    tx_type,
+                    client: 1,
+                    tx,
+                    amount,
+                    currency: None,
+                    timestamp: None,
+                };
+                apply_transaction_with_config(&mut ledger, &transaction, &config)
+            };
+            for deposit in deposits {
+                transaction(TransactionType::Deposit, Some(*deposit));
+            }
+            let outcome = transaction(TransactionType::Withdrawal, Some(amount));
+            (outcome, ledger.account().available)
+        };
+        let insufficient =
+            |shortfall| ApplyOutcome::Rejected(RejectReason::InsufficientFunds { shortfall });
+
+        // 0.7 + 0.1 is 0.7999999999999999: only an exact comparison refuses 0.8
+        assert_eq!(
+            withdraw(&[0.7, 0.1], 0.8, DEFAULT_WITHDRAWAL_EPSILON),
+            (ApplyOutcome::Applied, 0.0)
+        );
+        assert_eq!(
+            withdraw(&[0.7, 0.1], 0.8, 0.0),
+            (insufficient(0.0), 0.7999999999999999)
+        );
+
+        // 0.00004 over the balance is within half a minor unit, and empties the account
+        assert_eq!(
+            withdraw(&[1.0], 1.00004, DEFAULT_WITHDRAWAL_EPSILON),
+            (ApplyOutcome::Applied, 0.0)
+        );
+        assert_eq!(withdraw(&[1.0], 1.00004, 0.00001).0, insufficient(0.0));
+
+        // 0.00006 over rounds to a different 4-decimal amount
+        assert_eq!(
+            withdraw(&[1.0], 1.00006, DEFAULT_WITHDRAWAL_EPSILON),
+            (insufficient(0.0001), 1.0)
+        );
+
+        // Under the balance debits exactly the amount asked for, whatever the epsilon
+        for withdrawal_epsilon in [DEFAULT_WITHDRAWAL_EPSILON, 0.0] {
+            let (outcome, available) = withdraw(&[1.00004], 1.0, withdrawal_epsilon);
+            assert_eq!(outcome, ApplyOutcome::Applied);
+            assert_eq!(available, 1.00004 - 1.0);
+        }
+
+        for withdrawal_epsilon in [-0.00001, 0.0001, f64::NAN] {
+            let config = EngineConfig {
+                withdrawal_epsilon,
+                ..Default::default()
+            };
+            assert!(config.validate().is_err(), "{}", withdrawal_epsilon);
+        }
+    }
+
+    #[test]
+    fn test_cancelled_balance_written_as_unsigned_zero() {
+        // The withdrawals empty `available` exactly, but `total` keeps -2.8e-17 of f64 residue
+        let mut ledger = ClientLedger::new(1);
+        apply(&mut ledger, TransactionType::Deposit, 1, Some(0.4));
+        apply(&mut ledger, TransactionType::Deposit, 2, Some(0.1));
+        apply(&mut ledger, TransactionType::Dispute, 2, None);
+        apply(&mut ledger, TransactionType::Withdrawal, 3, Some(0.4));
+        apply(&mut ledger, TransactionType::Resolve, 2, None);
+        apply(&mut ledger, TransactionType::Withdrawal, 4, Some(0.1));
+        assert!(ledger.account().total < 0.0);
+
+        assert_eq!(ledger.account().to_csv_row(), "1,0.0,0.0,0.0,false");
+    }
+
+    #[test]
+    fn test_amount_below_minimum_rejected() {
+        let mut ledger = funded();
+
+        for tx_type in [TransactionType::Deposit, TransactionType::Withdrawal] {
+            assert_eq!(
+                apply(&mut ledger, tx_type, 9, Some(0.00004)),
+                ApplyOutcome::Rejected(RejectReason::AmountBelowMinimum)
+            );
+        }
+        assert!(!ledger.has_transaction(9));
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Dispute, 9, None),
+            ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction)
+        );
+
+        // Half a minor unit rounds up to 0.0001 and is recorded
+        let outcome = apply(&mut ledger, TransactionType::Deposit, 10, Some(0.00005));
+        assert_eq!(outcome, ApplyOutcome::Applied);
+    }
+
+    #[test]
+    fn test_deposit_overflow_rejected_without_touching_balances() {
+        // Just under half of the largest balance held to the minor unit
+        let large = 450_000_000_000.0;
+        let mut ledger = ClientLedger::new(1);
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Deposit, 1, Some(large)),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Deposit, 2, Some(large)),
+            ApplyOutcome::Applied
+        );
+
+        let before = balances(&ledger);
+        for tx in 3..10 {
+            assert_eq!(
+                apply(&mut ledger, TransactionType::Deposit, tx, Some(large)),
+                ApplyOutcome::Rejected(RejectReason::Overflow)
+            );
+            assert!(!ledger.has_transaction(tx));
+        }
+        let scale = i64::MAX as f64 / MINOR_UNITS_PER_UNIT;
+        for amount in [scale, f64::MAX] {
+            assert_eq!(
+                apply(&mut ledger, TransactionType::Deposit, 10, Some(amount)),
+                ApplyOutcome::Rejected(RejectReason::Overflow)
+            );
+        }
+        assert_eq!(balances(&ledger), before);
+        assert_eq!(ledger.account().total, 2.0 * large);
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_overflow_rejected() {
+        let large = 450_000_000_000.0;
+        let mut ledger = ClientLedger::new(1);
+        apply(&mut ledger, TransactionType::Deposit, 1, Some(large));
+        apply(&mut ledger, TransactionType::Withdrawal, 2, Some(large));
+        apply(&mut ledger, TransactionType::Deposit, 3, Some(2.0 * large));
+
+        // Holding the withdrawn funds again would take total past the limit
+        let before = balances(&ledger);
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Dispute, 2, None),
+            ApplyOutcome::Rejected(RejectReason::Overflow)
+        );
+        assert_eq!(balances(&ledger), before);
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Resolve, 2, None),
+            ApplyOutcome::Ignored(IgnoreReason::NotDisputed)
+        );
+    }
+
+    #[test]
+    fn test_violations_of_settled_and_corrupted_ledgers() {
+        let config = EngineConfig {
+            locked_deposits: LockedDepositPolicy::ToHeld,
+            dispute_funds: DisputeFundsPolicy::PartialHold,
+            ..Default::default()
+        };
+        let mut ledger = ClientLedger::new(1);
+        for (tx_type, tx, amount) in [
+            (TransactionType::Deposit, 1, Some(100.0)),
+            (TransactionType::Withdrawal, 2, Some(70.0)),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Deposit, 3, Some(5.0)),
+            (TransactionType::Dispute, 2, None),
+            (TransactionType::Chargeback, 1, None),
+            (TransactionType::Deposit, 4, Some(2.5)),
+            (TransactionType::Dispute, 4, None),
+        ] {
+            let transaction = Transaction {
+                tx_type,
+                client: 1,
+                tx,
+                amount,
+                currency: None,
+                timestamp: None,
+            };
+            apply_transaction_with_config(&mut ledger, &transaction, &config);
+        }
+        assert!(ledger.account().locked);
+        assert_eq!(ledger.violations(), vec![]);
+
+        let mut corrupted = ledger.clone();
+        corrupted.account.held += 1.0;
+        corrupted.account.lock_reason = None;
+        let invariants: Vec<_> = corrupted
+            .violations()
+            .into_iter()
+            .map(|violation| violation.invariant)
+            .collect();
+        assert_eq!(
+            invariants,
+            vec![
+                Invariant::TotalMismatch {
+                    available: ledger.account().available,
+                    held: ledger.account().held + 1.0,
+                    total: ledger.account().total,
+                },
+                Invariant::HeldMismatch {
+                    held: ledger.account().held + 1.0,
+                    expected: ledger.account().held,
+                },
+                Invariant::MissingLockCause,
+            ]
+        );
+
+        let mut negative = ClientLedger::new(2);
+        negative.account.held = -1.0;
+        negative.account.available = 1.0;
+        assert!(
+            negative
+                .violations()
+                .iter()
+                .any(|violation| violation.invariant == Invariant::NegativeHeld { held: -1.0 })
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_one_minor_unit_over_balance() {
+        let mut ledger = ClientLedger::new(1);
+        apply(&mut ledger, TransactionType::Deposit, 1, Some(10.0));
+
+        let outcome = apply(&mut ledger, TransactionType::Withdrawal, 2, Some(10.0001));
+
+        assert_eq!(
+            outcome,
+            ApplyOutcome::Rejected(RejectReason::InsufficientFunds { shortfall: 0.0001 })
+        );
+        assert_eq!(balances(&ledger), (10.0, 0.0, 10.0, false));
+        assert!(!ledger.has_transaction(2));
+    }
+
+    #[test]
+    fn test_dispute_deposit() {
+        let mut ledger = funded();
+
+        let outcome = apply(&mut ledger, TransactionType::Dispute, 1, None);
+
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        assert_eq!(balances(&ledger), (-30.0, 100.0, 70.0, false));
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Dispute, 1, None),
+            ApplyOutcome::Ignored(IgnoreReason::AlreadyDisputed)
+        );
+    }
+
+    #[test]
+    fn test_dispute_withdrawal() {
+        let mut ledger = funded();
+
+        let outcome = apply(&mut ledger, TransactionType::Dispute, 2, None);
+
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        assert_eq!(balances(&ledger), (70.0, 30.0, 100.0, false));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_models() {
+        // Deposit 100, withdraw 30, dispute the withdrawal, then resolve or charge it back
+        let cases = [
+            (
+                WithdrawalDisputeModel::ReverseAndHold,
+                TransactionType::Resolve,
+                [(70.0, 30.0, 100.0, false), (70.0, 0.0, 70.0, false)],
+            ),
+            (
+                WithdrawalDisputeModel::ReverseAndHold,
+                TransactionType::Chargeback,
+                [(70.0, 30.0, 100.0, false), (100.0, 0.0, 100.0, true)],
+            ),
+            (
+                WithdrawalDisputeModel::HoldFromAvailable,
+                TransactionType::Resolve,
+                [(40.0, 30.0, 70.0, false), (70.0, 0.0, 70.0, false)],
+            ),
+            (
+                WithdrawalDisputeModel::HoldFromAvailable,
+                TransactionType::Chargeback,
+                [(40.0, 30.0, 70.0, false), (100.0, 0.0, 100.0, true)],
+            ),
+        ];
+
+        for (model, close, [disputed, closed]) in cases {
+            let config = EngineConfig {
+                withdrawal_disputes: model,
+                ..Default::default()
+            };
+            let mut ledger = funded();
+            for (tx_type, expected) in [(TransactionType::Dispute, disputed), (close, closed)] {
+                let transaction = Transaction {
+                    tx_type,
+                    client: 1,
+                    tx: 2,
+                    amount: None,
+                    currency: None,
+                    timestamp: None,
+                };
+                assert_eq!(
+                    apply_transaction_with_config(&mut ledger, &transaction, &config),
+                    ApplyOutcome::Applied
+                );
+                assert_eq!(
+                    balances(&ledger),
+                    expected,
+                    "{:?} {}",
+                    model,
+                    transaction.tx_type.name()
+                );
+            }
+            assert_eq!(ledger.violations(), vec![]);
+        }
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_held_from_spent_funds() {
+        // Withdraw 30 of 100, then spend the rest, so nothing is left to hold
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Withdrawal, 3, Some(70.0));
+        let dispute = Transaction {
+            tx_type: TransactionType::Dispute,
+            client: 1,
+            tx: 2,
+            amount: None,
+            currency: None,
+            timestamp: None,
+        };
+
+        let config = |dispute_funds| EngineConfig {
+            withdrawal_disputes: WithdrawalDisputeModel::HoldFromAvailable,
+            dispute_funds,
+            ..Default::default()
+        };
+        let mut rejected = ledger.clone();
+        assert_eq!(
+            apply_transaction_with_config(
+                &mut rejected,
+                &dispute,
+                &config(DisputeFundsPolicy::RejectIfInsufficient)
+            ),
+            ApplyOutcome::Rejected(RejectReason::DisputeUnfunded { shortfall: 30.0 })
+        );
+        apply_transaction_with_config(
+            &mut ledger,
+            &dispute,
+            &config(DisputeFundsPolicy::AllowNegative),
+        );
+        assert_eq!(balances(&ledger), (-30.0, 30.0, 0.0, false));
+    }
+
+    #[test]
+    fn test_dispute_of_failed_withdrawal_ignored() {
+        let mut ledger = funded();
+        assert!(matches!(
+            apply(&mut ledger, TransactionType::Withdrawal, 3, Some(500.0)),
+            ApplyOutcome::Rejected(RejectReason::InsufficientFunds { .. })
+        ));
+
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Dispute, 3, None),
+            ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction)
+        );
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_rejected_on_locked_account() {
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Deposit, 3, Some(10.0));
+        apply(&mut ledger, TransactionType::Dispute, 3, None);
+        apply(&mut ledger, TransactionType::Chargeback, 3, None);
+
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Dispute, 2, None),
+            ApplyOutcome::Rejected(RejectReason::AccountLocked)
+        );
+        assert_eq!(ledger.dispute_state(2), Some(DisputeState::None));
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, true));
+    }
+
+    #[test]
+    fn test_withdrawal_dispute_adversarial_sequence() {
+        // Spend everything the withdrawal dispute left available, then try to make the dispute
+        // release or return funds more than once
+        let config = EngineConfig {
+            allow_redispute: true,
+            ..EngineConfig::default()
+        };
+        let mut ledger = ClientLedger::new(1);
+        let steps = [
+            (
+                TransactionType::Deposit,
+                1,
+                Some(100.0),
+                (100.0, 0.0, 100.0, false),
+            ),
+            (
+                TransactionType::Withdrawal,
+                2,
+                Some(60.0),
+                (40.0, 0.0, 40.0, false),
+            ),
+            (
+                TransactionType::Dispute,
+                2,
+                None,
+                (40.0, 60.0, 100.0, false),
+            ),
+            (
+                TransactionType::Withdrawal,
+                3,
+                Some(40.0),
+                (0.0, 60.0, 60.0, false),
+            ),
+            (
+                TransactionType::Withdrawal,
+                4,
+                Some(1.0),
+                (0.0, 60.0, 60.0, false),
+            ),
+            (TransactionType::Dispute, 4, None, (0.0, 60.0, 60.0, false)),
+            (TransactionType::Resolve, 2, None, (0.0, 0.0, 0.0, false)),
+            (TransactionType::Resolve, 2, None, (0.0, 0.0, 0.0, false)),
+            (TransactionType::Dispute, 2, None, (0.0, 60.0, 60.0, false)),
+            (
+                TransactionType::Dispute,
+                3,
+                None,
+                (0.0, 100.0, 100.0, false),
+            ),
+            (
+                TransactionType::Chargeback,
+                3,
+                None,
+                (40.0, 60.0, 100.0, true),
+            ),
+            (
+                TransactionType::Chargeback,
+                3,
+                None,
+                (40.0, 60.0, 100.0, true),
+            ),
+            (
+                TransactionType::Chargeback,
+                2,
+                None,
+                (100.0, 0.0, 100.0, true),
+            ),
+            (TransactionType::Dispute, 2, None, (100.0, 0.0, 100.0, true)),
+            (TransactionType::Resolve, 2, None, (100.0, 0.0, 100.0, true)),
+        ];
+
+        for (tx_type, tx, amount, expected) in steps {
+            let transaction = Transaction {
+                tx_type,
+                client: 1,
+                tx,
+                amount,
+                currency: None,
+                timestamp: None,
+            };
+            apply_transaction_with_config(&mut ledger, &transaction, &config);
+
+            let account = ledger.account();
+            assert_eq!(
+                balances(&ledger),
+                expected,
+                "after {} tx {}",
+                transaction.tx_type.name(),
+                tx
+            );
+            assert!(account.available >= 0.0);
+            assert_eq!(account.total, account.available + account.held);
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut ledger = funded();
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Resolve, 1, None),
+            ApplyOutcome::Ignored(IgnoreReason::NotDisputed)
+        );
+
+        apply(&mut ledger, TransactionType::Dispute, 1, None);
+        apply(&mut ledger, TransactionType::Dispute, 2, None);
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Resolve, 1, None),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Resolve, 2, None),
+            ApplyOutcome::Applied
+        );
+
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+    }
+
+    #[test]
+    fn test_chargeback_deposit() {
+        let mut ledger = funded();
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Chargeback, 1, None),
+            ApplyOutcome::Ignored(IgnoreReason::NotDisputed)
+        );
+
+        apply(&mut ledger, TransactionType::Dispute, 1, None);
+        let outcome = apply(&mut ledger, TransactionType::Chargeback, 1, None);
+
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        assert_eq!(balances(&ledger), (-30.0, 0.0, -30.0, true));
+    }
+
+    #[test]
+    fn test_partial_hold_released_on_resolve() {
+        let config = EngineConfig {
+            dispute_funds: DisputeFundsPolicy::PartialHold,
+            ..EngineConfig::default()
+        };
+        let mut ledger = funded();
+        let dispute = Transaction {
+            tx_type: TransactionType::Dispute,
+            client: 1,
+            tx: 1,
+            amount: None,
+            currency: None,
+            timestamp: None,
+        };
+
+        assert_eq!(
+            apply_transaction_with_config(&mut ledger, &dispute, &config),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(balances(&ledger), (0.0, 70.0, 70.0, false));
+        assert_eq!(ledger.dispute_shortfall(1), Some(30.0));
+
+        apply(&mut ledger, TransactionType::Resolve, 1, None);
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+        assert_eq!(ledger.dispute_shortfall(1), None);
+    }
+
+    #[test]
+    fn test_chargeback_withdrawal() {
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Dispute, 2, None);
+
+        let outcome = apply(&mut ledger, TransactionType::Chargeback, 2, None);
+
+        assert_eq!(outcome, ApplyOutcome::Applied);
+        assert_eq!(balances(&ledger), (100.0, 0.0, 100.0, true));
+    }
+
+    #[test]
+    fn test_chargeback_is_final() {
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Dispute, 1, None);
+        apply(&mut ledger, TransactionType::Chargeback, 1, None);
+
+        // Resolving after the chargeback used to hand the charged back funds back to the client
+        for tx_type in [
+            TransactionType::Resolve,
+            TransactionType::Dispute,
+            TransactionType::Chargeback,
+        ] {
+            assert_eq!(
+                apply(&mut ledger, tx_type, 1, None),
+                ApplyOutcome::Ignored(IgnoreReason::AlreadyChargedBack)
+            );
+        }
+
+        assert_eq!(ledger.dispute_state(1), Some(DisputeState::ChargedBack));
+        assert_eq!(balances(&ledger), (-30.0, 0.0, -30.0, true));
+    }
+
+    #[test]
+    fn test_resolved_dispute_not_reopened_by_default() {
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Dispute, 1, None);
+        apply(&mut ledger, TransactionType::Resolve, 1, None);
+
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Dispute, 1, None),
+            ApplyOutcome::Ignored(IgnoreReason::AlreadyResolved)
+        );
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Chargeback, 1, None),
+            ApplyOutcome::Ignored(IgnoreReason::NotDisputed)
+        );
+        assert_eq!(ledger.dispute_state(1), Some(DisputeState::Resolved));
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+    }
+
+    #[test]
+    fn test_repeated_dispute_cycle_charges_back_once() {
+        let config = EngineConfig {
+            allow_redispute: true,
+            ..EngineConfig::default()
+        };
+        let mut ledger = funded();
+        let mut cycle = |tx_type| {
+            let transaction = Transaction {
+                tx_type,
+                client: 1,
+                tx: 1,
+                amount: None,
+                currency: None,
+                timestamp: None,
+            };
+            apply_transaction_with_config(&mut ledger, &transaction, &config)
+        };
+
+        for _ in 0..3 {
+            assert_eq!(cycle(TransactionType::Dispute), ApplyOutcome::Applied);
+            assert_eq!(cycle(TransactionType::Resolve), ApplyOutcome::Applied);
+        }
+        assert_eq!(cycle(TransactionType::Dispute), ApplyOutcome::Applied);
+        assert_eq!(cycle(TransactionType::Chargeback), ApplyOutcome::Applied);
+        for _ in 0..3 {
+            assert_eq!(
+                cycle(TransactionType::Resolve),
+                ApplyOutcome::Ignored(IgnoreReason::AlreadyChargedBack)
+            );
+            assert_eq!(
+                cycle(TransactionType::Dispute),
+                ApplyOutcome::Ignored(IgnoreReason::AlreadyChargedBack)
+            );
+            assert_eq!(
+                cycle(TransactionType::Chargeback),
+                ApplyOutcome::Ignored(IgnoreReason::AlreadyChargedBack)
+            );
+        }
+
+        // The deposit is taken back exactly once
+        assert_eq!(balances(&ledger), (-30.0, 0.0, -30.0, true));
+    }
+
+    #[test]
+    fn test_dispute_actions_on_unknown_transaction_ignored() {
+        let mut ledger = funded();
+
+        for tx_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+        ] {
+            assert_eq!(
+                apply(&mut ledger, tx_type, 99, None),
+                ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction)
+            );
+        }
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+    }
+
+    #[test]
+    fn test_only_deposits_and_withdrawals_are_disputable() {
+        // Dispute rows are never recorded today; should one be, it must not be disputable
+        let ledger = funded();
+        let record = TransactionRecord {
+            kind: TransactionType::Dispute,
+            ..ledger.tx_history[&1].clone()
+        };
+        let mut ledger = ClientLedger::from_history(ledger.account().clone(), 2, [(3, record)]);
+
+        for tx_type in [
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+        ] {
+            assert_eq!(
+                apply(&mut ledger, tx_type, 3, None),
+                ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction)
+            );
+        }
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+        assert_eq!(ledger.open_disputes(), 0);
+    }
+
+    #[test]
+    fn test_transfer_moves_available_funds() {
+        let config = EngineConfig::default();
+        let mut source = funded();
+        let mut destination = ClientLedger::new(2);
+
+        assert_eq!(
+            apply_transfer(&mut source, &mut destination, 50.0, &config),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(balances(&source), (20.0, 0.0, 20.0, false));
+        assert_eq!(balances(&destination), (50.0, 0.0, 50.0, false));
+
+        assert_eq!(
+            apply_transfer(&mut source, &mut destination, 25.0, &config),
+            ApplyOutcome::Rejected(RejectReason::InsufficientFunds { shortfall: 5.0 })
+        );
+        assert_eq!(
+            apply_transfer(&mut source, &mut destination, -1.0, &config),
+            ApplyOutcome::Ignored(IgnoreReason::InvalidAmount)
+        );
+        // Held funds don't move, and a locked side refuses the transfer
+        apply(&mut destination, TransactionType::Deposit, 1, Some(10.0));
+        apply(&mut destination, TransactionType::Dispute, 1, None);
+        assert_eq!(
+            apply_transfer(&mut destination, &mut source, 55.0, &config),
+            ApplyOutcome::Rejected(RejectReason::InsufficientFunds { shortfall: 5.0 })
+        );
+        apply(&mut destination, TransactionType::Chargeback, 1, None);
+        assert_eq!(
+            apply_transfer(&mut source, &mut destination, 1.0, &config),
+            ApplyOutcome::Rejected(RejectReason::AccountLocked)
+        );
+        assert_eq!(balances(&source), (20.0, 0.0, 20.0, false));
+    }
+
+    #[test]
+    fn test_transfer_within_epsilon_never_moves_more_than_asked() {
+        let config = EngineConfig::default();
+        let transfer = |balance: f64, amount: f64| {
+            let mut source = ClientLedger::new(1);
+            apply(&mut source, TransactionType::Deposit, 1, Some(balance));
+            let mut destination = ClientLedger::new(2);
+            let outcome = apply_transfer(&mut source, &mut destination, amount, &config);
+            (
+                outcome,
+                source.account().available,
+                destination.account().available,
+            )
+        };
+
+        // Under the balance moves exactly the amount
+        assert_eq!(
+            transfer(1.00004, 1.0),
+            (ApplyOutcome::Applied, 1.00004 - 1.0, 1.0)
+        );
+        // Over it by less than the epsilon moves the whole balance
+        assert_eq!(transfer(1.0, 1.00004), (ApplyOutcome::Applied, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_locked_account_rejects_deposits_and_withdrawals() {
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Dispute, 2, None);
+        apply(&mut ledger, TransactionType::Chargeback, 2, None);
+
+        for (tx_type, tx) in [
+            (TransactionType::Deposit, 3),
+            (TransactionType::Withdrawal, 4),
+        ] {
+            assert_eq!(
+                apply(&mut ledger, tx_type, tx, Some(1.0)),
+                ApplyOutcome::Rejected(RejectReason::AccountLocked)
+            );
+        }
+        assert_eq!(balances(&ledger), (100.0, 0.0, 100.0, true));
+
+        // Dispute actions still apply to a locked account
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Dispute, 1, None),
+            ApplyOutcome::Applied
+        );
+    }
+
+    #[test]
+    fn test_closed_account_rejects_deposits_but_resolves_disputes() {
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Dispute, 1, None);
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Close, 3, None),
+            ApplyOutcome::Applied
+        );
+        assert!(ledger.account().closed);
+
+        for (tx_type, tx) in [
+            (TransactionType::Deposit, 4),
+            (TransactionType::Withdrawal, 5),
+        ] {
+            assert_eq!(
+                apply(&mut ledger, tx_type, tx, Some(1.0)),
+                ApplyOutcome::Rejected(RejectReason::AccountClosed)
+            );
+        }
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Close, 6, None),
+            ApplyOutcome::Ignored(IgnoreReason::AlreadyClosed)
+        );
+
+        // The dispute opened before the closure still settles
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Resolve, 1, None),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(balances(&ledger), (70.0, 0.0, 70.0, false));
+        assert_eq!(ledger.open_disputes(), 0);
+    }
+
+    #[test]
+    fn test_idempotent_skips_applied_transactions() {
+        let config = EngineConfig {
+            idempotent: true,
+            ..Default::default()
+        };
+        let mut ledger = funded();
+        apply(&mut ledger, TransactionType::Dispute, 1, None);
+
+        for (tx_type, tx) in [
+            (TransactionType::Deposit, 1),
+            (TransactionType::Withdrawal, 2),
+            (TransactionType::Deposit, 2),
+        ] {
+            let replay = Transaction {
+                tx_type,
+                client: 1,
+                tx,
+                amount: Some(5.0),
+                currency: None,
+                timestamp: None,
+            };
+            assert_eq!(
+                apply_transaction_with_config(&mut ledger, &replay, &config),
+                ApplyOutcome::Ignored(IgnoreReason::AlreadyApplied)
+            );
+        }
+        assert_eq!(balances(&ledger), (-30.0, 100.0, 70.0, false));
+        // The dispute was untouched by the replayed deposit
+        assert_eq!(ledger.dispute_state(1), Some(DisputeState::Open));
+    }
+
+    #[test]
+    fn test_dispute_outside_window_rejected() {
+        let config = EngineConfig {
+            max_dispute_age: Some(2),
+            ..Default::default()
+        };
+        let mut ledger = funded();
+        let dispute = |tx| Transaction {
+            tx_type: TransactionType::Dispute,
+            client: 1,
+            tx,
+            amount: None,
+            currency: None,
+            timestamp: None,
+        };
+
+        // Withdrawal tx 2 was the previous transaction, deposit tx 1 the one before it
+        assert_eq!(
+            apply_transaction_with_config(&mut ledger, &dispute(2), &config),
+            ApplyOutcome::Applied
+        );
+        assert_eq!(
+            apply_transaction_with_config(&mut ledger, &dispute(1), &config),
+            ApplyOutcome::Rejected(RejectReason::DisputeExpired { age: 3 })
+        );
+        assert_eq!(balances(&ledger), (70.0, 30.0, 100.0, false));
+        assert_eq!(ledger.sequence(), 4);
+    }
+
+    #[test]
+    fn test_dispute_threshold_locks_account() {
+        let config = EngineConfig {
+            max_open_disputes: Some(3),
+            ..EngineConfig::default()
+        };
+        let apply = |ledger: &mut ClientLedger, tx_type, tx, amount| {
+            let transaction = Transaction {
+                tx_type,
+                client: 1,
+                tx,
+                amount,
+                currency: None,
+                timestamp: None,
+            };
+            apply_transaction_with_config(ledger, &transaction, &config)
+        };
+        let mut ledger = ClientLedger::new(1);
+        for tx in 1..=4 {
+            apply(&mut ledger, TransactionType::Deposit, tx, Some(10.0));
+        }
+
+        // Two open disputes stay under the threshold, a resolved one no longer counts
+        apply(&mut ledger, TransactionType::Dispute, 1, None);
+        apply(&mut ledger, TransactionType::Resolve, 1, None);
+        apply(&mut ledger, TransactionType::Dispute, 2, None);
+        apply(&mut ledger, TransactionType::Dispute, 3, None);
+        assert!(!ledger.account().locked);
+
+        apply(&mut ledger, TransactionType::Dispute, 4, None);
+        assert_eq!(ledger.open_disputes(), 3);
+        assert_eq!(ledger.disputed_amount(), 30.0);
+        assert!(ledger.account().locked);
+        assert_eq!(
+            ledger.account().lock_reason,
+            Some(LockReason::DisputeThreshold)
+        );
+        assert_eq!(
+            apply(&mut ledger, TransactionType::Deposit, 5, Some(1.0)),
+            ApplyOutcome::Rejected(RejectReason::AccountLocked)
+        );
+
+        // A later chargeback is the stronger reason
+        apply(&mut ledger, TransactionType::Chargeback, 4, None);
+        assert_eq!(ledger.account().lock_reason, Some(LockReason::Chargeback));
+        assert_eq!(ledger.open_disputes(), 2);
+    }
+
+    #[test]
+    fn test_disputed_amount_threshold() {
+        let config = EngineConfig {
+            max_disputed_amount: Some(100.0),
+            ..EngineConfig::default()
+        };
+        let mut ledger = ClientLedger::new(1);
+        for (tx, amount) in [(1, 60.0), (2, 40.0), (3, 0.0001)] {
+            let deposit = Transaction {
+                tx_type: TransactionType::Deposit,
+                client: 1,
+                tx,
+                amount: Some(amount),
+                currency: None,
+                timestamp: None,
+            };
+            apply_transaction_with_config(&mut ledger, &deposit, &config);
+            let dispute = Transaction {
+                tx_type: TransactionType::Dispute,
+                amount: None,
+                ..deposit
+            };
+            apply_transaction_with_config(&mut ledger, &dispute, &config);
+            // Exactly 100 is not over the limit
+            assert_eq!(ledger.account().locked, tx == 3);
+        }
+    }
+
+    #[test]
+    fn test_multi_currency_chargeback_locks_every_currency() {
+        let config = EngineConfig {
+            multi_currency: true,
+            ..EngineConfig::default()
+        };
+        let usd = Some("USD".parse().unwrap());
+        let eur = Some("EUR".parse().unwrap());
+        let mut ledger = ClientLedger::new(1);
+        let transactions = [
+            (TransactionType::Deposit, 1, Some(10.0), usd),
+            (TransactionType::Deposit, 2, Some(4.0), eur),
+            (TransactionType::Dispute, 2, None, None),
+            (TransactionType::Chargeback, 2, None, None),
+        ];
+        for (tx_type, tx, amount, currency) in transactions {
+            let transaction = Transaction {
+                tx_type,
+                client: 1,
+                tx,
+                amount,
+                currency,
+                timestamp: None,
+            };
+            assert_eq!(
+                apply_transaction_with_config(&mut ledger, &transaction, &config),
+                ApplyOutcome::Applied
+            );
+        }
+
+        let [eur_account, usd_account] = ledger.currency_accounts() else {
+            panic!("expected two currencies");
+        };
+        assert_eq!((eur_account.total, eur_account.locked), (0.0, true));
+        assert_eq!((usd_account.total, usd_account.locked), (10.0, true));
+
+        let deposit = Transaction {
+            tx_type: TransactionType::Deposit,
+            client: 1,
+            tx: 3,
+            amount: Some(1.0),
+            currency: usd,
+            timestamp: None,
+        };
+        assert_eq!(
+            apply_transaction_with_config(&mut ledger, &deposit, &config),
+            ApplyOutcome::Rejected(RejectReason::AccountLocked)
+        );
+    }
+}
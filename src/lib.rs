@@ -1,7 +1,111 @@
+#![cfg_attr(feature = "legacy-fields", allow(deprecated))]
+
+/// Declares a struct whose fields are `pub(crate)`, read through accessor methods outside the
+/// crate; with the `legacy-fields` feature they stay `pub`, as deprecated, for one release
+macro_rules! sealed_fields {
+    (
+        $(#[$attr:meta])*
+        pub struct $name:ident {
+            $($(#[$field_attr:meta])* $field:ident: $ty:ty,)*
+        }
+    ) => {
+        #[cfg(feature = "legacy-fields")]
+        $(#[$attr])*
+        pub struct $name {
+            $(
+                $(#[$field_attr])*
+                #[deprecated(note = "read it through the accessor method of the same name")]
+                pub $field: $ty,
+            )*
+        }
+
+        #[cfg(not(feature = "legacy-fields"))]
+        $(#[$attr])*
+        pub struct $name {
+            $($(#[$field_attr])* pub(crate) $field: $ty,)*
+        }
+    };
+}
+
 pub mod account;
+mod affinity;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+#[cfg(feature = "async")]
+pub mod async_engine;
+pub mod binary;
+pub mod config;
+pub mod consume;
+pub mod currency;
+pub mod diff;
+pub mod dry_run;
+pub mod error;
+pub mod events;
+pub mod explain;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod in_memory;
+mod input;
+pub mod inspect;
+pub mod intern;
+pub mod journal;
+pub mod ledger;
+pub mod manifest;
 pub mod processor;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod redact;
+pub mod reject;
+mod reorder;
+pub mod selftest;
+pub mod shard;
+mod shared;
+pub mod snapshot;
+pub mod statement;
+pub mod stats;
+mod storage;
 pub mod transaction;
+pub mod verify;
 
-pub use account::ClientAccount;
-pub use processor::start_engine;
-pub use transaction::{Transaction, TransactionType};
+pub use account::{
+    AccountColumn, AccountError, ClientAccount, LockReason, MinorUnitsAccount, TransactionCounts,
+};
+#[cfg(feature = "arrow")]
+pub use arrow::{BatchOutcome, apply_record_batch, route_record_batches};
+#[cfg(feature = "async")]
+pub use async_engine::{process_reader_async, process_reader_async_with_config};
+pub use config::{
+    AccountCreationPolicy, AmountScale, DisputeFundsPolicy, EmptyOutputPolicy, EngineConfig,
+    InputFormat, LockedDepositPolicy, NumberFormat, RowLimitPolicy, StorageBackend,
+    WithdrawalDisputeModel,
+};
+pub use consume::{ConsumeConfig, MessageSource, PayloadFormat, SourceMessage, consume};
+pub use currency::Currency;
+pub use diff::{AccountDiff, diff_outputs};
+pub use dry_run::{DryRunReport, dry_run};
+pub use error::EngineError;
+pub use events::AccountEvent;
+pub use explain::ExplainStep;
+pub use in_memory::{PaymentEngine, process_csv_bytes, process_csv_bytes_with_config};
+pub use inspect::{InputSchema, inspect};
+pub use intern::ClientInterner;
+pub use journal::replay_journal;
+pub use ledger::{
+    ApplyOutcome, ClientLedger, DisputeState, IgnoreReason, apply_transaction,
+    apply_transaction_with_config,
+};
+pub use manifest::{Provenance, RunManifest};
+pub use processor::{
+    AccountStream, Engine, RunReport, process_file, process_file_with_report, process_transactions,
+    start_engine, start_engine_iter, start_engine_iter_with_config, start_engine_with_config,
+};
+pub use redact::{RedactKey, RedactReport, redact};
+pub use reject::{RejectHandler, RejectReason};
+pub use shard::{ModuloShard, ShardStrategy};
+pub use snapshot::SourceOffset;
+pub use statement::{ClientStatement, StatementEntry, StatementFormat, client_statement};
+#[cfg(feature = "peak-rss")]
+pub use stats::peak_rss_bytes;
+pub use stats::{DisputeAlert, EngineStats, HeldAlert, PhaseTimes, WorkerLoad};
+pub use transaction::{ClientId, Transaction, TransactionType, TxError};
+pub use verify::{Invariant, Violation, verify_ledgers};
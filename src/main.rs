@@ -1,17 +1,711 @@
-use payments_engine::start_engine;
+#[cfg(feature = "kafka")]
+use payments_engine::consume::{ConsumeConfig, start_consumer};
+use payments_engine::selftest::run_selftest;
+use payments_engine::snapshot::inspect_snapshot;
+use payments_engine::{
+    ClientId, EngineConfig, EngineError, InputFormat, RedactKey, StatementFormat, StorageBackend,
+    client_statement, diff_outputs, dry_run, inspect, redact, start_engine_with_config,
+};
+use std::collections::HashSet;
 use std::env;
+use std::path::Path;
 use std::process;
+use std::time::Duration;
+
+const USAGE: &str = "\
+Usage: payments_engine [OPTIONS] <transactions.csv | directory | https://...>
+       payments_engine --selftest
+       payments_engine --diff [--tolerance AMOUNT] <a.csv> <b.csv>
+       payments_engine inspect-snapshot <snapshot>
+       payments_engine consume [OPTIONS] --snapshot PATH <topic>
+       payments_engine ledger [OPTIONS] --client ID <transactions.csv>
+       payments_engine redact [OPTIONS] --key SECRET <transactions.csv> <redacted.csv>
+
+Options:
+  --selftest              Run the built-in scenarios in memory, report pass/fail and exit
+                          nonzero on any failure; takes no other arguments
+  --diff A B              Compare two account outputs by client and print every balance
+                          differing by more than --tolerance AMOUNT (default 0) and every
+                          locked flag differing; exits 2 on any difference
+  --http-retries N        Retries for transient HTTP(S) input failures (default 3)
+  --read-retries N        Retries of a read of the input that fails transiently, e.g. on a
+                          network filesystem (default 3)
+  --workers N             Number of worker threads (default one per CPU core)
+  --ordered               Apply transactions strictly in input order on one worker;
+                          the canonical result parallel runs must match
+  --deterministic         Identical stdout and stderr at any worker count, for diffing runs
+  --verbose               Print rows, clients and busy/idle time per worker to stderr
+  --profile               Print the number of stored transaction records and, built with
+                          the peak-rss feature, peak resident memory to stderr
+  --clients-per-worker    Print a histogram of how many clients each worker owned to stderr
+  --pin-workers           Pin each worker thread to a core of its own (affinity feature);
+                          skipped when there are fewer cores than threads
+  --pin-router            With --pin-workers, also pin the routing thread to the next core
+  --amount-scale SCALE    Output amounts as `decimal` (default) or `minor-units`
+  --columns LIST          Comma separated output columns and their order
+                          (default client,available,held,total,locked)
+  --include-clients IDS   Only process these clients: a comma separated list, or a file
+                          with one id per line; every other row, disputes included, is
+                          dropped before routing
+  --exclude-clients IDS   Never process these clients, given like --include-clients;
+                          cannot be combined with it
+  --string-client-ids     Accept arbitrary strings (e.g. UUIDs) in the client column
+  --account-creation POLICY
+                          Which transactions may create an account: implicit (default),
+                          deposits-only or seeded-only
+  --initial-balances PATH Starting accounts, in the output CSV format
+  --locked-deposits POLICY
+                          Deposits to locked accounts: reject (default), to-held or to-available
+  --allow-redispute       Allow a resolved dispute to be disputed again
+  --idempotent            Skip deposits and withdrawals whose tx id was already applied,
+                          e.g. in the state loaded by --resume
+  --dispute-funds POLICY  Disputes of partly spent deposits: allow-negative (default),
+                          reject-if-insufficient or partial-hold
+  --withdrawal-disputes MODEL
+                          Funds held by a withdrawal dispute: reverse-and-hold (default)
+                          or hold-from-available
+  --withdrawal-epsilon AMOUNT
+                          How far a withdrawal may exceed the available funds to absorb
+                          f64 noise, from 0 to below 0.0001 (default 0.00005)
+  --max-open-disputes N   Lock an account once it has N disputes open at the same time
+  --max-disputed-amount AMOUNT
+                          Lock an account once its open disputes add up to more than AMOUNT
+  --held-threshold AMOUNT Flag clients whose held funds ever go above AMOUNT as suspicious
+                          and list them on stderr
+  --reorder-window N      Apply each client's transactions in `timestamp` column order,
+                          holding back up to N per client to reorder them
+  --max-dispute-age N     Reject a dispute arriving more than N of the client's transactions
+                          after the one it disputes
+  --input-format FORMAT   Input as csv, binary fixed-width records or parquet (default
+                          parquet for a .parquet path, else csv)
+  --multi-currency        Keep balances per currency from the input's currency column and
+                          write one row per client and currency
+  --extended-output       Also write each client's applied deposits, withdrawals, disputes,
+                          resolves and chargebacks, and the amount in open disputes
+  --stream-shards         Write each worker's accounts as soon as it finishes; rows are then
+                          sorted per worker block only, not across the whole output
+  --delimiter CHAR        Input field delimiter, a single ASCII character or `tab` (default ,)
+  --number-format FORMAT  Input amounts as plain (default), comma-thousands (1,234.5)
+                          or european (1 234,5); the latter two need a non-comma delimiter
+  --explain CLIENT        Print every transaction of CLIENT and its effect to stderr
+  --empty-output POLICY   When no accounts are produced: ignore (default), warn or error
+  --manifest PATH         Write a JSON manifest of the run (run id, input SHA-256, engine
+                          version, options, row counts by type, worker count, UTC time)
+                          to PATH
+  --provenance-header     Write the run id, input SHA-256, engine version and options as
+                          `#` comment lines above the CSV header
+  --snapshot PATH         Write the final state, with the history disputes refer to, to PATH
+  --resume PATH           Start from the state in a snapshot written by --snapshot
+  --store DIR             Keep accounts and the history disputes refer to in a sled
+                          database in DIR, which the run starts from and writes every
+                          change to; refuses --resume and --snapshot (feature
+                          `sled-storage`)
+  --journal PATH          Write every applied transaction, in the order applied, to PATH
+                          as CSV that replays to the same accounts
+  --overlay PATH          After the input, process the corrections in PATH through the
+                          resulting state and output the accounts they lead to; --snapshot
+                          keeps the state from before them
+  --overlay-output PATH   With --overlay, write the accounts after the overlay to PATH and
+                          output the accounts from before it
+  --read-only             Audit mode: write nothing but the output, reading any --resume
+                          state without touching it; refuses --snapshot, --journal,
+                          --manifest and --overlay-output
+  --dry-run               Print what the run would apply, reject, lock and leave negative
+                          instead of the accounts, and write no snapshot; exits 2 if
+                          anything would be rejected or go negative
+  --inspect               Print the delimiter, columns and aliases found in the header and
+                          whether the first 10 amounts have at most 4 decimal places,
+                          without processing; exits 2 if a column is missing or an
+                          amount looks wrong
+  --verify                Check every account's invariants after settlement; the accounts
+                          are still written, but any violation is reported and exits 1
+  --verify-output         Read the written CSV back and check it against the accounts
+                          before it goes out; exits 1 on any difference
+  --read-buffer SIZE      Input read buffer, in bytes or with a K/M/G suffix (default 16M)
+  --channel-capacity N    Bound each worker channel to N queued messages (default unbounded)
+  --spill-limit N         Messages held back per worker while its channel is full (default 1024)
+  --batch-size N          Transactions grouped per worker message (default 256)
+  --hot-client-helpers N  Threads parsing the rows of a client making up half the input
+                          for its worker (default 0, off); CSV with numeric ids only
+  --max-bytes SIZE        Refuse an input file over SIZE bytes, K/M/G suffixes allowed
+  --max-rows N            Stop reading after N data rows, as if the input ended there
+  --row-limit POLICY      Past --max-rows: truncate (default) or error, failing the run
+  --stop-after-row N      Apply exactly the first N data rows (header not counted) and write
+                          the accounts as they stood after row N
+  --timeout SECS          Give up on input not read to the end after SECS seconds, exiting
+                          with status 124
+  --allow-partial         On --timeout, still write the accounts of the rows read in time
+
+Ledger options (every transaction of one client, with the balances after each):
+  --client ID             Client whose ledger to print
+  --format FORMAT         table (default), csv or json
+
+Consume options (built with the `kafka` feature):
+  --brokers LIST          Comma separated Kafka brokers (default localhost:9092)
+  --group-id ID           Consumer group to commit offsets for (default payments_engine)
+  --payload FORMAT        Message payloads as json (default) or csv rows without a header
+  --checkpoint-every N    Snapshot and commit offsets every N messages (default 10000)
+  --checkpoint-interval SECS
+                          Snapshot and commit at least every SECS seconds (default 5)
+  --idle-timeout SECS     Exit after SECS seconds without a message, writing the accounts
+                          to stdout (default: consume until stopped)";
+
+/// Exit status of a run stopped by `--timeout`, as used by coreutils `timeout`
+const TIMED_OUT_EXIT_CODE: i32 = 124;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <transactions.csv>", args[0]);
-        process::exit(1);
+
+    if args.len() == 2 && args[1] == "--selftest" {
+        process::exit(selftest());
+    }
+    if args.len() == 3 && args[1] == "inspect-snapshot" {
+        match inspect_snapshot(&args[2]) {
+            Ok(header) => println!("{}", header),
+            Err(e) => {
+                eprintln!("Error reading snapshot: {}", e);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if args.len() >= 2 && args[1] == "--diff" {
+        process::exit(diff(&args[2..]));
+    }
+    if args.len() >= 2 && args[1] == "consume" {
+        process::exit(consume(&args[2..]));
+    }
+    if args.len() >= 2 && args[1] == "ledger" {
+        process::exit(ledger(&args[2..]));
+    }
+    if args.len() >= 2 && args[1] == "redact" {
+        process::exit(redact_file(&args[2..]));
+    }
+
+    let (path, config) = match parse_args(&args[1..]) {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", USAGE);
+            process::exit(1);
+        }
+    };
+
+    if config.inspect {
+        match inspect(&path, &config) {
+            Ok(schema) => {
+                println!("{}", schema);
+                process::exit(if schema.is_clean() { 0 } else { 2 });
+            }
+            Err(e) => {
+                eprintln!("Error inspecting file: {}", e);
+                process::exit(1);
+            }
+        }
+    }
+    if config.dry_run {
+        match dry_run(&path, &config) {
+            Ok(report) => {
+                println!("{}", report);
+                process::exit(if report.is_clean() { 0 } else { 2 });
+            }
+            Err(e) => {
+                eprintln!("Error processing file: {}", e);
+                process::exit(1);
+            }
+        }
     }
-    let path = &args[1];
 
-    if let Err(e) = start_engine(path) {
+    if let Err(e) = start_engine_with_config(&path, &config) {
         eprintln!("Error processing file: {}", e);
-        process::exit(1);
+        process::exit(match e {
+            EngineError::TimedOut { .. } => TIMED_OUT_EXIT_CODE,
+            _ => 1,
+        });
     }
 }
+
+/// Print one line per built-in scenario and return the process exit code
+fn selftest() -> i32 {
+    let results = run_selftest();
+    let failed = results
+        .iter()
+        .filter(|result| result.outcome.is_err())
+        .count();
+    for result in &results {
+        match &result.outcome {
+            Ok(()) => println!("PASS {}", result.name),
+            Err(reason) => println!("FAIL {}: {}", result.name, reason),
+        }
+    }
+    println!("{} passed, {} failed", results.len() - failed, failed);
+    if failed == 0 { 0 } else { 1 }
+}
+
+/// Compare the two outputs named in `args` and return the process exit code
+fn diff(args: &[String]) -> i32 {
+    let (left, right, tolerance) = match parse_diff_args(args) {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+    match diff_outputs(&left, &right, tolerance) {
+        Ok(diffs) => {
+            for diff in &diffs {
+                println!("{}", diff);
+            }
+            println!("{} differences", diffs.len());
+            if diffs.is_empty() { 0 } else { 2 }
+        }
+        Err(e) => {
+            eprintln!("Error comparing outputs: {}", e);
+            1
+        }
+    }
+}
+
+/// Parse `--diff` arguments: two output paths and an optional `--tolerance`
+fn parse_diff_args(args: &[String]) -> Result<(String, String, f64), String> {
+    let mut tolerance = 0.0;
+    let mut paths = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--tolerance" => {
+                tolerance = flag_value(&mut iter, arg)?;
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    match <[String; 2]>::try_from(paths) {
+        Ok([left, right]) => Ok((left, right, tolerance)),
+        Err(_) => Err("--diff takes exactly two output files".to_string()),
+    }
+}
+
+/// Run `ledger` with the remaining arguments and return the process exit code
+fn ledger(args: &[String]) -> i32 {
+    let parsed = parse_ledger_args(args).and_then(|(client, format, rest)| {
+        let client = client.ok_or("ledger needs --client")?;
+        let (path, config) = parse_args(&rest)?;
+        Ok((client, format, path, config))
+    });
+    let (client, format, path, config) = match parsed {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+    let written = client_statement(&path, client, &config)
+        .and_then(|statement| statement.write(format, std::io::stdout().lock()));
+    match written {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error processing file: {}", e);
+            1
+        }
+    }
+}
+
+/// Write a redacted copy of a transaction file and return the process exit code: 0 when the
+/// copy reproduces the original's outcomes, 2 when it doesn't
+fn redact_file(args: &[String]) -> i32 {
+    let parsed = parse_redact_args(args).and_then(|(key, mut rest)| {
+        let key = key.ok_or("redact needs --key")?;
+        let output = rest
+            .pop()
+            .ok_or("redact needs an input and an output path")?;
+        let (input, config) = parse_args(&rest)?;
+        Ok((key, input, output, config))
+    });
+    let (key, input, output, config) = match parsed {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+    match redact(&input, &output, &RedactKey::new(&key), &config) {
+        Ok(report) => {
+            eprintln!("{}", report);
+            if report.is_clean() { 0 } else { 2 }
+        }
+        Err(e) => {
+            eprintln!("Error redacting file: {}", e);
+            1
+        }
+    }
+}
+
+/// Take `--key` out of `args`, returning the rest
+fn parse_redact_args(args: &[String]) -> Result<(Option<String>, Vec<String>), String> {
+    let mut key = None;
+    let mut rest = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--key" => key = Some(flag_value(&mut iter, arg)?),
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((key, rest))
+}
+
+/// Take the ledger flags out of `args`, returning the rest for `parse_args`
+fn parse_ledger_args(
+    args: &[String],
+) -> Result<(Option<ClientId>, StatementFormat, Vec<String>), String> {
+    let mut client = None;
+    let mut format = StatementFormat::default();
+    let mut rest = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--client" => {
+                client = Some(flag_value(&mut iter, arg)?);
+            }
+            "--format" => {
+                format = flag_value(&mut iter, arg)?;
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((client, format, rest))
+}
+
+/// Run `consume` with the remaining arguments and return the process exit code
+#[cfg(feature = "kafka")]
+fn consume(args: &[String]) -> i32 {
+    let parsed = parse_consume_args(args).and_then(|(consume, rest)| {
+        let (topic, config) = parse_args(&rest)?;
+        Ok((ConsumeConfig { topic, ..consume }, config))
+    });
+    let (consume, config) = match parsed {
+        Ok(parsed) => parsed,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            eprintln!("{}", USAGE);
+            return 1;
+        }
+    };
+    match start_consumer(&consume, &config) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("Error consuming {}: {}", consume.topic, e);
+            1
+        }
+    }
+}
+
+#[cfg(not(feature = "kafka"))]
+fn consume(_args: &[String]) -> i32 {
+    eprintln!("consume needs a build with the `kafka` feature");
+    1
+}
+
+/// Take the consume flags out of `args`, returning the rest for `parse_args`
+#[cfg(feature = "kafka")]
+fn parse_consume_args(args: &[String]) -> Result<(ConsumeConfig, Vec<String>), String> {
+    let mut consume = ConsumeConfig::default();
+    let mut rest = Vec::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--brokers" => {
+                consume.brokers = flag_value(&mut iter, arg)?;
+            }
+            "--group-id" => {
+                consume.group_id = flag_value(&mut iter, arg)?;
+            }
+            "--payload" => {
+                consume.payload = flag_value(&mut iter, arg)?;
+            }
+            "--checkpoint-every" => {
+                consume.checkpoint_every = flag_value(&mut iter, arg)?;
+            }
+            "--checkpoint-interval" => {
+                consume.checkpoint_interval = Duration::from_secs(flag_value(&mut iter, arg)?);
+            }
+            "--idle-timeout" => {
+                consume.idle_timeout = Some(Duration::from_secs(flag_value(&mut iter, arg)?));
+            }
+            _ => rest.push(arg.clone()),
+        }
+    }
+    Ok((consume, rest))
+}
+
+/// Parse command line flags followed by a single input path
+fn parse_args(args: &[String]) -> Result<(String, EngineConfig), String> {
+    let mut config = EngineConfig::default();
+    let mut path = None;
+    let mut input_format = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--http-retries" => {
+                config.http_retries = flag_value(&mut iter, arg)?;
+            }
+            "--read-retries" => {
+                config.read_retries = flag_value(&mut iter, arg)?;
+            }
+            "--workers" => {
+                config.workers = Some(flag_value(&mut iter, arg)?);
+            }
+            "--ordered" => {
+                config.ordered = true;
+            }
+            "--deterministic" => {
+                config.deterministic = true;
+            }
+            "--verbose" => {
+                config.verbose = true;
+            }
+            "--profile" => {
+                config.profile = true;
+            }
+            "--clients-per-worker" => {
+                config.clients_per_worker = true;
+            }
+            "--pin-workers" => {
+                config.pin_workers = true;
+            }
+            "--pin-router" => {
+                config.pin_router = true;
+            }
+            "--amount-scale" => {
+                config.amount_scale = flag_value(&mut iter, arg)?;
+            }
+            "--columns" => {
+                let value: String = flag_value(&mut iter, arg)?;
+                config.columns = value
+                    .split(',')
+                    .map(|name| name.trim().parse())
+                    .collect::<Result<_, _>>()?;
+            }
+            "--include-clients" => {
+                let value: String = flag_value(&mut iter, arg)?;
+                config.include_clients = Some(parse_client_list(&value, arg)?);
+            }
+            "--exclude-clients" => {
+                let value: String = flag_value(&mut iter, arg)?;
+                config.exclude_clients = parse_client_list(&value, arg)?;
+            }
+            "--string-client-ids" => {
+                config.string_client_ids = true;
+            }
+            "--account-creation" => {
+                config.account_creation = flag_value(&mut iter, arg)?;
+            }
+            "--initial-balances" => {
+                config.initial_balances = Some(flag_value(&mut iter, arg)?);
+            }
+            "--locked-deposits" => {
+                config.locked_deposits = flag_value(&mut iter, arg)?;
+            }
+            "--allow-redispute" => {
+                config.allow_redispute = true;
+            }
+            "--idempotent" => {
+                config.idempotent = true;
+            }
+            "--dispute-funds" => {
+                config.dispute_funds = flag_value(&mut iter, arg)?;
+            }
+            "--withdrawal-disputes" => {
+                config.withdrawal_disputes = flag_value(&mut iter, arg)?;
+            }
+            "--withdrawal-epsilon" => {
+                config.withdrawal_epsilon = flag_value(&mut iter, arg)?;
+            }
+            "--empty-output" => {
+                config.empty_output = flag_value(&mut iter, arg)?;
+            }
+            "--max-open-disputes" => {
+                config.max_open_disputes = Some(flag_value(&mut iter, arg)?);
+            }
+            "--max-disputed-amount" => {
+                config.max_disputed_amount = Some(flag_value(&mut iter, arg)?);
+            }
+            "--held-threshold" => {
+                config.held_threshold = Some(flag_value(&mut iter, arg)?);
+            }
+            "--reorder-window" => {
+                config.reorder_window = Some(flag_value(&mut iter, arg)?);
+            }
+            "--max-dispute-age" => {
+                config.max_dispute_age = Some(flag_value(&mut iter, arg)?);
+            }
+            "--multi-currency" => {
+                config.multi_currency = true;
+            }
+            "--extended-output" => {
+                config.extended_output = true;
+            }
+            "--stream-shards" => {
+                config.stream_shards = true;
+            }
+            "--input-format" => {
+                input_format = Some(flag_value(&mut iter, arg)?);
+            }
+            "--delimiter" => {
+                let value: String = flag_value(&mut iter, arg)?;
+                config.delimiter = parse_delimiter(&value)
+                    .ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?;
+            }
+            "--number-format" => {
+                config.number_format = flag_value(&mut iter, arg)?;
+            }
+            "--explain" => {
+                config.explain = Some(flag_value(&mut iter, arg)?);
+            }
+            "--manifest" => {
+                config.manifest = Some(flag_value(&mut iter, arg)?);
+            }
+            "--provenance-header" => {
+                config.provenance_header = true;
+            }
+            "--snapshot" => {
+                config.snapshot = Some(flag_value(&mut iter, arg)?);
+            }
+            "--journal" => {
+                config.journal = Some(flag_value(&mut iter, arg)?);
+            }
+            "--resume" => {
+                config.resume_from = Some(flag_value(&mut iter, arg)?);
+            }
+            "--store" => {
+                config.storage = StorageBackend::Sled(flag_value(&mut iter, arg)?);
+            }
+            "--overlay" => {
+                config.overlay = Some(flag_value(&mut iter, arg)?);
+            }
+            "--overlay-output" => {
+                config.overlay_output = Some(flag_value(&mut iter, arg)?);
+            }
+            "--read-only" => {
+                config.read_only = true;
+            }
+            "--dry-run" => {
+                config.dry_run = true;
+            }
+            "--inspect" => {
+                config.inspect = true;
+            }
+            "--verify" => {
+                config.verify = true;
+            }
+            "--verify-output" => {
+                config.verify_output = true;
+            }
+            "--read-buffer" => {
+                let value: String = flag_value(&mut iter, arg)?;
+                config.read_buffer_size = parse_byte_size(&value)
+                    .ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?;
+            }
+            "--channel-capacity" => {
+                config.channel_capacity = Some(flag_value(&mut iter, arg)?);
+            }
+            "--spill-limit" => {
+                config.spill_limit = flag_value(&mut iter, arg)?;
+            }
+            "--batch-size" => {
+                config.batch_size = flag_value(&mut iter, arg)?;
+            }
+            "--hot-client-helpers" => {
+                config.hot_client_helpers = flag_value(&mut iter, arg)?;
+            }
+            "--max-bytes" => {
+                let value: String = flag_value(&mut iter, arg)?;
+                let size = parse_byte_size(&value)
+                    .ok_or_else(|| format!("Invalid value for {}: {}", arg, value))?;
+                config.max_bytes = Some(size as u64);
+            }
+            "--max-rows" => {
+                config.max_rows = Some(flag_value(&mut iter, arg)?);
+            }
+            "--stop-after-row" => {
+                config.stop_after_row = Some(flag_value(&mut iter, arg)?);
+            }
+            "--row-limit" => {
+                config.row_limit = flag_value(&mut iter, arg)?;
+            }
+            "--timeout" => {
+                config.timeout = Some(Duration::from_secs(flag_value(&mut iter, arg)?));
+            }
+            "--allow-partial" => {
+                config.allow_partial = true;
+            }
+            _ if arg.starts_with("--") => return Err(format!("Unknown option {}", arg)),
+            _ if path.is_none() => path = Some(arg.clone()),
+            _ => return Err("Only one input path may be given".to_string()),
+        }
+    }
+
+    let path: String = path.ok_or("Missing input path")?;
+    config.input_format = input_format.unwrap_or_else(|| InputFormat::for_path(&path));
+    Ok((path, config))
+}
+
+/// Parse the value following a flag
+fn flag_value<'a, T: std::str::FromStr>(
+    iter: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<T, String> {
+    let value = iter
+        .next()
+        .ok_or_else(|| format!("{} requires a value", flag))?;
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+}
+
+/// Parse client ids given as a comma-separated list or, when `value` names a file, one per
+/// line of that file; blank lines are skipped
+fn parse_client_list(value: &str, flag: &str) -> Result<HashSet<ClientId>, String> {
+    let ids = if Path::new(value).is_file() {
+        std::fs::read_to_string(value)
+            .map_err(|e| format!("Cannot read {} file {}: {}", flag, value, e))?
+    } else {
+        value.replace(',', "\n")
+    };
+    ids.lines()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| {
+            id.parse()
+                .map_err(|_| format!("Invalid client id for {}: {}", flag, id))
+        })
+        .collect()
+}
+
+/// Parse a single-byte field delimiter, accepting `tab` for `\t`
+fn parse_delimiter(value: &str) -> Option<u8> {
+    match value {
+        "tab" | "\\t" => Some(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Some(value.as_bytes()[0]),
+        _ => None,
+    }
+}
+
+/// Parse a byte count with an optional binary K/M/G suffix, e.g. `4K` or `16M`
+fn parse_byte_size(value: &str) -> Option<usize> {
+    let (digits, multiplier) = match value.chars().last()?.to_ascii_uppercase() {
+        'K' => (&value[..value.len() - 1], 1024),
+        'M' => (&value[..value.len() - 1], 1024 * 1024),
+        'G' => (&value[..value.len() - 1], 1024 * 1024 * 1024),
+        _ => (value, 1),
+    };
+    digits.parse::<usize>().ok()?.checked_mul(multiplier)
+}
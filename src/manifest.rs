@@ -0,0 +1,271 @@
+use crate::{EngineConfig, EngineError, EngineStats};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Where a run's output came from, for tracing numbers back to the run that produced them
+/// Written into the `--manifest` JSON and the `--provenance-header` block, and returned in
+/// `RunReport::provenance`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Provenance {
+    /// Unique to the run: its UTC start time and a hash of the time, process, input digest
+    /// and options, e.g. `20261016T093000Z-3f2a9c01b7de`
+    pub run_id: String,
+    /// Input path or URL as given
+    pub input: String,
+    /// Hex SHA-256 of the input bytes as read (after any HTTP content decoding)
+    pub sha256: String,
+    pub engine_version: &'static str,
+    /// The options that decide the accounts and how they are written, keyed by
+    /// `EngineConfig` field; policies by variant name, unset options as an empty string
+    pub config: BTreeMap<&'static str, String>,
+}
+
+impl Provenance {
+    pub(crate) fn new(input: &str, sha256: String, config: &EngineConfig) -> Self {
+        let now = SystemTime::now();
+        let config = effective_options(config);
+        let mut hasher = Sha256::new();
+        let nanos = now
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        hasher.update(nanos.to_le_bytes());
+        hasher.update(std::process::id().to_le_bytes());
+        hasher.update(&sha256);
+        for (option, value) in &config {
+            hasher.update(format!("{}={};", option, value));
+        }
+        let hash = hex(&hasher.finalize());
+        let started = utc_timestamp(now).replace(['-', ':'], "");
+
+        Self {
+            run_id: format!("{}-{}", started, &hash[..12]),
+            input: input.to_string(),
+            sha256,
+            engine_version: env!("CARGO_PKG_VERSION"),
+            config,
+        }
+    }
+}
+
+/// The `--provenance-header` block: one `# key: value` line per field, the options on one line
+/// as `option=value` pairs
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# run_id: {}", self.run_id)?;
+        writeln!(f, "# input: {}", self.input)?;
+        writeln!(f, "# sha256: {}", self.sha256)?;
+        writeln!(f, "# engine_version: {}", self.engine_version)?;
+        let options: Vec<String> = self
+            .config
+            .iter()
+            .map(|(option, value)| format!("{}={}", option, value))
+            .collect();
+        writeln!(f, "# config: {}", options.join(" "))
+    }
+}
+
+/// `config`'s options that affect the accounts or the output
+fn effective_options(config: &EngineConfig) -> BTreeMap<&'static str, String> {
+    fn shown<T: fmt::Display>(value: Option<T>) -> String {
+        value.map(|value| value.to_string()).unwrap_or_default()
+    }
+    fn clients<'a>(clients: impl IntoIterator<Item = &'a crate::ClientId>) -> String {
+        let mut clients: Vec<_> = clients.into_iter().collect();
+        clients.sort_unstable();
+        let clients: Vec<String> = clients.iter().map(ToString::to_string).collect();
+        clients.join(",")
+    }
+    let columns: Vec<&str> = config
+        .output_columns()
+        .iter()
+        .map(|column| column.name())
+        .collect();
+
+    BTreeMap::from([
+        ("workers", config.worker_count().to_string()),
+        ("ordered", config.ordered.to_string()),
+        ("shard_strategy", format!("{:?}", config.shard_strategy)),
+        ("amount_scale", format!("{:?}", config.amount_scale)),
+        ("columns", columns.join(",")),
+        ("string_client_ids", config.string_client_ids.to_string()),
+        ("account_creation", format!("{:?}", config.account_creation)),
+        ("initial_balances", shown(config.initial_balances.as_ref())),
+        ("overlay", shown(config.overlay.as_ref())),
+        ("locked_deposits", format!("{:?}", config.locked_deposits)),
+        ("allow_redispute", config.allow_redispute.to_string()),
+        ("idempotent", config.idempotent.to_string()),
+        ("dispute_funds", format!("{:?}", config.dispute_funds)),
+        ("withdrawal_epsilon", config.withdrawal_epsilon.to_string()),
+        (
+            "withdrawal_disputes",
+            format!("{:?}", config.withdrawal_disputes),
+        ),
+        ("multi_currency", config.multi_currency.to_string()),
+        ("max_open_disputes", shown(config.max_open_disputes)),
+        ("max_disputed_amount", shown(config.max_disputed_amount)),
+        ("max_dispute_age", shown(config.max_dispute_age)),
+        ("reorder_window", shown(config.reorder_window)),
+        (
+            "include_clients",
+            shown(config.include_clients.as_ref().map(clients)),
+        ),
+        ("exclude_clients", clients(&config.exclude_clients)),
+        ("resume_from", shown(config.resume_from.as_ref())),
+        ("storage", format!("{:?}", config.storage)),
+        ("input_format", format!("{:?}", config.input_format)),
+        (
+            "delimiter",
+            (config.delimiter as char).escape_default().to_string(),
+        ),
+        ("number_format", format!("{:?}", config.number_format)),
+        ("max_rows", shown(config.max_rows)),
+        ("row_limit", format!("{:?}", config.row_limit)),
+        ("stop_after_row", shown(config.stop_after_row)),
+        (
+            "timeout",
+            shown(config.timeout.map(|timeout| format!("{:?}", timeout))),
+        ),
+        ("allow_partial", config.allow_partial.to_string()),
+    ])
+}
+
+/// Audit record of a single run, written as JSON by `--manifest`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunManifest {
+    /// Run id, input, SHA-256, engine version and options
+    #[serde(flatten)]
+    pub provenance: Provenance,
+    pub rows: u64,
+    /// Rows per transaction type, keyed by type name
+    pub transaction_types: BTreeMap<&'static str, u64>,
+    pub workers: usize,
+    /// UTC time the manifest was written, RFC 3339
+    pub timestamp: String,
+}
+
+impl RunManifest {
+    pub(crate) fn new(provenance: Provenance, stats: &EngineStats, workers: usize) -> Self {
+        Self {
+            provenance,
+            rows: stats.rows,
+            transaction_types: stats.transaction_types.clone(),
+            workers,
+            timestamp: utc_timestamp(SystemTime::now()),
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), EngineError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self).map_err(io::Error::from)?;
+        writeln!(writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Running SHA-256 of everything read through a `HashingReader`
+pub(crate) type InputDigest = Rc<RefCell<Sha256>>;
+
+/// Reader that hashes bytes as the CSV reader pulls them, so hashing needs no second pass
+pub(crate) struct HashingReader {
+    inner: Box<dyn Read>,
+    digest: InputDigest,
+}
+
+impl HashingReader {
+    pub(crate) fn wrap(inner: Box<dyn Read>) -> (Self, InputDigest) {
+        let digest = InputDigest::default();
+        let reader = Self {
+            inner,
+            digest: Rc::clone(&digest),
+        };
+        (reader, digest)
+    }
+}
+
+impl Read for HashingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.digest.borrow_mut().update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Hex encoding of the digest so far
+pub(crate) fn hex_digest(digest: &InputDigest) -> String {
+    hex(&digest.borrow().clone().finalize())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Format a time as `YYYY-MM-DDTHH:MM:SSZ`
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+    let (days, secs_of_day) = (secs / 86_400, secs % 86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's days_from_civil inverse)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_utc_timestamp() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            utc_timestamp(UNIX_EPOCH + Duration::from_secs(951_827_696)),
+            "2000-02-29T12:34:56Z"
+        );
+        assert_eq!(
+            utc_timestamp(UNIX_EPOCH + Duration::from_secs(1_790_812_799)),
+            "2026-09-30T23:59:59Z"
+        );
+    }
+
+    #[test]
+    fn test_hashing_reader() {
+        let (mut reader, digest) = HashingReader::wrap(Box::new(&b"abc"[..]));
+        let mut content = String::new();
+        reader.read_to_string(&mut content).unwrap();
+
+        assert_eq!(content, "abc");
+        assert_eq!(
+            hex_digest(&digest),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
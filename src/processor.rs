@@ -1,300 +1,2647 @@
-use crate::{ClientAccount, Transaction, TransactionType};
-use csv::{ReaderBuilder, Writer};
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs::File;
-use std::io::BufReader;
-use std::sync::mpsc::{Sender, channel};
+use crate::account::format_decimal;
+use crate::affinity;
+use crate::binary::binary_transactions;
+use crate::error::empty_output_message;
+use crate::events::{Baseline, publish};
+use crate::input::{
+    CountingReader, InputBytes, open_transaction_input, parquet_transactions, read_initial_balances,
+};
+use crate::intern::{ClientInterner, StringClientRecord};
+use crate::journal::{JournalPart, write_journal};
+use crate::ledger::{
+    ApplyOutcome, ClientLedger, FastHashMap, FastHashSet, IgnoreReason, apply_normalized,
+};
+use crate::manifest::{HashingReader, InputDigest, Provenance, RunManifest, hex_digest};
+use crate::reorder::ReorderBuffer;
+use crate::shared::{LedgerShard, SharedLedgers};
+use crate::snapshot::{read_snapshot, write_snapshot};
+use crate::stats::per_second;
+use crate::storage::{WorkerStore, worker_stores};
+use crate::transaction::is_column;
+use crate::verify::{check_written_output, verify_ledgers};
+use crate::{
+    AccountColumn, ClientAccount, ClientId, DisputeAlert, EmptyOutputPolicy, EngineConfig,
+    EngineError, EngineStats, ExplainStep, HeldAlert, InputFormat, LockReason, LockedDepositPolicy,
+    NumberFormat, RejectReason, RowLimitPolicy, Transaction, TransactionType, WorkerLoad,
+};
+use crossbeam_channel::{
+    Receiver, Select, Sender, TryRecvError, TrySendError, bounded, never, unbounded,
+};
+use csv::{Reader, ReaderBuilder, StringRecord, Writer};
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, VecDeque};
+use std::fs;
+use std::io::{BufReader, Read, Write};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 //Type aliases to simplify complex types and make clippy happy
-type WorkerHandle = thread::JoinHandle<HashMap<u16, ClientState>>;
-type WorkerPool = (Vec<WorkerHandle>, Vec<Sender<WorkerMessage>>);
+type WorkerHandle = thread::JoinHandle<(Vec<ClientLedger>, WorkerState)>;
+/// Workers, their channels, and the ids of workers as they finish
+type WorkerPool = (
+    Vec<WorkerHandle>,
+    Vec<WorkerSender>,
+    Vec<ControlSender>,
+    Receiver<usize>,
+);
+pub(crate) type TransactionIter = Box<dyn Iterator<Item = Result<Transaction, EngineError>>>;
+/// Receives each worker's sorted shard as soon as the worker finishes (`stream_shards`)
+type ShardSink<'a> = &'a mut dyn FnMut(&[ClientLedger]) -> Result<(), EngineError>;
 
-/// Transaction record stored for dispute handling
-#[derive(Debug, Clone)]
-struct TransactionRecord {
-    amount: f64,
-    disputed: bool,
-    is_deposit: bool, //track whether this was a deposit or withdrawal
+/// Message sent to worker threads
+enum WorkerMessage {
+    /// Starting account and history, sent before any transaction
+    Seed(ClientLedger),
+    Transaction(Transaction),
+    Batch(Vec<Transaction>),
+    /// Rows of the hot client parsed by a helper thread, numbered in input order; helpers
+    /// finish out of order, so the worker applies each row once every row before it has been
+    /// (`EngineConfig::hot_client_helpers`)
+    Sequenced(Vec<(u64, Transaction)>),
+    /// Reply with every client state and start over with none, queued in order with input
+    /// so the reply covers everything sent before it
+    Drain(Sender<Vec<ClientLedger>>),
+    /// Reply with a copy of every client state, queued in order with input like `Drain`
+    Collect(Sender<Vec<ClientLedger>>),
+    Shutdown,
+}
+
+/// Sending half of a worker channel, bounded when `EngineConfig::channel_capacity` is set
+/// crossbeam rather than std `mpsc`: it is markedly cheaper under contention (see the
+/// `worker_channels` bench) and a worker can `select!` over it alongside other channels
+#[derive(Clone)]
+struct WorkerSender {
+    channel: Sender<WorkerMessage>,
+    /// Shared with the receiving worker, which reports it in its `WorkerLoad`
+    pressure: Arc<ChannelPressure>,
+}
+
+/// Sends to one worker that found its channel full, and the time spent waiting on them
+/// Only written on the blocking path, so a send that goes straight through costs nothing more
+#[derive(Default)]
+struct ChannelPressure {
+    blocked_sends: AtomicU64,
+    blocked_nanos: AtomicU64,
+}
+
+impl ChannelPressure {
+    fn record(&self, blocked: Duration) {
+        self.blocked_sends.fetch_add(1, Ordering::Relaxed);
+        self.blocked_nanos
+            .fetch_add(blocked.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    /// Copy the counters into a worker's load; the router is done sending by then
+    fn report(&self, load: &mut WorkerLoad) {
+        load.blocked_sends = self.blocked_sends.load(Ordering::Relaxed);
+        load.blocked = Duration::from_nanos(self.blocked_nanos.load(Ordering::Relaxed));
+    }
+}
+
+/// Transactions between samples of a worker's queue depth when they arrive one per message;
+/// batches are sampled every time
+const DEPTH_SAMPLE_INTERVAL: u64 = 256;
+
+/// Runtime command for a worker, served ahead of any queued transactions
+/// Replies go over a one-shot channel created by the caller
+// Jumping the queue is why `Engine` doesn't use `Flush`: its accounts must cover everything
+// submitted before the call, so it queues `WorkerMessage::Collect` and `Drain` behind the
+// input instead. Only tests send the commands besides `Abort` so far
+enum ControlMessage {
+    /// Reply with a snapshot of the worker's accounts, sorted by client
+    #[cfg_attr(not(test), allow(dead_code))]
+    Flush(Sender<Vec<ClientAccount>>),
+    /// Reply with the worker's counters so far
+    #[cfg_attr(not(test), allow(dead_code))]
+    Stats(Sender<EngineStats>),
+    /// Stop taking transactions until `Resume`; commands are still served while paused
+    #[cfg_attr(not(test), allow(dead_code))]
+    Pause,
+    #[cfg_attr(not(test), allow(dead_code))]
+    Resume,
+    /// Exit right away, leaving any queued transactions unprocessed
+    Abort,
+}
+
+/// Sending half of a worker's control channel, which is always unbounded
+type ControlSender = Sender<ControlMessage>;
+
+/// Everything a worker builds up while processing its clients
+#[derive(Default)]
+pub(crate) struct WorkerState {
+    /// This worker's clients, each behind its own lock so `Engine::transfer` can reach them
+    pub(crate) client_states: LedgerShard,
+    pub(crate) stats: EngineStats,
+    /// Rows and idle time so far; clients and busy time are filled in at shutdown
+    load: WorkerLoad,
+    /// Steps of the `EngineConfig::explain` client, if this worker owns it
+    trace: Vec<ExplainStep>,
+    /// Transactions held back by `EngineConfig::reorder_window`
+    reorder: ReorderBuffer,
+    /// Clients already listed in `EngineStats::suspicious`
+    flagged: FastHashSet<ClientId>,
+    /// Where applied transactions are appended, in order, when `EngineConfig::journal` is set
+    journal: Option<JournalPart>,
+    /// Where the worker's clients are written through to when `EngineConfig::storage` is set
+    pub(crate) store: Option<WorkerStore>,
+    /// `WorkerMessage::Sequenced` rows that arrived ahead of `next_sequence`
+    sequenced: BTreeMap<u64, Transaction>,
+    next_sequence: u64,
+}
+
+/// Worker shards plus router-side results of a run, before output is written
+struct EngineRun {
+    shards: Vec<Vec<ClientLedger>>,
+    stats: EngineStats,
+    client_names: Option<Vec<String>>,
+    trace: Vec<ExplainStep>,
+    /// Parts of the `EngineConfig::journal`, one per worker
+    journal: Vec<JournalPart>,
+    /// Parts written while applying the `EngineConfig::overlay`, joined after `journal`
+    overlay_journal: Vec<JournalPart>,
+    /// Shards as they stood before the overlay, kept for `EngineConfig::overlay_output`
+    pre_overlay: Option<Vec<Vec<ClientLedger>>>,
+    provenance: Option<Provenance>,
+}
+
+/// Final accounts of a run together with its statistics
+#[derive(Debug)]
+pub struct RunReport {
+    pub accounts: HashMap<ClientId, ClientAccount>,
+    /// With `EngineConfig::multi_currency`, one account per client and currency, sorted by
+    /// client then currency; `accounts` then only carries each client's lock
+    pub currency_accounts: Vec<ClientAccount>,
+    pub stats: EngineStats,
+    /// Original identifiers indexed by `ClientId` when `string_client_ids` is enabled
+    pub client_names: Option<Vec<String>>,
+    /// How deposits to locked accounts were handled during the run
+    pub locked_deposits: LockedDepositPolicy,
+    /// Every transaction of the `EngineConfig::explain` client, in order
+    pub explain: Vec<ExplainStep>,
+    /// Where the accounts came from, with `EngineConfig::provenance`, `provenance_header` or
+    /// `manifest`
+    pub provenance: Option<Provenance>,
+}
+
+/// Process CSV file with worker thread pool
+/// Each client is consistently routed to the same worker thread
+pub fn start_engine(path: &str) -> Result<(), EngineError> {
+    start_engine_with_config(path, &EngineConfig::default())
+}
+
+/// Same as `start_engine` with explicit runtime options
+/// `path` may be a local file or, with the `http-input` feature, an HTTP(S) URL
+/// A run cut short by `timeout` with `allow_partial` writes its accounts and then returns
+/// `EngineError::TimedOut`
+/// Previews and inspections are run with `dry_run` and `inspect`, which return their reports;
+/// a config asking for either is refused here rather than processed for real
+pub fn start_engine_with_config(path: &str, config: &EngineConfig) -> Result<(), EngineError> {
+    if config.dry_run || config.inspect {
+        return Err(EngineError::InvalidConfig(
+            "dry runs and inspection are run with dry_run and inspect".to_string(),
+        ));
+    }
+    let mut run = if config.stream_shards {
+        run_engine_streaming(path, config)?
+    } else {
+        run_engine(path, config, None)?
+    };
+    let violations = if config.verify {
+        verify_ledgers(run.shards.iter().flatten())
+    } else {
+        Vec::new()
+    };
+
+    // Write output, merging the per-worker sorted shards as rows are written
+    // With an overlay output, stdout keeps the accounts from before the overlay
+    if !config.stream_shards {
+        let writing = Instant::now();
+        if let (true, Some(provenance)) = (config.provenance_header, &run.provenance) {
+            print!("{}", provenance);
+        }
+        let shards = match (&config.overlay_output, run.pre_overlay.take()) {
+            (Some(path), Some(pre_overlay)) => {
+                write_output(
+                    run.shards,
+                    config,
+                    run.client_names.as_deref(),
+                    fs::File::create(path)?,
+                )?;
+                pre_overlay
+            }
+            _ => run.shards,
+        };
+        write_output(
+            shards,
+            config,
+            run.client_names.as_deref(),
+            std::io::stdout(),
+        )?;
+        run.stats.phases.write = writing.elapsed();
+        run.stats.phases.elapsed += run.stats.phases.write;
+    }
+
+    if config.string_client_ids {
+        eprintln!(
+            "Interned {} client identifiers (~{} bytes)",
+            run.stats.interned_clients, run.stats.intern_table_bytes
+        );
+    }
+    if config.verbose {
+        eprintln!("Locked deposit policy: {}", config.locked_deposits);
+    }
+    if config.read_only {
+        eprintln!("Read-only run: no snapshot, journal or manifest written");
+    }
+    if let Some(client) = config.explain {
+        eprintln!(
+            "Explain client {} ({} transactions):",
+            client,
+            run.trace.len()
+        );
+        for step in &run.trace {
+            eprintln!("  {}", step);
+        }
+    }
+    if config.verbose {
+        for (code, count) in &run.stats.rejected {
+            eprintln!("Rejected {} transactions: {}", count, code);
+        }
+    }
+    if let Some(overlay) = &run.stats.overlay {
+        eprintln!(
+            "Overlay: {} rows, {} applied",
+            overlay.rows, overlay.applied
+        );
+        for (code, count) in &overlay.rejected {
+            eprintln!("Overlay rejected {} transactions: {}", count, code);
+        }
+    }
+    let mut alerts: Vec<_> = run.stats.alerts.iter().collect();
+    alerts.sort_unstable_by_key(|alert| alert.client);
+    for alert in alerts {
+        eprintln!(
+            "Locked client {} at tx {}: {} open disputes totalling {} (dispute threshold)",
+            alert.client,
+            alert.tx,
+            alert.open_disputes,
+            format_decimal(alert.disputed_amount)
+        );
+    }
+    if let Some(threshold) = config.held_threshold
+        && !run.stats.suspicious.is_empty()
+    {
+        let clients: Vec<String> = run
+            .stats
+            .suspicious_clients()
+            .iter()
+            .map(ClientId::to_string)
+            .collect();
+        eprintln!(
+            "Suspicious clients, held funds above {}: {}",
+            format_decimal(threshold),
+            clients.join(", ")
+        );
+    }
+    if !run.stats.overflowed.is_empty() {
+        let mut clients = run.stats.overflowed.clone();
+        clients.sort_unstable();
+        let clients: Vec<String> = clients.iter().map(ClientId::to_string).collect();
+        eprintln!(
+            "Warning: transactions rejected for overflowing the balances of clients: {}",
+            clients.join(", ")
+        );
+    }
+    if run.stats.late > 0 {
+        eprintln!(
+            "Warning: {} transactions arrived further out of timestamp order than \
+             --reorder-window allows and were applied late",
+            run.stats.late
+        );
+    }
+    if run.stats.row_limit_reached {
+        eprintln!(
+            "Warning: stopped reading the input at the max_rows limit of {} rows; any later \
+             rows were not applied",
+            run.stats.rows
+        );
+    }
+    if run.stats.filtered > 0 {
+        eprintln!(
+            "Skipped {} transactions of filtered-out clients",
+            run.stats.filtered
+        );
+    }
+    if config.verbose && run.stats.already_applied > 0 {
+        eprintln!(
+            "Skipped {} deposits and withdrawals already applied",
+            run.stats.already_applied
+        );
+    }
+    if config.verbose && run.stats.orphan_disputes > 0 {
+        eprintln!(
+            "Ignored {} disputes, resolves and chargebacks of unknown transactions",
+            run.stats.orphan_disputes
+        );
+    }
+    if config.verbose && !config.deterministic {
+        print_worker_loads(&run.stats);
+        print_throughput(&run.stats);
+    }
+    if config.clients_per_worker {
+        eprint!("{}", run.stats.client_histogram());
+    }
+    if config.profile {
+        eprintln!("Stored transaction records: {}", run.stats.stored_records());
+        // Depends on timing and the allocator, so deterministic runs leave it out
+        #[cfg(feature = "peak-rss")]
+        if !config.deterministic {
+            match crate::stats::peak_rss_bytes() {
+                Some(bytes) => {
+                    eprintln!("Peak resident memory: {:.1} MiB", bytes as f64 / 1048576.0)
+                }
+                None => eprintln!("Peak resident memory: unavailable on this platform"),
+            }
+        }
+    }
+    // Timing dependent, so deterministic runs leave it out
+    if let (Some((worker_id, share)), false) = (run.stats.blocked_skew(), config.deterministic) {
+        eprintln!(
+            "Warning: {:.0}% of the time routing was blocked went to worker {} ({} ms); its \
+             clients may be skewed, see --verbose",
+            share * 100.0,
+            worker_id,
+            run.stats.per_worker[worker_id].blocked.as_millis()
+        );
+    }
+
+    // The accounts are out for debugging; the run still fails
+    if !violations.is_empty() {
+        for violation in &violations {
+            eprintln!("Verification failed for {}", violation);
+        }
+        return Err(EngineError::Inconsistent(violations));
+    }
+
+    // The accounts of the rows read in time are out; the run still counts as timed out
+    if let (true, Some(timeout)) = (run.stats.timed_out, config.timeout) {
+        eprintln!(
+            "Warning: wrote the accounts of the {} rows read before the timeout",
+            run.stats.rows
+        );
+        return Err(EngineError::TimedOut { timeout });
+    }
+    Ok(())
+}
+
+/// Per-worker load table for `EngineConfig::verbose`
+fn print_worker_loads(stats: &EngineStats) {
+    let total_rows = stats
+        .per_worker
+        .iter()
+        .map(|load| load.rows)
+        .sum::<u64>()
+        .max(1);
+    eprintln!("Worker load:");
+    eprintln!(
+        "  {:>6} {:>12} {:>7} {:>9} {:>10} {:>10} {:>10} {:>10} {:>9}",
+        "worker",
+        "rows",
+        "share",
+        "clients",
+        "busy_ms",
+        "idle_ms",
+        "blocked",
+        "blocked_ms",
+        "max_depth"
+    );
+    for (worker_id, load) in stats.per_worker.iter().enumerate() {
+        eprintln!(
+            "  {:>6} {:>12} {:>6.1}% {:>9} {:>10} {:>10} {:>10} {:>10} {:>9}",
+            worker_id,
+            load.rows,
+            load.rows as f64 * 100.0 / total_rows as f64,
+            load.clients,
+            load.busy.as_millis(),
+            load.idle.as_millis(),
+            load.blocked_sends,
+            load.blocked.as_millis(),
+            load.max_queue_depth
+        );
+    }
+}
+
+/// Phase timings and throughput for `EngineConfig::verbose`
+fn print_throughput(stats: &EngineStats) {
+    let phases = &stats.phases;
+    let line = |phase: &str, elapsed: Duration| {
+        eprintln!(
+            "  {:<11} {:>10} ms {:>14.0} rows/s",
+            phase,
+            elapsed.as_millis(),
+            per_second(stats.rows, elapsed)
+        );
+    };
+    eprintln!(
+        "Throughput ({} rows, {} input bytes):",
+        stats.rows, stats.input_bytes
+    );
+    line("parse+route", phases.route);
+    line("settle", phases.settle);
+    line("write", phases.write);
+    line("overall", phases.elapsed);
+    eprintln!(
+        "  {:<11} {:>10} ms {:>14.1} MiB/s",
+        "input",
+        phases.elapsed.as_millis(),
+        stats.input_bytes_per_second() / 1048576.0
+    );
+}
+
+/// Process the input and return the final account of every client instead of writing CSV
+pub fn process_file(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<HashMap<ClientId, ClientAccount>, EngineError> {
+    Ok(process_file_with_report(path, config)?.accounts)
+}
+
+/// Like `process_file`, also returning run statistics and any interned client identifiers
+pub fn process_file_with_report(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<RunReport, EngineError> {
+    let run = run_engine(path, config, None)?;
+
+    let mut currency_accounts: Vec<ClientAccount> = run
+        .shards
+        .iter()
+        .flatten()
+        .flat_map(|state| state.currency_accounts().iter().cloned())
+        .collect();
+    currency_accounts.sort_unstable_by_key(|account| (account.client, account.currency));
+
+    Ok(RunReport {
+        accounts: into_accounts(run.shards),
+        currency_accounts,
+        stats: run.stats,
+        client_names: run.client_names,
+        locked_deposits: config.locked_deposits,
+        explain: run.trace,
+        provenance: run.provenance,
+    })
+}
+
+/// Process already-parsed transactions through the same worker pool as `process_file`
+/// Transactions for each client are applied in iteration order
+pub fn process_transactions<I>(
+    transactions: I,
+    config: &EngineConfig,
+) -> Result<HashMap<ClientId, ClientAccount>, EngineError>
+where
+    I: IntoIterator<Item = Transaction>,
+{
+    config.validate()?;
+    if config.overlay.is_some() {
+        return Err(EngineError::InvalidConfig(
+            "an overlay is a file applied after the input file; use process_file_with_report"
+                .to_string(),
+        ));
+    }
+    let num_workers = config.worker_count();
+
+    let run = process_stream(transactions.into_iter().map(Ok), num_workers, config)?;
+    check_empty_output(has_accounts(&run), run.stats.rows, config)?;
+
+    Ok(into_accounts(run.shards))
+}
+
+/// Accounts buffered per worker ahead of an `AccountStream` consumer
+const ACCOUNT_STREAM_CAPACITY: usize = 1024;
+
+/// Final accounts of a run, streamed from the workers in ascending client id order
+///
+/// Each worker sorts its own clients and sends them over a bounded channel once the input is
+/// exhausted; the stream K-way merges those channels, so the whole output is globally sorted
+/// and never materialized as one collection. Dropping the stream early is fine: workers
+/// notice the closed channels, discard their remaining accounts and are joined before `drop`
+/// returns.
+pub struct AccountStream {
+    // Declared before `engine` so the output channels close before the workers are joined
+    merge: ShardMerge<crossbeam_channel::IntoIter<ClientAccount>>,
+    engine: EngineHandle,
+}
+
+impl Iterator for AccountStream {
+    type Item = ClientAccount;
+
+    /// Re-raises a worker panic once the stream is exhausted, rather than ending it short
+    fn next(&mut self) -> Option<ClientAccount> {
+        let account = self.merge.next();
+        if account.is_none()
+            && let Err(panic) = self.engine.join()
+        {
+            std::panic::resume_unwind(panic);
+        }
+        account
+    }
+}
+
+/// A worker pool that stays up across inputs, for services that settle accounts per period
+///
+/// Transactions are routed to the workers as they are submitted, under the same rules as
+/// `process_transactions`. `drain_and_reset` returns the accounts so far and empties every
+/// worker, dispute history included, so the next period starts from no accounts on the same
+/// threads. Dropping the engine stops the workers and discards anything not yet drained.
+pub struct Engine {
+    engine: EngineHandle,
+    config: EngineConfig,
+    num_workers: usize,
+}
+
+impl Engine {
+    /// Spawn the workers, seeded from `initial_balances` or `resume_from` if set
+    /// Options for whole-file runs (`manifest`, `snapshot`, `journal`, `storage`, `overlay`,
+    /// `max_rows`) are not used
+    pub fn new(config: &EngineConfig) -> Result<Self, EngineError> {
+        config.validate()?;
+        // Load starting state before spawning workers so a bad file doesn't leave threads behind
+        let seeds = read_seeds(config)?;
+        Self::with_seeds(config, seeds)
+    }
+
+    /// Spawn the workers with the given starting state, for a caller that has loaded it itself
+    pub(crate) fn with_seeds(
+        config: &EngineConfig,
+        seeds: Vec<ClientLedger>,
+    ) -> Result<Self, EngineError> {
+        let num_workers = config.worker_count();
+        let engine = EngineHandle::spawn(num_workers, config, None);
+        seed_accounts(seeds, engine.senders(), num_workers, config)?;
+        Ok(Self {
+            engine,
+            config: config.clone(),
+            num_workers,
+        })
+    }
+
+    /// Queue a transaction on its client's worker
+    /// Blocks while that worker's channel is full when `channel_capacity` is set
+    pub fn submit(&self, transaction: Transaction) -> Result<(), EngineError> {
+        if !self.config.processes_client(transaction.client) {
+            return Ok(());
+        }
+        let worker_id = worker_for(transaction.client, self.num_workers, &self.config)?;
+        send_to_worker(
+            &self.engine.senders()[worker_id],
+            WorkerMessage::Transaction(transaction),
+        )
+    }
+
+    /// Move `amount` of available funds from client `from` to client `to`, on the calling
+    /// thread and as their balances stand, whichever workers own them
+    /// Only the two clients' ledgers are locked, in client id order, so transfers can run
+    /// from several threads while the workers settle other clients. Transactions still queued
+    /// for either client are not waited for, and transfers are neither counted in the
+    /// statistics nor reported to `on_reject`; the outcome is returned instead. Both clients
+    /// need an account, open and unlocked, and the source must cover the amount as for a
+    /// withdrawal. Not available with `multi_currency`
+    pub fn transfer(
+        &self,
+        from: ClientId,
+        to: ClientId,
+        amount: f64,
+    ) -> Result<ApplyOutcome, EngineError> {
+        if self.config.multi_currency {
+            return Err(EngineError::InvalidConfig(
+                "transfers move single-currency balances and can't be used with multi_currency"
+                    .to_string(),
+            ));
+        }
+        let from_worker = worker_for(from, self.num_workers, &self.config)?;
+        let to_worker = worker_for(to, self.num_workers, &self.config)?;
+        self.engine
+            .ledgers
+            .transfer((from_worker, from), (to_worker, to), amount, &self.config)
+    }
+
+    /// Transactions the workers have processed since the engine started, across
+    /// `drain_and_reset` periods
+    /// Safe to poll from any thread while transactions are still being submitted; it lags
+    /// `submit` by whatever is still queued.
+    pub fn processed_count(&self) -> u64 {
+        self.engine.processed.load(Ordering::Relaxed)
+    }
+
+    /// Wait until every submitted transaction is applied, then return the accounts keyed by
+    /// client id and clear all client states, leaving the workers running for the next period
+    /// Seeded accounts are cleared too; the next period starts from no accounts at all
+    pub fn drain_and_reset(&mut self) -> Result<HashMap<ClientId, ClientAccount>, EngineError> {
+        self.ask_workers(WorkerMessage::Drain).map(into_accounts)
+    }
+
+    /// Wait until every submitted transaction is applied and copy out the client states,
+    /// leaving the workers as they are
+    pub(crate) fn ledgers(&self) -> Result<Vec<ClientLedger>, EngineError> {
+        let shards = self.ask_workers(WorkerMessage::Collect)?;
+        Ok(shards.into_iter().flatten().collect())
+    }
+
+    /// Queue a request behind the input of every worker and wait for all the replies
+    fn ask_workers(
+        &self,
+        request: fn(Sender<Vec<ClientLedger>>) -> WorkerMessage,
+    ) -> Result<Vec<Vec<ClientLedger>>, EngineError> {
+        // Ask every worker before waiting on any, so they answer in parallel
+        let mut replies = Vec::with_capacity(self.num_workers);
+        for sender in self.engine.senders() {
+            let (reply, answer) = bounded(1);
+            send_to_worker(sender, request(reply))?;
+            replies.push(answer);
+        }
+
+        replies
+            .into_iter()
+            .map(|answer| {
+                answer
+                    .recv()
+                    .map_err(|_| EngineError::Worker("Worker thread panicked".to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Process the input and stream the final accounts instead of writing CSV
+/// The whole input is processed before this returns; accounts then arrive as they are read
+pub fn start_engine_iter(path: &str) -> Result<AccountStream, EngineError> {
+    start_engine_iter_with_config(path, &EngineConfig::default())
+}
+
+/// Same as `start_engine_iter` with explicit runtime options
+/// Only accounts are produced: rejection counts and `explain` traces are not available, and
+/// `string_client_ids` is refused since the stream has no way to return the original names
+pub fn start_engine_iter_with_config(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<AccountStream, EngineError> {
+    config.validate()?;
+    if config.string_client_ids {
+        return Err(EngineError::InvalidConfig(
+            "streamed accounts carry numeric client ids; use process_file_with_report for \
+             string client ids"
+                .to_string(),
+        ));
+    }
+    if config.snapshot.is_some() || config.overlay.is_some() {
+        return Err(EngineError::InvalidConfig(
+            "streamed accounts leave the workers as they are read, so no snapshot can be \
+             written or overlay applied; use process_file_with_report"
+                .to_string(),
+        ));
+    }
+    let num_workers = config.worker_count();
+
+    let OpenedInput {
+        input: transactions,
+        digest,
+        ..
+    } = open_transactions(path, config)?;
+    let seeds = read_seeds(config)?;
+
+    let (outputs, receivers): (Vec<_>, Vec<_>) = (0..num_workers)
+        .map(|_| bounded::<ClientAccount>(ACCOUNT_STREAM_CAPACITY))
+        .unzip();
+    let mut engine = EngineHandle::spawn(num_workers, config, Some(outputs));
+    let senders = engine.senders();
+
+    let routed = seed_accounts(seeds, senders, num_workers, config)
+        .and_then(|()| route_transactions(transactions, senders, num_workers, config));
+
+    let routed = match routed {
+        Ok(routed) => routed,
+        Err(e) => {
+            // Nobody will read the accounts, so close the outputs before stopping the workers
+            drop(receivers);
+            engine.abort();
+            return Err(e);
+        }
+    };
+
+    engine.finish_input();
+    // Waits for each worker's first account, i.e. until it has applied all its transactions
+    let shards = receivers.into_iter().map(Receiver::into_iter).collect();
+    let stream = AccountStream {
+        merge: ShardMerge::new(shards, |account| account.client),
+        engine,
+    };
+
+    // On failure the stream is dropped, which joins the workers
+    let provenance = digest.map(|digest| Provenance::new(path, hex_digest(&digest), config));
+    write_manifest(provenance, &routed, num_workers, config)?;
+    Ok(stream)
+}
+
+fn into_accounts(shards: Vec<Vec<ClientLedger>>) -> HashMap<ClientId, ClientAccount> {
+    shards
+        .into_iter()
+        .flatten()
+        .map(|state| (state.account().client, state.into_account()))
+        .collect()
+}
+
+/// Run the engine over the input at `path`, handing each worker's shard to `on_shard` as the
+/// worker finishes, if given
+fn run_engine(
+    path: &str,
+    config: &EngineConfig,
+    on_shard: Option<ShardSink<'_>>,
+) -> Result<EngineRun, EngineError> {
+    config.validate()?;
+    let num_workers = config.worker_count();
+    let started = Instant::now();
+
+    // Open input before spawning workers so a bad path doesn't leave threads behind
+    let (mut run, digest, bytes) = if config.string_client_ids {
+        let OpenedInput {
+            input: reader,
+            digest,
+            bytes,
+        } = open_engine_input(path, config)?;
+        let seeds = read_seeds(config)?;
+        let parts = worker_parts(num_workers, config, false)?;
+        let mut interner = ClientInterner::default();
+        let transactions = string_client_transactions(reader, config, &mut interner);
+        let mut run = process_stream_with(
+            |senders| route_transactions(transactions, senders, num_workers, config),
+            seeds,
+            parts,
+            num_workers,
+            config,
+            on_shard,
+        )?;
+
+        run.stats.interned_clients = interner.len();
+        run.stats.intern_table_bytes = interner.memory_bytes();
+        run.client_names = Some(interner.into_names());
+        (run, digest, bytes)
+    } else if config.hot_client_helpers > 0 {
+        let OpenedInput {
+            input: reader,
+            digest,
+            bytes,
+        } = open_engine_input(path, config)?;
+        let seeds = read_seeds(config)?;
+        let parts = worker_parts(num_workers, config, false)?;
+        let run = process_stream_with(
+            |senders| route_csv_records(reader, senders, num_workers, config),
+            seeds,
+            parts,
+            num_workers,
+            config,
+            on_shard,
+        )?;
+        (run, digest, bytes)
+    } else {
+        let OpenedInput {
+            input: transactions,
+            digest,
+            bytes,
+        } = open_transactions(path, config)?;
+        let seeds = read_seeds(config)?;
+        let parts = worker_parts(num_workers, config, false)?;
+        let run = process_stream_with(
+            |senders| route_transactions(transactions, senders, num_workers, config),
+            seeds,
+            parts,
+            num_workers,
+            config,
+            on_shard,
+        )?;
+        (run, digest, bytes)
+    };
+    run.stats.input_bytes = bytes.get();
+    run.stats.phases.elapsed = started.elapsed();
+
+    run.provenance = digest.map(|digest| Provenance::new(path, hex_digest(&digest), config));
+    write_manifest(run.provenance.clone(), &run.stats, num_workers, config)?;
+    // The snapshot is the state the main input leads to; the overlay is only simulated
+    if let Some(path) = &config.snapshot {
+        write_snapshot(path, run.shards.iter().flatten(), &[])?;
+    }
+    if let Some(overlay) = &config.overlay {
+        run = apply_overlay(overlay, run, num_workers, config)?;
+    }
+    write_journal_of(&mut run, config)?;
+    check_empty_output(has_accounts(&run), run.stats.rows, config)?;
+
+    Ok(run)
+}
+
+/// Process the `EngineConfig::overlay` file at `path` through the state `base` ended in
+/// The run that comes back has the overlay's shards and trace steps, and `base`'s statistics
+/// with the overlay's own in `EngineStats::overlay`
+fn apply_overlay(
+    path: &str,
+    mut base: EngineRun,
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<EngineRun, EngineError> {
+    let started = Instant::now();
+    let transactions = open_transactions(path, config)?.input;
+    let seeds = if config.overlay_output.is_some() {
+        let seeds = base.shards.iter().flatten().cloned().collect();
+        base.pre_overlay = Some(std::mem::take(&mut base.shards));
+        seeds
+    } else {
+        std::mem::take(&mut base.shards)
+            .into_iter()
+            .flatten()
+            .collect()
+    };
+    let parts = worker_parts(num_workers, config, true)?;
+    let mut overlay = process_stream_with(
+        |senders| route_transactions(transactions, senders, num_workers, config),
+        seeds,
+        parts,
+        num_workers,
+        config,
+        None,
+    )?;
+    overlay.stats.phases.elapsed = started.elapsed();
+
+    base.shards = overlay.shards;
+    base.trace.extend(overlay.trace);
+    base.overlay_journal = overlay.journal;
+    base.stats.phases.elapsed += overlay.stats.phases.elapsed;
+    base.stats.overlay = Some(Box::new(overlay.stats));
+    Ok(base)
+}
+
+/// What each worker of a file run writes to besides its shard, one entry per worker
+#[derive(Default)]
+struct WorkerParts {
+    journal: Option<Vec<JournalPart>>,
+    stores: Option<Vec<WorkerStore>>,
+}
+
+/// The `EngineConfig::journal` parts and `EngineConfig::storage` handles of a run's workers;
+/// `overlay` for the pool applying `EngineConfig::overlay`
+fn worker_parts(
+    num_workers: usize,
+    config: &EngineConfig,
+    overlay: bool,
+) -> Result<WorkerParts, EngineError> {
+    Ok(WorkerParts {
+        journal: journal_parts(num_workers, config, overlay)?,
+        stores: worker_stores(num_workers, config)?,
+    })
+}
+
+/// One `EngineConfig::journal` part per worker, if the run keeps a journal; `overlay` for the
+/// pool applying `EngineConfig::overlay`
+fn journal_parts(
+    num_workers: usize,
+    config: &EngineConfig,
+    overlay: bool,
+) -> Result<Option<Vec<JournalPart>>, EngineError> {
+    let Some(path) = &config.journal else {
+        return Ok(None);
+    };
+    let overlay = config.overlay.as_ref().map(|_| overlay);
+    (0..num_workers)
+        .map(|worker_id| JournalPart::create(path, worker_id, overlay))
+        .collect::<Result<_, _>>()
+        .map(Some)
+}
+
+/// Join the `EngineConfig::journal` parts into the journal file, if any, once the run is
+/// complete
+fn write_journal_of(run: &mut EngineRun, config: &EngineConfig) -> Result<(), EngineError> {
+    if let Some(path) = &config.journal {
+        let mut parts = std::mem::take(&mut run.journal);
+        parts.append(&mut run.overlay_journal);
+        write_journal(path, parts)?;
+    }
+    Ok(())
+}
+
+/// `run_engine` writing each worker's shard to stdout as soon as the worker finishes
+/// (`EngineConfig::stream_shards`); the shards are still returned in the run
+fn run_engine_streaming(path: &str, config: &EngineConfig) -> Result<EngineRun, EngineError> {
+    let mut output = ShardWriter::new(std::io::stdout(), config, None);
+    let mut write = Duration::ZERO;
+    let mut run = run_engine(
+        path,
+        config,
+        Some(&mut |shard| {
+            let writing = Instant::now();
+            output.write(shard)?;
+            // Out to stdout now, rather than whenever the buffer fills
+            output.flush()?;
+            write += writing.elapsed();
+            Ok(())
+        }),
+    )?;
+    output.finish()?;
+    // Written while other workers were still settling, so already part of `elapsed`
+    run.stats.phases.write = write;
+    Ok(run)
+}
+
+fn has_accounts(run: &EngineRun) -> bool {
+    run.shards.iter().any(|shard| !shard.is_empty())
+}
+
+/// Apply `EngineConfig::empty_output` to a finished run that read `rows` transactions
+pub(crate) fn check_empty_output(
+    has_accounts: bool,
+    rows: u64,
+    config: &EngineConfig,
+) -> Result<(), EngineError> {
+    if has_accounts {
+        return Ok(());
+    }
+    match config.empty_output {
+        EmptyOutputPolicy::Ignore => Ok(()),
+        EmptyOutputPolicy::Warn => {
+            eprintln!("Warning: {}", empty_output_message(rows));
+            Ok(())
+        }
+        EmptyOutputPolicy::Error => Err(EngineError::EmptyOutput { rows }),
+    }
+}
+
+/// Fail once data row `row` is past `config.max_rows` under `RowLimitPolicy::Error`
+/// `RowLimitPolicy::Truncate` stops reading at the limit instead, see
+/// `EngineConfig::truncate_rows_at`
+pub(crate) fn check_row_limit(row: u64, config: &EngineConfig) -> Result<(), EngineError> {
+    match (config.max_rows, config.row_limit) {
+        (Some(max_rows), RowLimitPolicy::Error) if row > max_rows => {
+            Err(EngineError::TooManyRows { row, max_rows })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Rows to read before treating the input as ended, per `EngineConfig::last_row`
+pub(crate) fn rows_to_read(config: &EngineConfig) -> usize {
+    config.last_row().map_or(usize::MAX, |rows| {
+        usize::try_from(rows).unwrap_or(usize::MAX)
+    })
+}
+
+/// An opened input together with what is tracked while it is read
+struct OpenedInput<T> {
+    input: T,
+    /// Digest of the bytes read, when a manifest is requested
+    digest: Option<InputDigest>,
+    bytes: InputBytes,
+}
+
+/// Open the input, hashing it as the reader consumes it when a manifest or provenance is
+/// requested rather than in a second pass, and counting the bytes read
+fn open_engine_input(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<OpenedInput<Box<dyn Read>>, EngineError> {
+    let (reader, bytes) = CountingReader::wrap(open_transaction_input(path, config)?);
+    let reader: Box<dyn Read> = Box::new(reader);
+    Ok(if config.hashes_input() {
+        let (reader, digest) = HashingReader::wrap(reader);
+        OpenedInput {
+            input: Box::new(reader),
+            digest: Some(digest),
+            bytes,
+        }
+    } else {
+        OpenedInput {
+            input: reader,
+            digest: None,
+            bytes,
+        }
+    })
+}
+
+/// Open the input and stream its transactions in `config.input_format`, with numeric client ids
+/// Parquet is read straight from the file, so it has no digest for a manifest and counts as
+/// read in full
+fn open_transactions(
+    path: &str,
+    config: &EngineConfig,
+) -> Result<OpenedInput<TransactionIter>, EngineError> {
+    if config.input_format == InputFormat::Parquet {
+        let transactions = parquet_transactions(path, config)?;
+        let bytes = InputBytes::new(
+            fs::metadata(path)
+                .map_or(0, |metadata| metadata.len())
+                .into(),
+        );
+        return Ok(OpenedInput {
+            input: transactions,
+            digest: None,
+            bytes,
+        });
+    }
+    let OpenedInput {
+        input: reader,
+        digest,
+        bytes,
+    } = open_engine_input(path, config)?;
+    let transactions: TransactionIter = match config.input_format {
+        InputFormat::Binary => {
+            let reader = BufReader::with_capacity(config.read_buffer_size, reader);
+            Box::new(binary_transactions(reader))
+        }
+        _ => Box::new(csv_transactions(reader, config)),
+    };
+    Ok(OpenedInput {
+        input: transactions,
+        digest,
+        bytes,
+    })
+}
+
+/// Write the `EngineConfig::manifest` file, if any, once the input has been read
+fn write_manifest(
+    provenance: Option<Provenance>,
+    stats: &EngineStats,
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<(), EngineError> {
+    if let (Some(manifest_path), Some(provenance)) = (&config.manifest, provenance) {
+        RunManifest::new(provenance, stats, num_workers).write(manifest_path)?;
+    }
+    Ok(())
+}
+
+/// Route every transaction through a fresh worker pool and collect final client states,
+/// one shard per worker sorted by client id
+/// Workers are always signaled and joined, even when routing fails part way through the input
+fn process_stream<I>(
+    transactions: I,
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<EngineRun, EngineError>
+where
+    I: Iterator<Item = Result<Transaction, EngineError>>,
+{
+    // Load starting state before spawning workers so a bad file doesn't leave threads behind
+    let seeds = read_seeds(config)?;
+    let parts = worker_parts(num_workers, config, false)?;
+    let mut run = process_stream_with(
+        |senders| route_transactions(transactions, senders, num_workers, config),
+        seeds,
+        parts,
+        num_workers,
+        config,
+        None,
+    )?;
+    if let Some(path) = &config.snapshot {
+        write_snapshot(path, run.shards.iter().flatten(), &[])?;
+    }
+    write_journal_of(&mut run, config)?;
+    Ok(run)
+}
+
+/// `process_stream` starting from `seeds`, with `route` sending the input to the workers and
+/// returning the router's statistics, and handing each worker's shard to `on_shard` as soon
+/// as the worker finishes once routing succeeded
+/// Workers append to their journal part and write through to their store as they go; callers
+/// write the snapshot and join the journal once the whole run is done
+fn process_stream_with<R>(
+    route: R,
+    seeds: Vec<ClientLedger>,
+    parts: WorkerParts,
+    num_workers: usize,
+    config: &EngineConfig,
+    on_shard: Option<ShardSink<'_>>,
+) -> Result<EngineRun, EngineError>
+where
+    R: FnOnce(&[WorkerSender]) -> Result<EngineStats, EngineError>,
+{
+    // Create worker threads and channels
+    // The control channels stay open until the workers are joined, to abort them if routing
+    // fails
+    let processed = Arc::default();
+    let cores = affinity::plan(num_workers, config, true);
+    let (workers, senders, controls, finished) = create_worker_pool(
+        num_workers,
+        config,
+        None,
+        parts,
+        &processed,
+        &cores.workers,
+        &SharedLedgers::new(num_workers),
+    );
+    // Held until the run is collected, then the caller's thread gets its cores back
+    let _router_pin = cores.router.map(affinity::RouterPin::new);
+
+    // Seed accounts, then stream transactions and route them to workers
+    let routing = Instant::now();
+    let routed = seed_accounts(seeds, &senders, num_workers, config)
+        .and_then(|()| route(&senders))
+        .map(|mut routed| {
+            routed.phases.route = routing.elapsed();
+            routed
+        });
+
+    // The run is lost, e.g. to a timeout, so skip whatever is still queued
+    if routed.is_err() {
+        for control in &controls {
+            let _ = control.send(ControlMessage::Abort);
+        }
+    }
+
+    // Shutdown workers and collect results, whether or not routing succeeded
+    // A failed run writes nothing, so shards only go out once routing is known to be done
+    let on_shard = on_shard.filter(|_| routed.is_ok());
+    let collected = shutdown_and_collect(workers, senders, finished, on_shard);
+
+    match routed {
+        Ok(routed) => {
+            let mut run = collected?;
+            run.stats.merge(&routed);
+            Ok(run)
+        }
+        Err(e) => {
+            // How far workers got before the failure depends on scheduling
+            if let (Ok(partial), false) = (&collected, config.deterministic) {
+                eprintln!(
+                    "Routing failed after {} clients were partially processed",
+                    partial.shards.iter().map(Vec::len).sum::<usize>()
+                );
+            }
+            Err(e)
+        }
+    }
+}
+
+/// How long dropping an `EngineHandle` waits for each worker before detaching it
+const WORKER_JOIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Owns a running worker pool and makes sure its threads end with it
+/// Dropping the handle stops the workers at their next message, discarding whatever is still
+/// queued, and joins them; a worker that does not exit within `WORKER_JOIN_TIMEOUT` is
+/// reported and left detached rather than hanging the caller
+struct EngineHandle {
+    workers: Vec<WorkerHandle>,
+    senders: Vec<WorkerSender>,
+    controls: Vec<ControlSender>,
+    /// Transactions the workers have taken off their queues, read while they run
+    processed: Arc<AtomicU64>,
+    /// The workers' client ledgers, one shard per worker
+    ledgers: SharedLedgers,
+}
+
+impl EngineHandle {
+    fn spawn(
+        num_workers: usize,
+        config: &EngineConfig,
+        outputs: Option<Vec<Sender<ClientAccount>>>,
+    ) -> Self {
+        let processed = Arc::default();
+        let cores = affinity::plan(num_workers, config, false);
+        let ledgers = SharedLedgers::new(num_workers);
+        // Dropping the handle joins workers with a timeout instead
+        let (workers, senders, controls, _finished) = create_worker_pool(
+            num_workers,
+            config,
+            outputs,
+            WorkerParts::default(),
+            &processed,
+            &cores.workers,
+            &ledgers,
+        );
+        Self {
+            workers,
+            senders,
+            controls,
+            processed,
+            ledgers,
+        }
+    }
+
+    /// Worker channels, indexed by worker id; empty once `finish_input` has been called
+    fn senders(&self) -> &[WorkerSender] {
+        &self.senders
+    }
+
+    /// Tell every worker that no more input is coming, so each finishes its queue and exits
+    fn finish_input(&mut self) {
+        for sender in self.senders.drain(..) {
+            let _ = sender.channel.send(WorkerMessage::Shutdown);
+        }
+    }
+
+    /// Wait for workers that have been told to finish, returning the first panic if any
+    fn join(&mut self) -> thread::Result<()> {
+        let mut result = Ok(());
+        for worker in self.workers.drain(..) {
+            if let (Err(panic), Ok(())) = (worker.join(), &result) {
+                result = Err(panic);
+            }
+        }
+        result
+    }
+
+    /// Stop the workers without finishing their queues; the same as dropping the handle
+    fn abort(self) {}
+}
+
+impl Drop for EngineHandle {
+    fn drop(&mut self) {
+        // Commands are served ahead of queued transactions, so this skips the backlog
+        for control in &self.controls {
+            let _ = control.send(ControlMessage::Abort);
+        }
+        // Closing the worker channels also ends a worker waiting for input, and frees
+        // the queued messages once it exits
+        self.senders.clear();
+
+        let deadline = Instant::now() + WORKER_JOIN_TIMEOUT;
+        for (worker_id, worker) in self.workers.drain(..).enumerate() {
+            while !worker.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            if worker.is_finished() {
+                let _ = worker.join();
+            } else {
+                eprintln!(
+                    "Worker {} did not exit within {:?}; detaching it",
+                    worker_id, WORKER_JOIN_TIMEOUT
+                );
+            }
+        }
+    }
+}
+
+/// Create worker thread pool with one channel per worker
+/// With `outputs`, each worker sends its sorted accounts over its output channel at shutdown
+/// instead of returning them from the join handle
+/// With `parts`, each worker appends the transactions it applies to its journal part and
+/// writes its clients through to its store
+/// Every worker adds the transactions it processes to `processed`
+/// Each worker pins itself to its core in `cores`, if it has one
+fn create_worker_pool(
+    num_workers: usize,
+    config: &EngineConfig,
+    outputs: Option<Vec<Sender<ClientAccount>>>,
+    parts: WorkerParts,
+    processed: &Arc<AtomicU64>,
+    cores: &[usize],
+    ledgers: &SharedLedgers,
+) -> WorkerPool {
+    let mut outputs = outputs.map(Vec::into_iter);
+    let mut journal = parts.journal.map(Vec::into_iter);
+    let mut stores = parts.stores.map(Vec::into_iter);
+    let config = Arc::new(config.clone());
+    let mut workers = Vec::with_capacity(num_workers);
+    let mut senders = Vec::with_capacity(num_workers);
+    let mut controls = Vec::with_capacity(num_workers);
+    let (finished_tx, finished) = unbounded::<usize>();
+
+    for worker_id in 0..num_workers {
+        let (tx, rx) = match config.channel_capacity {
+            Some(capacity) => bounded::<WorkerMessage>(capacity),
+            None => unbounded::<WorkerMessage>(),
+        };
+        let pressure = Arc::new(ChannelPressure::default());
+        senders.push(WorkerSender {
+            channel: tx,
+            pressure: Arc::clone(&pressure),
+        });
+        let (control_tx, control_rx) = unbounded::<ControlMessage>();
+        controls.push(control_tx);
+
+        let config = Arc::clone(&config);
+        let processed = Arc::clone(processed);
+        let output = outputs.as_mut().and_then(Iterator::next);
+        let core = cores.get(worker_id).copied();
+        let state = WorkerState {
+            client_states: ledgers.shard(worker_id),
+            journal: journal.as_mut().and_then(Iterator::next),
+            store: stores.as_mut().and_then(Iterator::next),
+            ..Default::default()
+        };
+        let finished = FinishedSignal {
+            worker_id,
+            finished: finished_tx.clone(),
+        };
+        let handle = thread::spawn(move || {
+            let _finished = finished;
+            if let Some(core) = core {
+                affinity::pin_current(core, &format!("worker {}", worker_id));
+            }
+            let state = worker_thread(
+                worker_id, state, rx, control_rx, &config, &pressure, &processed,
+            );
+            let shard = state.client_states.take_sorted();
+            match output {
+                Some(output) => {
+                    // A closed channel means the consumer stopped early; the rest is dropped
+                    let accounts = shard
+                        .into_iter()
+                        .flat_map(ClientLedger::into_output_accounts);
+                    for account in accounts {
+                        if output.send(account).is_err() {
+                            break;
+                        }
+                    }
+                    (Vec::new(), state)
+                }
+                None => (shard, state),
+            }
+        });
+
+        workers.push(handle);
+    }
+
+    (workers, senders, controls, finished)
+}
+
+/// Sends its worker's id when dropped, as the worker returns or panics
+struct FinishedSignal {
+    worker_id: usize,
+    finished: Sender<usize>,
+}
+
+impl Drop for FinishedSignal {
+    fn drop(&mut self) {
+        // Nobody listens outside `stream_shards` runs
+        let _ = self.finished.send(self.worker_id);
+    }
+}
+
+/// Worker thread that processes transactions for assigned clients
+/// Control commands are checked before each message and served even while paused
+/// Returns the final client states along with the worker's rejection counts and trace
+/// `processed` is shared by the whole pool and bumped once per message, not per transaction
+/// `state` starts out with the worker's shard, journal part and store
+/// The changes of each transaction message are committed to the store together once it is
+/// applied, and the store is flushed before the worker returns
+fn worker_thread(
+    worker_id: usize,
+    mut state: WorkerState,
+    receiver: Receiver<WorkerMessage>,
+    mut control: Receiver<ControlMessage>,
+    config: &EngineConfig,
+    pressure: &ChannelPressure,
+    processed: &AtomicU64,
+) -> WorkerState {
+    let mut paused = false;
+    let started = Instant::now();
+
+    // Process messages until shutdown
+    loop {
+        // Serve pending commands first so they don't wait behind queued transactions
+        match control.try_recv() {
+            Ok(command) => {
+                if handle_control(&state, command, &mut paused).is_break() {
+                    break;
+                }
+                continue;
+            }
+            // Nobody can send commands any more; stop watching the channel
+            Err(TryRecvError::Disconnected) => {
+                control = never();
+                paused = false;
+            }
+            Err(TryRecvError::Empty) => {}
+        }
+
+        if paused {
+            let waiting = Instant::now();
+            let command = control.recv();
+            state.load.idle += waiting.elapsed();
+            if let Ok(command) = command
+                && handle_control(&state, command, &mut paused).is_break()
+            {
+                break;
+            }
+            continue;
+        }
+
+        let message = match receiver.try_recv() {
+            Ok(message) => message,
+            Err(TryRecvError::Disconnected) => break,
+            Err(TryRecvError::Empty) => {
+                // Wait until either channel has something, then go round again so a command
+                // that arrived alongside a transaction is still served first
+                let mut ready = Select::new();
+                ready.recv(&control);
+                ready.recv(&receiver);
+                // Only blocking waits are timed, so a busy worker pays for no clock reads
+                let waiting = Instant::now();
+                ready.ready();
+                state.load.idle += waiting.elapsed();
+                continue;
+            }
+        };
+
+        match message {
+            WorkerMessage::Seed(ledger) => {
+                state.client_states.insert(ledger);
+            }
+            WorkerMessage::Transaction(transaction) => {
+                state.load.rows += 1;
+                if state.load.rows.is_multiple_of(DEPTH_SAMPLE_INTERVAL) {
+                    state.load.max_queue_depth = state.load.max_queue_depth.max(receiver.len());
+                }
+                submit_to_client(&mut state, transaction, config);
+                commit_store(&mut state);
+                processed.fetch_add(1, Ordering::Relaxed);
+            }
+            WorkerMessage::Batch(transactions) => {
+                let settling = Instant::now();
+                let count = transactions.len() as u64;
+                state.load.rows += count;
+                state.load.max_queue_depth = state.load.max_queue_depth.max(receiver.len());
+                for transaction in transactions {
+                    submit_to_client(&mut state, transaction, config);
+                }
+                commit_store(&mut state);
+                state.stats.phases.settle += settling.elapsed();
+                processed.fetch_add(count, Ordering::Relaxed);
+            }
+            WorkerMessage::Sequenced(transactions) => {
+                let settling = Instant::now();
+                let count = transactions.len() as u64;
+                state.load.rows += count;
+                state.load.max_queue_depth = state.load.max_queue_depth.max(receiver.len());
+                state.sequenced.extend(transactions);
+                while let Some(transaction) = state.sequenced.remove(&state.next_sequence) {
+                    state.next_sequence += 1;
+                    submit_to_client(&mut state, transaction, config);
+                }
+                commit_store(&mut state);
+                state.stats.phases.settle += settling.elapsed();
+                processed.fetch_add(count, Ordering::Relaxed);
+            }
+            WorkerMessage::Drain(reply) => {
+                release_held(&mut state, config);
+                let _ = reply.send(state.client_states.take());
+            }
+            WorkerMessage::Collect(reply) => {
+                release_held(&mut state, config);
+                let _ = reply.send(state.client_states.map(ClientLedger::clone));
+            }
+            WorkerMessage::Shutdown => {
+                break;
+            }
+        }
+    }
+
+    release_held(&mut state, config);
+    if let Some(store) = &mut state.store {
+        store.finish(&state.client_states, config);
+    }
+    state.load.clients = state.client_states.len();
+    if config.profile {
+        state.load.records = state
+            .client_states
+            .map(ClientLedger::record_count)
+            .into_iter()
+            .sum();
+    }
+    state.load.busy = started.elapsed().saturating_sub(state.load.idle);
+    pressure.report(&mut state.load);
+    let load = std::mem::take(&mut state.load);
+    state.stats.per_worker.push(load);
+
+    //this will provide log info without compromising stdout required format
+    //workers finish in scheduling order, so deterministic runs leave it out
+    if !config.deterministic {
+        eprintln!(
+            "Worker {} processed {} clients",
+            worker_id,
+            state.client_states.len()
+        );
+    }
+    state
+}
+
+/// Carry out a control command on the worker thread
+/// A caller that gave up waiting for a reply is not an error, so failed replies are dropped
+/// Breaks when the worker should exit
+fn handle_control(
+    state: &WorkerState,
+    command: ControlMessage,
+    paused: &mut bool,
+) -> ControlFlow<()> {
+    match command {
+        ControlMessage::Flush(reply) => {
+            let mut accounts: Vec<ClientAccount> =
+                state.client_states.map(|ledger| ledger.account().clone());
+            accounts.sort_unstable_by_key(|account| account.client);
+            let _ = reply.send(accounts);
+        }
+        ControlMessage::Stats(reply) => {
+            let _ = reply.send(state.stats.clone());
+        }
+        ControlMessage::Pause => *paused = true,
+        ControlMessage::Resume => *paused = false,
+        ControlMessage::Abort => return ControlFlow::Break(()),
+    }
+    ControlFlow::Continue(())
+}
+
+/// Apply the transaction, or with `config.reorder_window` hold it back until its client's
+/// transactions can be applied in timestamp order
+pub(crate) fn submit_to_client(
+    worker: &mut WorkerState,
+    transaction: Transaction,
+    config: &EngineConfig,
+) {
+    let Some(window) = config.reorder_window else {
+        apply_to_client(worker, transaction, config);
+        return;
+    };
+    worker
+        .reorder
+        .push(transaction, window, &mut worker.stats.late);
+    apply_due(worker, config);
+}
+
+/// Apply every transaction `submit_to_client` still holds back, at the end of the input or
+/// before the accounts are read
+pub(crate) fn release_held(worker: &mut WorkerState, config: &EngineConfig) {
+    worker.reorder.release_all(&mut worker.stats.late);
+    apply_due(worker, config);
+}
+
+/// Commit what the worker wrote to its store since the last commit, if it has one
+fn commit_store(worker: &mut WorkerState) {
+    if let Some(store) = &mut worker.store {
+        store.commit(&worker.client_states);
+    }
+}
+
+fn apply_due(worker: &mut WorkerState, config: &EngineConfig) {
+    let mut due = std::mem::take(&mut worker.reorder.due);
+    for transaction in due.drain(..) {
+        apply_to_client(worker, transaction, config);
+    }
+    // Hand the allocation back for the next release
+    worker.reorder.due = due;
+}
+
+/// Get or create the client's state and apply the transaction to it
+/// Unknown clients only get an account when `config.account_creation` allows it
+pub(crate) fn apply_to_client(
+    worker: &mut WorkerState,
+    mut transaction: Transaction,
+    config: &EngineConfig,
+) -> ApplyOutcome {
+    let client_id = transaction.client;
+    // Normalized once here, by value, so the ledger can take it as it is
+    let normalized = transaction.normalize_in_place();
+
+    if let Some(store) = &mut worker.store {
+        store.load(&worker.client_states, client_id, transaction.tx);
+    }
+    if !worker.client_states.contains(client_id)
+        && config.account_creation.creates_account(&transaction)
+    {
+        worker.client_states.insert(ClientLedger::new(client_id));
+    }
+    // A new client's empty ledger has the same baseline and lock state as no ledger at all
+    let ledgers = worker.client_states.read();
+    let mut guard = ledgers.lock(client_id);
+
+    // Only a dispute can trip a threshold lock, so only then is the prior lock state needed
+    let watch_threshold =
+        transaction.tx_type == TransactionType::Dispute && config.has_dispute_thresholds();
+    let was_locked =
+        watch_threshold && guard.as_ref().is_some_and(|ledger| ledger.account().locked);
+    let baseline = config
+        .events
+        .as_ref()
+        .map(|_| Baseline::of(guard.as_deref()));
+
+    let outcome = match guard.as_deref_mut() {
+        Some(ledger) => {
+            apply_normalized(ledger, normalized.as_ref().map(|()| &transaction), config)
+        }
+        None => ApplyOutcome::Rejected(RejectReason::UnknownClient),
+    };
+    let ledger = guard.as_deref();
+
+    match outcome {
+        ApplyOutcome::Applied => worker.stats.applied += 1,
+        ApplyOutcome::Ignored(IgnoreReason::AlreadyApplied) => worker.stats.already_applied += 1,
+        ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction) => {
+            worker.stats.orphan_disputes += 1
+        }
+        _ => {}
+    }
+
+    if let (Some(events), Some(baseline), ApplyOutcome::Applied, Some(ledger)) =
+        (&config.events, &baseline, &outcome, ledger)
+    {
+        publish(
+            events,
+            baseline,
+            ledger,
+            &transaction,
+            &mut worker.stats.dropped_events,
+        );
+    }
+
+    if watch_threshold
+        && !was_locked
+        && outcome == ApplyOutcome::Applied
+        && let Some(ledger) = ledger
+        && ledger.account().lock_reason == Some(LockReason::DisputeThreshold)
+    {
+        worker.stats.alerts.push(DisputeAlert {
+            client: client_id,
+            tx: transaction.tx,
+            open_disputes: ledger.open_disputes(),
+            disputed_amount: ledger.disputed_amount(),
+        });
+    }
+
+    if let (Some(threshold), ApplyOutcome::Applied, Some(ledger)) =
+        (config.held_threshold, &outcome, ledger)
+    {
+        let held = ledger.max_held();
+        if held > threshold && worker.flagged.insert(client_id) {
+            worker.stats.suspicious.push(HeldAlert {
+                client: client_id,
+                tx: transaction.tx,
+                held,
+            });
+        }
+    }
+
+    if let (Some(journal), ApplyOutcome::Applied) = (&mut worker.journal, &outcome) {
+        journal.append(&transaction);
+    }
+
+    if let (Some(store), Some(ledger)) = (&mut worker.store, ledger) {
+        store.save(ledger, transaction.tx);
+    }
+
+    if config.explain == Some(client_id) {
+        let outcome = match &outcome {
+            ApplyOutcome::Rejected(reason) => Err(reason.clone()),
+            _ => ledger
+                .map(|ledger| ledger.account().clone())
+                .ok_or(RejectReason::UnknownClient),
+        };
+        worker.trace.push(ExplainStep {
+            transaction: transaction.clone(),
+            outcome,
+        });
+    }
+
+    if outcome == ApplyOutcome::Rejected(RejectReason::Overflow)
+        && !worker.stats.overflowed.contains(&client_id)
+    {
+        worker.stats.overflowed.push(client_id);
+    }
+
+    // `on_reject` is caller code, which may transfer to or from this client
+    drop(guard);
+    drop(ledgers);
+    if let ApplyOutcome::Rejected(reason) = &outcome {
+        reject(&transaction, reason.clone(), config, &mut worker.stats);
+    }
+    outcome
 }
 
-/// State for a single client (account + transaction history)
-#[derive(Debug)]
-struct ClientState {
-    account: ClientAccount,
-    tx_history: HashMap<u32, TransactionRecord>,
+/// Count a rejected transaction and report it to `config.on_reject`
+fn reject(
+    transaction: &Transaction,
+    reason: RejectReason,
+    config: &EngineConfig,
+    stats: &mut EngineStats,
+) {
+    stats.record_rejection(&reason);
+    if let Some(handler) = &config.on_reject {
+        handler.call(transaction, &reason);
+    }
+}
+
+/// CSV reader over the input with a buffer of `config.read_buffer_size`
+fn csv_reader(reader: Box<dyn Read>, config: &EngineConfig) -> Reader<BufReader<Box<dyn Read>>> {
+    let buf_reader = BufReader::with_capacity(config.read_buffer_size, reader);
+
+    ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(config.delimiter)
+        .from_reader(buf_reader)
+}
+
+/// Deserialize rows of CSV input, first rewriting the amount column to the plain form
+/// when `config.number_format` isn't `Plain`
+/// Each of `id_columns` must be an unsigned integer written without a sign; leading zeros
+/// are fine
+fn deserialize_rows<T>(
+    reader: Box<dyn Read>,
+    config: &EngineConfig,
+    id_columns: &'static [&'static str],
+) -> Box<dyn Iterator<Item = Result<T, EngineError>>>
+where
+    T: DeserializeOwned + 'static,
+{
+    let mut reader = csv_reader(reader, config);
+
+    let decoder = match reader.headers() {
+        Ok(headers) => RowDecoder::new(headers.clone(), config, id_columns),
+        Err(e) => return Box::new(std::iter::once(Err(e.into()))),
+    };
+
+    Box::new(
+        reader
+            .into_records()
+            .map(move |result| decoder.decode(result?)),
+    )
+}
+
+/// Turns CSV records into rows given the header, as `deserialize_rows` describes
+pub(crate) struct RowDecoder {
+    headers: StringRecord,
+    amount_index: Option<usize>,
+    /// The currency column outside multi-currency mode, blanked so a bad code cannot fail the row
+    ignored_currency_index: Option<usize>,
+    id_indexes: Vec<(usize, &'static str)>,
+    number_format: NumberFormat,
 }
 
-impl ClientState {
-    fn new(client_id: u16) -> Self {
+impl RowDecoder {
+    pub(crate) fn new(
+        headers: StringRecord,
+        config: &EngineConfig,
+        id_columns: &'static [&'static str],
+    ) -> Self {
+        let position = |column| headers.iter().position(|name| is_column(name, column));
+        let amount_index = position("amount");
+        let ignored_currency_index = position("currency").filter(|_| !config.multi_currency);
+        let id_indexes = id_columns
+            .iter()
+            .filter_map(|&column| Some((position(column)?, column)))
+            .collect();
         Self {
-            account: ClientAccount::new(client_id),
-            tx_history: HashMap::new(),
+            headers,
+            amount_index,
+            ignored_currency_index,
+            id_indexes,
+            number_format: config.number_format,
+        }
+    }
+
+    pub(crate) fn decode<T: DeserializeOwned>(
+        &self,
+        mut record: StringRecord,
+    ) -> Result<T, EngineError> {
+        for &(index, column) in &self.id_indexes {
+            check_unsigned_id(&record, index, column)?;
         }
+        let amount_index = self
+            .amount_index
+            .filter(|_| self.number_format != NumberFormat::Plain);
+        let currency_index = self
+            .ignored_currency_index
+            .filter(|&index| record.get(index).is_some_and(|field| !field.is_empty()));
+        if amount_index.is_some() || currency_index.is_some() {
+            let position = record.position().cloned();
+            record = record
+                .iter()
+                .enumerate()
+                .map(|(i, field)| {
+                    if Some(i) == amount_index {
+                        Cow::Owned(self.number_format.normalize(field))
+                    } else if Some(i) == currency_index {
+                        Cow::Borrowed("")
+                    } else {
+                        Cow::Borrowed(field)
+                    }
+                })
+                .collect::<StringRecord>();
+            record.set_position(position);
+        }
+        Ok(record.deserialize(Some(&self.headers))?)
     }
 }
 
-/// Message sent to worker threads
-enum WorkerMessage {
-    Transaction(Transaction),
-    Shutdown,
+/// Rejects a signed id such as `+5` or `-1`, which the integer parser would otherwise accept
+/// or report as a bare "invalid digit"
+fn check_unsigned_id(
+    record: &StringRecord,
+    index: usize,
+    column: &'static str,
+) -> Result<(), EngineError> {
+    match record.get(index) {
+        Some(value) if value.starts_with(['+', '-']) => Err(EngineError::SignedId {
+            column,
+            value: value.to_string(),
+            line: record.position().map_or(0, |position| position.line()),
+        }),
+        _ => Ok(()),
+    }
 }
 
-/// Process CSV file with worker thread pool
-/// Each client is consistently routed to the same worker thread
-pub fn start_engine(path: &str) -> Result<(), Box<dyn Error>> {
-    let num_workers = num_cpus::get();
+/// Run CSV input through the engine and write the output CSV, as a command line run would
+/// without the summary on stderr
+pub(crate) fn process_csv_to_writer<W: Write>(
+    reader: Box<dyn Read>,
+    config: &EngineConfig,
+    output: W,
+) -> Result<(), EngineError> {
+    config.validate()?;
+    let run = process_stream(
+        csv_transactions(reader, config),
+        config.worker_count(),
+        config,
+    )?;
+    write_output(run.shards, config, None, output)
+}
 
-    // Create worker threads and channels
-    let (workers, senders) = create_worker_pool(num_workers);
+/// Stream transactions out of CSV input
+pub(crate) fn csv_transactions(
+    reader: Box<dyn Read>,
+    config: &EngineConfig,
+) -> impl Iterator<Item = Result<Transaction, EngineError>> + use<> {
+    deserialize_rows(reader, config, &["client", "tx"])
+}
 
-    // Stream CSV and route transactions to workers
-    route_transactions(path, &senders, num_workers)?;
+/// Stream transactions out of CSV input with string client identifiers, interning each one
+fn string_client_transactions<'a>(
+    reader: Box<dyn Read>,
+    config: &EngineConfig,
+    interner: &'a mut ClientInterner,
+) -> impl Iterator<Item = Result<Transaction, EngineError>> + 'a {
+    deserialize_rows::<StringClientRecord>(reader, config, &["tx"])
+        .map(move |result| result?.into_transaction(interner))
+}
 
-    // Shutdown workers and collect results
-    let all_states = shutdown_and_collect(workers, senders)?;
+/// Route transactions to appropriate worker threads, returning the number routed
+///
+/// Messages are sent with `try_send`. When a worker's bounded channel is full the message waits
+/// in that worker's spill buffer, behind any already waiting so per-client order is kept, and
+/// routing carries on with the next transaction. Each later message for the worker first tries
+/// to drain its buffer. Only when a buffer reaches `config.spill_limit` does the router block on
+/// that one worker, which bounds memory at `channel_capacity + spill_limit` messages per worker.
+/// Whatever is still spilled at the end of the input is drained round-robin across workers.
+fn route_transactions<I>(
+    transactions: I,
+    senders: &[WorkerSender],
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<EngineStats, EngineError>
+where
+    I: Iterator<Item = Result<Transaction, EngineError>>,
+{
+    let mut router = Router::new(senders, num_workers, config);
+
+    // Stream transactions and route to workers; stopping at the row limit rather than on the
+    // row after it means a source that never ends can't hold the run up
+    for result in transactions.take(rows_to_read(config)) {
+        let transaction = match result {
+            // Flush what was read so far below, as at the end of the input
+            Err(EngineError::TimedOut { .. }) if config.allow_partial => {
+                router.stats.timed_out = true;
+                break;
+            }
+            result => result?,
+        };
+        router.route(transaction)?;
+    }
 
-    // Write output
-    write_output(&all_states)?;
+    router.finish()
+}
 
-    Ok(())
+/// Per-worker batches and spill buffers of the router, and what it has counted so far
+struct Router<'a> {
+    senders: &'a [WorkerSender],
+    num_workers: usize,
+    config: &'a EngineConfig,
+    /// Only used when batch_size > 1; a worker applies a batch in order, so per-client order
+    /// holds within and across batches
+    batches: Vec<Vec<Transaction>>,
+    spills: Vec<VecDeque<WorkerMessage>>,
+    stats: EngineStats,
 }
 
-/// Create worker thread pool with one channel per worker
-fn create_worker_pool(num_workers: usize) -> WorkerPool {
-    let mut workers = Vec::with_capacity(num_workers);
-    let mut senders = Vec::with_capacity(num_workers);
+impl<'a> Router<'a> {
+    fn new(senders: &'a [WorkerSender], num_workers: usize, config: &'a EngineConfig) -> Self {
+        Self {
+            senders,
+            num_workers,
+            config,
+            batches: vec![Vec::new(); num_workers],
+            spills: (0..num_workers).map(|_| VecDeque::new()).collect(),
+            stats: EngineStats::default(),
+        }
+    }
 
-    for worker_id in 0..num_workers {
-        let (tx, rx) = channel::<WorkerMessage>();
-        senders.push(tx);
+    /// Count a row read from the input, failing once it is past `max_rows`
+    fn count_row(&mut self, tx_type: &TransactionType) -> Result<(), EngineError> {
+        self.stats.rows += 1;
+        check_row_limit(self.stats.rows, self.config)?;
+        self.stats.record_transaction(tx_type);
+        Ok(())
+    }
 
-        let handle = thread::spawn(move || worker_thread(worker_id, rx));
+    /// Count `transaction` and pass it on to the worker that owns its client
+    fn route(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        self.count_row(&transaction.tx_type)?;
 
-        workers.push(handle);
-    }
+        if !self.config.processes_client(transaction.client) {
+            self.stats.filtered += 1;
+            return Ok(());
+        }
 
-    (workers, senders)
-}
+        let worker_id = worker_for(transaction.client, self.num_workers, self.config)?;
 
-/// Worker thread that processes transactions for assigned clients
-fn worker_thread(
-    worker_id: usize,
-    receiver: std::sync::mpsc::Receiver<WorkerMessage>,
-) -> HashMap<u16, ClientState> {
-    let mut client_states: HashMap<u16, ClientState> = HashMap::new();
+        if self.config.batch_size <= 1 {
+            return self.dispatch(worker_id, WorkerMessage::Transaction(transaction));
+        }
 
-    // Process messages until shutdown
-    while let Ok(message) = receiver.recv() {
-        match message {
-            WorkerMessage::Transaction(transaction) => {
-                let client_id = transaction.client;
+        let batch_size = self.config.batch_size;
+        let batch = &mut self.batches[worker_id];
+        batch.push(transaction);
+        if batch.len() >= batch_size {
+            let full = std::mem::replace(batch, Vec::with_capacity(batch_size));
+            self.dispatch(worker_id, WorkerMessage::Batch(full))?;
+        }
+        Ok(())
+    }
+
+    fn dispatch(&mut self, worker_id: usize, message: WorkerMessage) -> Result<(), EngineError> {
+        spill_to_worker(
+            &self.senders[worker_id],
+            &mut self.spills[worker_id],
+            message,
+            self.config.spill_limit,
+        )
+    }
+
+    /// Hand over everything held back for `worker_id`, waiting on its channel as needed, so
+    /// whatever is sent to the worker next arrives behind it
+    fn flush_worker(&mut self, worker_id: usize) -> Result<(), EngineError> {
+        let batch = std::mem::take(&mut self.batches[worker_id]);
+        if !batch.is_empty() {
+            self.dispatch(worker_id, WorkerMessage::Batch(batch))?;
+        }
+        for message in self.spills[worker_id].drain(..) {
+            send_to_worker(&self.senders[worker_id], message)?;
+        }
+        Ok(())
+    }
 
-                // Get or create client state
-                let state = client_states
-                    .entry(client_id)
-                    .or_insert_with(|| ClientState::new(client_id));
+    /// Send what is left once the input is done, returning the router's statistics
+    fn finish(mut self) -> Result<EngineStats, EngineError> {
+        let config = self.config;
+        self.stats.row_limit_reached = config.truncate_rows_at() == Some(self.stats.rows)
+            && config.stop_after_row != Some(self.stats.rows);
+
+        // Flush partially filled batches
+        for worker_id in 0..self.num_workers {
+            let batch = std::mem::take(&mut self.batches[worker_id]);
+            if !batch.is_empty() {
+                self.dispatch(worker_id, WorkerMessage::Batch(batch))?;
+            }
+        }
 
-                // Process transaction
-                process_single_transaction(state, transaction);
+        // Hand over everything still spilled without waiting on any one worker, since a worker
+        // that is behind may be waiting for another to make progress
+        let (senders, spills) = (self.senders, &mut self.spills);
+        while spills.iter().any(|spill| !spill.is_empty()) {
+            let mut progressed = false;
+            for (sender, spill) in senders.iter().zip(spills.iter_mut()) {
+                let before = spill.len();
+                drain_spill(sender, spill, usize::MAX)?;
+                progressed |= spill.len() < before;
             }
-            WorkerMessage::Shutdown => {
-                break;
+            if !progressed {
+                let waiting = Instant::now();
+                thread::sleep(Duration::from_micros(50));
+                let waited = waiting.elapsed();
+                // Charged to every worker still holding up the router
+                for (sender, spill) in senders.iter().zip(spills.iter()) {
+                    if !spill.is_empty() {
+                        sender.pressure.record(waited);
+                    }
+                }
             }
         }
+
+        Ok(self.stats)
     }
+}
 
-    //this will provide log info without compromising stdout required format
-    eprintln!(
-        "Worker {} processed {} clients",
-        worker_id,
-        client_states.len()
+/// Rows the router tallies by client before checking whether one of them is hot
+const HOT_CLIENT_WINDOW: u32 = 4096;
+
+/// Chunks queued per hot client helper before the router waits for it
+const HELPER_QUEUE_DEPTH: usize = 4;
+
+/// `route_transactions` over raw CSV records, handing the rows of a hot client to
+/// `config.hot_client_helpers` threads to parse
+///
+/// The router reads only the client of each row until one client makes up at least half of a
+/// window of `HOT_CLIENT_WINDOW` rows. That client stays hot for the rest of the input: what
+/// was already routed for its worker is flushed, then its rows are numbered and sent in
+/// chunks of `batch_size` to the helpers in turn. Each helper parses its chunks and sends them
+/// straight to the owning worker. The rows of other clients on that worker are parsed by the
+/// router and numbered in the same sequence, and the worker applies all of them in number
+/// order however the chunks arrive, so it sees exactly the transactions, in exactly the order,
+/// the router would have sent it. Rows for every other worker are routed as usual.
+fn route_csv_records(
+    reader: Box<dyn Read>,
+    senders: &[WorkerSender],
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<EngineStats, EngineError> {
+    let mut reader = csv_reader(reader, config);
+    let headers = reader.headers()?.clone();
+    let decoder = Arc::new(RowDecoder::new(headers.clone(), config, &["client", "tx"]));
+
+    let mut router = Router::new(senders, num_workers, config);
+    let mut helpers = None;
+    let read = route_records(
+        reader.into_records(),
+        &headers,
+        &decoder,
+        &mut router,
+        &mut helpers,
     );
-    client_states
+    // A helper only fails on a row the router had already read, so its error comes first
+    let parsed = helpers.map_or(Ok(()), HotClientHelpers::finish);
+    parsed.and(read)?;
+    router.finish()
 }
 
-/// Route transactions from CSV to appropriate worker threads
-fn route_transactions(
-    path: &str,
-    senders: &[Sender<WorkerMessage>],
-    num_workers: usize,
-) -> Result<(), Box<dyn Error>> {
-    let file = File::open(path)?;
-    let buf_reader = BufReader::with_capacity(16 * 1024 * 1024, file);
+fn route_records<I>(
+    records: I,
+    headers: &StringRecord,
+    decoder: &Arc<RowDecoder>,
+    router: &mut Router<'_>,
+    helpers: &mut Option<HotClientHelpers>,
+) -> Result<(), EngineError>
+where
+    I: Iterator<Item = Result<StringRecord, csv::Error>>,
+{
+    let config = router.config;
+    let position = |column| headers.iter().position(|name| is_column(name, column));
+    let (client_index, type_index) = (position("client"), position("type"));
+    let mut window: FastHashMap<ClientId, u32> = FastHashMap::default();
+    let mut window_rows = 0;
+
+    for result in records.take(rows_to_read(config)) {
+        let record = match result.map_err(EngineError::from) {
+            // Flush what was read so far, as at the end of the input
+            Err(EngineError::TimedOut { .. }) if config.allow_partial => {
+                router.stats.timed_out = true;
+                break;
+            }
+            result => result?,
+        };
+        let field = |index: Option<usize>| index.and_then(|index| record.get(index));
+        // Rows of filtered clients, or whose client doesn't parse, are left to the router
+        let Some(client) = field(client_index)
+            .and_then(|field| field.parse::<ClientId>().ok())
+            .filter(|&client| config.processes_client(client))
+        else {
+            router.route(decoder.decode(record)?)?;
+            continue;
+        };
+
+        match helpers {
+            Some(helpers) if helpers.client == client => {
+                // A type the decoder can't read fails the run on the router, as it would
+                // without helpers
+                let Some(tx_type) = field(type_index).and_then(TransactionType::from_name) else {
+                    router.route(decoder.decode(record)?)?;
+                    continue;
+                };
+                router.count_row(&tx_type)?;
+                helpers.push(record)?;
+            }
+            Some(helpers) => {
+                let transaction: Transaction = decoder.decode(record)?;
+                if worker_for(client, router.num_workers, config)? == helpers.owner {
+                    router.count_row(&transaction.tx_type)?;
+                    helpers.push_parsed(transaction)?;
+                } else {
+                    router.route(transaction)?;
+                }
+            }
+            None => {
+                *window.entry(client).or_default() += 1;
+                window_rows += 1;
+                router.route(decoder.decode(record)?)?;
+                if window_rows < HOT_CLIENT_WINDOW {
+                    continue;
+                }
+                let hot = window
+                    .iter()
+                    .find(|&(_, &rows)| rows * 2 >= window_rows)
+                    .map(|(&client, _)| client);
+                window.clear();
+                window_rows = 0;
+                if let Some(client) = hot {
+                    let owner = worker_for(client, router.num_workers, config)?;
+                    router.flush_worker(owner)?;
+                    *helpers = Some(HotClientHelpers::spawn(
+                        client,
+                        owner,
+                        &router.senders[owner],
+                        decoder,
+                        config,
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
 
-    let mut csv_reader = ReaderBuilder::new()
-        .trim(csv::Trim::All)
-        .from_reader(buf_reader);
+/// Threads parsing the rows of one hot client for the worker that owns it
+struct HotClientHelpers {
+    client: ClientId,
+    /// The worker that owns `client`, and its channel
+    owner: usize,
+    owner_sender: WorkerSender,
+    chunk: Vec<(u64, StringRecord)>,
+    /// Rows of the owner's other clients, already parsed by the router
+    parsed: Vec<(u64, Transaction)>,
+    chunk_size: usize,
+    next_sequence: u64,
+    queues: Vec<Sender<Vec<(u64, StringRecord)>>>,
+    next_queue: usize,
+    threads: Vec<thread::JoinHandle<()>>,
+    /// The first row any helper failed to parse, by number, and why
+    failure: Arc<Mutex<Option<(u64, EngineError)>>>,
+}
+
+impl HotClientHelpers {
+    fn spawn(
+        client: ClientId,
+        owner_id: usize,
+        owner: &WorkerSender,
+        decoder: &Arc<RowDecoder>,
+        config: &EngineConfig,
+    ) -> Self {
+        let failure: Arc<Mutex<Option<(u64, EngineError)>>> = Arc::default();
+        let (queues, threads) = (0..config.hot_client_helpers)
+            .map(|_| {
+                let (queue, chunks) = bounded::<Vec<(u64, StringRecord)>>(HELPER_QUEUE_DEPTH);
+                let (owner, decoder, failure) =
+                    (owner.clone(), Arc::clone(decoder), Arc::clone(&failure));
+                let thread = thread::spawn(move || {
+                    for chunk in chunks {
+                        let mut parsed = Vec::with_capacity(chunk.len());
+                        for (sequence, record) in chunk {
+                            match decoder.decode(record) {
+                                Ok(transaction) => parsed.push((sequence, transaction)),
+                                Err(e) => {
+                                    let mut failure = failure.lock().unwrap();
+                                    if failure.as_ref().is_none_or(|&(first, _)| sequence < first) {
+                                        *failure = Some((sequence, e));
+                                    }
+                                    return;
+                                }
+                            }
+                        }
+                        if send_to_worker(&owner, WorkerMessage::Sequenced(parsed)).is_err() {
+                            return;
+                        }
+                    }
+                });
+                (queue, thread)
+            })
+            .unzip();
+        Self {
+            client,
+            owner: owner_id,
+            owner_sender: owner.clone(),
+            chunk: Vec::with_capacity(config.batch_size),
+            parsed: Vec::new(),
+            chunk_size: config.batch_size,
+            next_sequence: 0,
+            queues,
+            next_queue: 0,
+            threads,
+            failure,
+        }
+    }
+
+    /// Number the hot client's next row and queue it for a helper
+    fn push(&mut self, record: StringRecord) -> Result<(), EngineError> {
+        self.chunk.push((self.next_sequence, record));
+        self.next_sequence += 1;
+        if self.chunk.len() >= self.chunk_size {
+            self.send_chunk()?;
+        }
+        Ok(())
+    }
+
+    /// Number a row of another of the owner's clients, parsed by the router, and pass it on
+    /// to the owner with the next rows like it
+    fn push_parsed(&mut self, transaction: Transaction) -> Result<(), EngineError> {
+        self.parsed.push((self.next_sequence, transaction));
+        self.next_sequence += 1;
+        if self.parsed.len() >= self.chunk_size {
+            self.send_parsed()?;
+        }
+        Ok(())
+    }
+
+    fn send_parsed(&mut self) -> Result<(), EngineError> {
+        let parsed = std::mem::take(&mut self.parsed);
+        send_to_worker(&self.owner_sender, WorkerMessage::Sequenced(parsed))
+    }
+
+    fn send_chunk(&mut self) -> Result<(), EngineError> {
+        let chunk = std::mem::replace(&mut self.chunk, Vec::with_capacity(self.chunk_size));
+        // Rows after a failed one would only pile up at the worker waiting for it
+        let helper_exited = self.failure.lock().unwrap().is_some()
+            || self.queues[self.next_queue].send(chunk).is_err();
+        if helper_exited {
+            return Err(self
+                .take_failure()
+                .unwrap_or_else(|| EngineError::Worker("hot client helper exited".to_string())));
+        }
+        self.next_queue = (self.next_queue + 1) % self.queues.len();
+        Ok(())
+    }
+
+    fn take_failure(&self) -> Option<EngineError> {
+        self.failure.lock().unwrap().take().map(|(_, e)| e)
+    }
+
+    /// Hand over the last rows and wait for the helpers to pass everything on to the worker
+    fn finish(mut self) -> Result<(), EngineError> {
+        let sent = if self.chunk.is_empty() {
+            Ok(())
+        } else {
+            self.send_chunk()
+        };
+        let sent = if self.parsed.is_empty() {
+            sent
+        } else {
+            sent.and(self.send_parsed())
+        };
+        self.queues.clear();
+        for thread in self.threads.drain(..) {
+            thread
+                .join()
+                .map_err(|_| EngineError::Worker("hot client helper panicked".to_string()))?;
+        }
+        match self.take_failure() {
+            Some(e) => Err(e),
+            None => sent,
+        }
+    }
+}
+
+/// Queue a message behind the worker's spill buffer and drain the buffer
+fn spill_to_worker(
+    sender: &WorkerSender,
+    spill: &mut VecDeque<WorkerMessage>,
+    message: WorkerMessage,
+    spill_limit: usize,
+) -> Result<(), EngineError> {
+    spill.push_back(message);
+    drain_spill(sender, spill, spill_limit)
+}
+
+/// Send as much of the spill buffer as the worker's channel accepts without waiting,
+/// blocking only while the buffer holds more than `spill_limit` messages
+fn drain_spill(
+    sender: &WorkerSender,
+    spill: &mut VecDeque<WorkerMessage>,
+    spill_limit: usize,
+) -> Result<(), EngineError> {
+    while let Some(message) = spill.pop_front() {
+        match sender.channel.try_send(message) {
+            Ok(()) => {}
+            Err(TrySendError::Full(message)) if spill.len() < spill_limit => {
+                spill.push_front(message);
+                break;
+            }
+            Err(TrySendError::Full(message)) => send_to_worker(sender, message)?,
+            Err(TrySendError::Disconnected(_)) => {
+                return Err(EngineError::Worker(
+                    "Failed to send to worker: worker disconnected".to_string(),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
 
-    // Stream transactions and route to workers
-    for result in csv_reader.deserialize() {
-        let transaction: Transaction = result?;
+/// Send each starting account to the worker that owns its client
+/// Starting ledgers from `EngineConfig::initial_balances` or `EngineConfig::resume_from`
+pub(crate) fn read_seeds(config: &EngineConfig) -> Result<Vec<ClientLedger>, EngineError> {
+    if let Some(path) = &config.resume_from {
+        return read_snapshot(path);
+    }
+    Ok(match &config.initial_balances {
+        Some(path) => read_initial_balances(path, config)?
+            .into_iter()
+            .map(ClientLedger::from_account)
+            .collect(),
+        None => Vec::new(),
+    })
+}
 
-        // Route based on client ID - ensures same client always goes to same worker
-        let worker_id = (transaction.client as usize) % num_workers;
+fn seed_accounts(
+    seeds: Vec<ClientLedger>,
+    senders: &[WorkerSender],
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<(), EngineError> {
+    for ledger in seeds {
+        let client = ledger.account().client;
+        if !config.processes_client(client) {
+            continue;
+        }
+        let worker_id = worker_for(client, num_workers, config)?;
+        send_to_worker(&senders[worker_id], WorkerMessage::Seed(ledger))?;
+    }
+    Ok(())
+}
 
-        senders[worker_id]
-            .send(WorkerMessage::Transaction(transaction))
-            .map_err(|e| format!("Failed to send to worker: {}", e))?;
+/// Route based on client ID - ensures same client always goes to same worker
+pub(crate) fn worker_for(
+    client: ClientId,
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<usize, EngineError> {
+    let worker_id = config.shard_strategy.worker_for(client, num_workers);
+    if worker_id >= num_workers {
+        return Err(EngineError::InvalidConfig(format!(
+            "shard strategy {:?} sent client {} to worker {} of {}",
+            config.shard_strategy, client, worker_id, num_workers
+        )));
     }
+    Ok(worker_id)
+}
 
+/// Send, waiting while the worker's channel is full and recording the wait as backpressure
+fn send_to_worker(sender: &WorkerSender, message: WorkerMessage) -> Result<(), EngineError> {
+    let disconnected =
+        || EngineError::Worker("Failed to send to worker: worker disconnected".into());
+    let message = match sender.channel.try_send(message) {
+        Ok(()) => return Ok(()),
+        Err(TrySendError::Full(message)) => message,
+        Err(TrySendError::Disconnected(_)) => return Err(disconnected()),
+    };
+    let waiting = Instant::now();
+    sender.channel.send(message).map_err(|_| disconnected())?;
+    sender.pressure.record(waiting.elapsed());
     Ok(())
 }
 
-/// Shutdown workers and collect all client states
+/// Shutdown workers and collect each worker's sorted shard of client states
+/// along with their combined statistics and trace
+/// With `on_shard`, workers are joined in the order they finish and each shard is handed to it
+/// right away; the run still lists shards and statistics by worker id
 fn shutdown_and_collect(
-    workers: Vec<thread::JoinHandle<HashMap<u16, ClientState>>>,
-    senders: Vec<Sender<WorkerMessage>>,
-) -> Result<HashMap<u16, ClientState>, Box<dyn Error>> {
+    workers: Vec<WorkerHandle>,
+    senders: Vec<WorkerSender>,
+    finished: Receiver<usize>,
+    on_shard: Option<ShardSink<'_>>,
+) -> Result<EngineRun, EngineError> {
     // Send shutdown signal to all workers
     for sender in senders {
-        let _ = sender.send(WorkerMessage::Shutdown);
+        let _ = sender.channel.send(WorkerMessage::Shutdown);
     }
 
-    // Collect results from all workers
-    let mut all_states = HashMap::new();
-
-    for worker in workers {
-        let worker_states = worker.join().map_err(|_| "Worker thread panicked")?;
+    let joined = match on_shard {
+        Some(on_shard) => join_as_finished(workers, finished, on_shard)?,
+        None => workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .map_err(|_| EngineError::Worker("Worker thread panicked".to_string()))
+            })
+            .collect::<Result<Vec<_>, _>>()?,
+    };
 
-        // Merge worker results
-        all_states.extend(worker_states);
+    // Collect results from all workers
+    let mut run = EngineRun {
+        shards: Vec::with_capacity(joined.len()),
+        stats: EngineStats::default(),
+        client_names: None,
+        trace: Vec::new(),
+        journal: Vec::new(),
+        overlay_journal: Vec::new(),
+        pre_overlay: None,
+        provenance: None,
+    };
+
+    for (shard, state) in joined {
+        if let Some(store) = state.store {
+            store.into_result()?;
+        }
+        run.shards.push(shard);
+        run.stats.merge(&state.stats);
+        // Only the worker owning the explained client records any steps
+        run.trace.extend(state.trace);
+        run.journal.extend(state.journal);
     }
 
-    Ok(all_states)
+    Ok(run)
 }
 
-fn process_single_transaction(state: &mut ClientState, transaction: Transaction) {
-    if !transaction.is_valid() {
-        return;
+/// Join `workers` in the order they finish, handing each shard to `on_shard` as it arrives,
+/// and return the results by worker id
+/// Each worker's id arrives on `finished` as it ends; the first panic or `on_shard` error ends
+/// the wait, leaving the remaining workers to exit on their own
+fn join_as_finished(
+    workers: Vec<WorkerHandle>,
+    finished: Receiver<usize>,
+    on_shard: ShardSink<'_>,
+) -> Result<Vec<(Vec<ClientLedger>, WorkerState)>, EngineError> {
+    let mut joined: Vec<Option<_>> = workers.iter().map(|_| None).collect();
+    let mut pending: Vec<_> = workers.into_iter().map(Some).collect();
+    for worker_id in finished.iter().take(pending.len()) {
+        let Some(worker) = pending[worker_id].take() else {
+            continue;
+        };
+        let (shard, state) = worker
+            .join()
+            .map_err(|_| EngineError::Worker("Worker thread panicked".to_string()))?;
+        on_shard(&shard)?;
+        joined[worker_id] = Some((shard, state));
     }
+    Ok(joined.into_iter().flatten().collect())
+}
 
-    let account = &mut state.account;
-    let tx_history = &mut state.tx_history;
-
-    if account.locked && !transaction.is_dispute_action() {
-        return;
-    }
+/// K-way merge over per-worker shards, yielding client states in ascending client id order
+/// Each shard must be sorted; workers own disjoint clients so ids never tie across shards
+/// `key` gives the client id of an item, so the merge works over ledgers and accounts alike
+struct ShardMerge<I: Iterator> {
+    shards: Vec<I>,
+    heads: Vec<Option<I::Item>>,
+    order: BinaryHeap<Reverse<(ClientId, usize)>>,
+    key: fn(&I::Item) -> ClientId,
+}
 
-    match transaction.tx_type {
-        TransactionType::Deposit => {
-            if let Some(amount) = transaction.amount {
-                account.available += amount;
-                account.total += amount;
+impl<I: Iterator> ShardMerge<I> {
+    fn new(mut shards: Vec<I>, key: fn(&I::Item) -> ClientId) -> Self {
+        let mut heads = Vec::with_capacity(shards.len());
+        let mut order = BinaryHeap::with_capacity(shards.len());
 
-                tx_history.insert(
-                    transaction.tx,
-                    TransactionRecord {
-                        amount,
-                        disputed: false,
-                        is_deposit: true, // Mark as deposit
-                    },
-                );
+        for (index, shard) in shards.iter_mut().enumerate() {
+            let head = shard.next();
+            if let Some(item) = &head {
+                order.push(Reverse((key(item), index)));
             }
+            heads.push(head);
         }
 
-        TransactionType::Withdrawal => {
-            if let Some(amount) = transaction.amount
-                && account.available >= amount
-            {
-                account.available -= amount;
-                account.total -= amount;
-
-                tx_history.insert(
-                    transaction.tx,
-                    TransactionRecord {
-                        amount,
-                        disputed: false,
-                        is_deposit: false, // Mark as withdrawal
-                    },
-                );
-            }
+        Self {
+            shards,
+            heads,
+            order,
+            key,
         }
+    }
+}
 
-        TransactionType::Dispute => {
-            if let Some(record) = tx_history.get_mut(&transaction.tx)
-                && !record.disputed
-            {
-                if record.is_deposit {
-                    // Disputing a deposit: hold the deposited funds
-                    // available decreases, held increases, total unchanged
-                    account.available -= record.amount;
-                    account.held += record.amount;
-                } else {
-                    // Disputing a withdrawal: reverse the withdrawal but hold funds
-                    // available unchanged, held increases, total increases
-                    account.held += record.amount;
-                    account.total += record.amount;
-                }
-                record.disputed = true;
-            }
+impl<I: Iterator> Iterator for ShardMerge<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        let Reverse((_, index)) = self.order.pop()?;
+        let next = self.shards[index].next();
+        if let Some(item) = &next {
+            self.order.push(Reverse(((self.key)(item), index)));
         }
+        std::mem::replace(&mut self.heads[index], next)
+    }
+}
 
-        TransactionType::Resolve => {
-            if let Some(record) = tx_history.get_mut(&transaction.tx)
-                && record.disputed
-            {
-                if record.is_deposit {
-                    // Resolving a deposit dispute: release held funds
-                    // available increases, held decreases, total unchanged
-                    account.available += record.amount;
-                    account.held -= record.amount;
-                } else {
-                    // Resolving a withdrawal dispute: withdrawal was legitimate
-                    // available unchanged, held decreases, total decreases
-                    account.held -= record.amount;
-                    account.total -= record.amount;
-                }
-                record.disputed = false;
-            }
+/// Write results to stdout in CSV format
+/// Rows are streamed from a K-way merge of the sorted worker shards, so output is globally
+/// sorted by client id without building a combined map first
+/// Header and fields are written explicitly so only `config.output_columns()` appear, in that
+/// order; in multi-currency mode each client has one row per currency
+/// With `client_names`, the client column shows the original identifier instead of the interned id
+/// With `EngineConfig::verify_output`, the rows are written to memory and read back first, and
+/// only reach `output` once they match
+pub(crate) fn write_output<W: Write>(
+    shards: Vec<Vec<ClientLedger>>,
+    config: &EngineConfig,
+    client_names: Option<&[String]>,
+    mut output: W,
+) -> Result<(), EngineError> {
+    if !config.verify_output {
+        return write_rows(shards, config, client_names, output);
+    }
+    // Clients are unique across shards, so a stable sort gives the merged row order
+    let mut expected: Vec<ClientAccount> = shards
+        .iter()
+        .flatten()
+        .flat_map(ClientLedger::output_accounts)
+        .cloned()
+        .collect();
+    expected.sort_by_key(|account| account.client);
+
+    let mut written = Vec::new();
+    write_rows(shards, config, client_names, &mut written)?;
+    check_written_output(&written, &expected, config, client_names)?;
+    output.write_all(&written)?;
+    output.flush()?;
+    Ok(())
+}
+
+fn write_rows<W: Write>(
+    shards: Vec<Vec<ClientLedger>>,
+    config: &EngineConfig,
+    client_names: Option<&[String]>,
+    output: W,
+) -> Result<(), EngineError> {
+    let mut writer = ShardWriter::new(output, config, client_names);
+    let shards = shards.into_iter().map(Vec::into_iter).collect();
+    writer.write(ShardMerge::new(shards, |state| state.account().client))?;
+    writer.finish()
+}
+
+/// Writes account rows in `config.output_columns()`, a batch of client states at a time
+/// The header goes out with the first batch, or on `finish` if there was none
+struct ShardWriter<'a, W: Write> {
+    writer: Writer<W>,
+    columns: Vec<AccountColumn>,
+    config: &'a EngineConfig,
+    client_names: Option<&'a [String]>,
+    header_written: bool,
+}
+
+impl<'a, W: Write> ShardWriter<'a, W> {
+    fn new(output: W, config: &'a EngineConfig, client_names: Option<&'a [String]>) -> Self {
+        Self {
+            writer: Writer::from_writer(output),
+            columns: config.output_columns(),
+            config,
+            client_names,
+            header_written: false,
         }
+    }
 
-        TransactionType::Chargeback => {
-            if let Some(record) = tx_history.get(&transaction.tx)
-                && record.disputed
-            {
-                if record.is_deposit {
-                    // Chargeback on deposit: remove held funds
-                    // held decreases, total decreases, lock account
-                    account.held -= record.amount;
-                    account.total -= record.amount;
-                } else {
-                    // Chargeback on withdrawal: withdrawal was fraudulent, return funds
-                    // held decreases, available increases, total unchanged, lock account
-                    account.held -= record.amount;
-                    account.available += record.amount;
-                }
-                account.locked = true;
+    /// Write the rows of `states`, in the order given
+    fn write<S>(&mut self, states: impl IntoIterator<Item = S>) -> Result<(), EngineError>
+    where
+        S: std::borrow::Borrow<ClientLedger>,
+    {
+        self.write_header()?;
+        for state in states {
+            for account in state.borrow().output_accounts() {
+                self.writer.write_record(self.columns.iter().map(|column| {
+                    match (column, self.client_names) {
+                        (AccountColumn::Client, Some(names)) => {
+                            names[account.client as usize].clone()
+                        }
+                        _ => account.field(*column, self.config.amount_scale),
+                    }
+                }))?;
             }
         }
+        Ok(())
     }
-}
 
-/// Write results to stdout in CSV format
-fn write_output(client_states: &HashMap<u16, ClientState>) -> Result<(), Box<dyn Error>> {
-    let mut writer = Writer::from_writer(std::io::stdout());
+    fn flush(&mut self) -> Result<(), EngineError> {
+        self.writer.flush()?;
+        Ok(())
+    }
 
-    let mut client_ids: Vec<u16> = client_states.keys().copied().collect();
-    client_ids.sort_unstable();
+    /// Write the header if no batch did, and flush
+    fn finish(mut self) -> Result<(), EngineError> {
+        self.write_header()?;
+        self.flush()
+    }
 
-    for client_id in client_ids {
-        if let Some(state) = client_states.get(&client_id) {
-            writer.serialize(&state.account)?;
+    fn write_header(&mut self) -> Result<(), EngineError> {
+        if !self.header_written {
+            self.writer
+                .write_record(self.columns.iter().map(|column| column.name()))?;
+            self.header_written = true;
         }
+        Ok(())
     }
-
-    writer.flush()?;
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{ModuloShard, ShardStrategy};
 
     #[test]
     fn test_worker_processes_transactions() {
-        let (tx, rx) = channel();
+        let (tx, rx) = unbounded();
 
         // Send transactions
         tx.send(WorkerMessage::Transaction(Transaction {
@@ -302,6 +2649,8 @@ mod tests {
             client: 1,
             tx: 1,
             amount: Some(100.0),
+            currency: None,
+            timestamp: None,
         }))
         .unwrap();
 
@@ -310,21 +2659,33 @@ mod tests {
             client: 1,
             tx: 2,
             amount: Some(50.0),
+            currency: None,
+            timestamp: None,
         }))
         .unwrap();
 
         tx.send(WorkerMessage::Shutdown).unwrap();
 
-        let states = worker_thread(0, rx);
+        let states = worker_thread(
+            0,
+            WorkerState::default(),
+            rx,
+            never(),
+            &EngineConfig::default(),
+            &ChannelPressure::default(),
+            &AtomicU64::default(),
+        )
+        .client_states
+        .take();
 
         assert_eq!(states.len(), 1);
-        let state = states.get(&1).unwrap();
-        assert_eq!(state.account.available, 150.0);
+        let state = &states[0];
+        assert_eq!(state.account().available, 150.0);
     }
 
     #[test]
     fn test_transaction_ordering() {
-        let (tx, rx) = channel();
+        let (tx, rx) = unbounded();
 
         // These must be processed in order
         tx.send(WorkerMessage::Transaction(Transaction {
@@ -332,6 +2693,8 @@ mod tests {
             client: 1,
             tx: 1,
             amount: Some(100.0),
+            currency: None,
+            timestamp: None,
         }))
         .unwrap();
 
@@ -340,26 +2703,187 @@ mod tests {
             client: 1,
             tx: 2,
             amount: Some(30.0),
+            currency: None,
+            timestamp: None,
+        }))
+        .unwrap();
+
+        tx.send(WorkerMessage::Shutdown).unwrap();
+
+        let states = worker_thread(
+            0,
+            WorkerState::default(),
+            rx,
+            never(),
+            &EngineConfig::default(),
+            &ChannelPressure::default(),
+            &AtomicU64::default(),
+        )
+        .client_states
+        .take();
+        let state = &states[0];
+
+        assert_eq!(state.account().available, 70.0);
+    }
+
+    fn deposit(client: ClientId, tx: u32, amount: f64) -> WorkerMessage {
+        WorkerMessage::Transaction(Transaction {
+            tx_type: TransactionType::Deposit,
+            client,
+            tx,
+            amount: Some(amount),
+            currency: None,
+            timestamp: None,
+        })
+    }
+
+    /// Worker thread fed by fresh data and control channels
+    fn spawn_worker() -> (
+        Sender<WorkerMessage>,
+        ControlSender,
+        thread::JoinHandle<WorkerState>,
+    ) {
+        let (tx, rx) = unbounded();
+        let (control_tx, control_rx) = unbounded();
+        let handle = thread::spawn(move || {
+            worker_thread(
+                0,
+                WorkerState::default(),
+                rx,
+                control_rx,
+                &EngineConfig::default(),
+                &ChannelPressure::default(),
+                &AtomicU64::default(),
+            )
+        });
+        (tx, control_tx, handle)
+    }
+
+    fn flush(control: &ControlSender) -> Vec<ClientAccount> {
+        let (reply, accounts) = bounded(1);
+        control.send(ControlMessage::Flush(reply)).unwrap();
+        accounts.recv().unwrap()
+    }
+
+    #[test]
+    fn test_worker_flush_command() {
+        let (tx, control, handle) = spawn_worker();
+        tx.send(deposit(2, 1, 5.0)).unwrap();
+        tx.send(deposit(1, 2, 7.5)).unwrap();
+        tx.send(deposit(2, 3, 1.0)).unwrap();
+
+        // A flush may overtake queued transactions, so wait until all three are applied
+        let accounts = loop {
+            let accounts = flush(&control);
+            if accounts.iter().map(|account| account.total).sum::<f64>() == 13.5 {
+                break accounts;
+            }
+            thread::yield_now();
+        };
+        assert_eq!(accounts.len(), 2);
+        assert_eq!((accounts[0].client, accounts[0].available), (1, 7.5));
+        assert_eq!((accounts[1].client, accounts[1].available), (2, 6.0));
+
+        // The worker keeps running after a flush
+        tx.send(WorkerMessage::Shutdown).unwrap();
+        assert_eq!(handle.join().unwrap().client_states.len(), 2);
+    }
+
+    #[test]
+    fn test_worker_stats_command() {
+        let (tx, control, handle) = spawn_worker();
+        tx.send(deposit(1, 1, 5.0)).unwrap();
+        tx.send(WorkerMessage::Transaction(Transaction {
+            tx_type: TransactionType::Withdrawal,
+            client: 1,
+            tx: 2,
+            amount: Some(9.0),
+            currency: None,
+            timestamp: None,
         }))
         .unwrap();
 
+        let stats = loop {
+            let (reply, stats) = bounded(1);
+            control.send(ControlMessage::Stats(reply)).unwrap();
+            let stats = stats.recv().unwrap();
+            if stats.rejected_count("insufficient_funds") == 1 {
+                break stats;
+            }
+            thread::yield_now();
+        };
+        assert_eq!(stats.rejected.len(), 1);
+
+        tx.send(WorkerMessage::Shutdown).unwrap();
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn test_worker_pause_and_resume_commands() {
+        let (tx, control, handle) = spawn_worker();
+        control.send(ControlMessage::Pause).unwrap();
+        // Commands are served in order, so once this flush answers the worker is paused
+        assert!(flush(&control).is_empty());
+
+        tx.send(deposit(1, 1, 5.0)).unwrap();
         tx.send(WorkerMessage::Shutdown).unwrap();
+        thread::sleep(Duration::from_millis(20));
+
+        // Paused: the queued deposit and shutdown stay queued, but commands are still served
+        assert!(flush(&control).is_empty());
+        assert!(!handle.is_finished());
 
-        let states = worker_thread(0, rx);
-        let state = states.get(&1).unwrap();
+        control.send(ControlMessage::Resume).unwrap();
+        let state = handle.join().unwrap();
+        assert_eq!(state.client_states.take()[0].account().available, 5.0);
+    }
+
+    #[test]
+    fn test_worker_abort_command_skips_queue() {
+        let (tx, control, handle) = spawn_worker();
+        control.send(ControlMessage::Pause).unwrap();
+        tx.send(deposit(1, 1, 5.0)).unwrap();
+        control.send(ControlMessage::Abort).unwrap();
+
+        // Exits without a shutdown message and without applying the queued deposit
+        let state = handle.join().unwrap();
+        assert!(state.client_states.is_empty());
+    }
 
-        assert_eq!(state.account.available, 70.0);
+    #[test]
+    fn test_engine_handle_drop_mid_run_stops_workers() {
+        // Every worker holds a clone of the config, and with it the shard strategy
+        let strategy: Arc<dyn ShardStrategy> = Arc::new(ModuloShard);
+        let config = EngineConfig {
+            shard_strategy: Arc::clone(&strategy),
+            ..Default::default()
+        };
+        let engine = EngineHandle::spawn(2, &config, None);
+        for tx in 0..200_000u32 {
+            let worker_id = tx as usize % 2;
+            engine.senders()[worker_id]
+                .channel
+                .send(deposit(worker_id as ClientId, tx, 1.0))
+                .unwrap();
+        }
+
+        let dropped = Instant::now();
+        drop(engine);
+        assert!(dropped.elapsed() < WORKER_JOIN_TIMEOUT);
+        assert_eq!(Arc::strong_count(&strategy), 2);
     }
 
     #[test]
     fn test_dispute_flow() {
-        let (tx, rx) = channel();
+        let (tx, rx) = unbounded();
 
         tx.send(WorkerMessage::Transaction(Transaction {
             tx_type: TransactionType::Deposit,
             client: 1,
             tx: 1,
             amount: Some(100.0),
+            currency: None,
+            timestamp: None,
         }))
         .unwrap();
 
@@ -368,15 +2892,195 @@ mod tests {
             client: 1,
             tx: 1,
             amount: None,
+            currency: None,
+            timestamp: None,
         }))
         .unwrap();
 
         tx.send(WorkerMessage::Shutdown).unwrap();
 
-        let states = worker_thread(0, rx);
-        let state = states.get(&1).unwrap();
+        let states = worker_thread(
+            0,
+            WorkerState::default(),
+            rx,
+            never(),
+            &EngineConfig::default(),
+            &ChannelPressure::default(),
+            &AtomicU64::default(),
+        )
+        .client_states
+        .take();
+        let state = &states[0];
+
+        assert_eq!(state.account().available, 0.0);
+        assert_eq!(state.account().held, 100.0);
+    }
+
+    #[test]
+    fn test_routing_error_terminates_workers() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,10.0\n\
+                     deposit,2,2,5.0\n\
+                     bogus,3,3,1.0\n\
+                     deposit,4,4,1.0\n";
+        let config = EngineConfig::default();
+
+        // The whole pipeline surfaces the parse error only after tearing the pool down
+        let result = process_stream(
+            csv_transactions(Box::new(input.as_bytes()), &config),
+            4,
+            &config,
+        );
+        assert!(matches!(result, Err(EngineError::Csv(_))));
+
+        // After a routing error every worker still receives shutdown and can be joined; rows
+        // are sent one at a time so the ones before the error have reached their workers
+        let config = EngineConfig {
+            channel_capacity: Some(1),
+            batch_size: 1,
+            ..config
+        };
+        let (workers, senders, _controls, finished) = create_worker_pool(
+            4,
+            &config,
+            None,
+            WorkerParts::default(),
+            &Arc::default(),
+            &[],
+            &SharedLedgers::new(4),
+        );
+        let transactions = csv_transactions(Box::new(input.as_bytes()), &config);
+        let routed = route_transactions(transactions, &senders, 4, &config);
+        assert!(routed.is_err());
+
+        let partial: Vec<ClientLedger> = shutdown_and_collect(workers, senders, finished, None)
+            .unwrap()
+            .shards
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(partial.len(), 2);
+        assert_eq!(partial[0].account().available, 10.0);
+    }
+
+    #[derive(Debug)]
+    struct PinClientOne;
+
+    impl crate::ShardStrategy for PinClientOne {
+        fn worker_for(&self, client: ClientId, num_workers: usize) -> usize {
+            match client {
+                _ if num_workers <= 1 => 0,
+                1 => 0,
+                _ => 1 + client as usize % (num_workers - 1),
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_shard_strategy_pins_client() {
+        let mut input = String::from("type,client,tx,amount\n");
+        for i in 0..200u32 {
+            input.push_str(&format!("deposit,{},{},1.0\n", i % 20, i));
+        }
+        let config = EngineConfig {
+            shard_strategy: Arc::new(PinClientOne),
+            ..Default::default()
+        };
+
+        let input = input.into_bytes();
+        let reader = Box::new(std::io::Cursor::new(input.clone()));
+        let shards = process_stream(csv_transactions(reader, &config), 4, &config)
+            .unwrap()
+            .shards;
+        let reader = Box::new(std::io::Cursor::new(input));
+        let single = process_stream(csv_transactions(reader, &config), 1, &config).unwrap();
+        assert_eq!(single.shards[0].len(), 20);
+
+        let clients = |shard: &Vec<ClientLedger>| -> Vec<ClientId> {
+            shard.iter().map(|ledger| ledger.account().client).collect()
+        };
+        assert_eq!(clients(&shards[0]), vec![1]);
+        assert!(shards[1..].iter().all(|shard| !clients(shard).contains(&1)));
+        assert_eq!(shards.iter().map(Vec::len).sum::<usize>(), 20);
+    }
+
+    #[test]
+    fn test_output_sorted_across_workers() {
+        // Clients 0..1000 in scrambled order, spread across 4 workers by modulo routing
+        let mut input = String::from("type,client,tx,amount\n");
+        for i in 0..1000u32 {
+            let client = (i * 7) % 1000;
+            input.push_str(&format!("deposit,{},{},1.0\n", client, i));
+        }
+
+        let config = EngineConfig::default();
+        let reader = Box::new(std::io::Cursor::new(input.into_bytes()));
+        let shards = process_stream(csv_transactions(reader, &config), 4, &config)
+            .unwrap()
+            .shards;
+        assert_eq!(shards.len(), 4);
 
-        assert_eq!(state.account.available, 0.0);
-        assert_eq!(state.account.held, 100.0);
+        let mut output = Vec::new();
+        write_output(shards, &config, None, &mut output).unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let clients: Vec<ClientId> = output
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').next().unwrap().parse().unwrap())
+            .collect();
+
+        assert_eq!(clients, (0..1000).collect::<Vec<ClientId>>());
+    }
+
+    #[test]
+    fn test_output_column_selection() {
+        let input = "type,client,tx,amount\n\
+                     deposit,2,1,10.5\n\
+                     deposit,1,2,3.0\n\
+                     dispute,1,2,\n\
+                     chargeback,1,2,\n";
+        let config = EngineConfig {
+            columns: vec![
+                AccountColumn::Locked,
+                AccountColumn::Client,
+                AccountColumn::Available,
+            ],
+            ..Default::default()
+        };
+
+        let transactions = csv_transactions(Box::new(input.as_bytes()), &config);
+        let shards = process_stream(transactions, 2, &config).unwrap().shards;
+
+        let mut output = Vec::new();
+        write_output(shards, &config, None, &mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "locked,client,available\ntrue,1,0.0\nfalse,2,10.5\n"
+        );
+    }
+
+    #[test]
+    fn test_output_header_and_large_balance() {
+        let input = "type,client,tx,amount\n\
+                     deposit,1,1,123456789012.5\n\
+                     deposit,2,2,0.25\n\
+                     dispute,2,2,\n\
+                     chargeback,2,2,\n";
+        let config = EngineConfig::default();
+
+        let transactions = csv_transactions(Box::new(input.as_bytes()), &config);
+        let shards = process_stream(transactions, 1, &config).unwrap().shards;
+
+        let mut output = Vec::new();
+        write_output(shards, &config, None, &mut output).unwrap();
+
+        assert_eq!(
+            output,
+            b"client,available,held,total,locked\n\
+              1,123456789012.5,0.0,123456789012.5,false\n\
+              2,0.0,0.0,0.0,true\n"
+        );
     }
 }
@@ -0,0 +1,224 @@
+//! Python bindings (feature `python`), built into a `payments_engine` extension module with
+//! `maturin develop --features python`.
+//!
+//! `process(path, **options)` runs a file through the worker pool with the GIL released and
+//! returns the accounts as a list of dicts, ready for `pandas.DataFrame`. `Engine` applies
+//! transactions one at a time. Amounts are floats rounded to 4 decimal places, the engine's
+//! precision. Options are named as the `EngineConfig` fields and policies take their CLI names.
+
+use crate::account::round_to_four;
+use crate::ledger::ApplyOutcome;
+use crate::processor::{WorkerState, apply_to_client};
+use crate::{ClientAccount, ClientId, EngineConfig, EngineError, Transaction, TransactionType};
+use pyo3::exceptions::{
+    PyException, PyFileNotFoundError, PyIsADirectoryError, PyOSError, PyValueError,
+};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::str::FromStr;
+
+pyo3::create_exception!(
+    payments_engine,
+    PaymentsEngineError,
+    PyException,
+    "An input or processing error from the engine"
+);
+pyo3::create_exception!(
+    payments_engine,
+    TransactionRejected,
+    PaymentsEngineError,
+    "A transaction refused by `Engine.apply`; `code` is the `RejectReason` code"
+);
+
+fn to_py_err(error: EngineError) -> PyErr {
+    let message = error.to_string();
+    match error {
+        EngineError::NotFound(_) | EngineError::EmptyPath => PyFileNotFoundError::new_err(message),
+        EngineError::IsDirectory(_) => PyIsADirectoryError::new_err(message),
+        EngineError::Io(_) => PyOSError::new_err(message),
+        EngineError::InvalidConfig(_) => PyValueError::new_err(message),
+        _ => PaymentsEngineError::new_err(message),
+    }
+}
+
+/// Engine options shared by `process` and `Engine`
+#[derive(Default)]
+struct Options {
+    workers: Option<usize>,
+    account_creation: Option<String>,
+    locked_deposits: Option<String>,
+    dispute_funds: Option<String>,
+    withdrawal_disputes: Option<String>,
+    withdrawal_epsilon: Option<f64>,
+    allow_redispute: Option<bool>,
+    idempotent: Option<bool>,
+    max_open_disputes: Option<usize>,
+    max_disputed_amount: Option<f64>,
+    max_dispute_age: Option<u64>,
+}
+
+impl Options {
+    fn from_kwargs(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut options = Options::default();
+        for (key, value) in kwargs.into_iter().flatten() {
+            let key: String = key.extract()?;
+            match key.as_str() {
+                "workers" => options.workers = value.extract()?,
+                "account_creation" => options.account_creation = value.extract()?,
+                "locked_deposits" => options.locked_deposits = value.extract()?,
+                "dispute_funds" => options.dispute_funds = value.extract()?,
+                "withdrawal_disputes" => options.withdrawal_disputes = value.extract()?,
+                "withdrawal_epsilon" => options.withdrawal_epsilon = value.extract()?,
+                "allow_redispute" => options.allow_redispute = value.extract()?,
+                "idempotent" => options.idempotent = value.extract()?,
+                "max_open_disputes" => options.max_open_disputes = value.extract()?,
+                "max_disputed_amount" => options.max_disputed_amount = value.extract()?,
+                "max_dispute_age" => options.max_dispute_age = value.extract()?,
+                _ => return Err(PyValueError::new_err(format!("Unknown option {}", key))),
+            }
+        }
+        Ok(options)
+    }
+
+    fn into_config(self) -> PyResult<EngineConfig> {
+        let defaults = EngineConfig::default();
+        let config = EngineConfig {
+            workers: self.workers,
+            account_creation: parse_or(self.account_creation, defaults.account_creation)?,
+            locked_deposits: parse_or(self.locked_deposits, defaults.locked_deposits)?,
+            dispute_funds: parse_or(self.dispute_funds, defaults.dispute_funds)?,
+            withdrawal_disputes: parse_or(self.withdrawal_disputes, defaults.withdrawal_disputes)?,
+            withdrawal_epsilon: self
+                .withdrawal_epsilon
+                .unwrap_or(defaults.withdrawal_epsilon),
+            allow_redispute: self.allow_redispute.unwrap_or(defaults.allow_redispute),
+            idempotent: self.idempotent.unwrap_or(defaults.idempotent),
+            max_open_disputes: self.max_open_disputes,
+            max_disputed_amount: self.max_disputed_amount,
+            max_dispute_age: self.max_dispute_age,
+            ..defaults
+        };
+        config.validate().map_err(to_py_err)?;
+        Ok(config)
+    }
+}
+
+fn parse_or<T: FromStr<Err = String>>(value: Option<String>, default: T) -> PyResult<T> {
+    value.map_or(Ok(default), |value| {
+        value.parse().map_err(PyValueError::new_err)
+    })
+}
+
+fn account_record<'py>(py: Python<'py>, account: &ClientAccount) -> PyResult<Bound<'py, PyDict>> {
+    let record = PyDict::new(py);
+    record.set_item("client", account.client)?;
+    record.set_item("available", round_to_four(account.available))?;
+    record.set_item("held", round_to_four(account.held))?;
+    record.set_item("total", round_to_four(account.total))?;
+    record.set_item("locked", account.locked)?;
+    Ok(record)
+}
+
+/// Process a transactions CSV and return the accounts sorted by client, as a list of dicts
+/// with `client`, `available`, `held`, `total` and `locked`
+#[pyfunction]
+#[pyo3(signature = (path, **options))]
+fn process<'py>(
+    py: Python<'py>,
+    path: &str,
+    options: Option<&Bound<'py, PyDict>>,
+) -> PyResult<Vec<Bound<'py, PyDict>>> {
+    let config = Options::from_kwargs(options)?.into_config()?;
+    let accounts = py
+        .detach(|| crate::process_file(path, &config))
+        .map_err(to_py_err)?;
+
+    let mut accounts: Vec<ClientAccount> = accounts.into_values().collect();
+    accounts.sort_unstable_by_key(|account| account.client);
+    accounts
+        .iter()
+        .map(|account| account_record(py, account))
+        .collect()
+}
+
+/// Applies transactions one at a time on the calling thread, under the same rules as
+/// `process`
+#[pyclass(name = "Engine")]
+struct PyEngine {
+    config: EngineConfig,
+    state: WorkerState,
+}
+
+#[pymethods]
+impl PyEngine {
+    #[new]
+    #[pyo3(signature = (**options))]
+    fn new(options: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        Ok(PyEngine {
+            config: Options::from_kwargs(options)?.into_config()?,
+            state: WorkerState::default(),
+        })
+    }
+
+    /// Apply a transaction given as a dict with `type`, `client`, `tx` and, for deposits and
+    /// withdrawals, `amount`. Returns True if it was applied and False if it changed nothing,
+    /// e.g. a dispute of an unknown tx. Raises `TransactionRejected` when it is refused.
+    fn apply(&mut self, py: Python<'_>, transaction: &Bound<'_, PyDict>) -> PyResult<bool> {
+        let item = |key: &str| {
+            transaction
+                .get_item(key)?
+                .ok_or_else(|| PyValueError::new_err(format!("Transaction has no `{}`", key)))
+        };
+        let name: String = item("type")?.extract()?;
+        let tx_type = TransactionType::from_name(&name)
+            .ok_or_else(|| PyValueError::new_err(format!("Unknown transaction type {}", name)))?;
+        let amount = match transaction.get_item("amount")? {
+            Some(amount) if !amount.is_none() => Some(amount.extract::<f64>()?),
+            _ => None,
+        };
+
+        let transaction = Transaction {
+            tx_type,
+            client: item("client")?.extract::<ClientId>()?,
+            tx: item("tx")?.extract()?,
+            amount,
+            currency: None,
+            timestamp: None,
+        };
+        match apply_to_client(&mut self.state, transaction, &self.config) {
+            ApplyOutcome::Applied => Ok(true),
+            ApplyOutcome::Ignored(_) => Ok(false),
+            ApplyOutcome::Rejected(reason) => {
+                let error = TransactionRejected::new_err(format!("Rejected: {}", reason));
+                error.value(py).setattr("code", reason.code())?;
+                Err(error)
+            }
+        }
+    }
+
+    /// Accounts so far sorted by client, in the same format as `process`
+    fn accounts<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        self.state
+            .client_states
+            .sorted()
+            .iter()
+            .map(|ledger| account_record(py, ledger.account()))
+            .collect()
+    }
+}
+
+/// Payments engine: process transaction files into client accounts
+#[pymodule]
+pub fn payments_engine(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(process, m)?)?;
+    m.add_class::<PyEngine>()?;
+    m.add(
+        "PaymentsEngineError",
+        m.py().get_type::<PaymentsEngineError>(),
+    )?;
+    m.add(
+        "TransactionRejected",
+        m.py().get_type::<TransactionRejected>(),
+    )?;
+    Ok(())
+}
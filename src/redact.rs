@@ -0,0 +1,331 @@
+//! Redacted copies of transaction files, for sharing as debug fixtures (`redact`).
+//!
+//! Every row keeps its type and position, but client and tx ids go through keyed Feistel
+//! permutations over the id's width, so each client's rows stay in order and disputes still
+//! refer to the transactions they did, while known pairs of ids give nothing away about the
+//! rest. Each client's amounts are multiplied by one whole factor from 2
+//! to 9, also picked by the key. Scaling everything a client moves by the same whole number
+//! keeps every balance comparison of that client exact in minor units, so funds checks,
+//! disputes and chargebacks come out the same; amounts that round to 0.0000 are kept as they
+//! were, since scaling could lift them over the minimum, and so are amounts whose scaled
+//! value would be beyond `MAX_MINOR_UNITS`. Only the `type`, `client`, `tx`,
+//! `amount`, `currency` and `timestamp` columns are written, under those names, as plain
+//! comma-separated CSV.
+//!
+//! Limits given as absolute amounts, such as `max_disputed_amount` or `held_threshold`, are
+//! not scaled, so the copy is checked to reproduce the original's outcomes by running both
+//! through `dry_run` under the options it was redacted with.
+
+use crate::account::{MAX_MINOR_UNITS, MINOR_UNITS_PER_UNIT, format_decimal, to_minor_units};
+use crate::input::open_transaction_input;
+use crate::processor::csv_transactions;
+use crate::transaction::is_column;
+use crate::{ClientId, DryRunReport, EngineConfig, EngineError, InputFormat, dry_run};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fs::File;
+use std::io::BufWriter;
+
+/// The permutations and amount factors derived from a redaction key
+#[derive(Debug, Clone, Copy)]
+pub struct RedactKey {
+    client_rounds: [u64; 4],
+    tx_rounds: [u64; 4],
+    factor_seed: u64,
+}
+
+impl RedactKey {
+    /// Derive everything from SHA-256 hashes of `secret`; the same secret always redacts a
+    /// file the same way
+    pub fn new(secret: &str) -> Self {
+        let words = |label: &str| -> [u64; 4] {
+            let digest = Sha256::new()
+                .chain_update(label)
+                .chain_update([0])
+                .chain_update(secret)
+                .finalize();
+            std::array::from_fn(|i| {
+                u64::from_le_bytes(digest[i * 8..i * 8 + 8].try_into().unwrap())
+            })
+        };
+        Self {
+            client_rounds: words("client"),
+            tx_rounds: words("tx"),
+            factor_seed: words("factor")[0] | 1,
+        }
+    }
+
+    /// The id `client` is written as
+    pub fn client(&self, client: ClientId) -> ClientId {
+        feistel(u64::from(client), ClientId::BITS, &self.client_rounds) as ClientId
+    }
+
+    /// The id `tx` is written as
+    pub fn tx(&self, tx: u32) -> u32 {
+        feistel(u64::from(tx), u32::BITS, &self.tx_rounds) as u32
+    }
+
+    /// The whole factor, 2 to 9, every amount of `client` is multiplied by
+    pub fn factor(&self, client: ClientId) -> i64 {
+        2 + (u64::from(client).wrapping_mul(self.factor_seed) >> 61) as i64
+    }
+
+    /// `amount` of `client` as written, or `None` to keep the original
+    fn amount(&self, client: ClientId, amount: f64) -> Option<f64> {
+        let minor_units = to_minor_units(amount);
+        if minor_units == 0 {
+            return None;
+        }
+        let scaled = minor_units.checked_mul(self.factor(client))? as f64;
+        (scaled.abs() <= MAX_MINOR_UNITS).then(|| scaled / MINOR_UNITS_PER_UNIT)
+    }
+}
+
+/// A permutation of the `bits`-wide values (`bits` even) keyed by `rounds`: four rounds of a
+/// balanced Feistel network whose round function is a keyed 64-bit mix
+fn feistel(value: u64, bits: u32, rounds: &[u64; 4]) -> u64 {
+    let half = bits / 2;
+    let mask = (1 << half) - 1;
+    let (mut left, mut right) = (value >> half, value & mask);
+    for &round in rounds {
+        (left, right) = (right, left ^ (mix(right ^ round) & mask));
+    }
+    (left << half) | right
+}
+
+/// The splitmix64 finalizer
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// What `redact` wrote and how the copy's outcomes compare with the original's
+#[derive(Debug, Clone, PartialEq)]
+pub struct RedactReport {
+    /// Rows written
+    pub rows: u64,
+    /// Ways the copy's dry run differs from the original's, with client ids in the copy's
+    /// numbering; empty when the outcomes match
+    pub differences: Vec<String>,
+}
+
+impl RedactReport {
+    /// Whether the copy rejects, locks and leaves negative the same as the original
+    pub fn is_clean(&self) -> bool {
+        self.differences.is_empty()
+    }
+}
+
+impl fmt::Display for RedactReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Redacted {} rows", self.rows)?;
+        if self.is_clean() {
+            return write!(f, "outcomes match the original");
+        }
+        write!(f, "outcomes differ from the original:")?;
+        for difference in &self.differences {
+            write!(f, "\n  {}", difference)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write a redacted copy of the CSV transactions at `input` to `output` with `key`, then dry
+/// run both and compare what they reject, lock and leave negative
+/// The input is read with `config`'s delimiter and number format. Snapshots, journals,
+/// manifests and overlays are not written or applied by the comparison.
+pub fn redact(
+    input: &str,
+    output: &str,
+    key: &RedactKey,
+    config: &EngineConfig,
+) -> Result<RedactReport, EngineError> {
+    if config.string_client_ids
+        || config.input_format != InputFormat::Csv
+        || config.resume_from.is_some()
+        || config.initial_balances.is_some()
+    {
+        return Err(EngineError::InvalidConfig(
+            "redaction rewrites CSV with numeric client ids and starts from empty accounts, \
+             since starting state would not match the new ids"
+                .to_string(),
+        ));
+    }
+    let config = EngineConfig {
+        snapshot: None,
+        journal: None,
+        manifest: None,
+        overlay: None,
+        overlay_output: None,
+        ..config.clone()
+    };
+    config.validate()?;
+
+    let rows = write_redacted(input, output, key, &config)?;
+
+    let original = dry_run(input, &config)?;
+    let redacted_config = EngineConfig {
+        delimiter: EngineConfig::default().delimiter,
+        number_format: Default::default(),
+        include_clients: config
+            .include_clients
+            .as_ref()
+            .map(|clients| clients.iter().map(|&client| key.client(client)).collect()),
+        exclude_clients: config
+            .exclude_clients
+            .iter()
+            .map(|&client| key.client(client))
+            .collect(),
+        ..config.clone()
+    };
+    let redacted = dry_run(output, &redacted_config)?;
+
+    Ok(RedactReport {
+        rows,
+        differences: compare_outcomes(&original, &redacted, key),
+    })
+}
+
+/// Columns written besides `type,client,tx,amount`, when the input has them
+const OPTIONAL_COLUMNS: [&str; 2] = ["currency", "timestamp"];
+
+fn write_redacted(
+    input: &str,
+    output: &str,
+    key: &RedactKey,
+    config: &EngineConfig,
+) -> Result<u64, EngineError> {
+    let headers = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .delimiter(config.delimiter)
+        .from_reader(open_transaction_input(input, config)?)
+        .headers()?
+        .clone();
+    let optional: Vec<bool> = OPTIONAL_COLUMNS
+        .iter()
+        .map(|column| headers.iter().any(|name| is_column(name, column)))
+        .collect();
+
+    let mut writer = csv::Writer::from_writer(BufWriter::new(File::create(output)?));
+    let header = ["type", "client", "tx", "amount"].into_iter().chain(
+        OPTIONAL_COLUMNS
+            .iter()
+            .zip(&optional)
+            .filter_map(|(column, &has)| has.then_some(*column)),
+    );
+    writer.write_record(header)?;
+
+    let mut rows = 0;
+    for transaction in csv_transactions(open_transaction_input(input, config)?, config) {
+        let transaction = transaction?;
+        let amount =
+            transaction
+                .amount
+                .map(|amount| match key.amount(transaction.client, amount) {
+                    Some(scaled) => format_decimal(scaled),
+                    None => amount.to_string(),
+                });
+        let mut record = vec![
+            transaction.tx_type.name().to_string(),
+            key.client(transaction.client).to_string(),
+            key.tx(transaction.tx).to_string(),
+            amount.unwrap_or_default(),
+        ];
+        if optional[0] {
+            record.push(
+                transaction
+                    .currency
+                    .map(|c| c.to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        if optional[1] {
+            record.push(
+                transaction
+                    .timestamp
+                    .map(|t| t.to_string())
+                    .unwrap_or_default(),
+            );
+        }
+        writer.write_record(&record)?;
+        rows += 1;
+    }
+    writer.flush()?;
+    Ok(rows)
+}
+
+/// Differences between the dry runs of the original and the copy, naming clients by their id
+/// in the copy
+fn compare_outcomes(
+    original: &DryRunReport,
+    redacted: &DryRunReport,
+    key: &RedactKey,
+) -> Vec<String> {
+    let mut differences = Vec::new();
+    if original.rejected != redacted.rejected {
+        differences.push(format!(
+            "rejections {:?} became {:?}",
+            original.rejected, redacted.rejected
+        ));
+    }
+    let clients =
+        |clients: &mut dyn Iterator<Item = ClientId>| -> BTreeSet<ClientId> { clients.collect() };
+    let locked = clients(
+        &mut original
+            .newly_locked
+            .iter()
+            .map(|&client| key.client(client)),
+    );
+    let redacted_locked = clients(&mut redacted.newly_locked.iter().copied());
+    if locked != redacted_locked {
+        differences.push(format!(
+            "locked clients {:?} became {:?}",
+            locked, redacted_locked
+        ));
+    }
+    let negative = clients(
+        &mut original
+            .negative
+            .iter()
+            .map(|account| key.client(account.client)),
+    );
+    let redacted_negative = clients(&mut redacted.negative.iter().map(|account| account.client));
+    if negative != redacted_negative {
+        differences.push(format!(
+            "clients with negative balances {:?} became {:?}",
+            negative, redacted_negative
+        ));
+    }
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feistel_permutes_the_id_width() {
+        let key = RedactKey::new("test key");
+        let mut seen = vec![false; 1 << 16];
+        for value in 0..1 << 16 {
+            let mapped = feistel(value, 16, &key.client_rounds);
+            assert!(!seen[mapped as usize]);
+            seen[mapped as usize] = true;
+        }
+        // Consecutive ids do not map a fixed distance apart, as an affine map would
+        let gaps: BTreeSet<u32> = (0..8)
+            .map(|tx| key.tx(tx + 1).wrapping_sub(key.tx(tx)))
+            .collect();
+        assert!(gaps.len() > 1);
+    }
+
+    #[test]
+    fn test_amount_beyond_limit_once_scaled_is_kept() {
+        let key = RedactKey::new("test key");
+        let largest = MAX_MINOR_UNITS / MINOR_UNITS_PER_UNIT;
+        assert_eq!(key.amount(1, largest), None);
+        assert_eq!(key.amount(1, 1.5), Some(1.5 * key.factor(1) as f64));
+    }
+}
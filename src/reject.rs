@@ -0,0 +1,117 @@
+use crate::Transaction;
+use crate::account::format_decimal;
+use std::fmt;
+use std::sync::Arc;
+
+/// Why the engine declined to apply a transaction
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    /// No account exists for the client and the `AccountCreationPolicy` does not let
+    /// this transaction create one
+    UnknownClient,
+    /// The account is locked and the transaction isn't allowed on a locked account
+    AccountLocked,
+    /// A deposit or withdrawal on an account closed by `TransactionType::Close`
+    AccountClosed,
+    /// A withdrawal exceeded the available funds by `shortfall`
+    InsufficientFunds { shortfall: f64 },
+    /// A dispute found `shortfall` less available than it needed to hold
+    /// (`DisputeFundsPolicy::RejectIfInsufficient`)
+    DisputeUnfunded { shortfall: f64 },
+    /// A deposit or withdrawal without a currency in multi-currency mode
+    MissingCurrency,
+    /// A dispute arriving `age` of the client's transactions after the transaction it
+    /// disputes, more than `EngineConfig::max_dispute_age` allows
+    DisputeExpired { age: u64 },
+    /// A deposit or withdrawal whose amount rounds to zero at 4 decimal places
+    AmountBelowMinimum,
+    /// A deposit or withdrawal reusing the tx id of one already applied to the client,
+    /// without `EngineConfig::idempotent`
+    DuplicateTransaction,
+    /// A deposit or withdrawal with no amount, e.g. in an input without an `amount` column
+    MissingAmount,
+    /// A transaction that would take a balance beyond `account::MAX_MINOR_UNITS`, where it
+    /// could no longer be held to the minor unit; the account is left as it was
+    Overflow,
+}
+
+impl RejectReason {
+    /// Stable identifier used as the key of `EngineStats::rejected`
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::UnknownClient => "unknown_client",
+            RejectReason::AccountLocked => "account_locked",
+            RejectReason::AccountClosed => "account_closed",
+            RejectReason::InsufficientFunds { .. } => "insufficient_funds",
+            RejectReason::DisputeUnfunded { .. } => "dispute_unfunded",
+            RejectReason::MissingCurrency => "missing_currency",
+            RejectReason::DisputeExpired { .. } => "dispute_expired",
+            RejectReason::AmountBelowMinimum => "amount_below_minimum",
+            RejectReason::DuplicateTransaction => "duplicate_tx",
+            RejectReason::MissingAmount => "missing_amount",
+            RejectReason::Overflow => "overflow",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RejectReason::UnknownClient => write!(f, "unknown client"),
+            RejectReason::AccountLocked => write!(f, "account locked"),
+            RejectReason::AccountClosed => write!(f, "account closed"),
+            RejectReason::MissingCurrency => write!(f, "missing currency"),
+            RejectReason::AmountBelowMinimum => write!(f, "amount below 0.0001"),
+            RejectReason::DuplicateTransaction => write!(f, "duplicate transaction id"),
+            RejectReason::MissingAmount => write!(f, "missing amount"),
+            RejectReason::Overflow => write!(f, "balance overflow"),
+            RejectReason::DisputeExpired { age } => {
+                write!(
+                    f,
+                    "dispute expired ({} transactions after the original)",
+                    age
+                )
+            }
+            RejectReason::InsufficientFunds { shortfall } => {
+                write!(
+                    f,
+                    "insufficient funds (short by {})",
+                    format_decimal(*shortfall)
+                )
+            }
+            RejectReason::DisputeUnfunded { shortfall } => {
+                write!(
+                    f,
+                    "insufficient funds to hold dispute (short by {})",
+                    format_decimal(*shortfall)
+                )
+            }
+        }
+    }
+}
+
+type RejectCallback = dyn Fn(&Transaction, &RejectReason) + Send + Sync;
+
+/// Callback invoked on the worker thread for every rejected transaction
+/// Must be cheap and non-blocking since it runs inline with settlement
+#[derive(Clone)]
+pub struct RejectHandler(Arc<RejectCallback>);
+
+impl RejectHandler {
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&Transaction, &RejectReason) + Send + Sync + 'static,
+    {
+        Self(Arc::new(callback))
+    }
+
+    pub fn call(&self, transaction: &Transaction, reason: &RejectReason) {
+        (self.0)(transaction, reason)
+    }
+}
+
+impl fmt::Debug for RejectHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RejectHandler")
+    }
+}
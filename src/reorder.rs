@@ -0,0 +1,191 @@
+//! Per-client reordering of timestamped transactions (`EngineConfig::reorder_window`).
+
+use crate::ledger::FastHashMap;
+use crate::{ClientId, Transaction};
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+/// Transactions held back per client until they can be applied in timestamp order
+///
+/// Each client holds at most `window` transactions; one more releases the earliest. Equal
+/// timestamps keep their input order, and a transaction without a timestamp first releases
+/// everything its client holds, so it applies where it appears in the input.
+#[derive(Default)]
+pub(crate) struct ReorderBuffer {
+    held: FastHashMap<ClientId, BinaryHeap<Reverse<Held>>>,
+    /// Latest timestamp released for each client, to spot transactions that came too late
+    released: FastHashMap<ClientId, u64>,
+    arrivals: u64,
+    /// Transactions released by the last call, in the order they are to be applied
+    pub(crate) due: Vec<Transaction>,
+}
+
+struct Held {
+    timestamp: u64,
+    arrival: u64,
+    transaction: Transaction,
+}
+
+impl Held {
+    fn key(&self) -> (u64, u64) {
+        (self.timestamp, self.arrival)
+    }
+}
+
+impl PartialEq for Held {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Held {}
+
+impl PartialOrd for Held {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Held {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl ReorderBuffer {
+    /// Take in the next transaction and move whatever it releases to `due`
+    /// `late` counts released transactions older than one their client already released.
+    pub(crate) fn push(&mut self, transaction: Transaction, window: usize, late: &mut u64) {
+        let client = transaction.client;
+        let Some(timestamp) = transaction.timestamp else {
+            self.release_client(client, late);
+            self.due.push(transaction);
+            return;
+        };
+
+        self.arrivals += 1;
+        let held = self.held.entry(client).or_default();
+        held.push(Reverse(Held {
+            timestamp,
+            arrival: self.arrivals,
+            transaction,
+        }));
+        if held.len() > window
+            && let Some(Reverse(earliest)) = held.pop()
+        {
+            self.release(earliest, late);
+        }
+    }
+
+    /// Move everything still held to `due`, client by client in timestamp order
+    pub(crate) fn release_all(&mut self, late: &mut u64) {
+        let mut clients: Vec<ClientId> = self.held.keys().copied().collect();
+        clients.sort_unstable();
+        for client in clients {
+            self.release_client(client, late);
+        }
+    }
+
+    fn release_client(&mut self, client: ClientId, late: &mut u64) {
+        if let Some(held) = self.held.remove(&client) {
+            for Reverse(next) in held.into_sorted_vec().into_iter().rev() {
+                self.release(next, late);
+            }
+        }
+    }
+
+    fn release(&mut self, held: Held, late: &mut u64) {
+        let last = self
+            .released
+            .entry(held.transaction.client)
+            .or_insert(held.timestamp);
+        if held.timestamp < *last {
+            *late += 1;
+        } else {
+            *last = held.timestamp;
+        }
+        self.due.push(held.transaction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TransactionType;
+
+    fn deposit(client: ClientId, tx: u32, timestamp: Option<u64>) -> Transaction {
+        Transaction {
+            tx_type: TransactionType::Deposit,
+            client,
+            tx,
+            amount: Some(1.0),
+            currency: None,
+            timestamp,
+        }
+    }
+
+    fn push_all(buffer: &mut ReorderBuffer, window: usize, input: &[Transaction]) -> Vec<u32> {
+        let mut late = 0;
+        for transaction in input {
+            buffer.push(transaction.clone(), window, &mut late);
+        }
+        buffer.release_all(&mut late);
+        buffer
+            .due
+            .drain(..)
+            .map(|transaction| transaction.tx)
+            .collect()
+    }
+
+    #[test]
+    fn test_clients_reordered_independently() {
+        let mut buffer = ReorderBuffer::default();
+        let order = push_all(
+            &mut buffer,
+            2,
+            &[
+                deposit(1, 1, Some(30)),
+                deposit(2, 2, Some(5)),
+                deposit(1, 3, Some(10)),
+                deposit(1, 4, Some(10)),
+                deposit(2, 5, Some(1)),
+                deposit(1, 6, Some(20)),
+            ],
+        );
+        // Equal timestamps keep input order; client 1 released 3 and 4 as it went past 2 held
+        assert_eq!(order, [3, 4, 6, 1, 5, 2]);
+    }
+
+    #[test]
+    fn test_missing_timestamp_releases_its_client_first() {
+        let mut buffer = ReorderBuffer::default();
+        let order = push_all(
+            &mut buffer,
+            8,
+            &[
+                deposit(1, 1, Some(9)),
+                deposit(1, 2, Some(3)),
+                deposit(1, 3, None),
+                deposit(1, 4, Some(1)),
+            ],
+        );
+        assert_eq!(order, [2, 1, 3, 4]);
+    }
+
+    #[test]
+    fn test_beyond_window_counted_late() {
+        let mut buffer = ReorderBuffer::default();
+        let mut late = 0;
+        for (tx, timestamp) in [(1, 5), (2, 6), (3, 1)] {
+            buffer.push(deposit(1, tx, Some(timestamp)), 1, &mut late);
+        }
+        buffer.release_all(&mut late);
+        let order: Vec<u32> = buffer
+            .due
+            .iter()
+            .map(|transaction| transaction.tx)
+            .collect();
+        assert_eq!(order, [1, 3, 2]);
+        assert_eq!(late, 1);
+    }
+}
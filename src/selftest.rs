@@ -0,0 +1,118 @@
+//! Built-in scenarios for `--selftest`.
+//!
+//! The cases are fixtures from `tests/inputs` and their expected outputs, compiled into the
+//! binary so a deployed engine can be checked without any files. Each one runs in memory
+//! through the same parsing, routing and output code as a normal run.
+
+use crate::EngineConfig;
+use crate::processor::process_csv_to_writer;
+
+/// One embedded scenario: CSV input and the exact output it must produce
+struct SelfTestCase {
+    name: &'static str,
+    input: &'static str,
+    expected: &'static str,
+}
+
+macro_rules! fixture {
+    ($name:literal) => {
+        SelfTestCase {
+            name: $name,
+            input: include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/inputs/",
+                $name,
+                ".csv"
+            )),
+            expected: include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/tests/expected_outputs/",
+                $name,
+                "_output.csv"
+            )),
+        }
+    };
+}
+
+const CASES: &[SelfTestCase] = &[
+    fixture!("test_multiple_clients"),
+    fixture!("test_insufficient_funds"),
+    fixture!("test_precision"),
+    fixture!("test_dispute_resolve"),
+    fixture!("test_invalid_disputes"),
+    fixture!("test_dispute_chargeback"),
+    fixture!("test_locked_account"),
+];
+
+/// Outcome of one built-in scenario
+#[derive(Debug)]
+pub struct SelfTestResult {
+    pub name: &'static str,
+    /// `Err` describes the first difference from the expected output
+    pub outcome: Result<(), String>,
+}
+
+/// Run every built-in scenario, on two workers so routing is exercised too
+pub fn run_selftest() -> Vec<SelfTestResult> {
+    let config = EngineConfig {
+        workers: Some(2),
+        // Keeps the per-worker log lines out of the report
+        deterministic: true,
+        ..Default::default()
+    };
+    CASES
+        .iter()
+        .map(|case| SelfTestResult {
+            name: case.name,
+            outcome: run_case(case, &config),
+        })
+        .collect()
+}
+
+fn run_case(case: &SelfTestCase, config: &EngineConfig) -> Result<(), String> {
+    let mut output = Vec::new();
+    process_csv_to_writer(Box::new(case.input.as_bytes()), config, &mut output)
+        .map_err(|e| e.to_string())?;
+    let output = String::from_utf8(output).map_err(|e| e.to_string())?;
+
+    let actual: Vec<&str> = output.lines().map(str::trim).collect();
+    let expected: Vec<&str> = case
+        .expected
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    if let Some((line, (want, got))) = expected
+        .iter()
+        .zip(&actual)
+        .enumerate()
+        .find(|(_, (want, got))| want != got)
+    {
+        return Err(format!(
+            "line {}: expected `{}`, got `{}`",
+            line + 1,
+            want,
+            got
+        ));
+    }
+    if actual.len() != expected.len() {
+        return Err(format!(
+            "expected {} lines, got {}",
+            expected.len(),
+            actual.len()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_selftest_passes() {
+        for result in run_selftest() {
+            assert!(result.outcome.is_ok(), "{}: {:?}", result.name, result);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Mapping of clients to workers.
+//!
+//! Every transaction of a client must reach the same worker, which is what keeps per-client
+//! order without locks. A `ShardStrategy` decides which worker that is; the default spreads
+//! clients by modulo, and `EngineConfig::shard_strategy` can replace it, e.g. to give
+//! high-volume clients a worker of their own.
+
+use crate::ClientId;
+use std::fmt;
+
+/// Decides which worker owns a client
+///
+/// `worker_for` must be a pure function of its arguments and return a value below
+/// `num_workers`; the engine rejects out-of-range answers with `EngineError::InvalidConfig`.
+///
+/// ```
+/// use payments_engine::{ClientId, EngineConfig, ShardStrategy, Transaction, TransactionType};
+/// use std::sync::Arc;
+///
+/// /// Client 1 gets worker 0 to itself, everyone else shares the rest; a single worker
+/// /// owns everyone
+/// #[derive(Debug)]
+/// struct PinVip;
+///
+/// impl ShardStrategy for PinVip {
+///     fn worker_for(&self, client: ClientId, num_workers: usize) -> usize {
+///         match client {
+///             _ if num_workers <= 1 => 0,
+///             1 => 0,
+///             _ => 1 + client as usize % (num_workers - 1),
+///         }
+///     }
+/// }
+///
+/// for workers in [1, 4] {
+///     let config = EngineConfig {
+///         workers: Some(workers),
+///         shard_strategy: Arc::new(PinVip),
+///         ..Default::default()
+///     };
+///     let deposits = (1..=3).map(|client| {
+///         Transaction::try_new(TransactionType::Deposit, client, client.into(), Some(1.0)).unwrap()
+///     });
+///     let accounts = payments_engine::process_transactions(deposits, &config).unwrap();
+///     assert_eq!(accounts.len(), 3);
+/// }
+/// ```
+pub trait ShardStrategy: fmt::Debug + Send + Sync {
+    fn worker_for(&self, client: ClientId, num_workers: usize) -> usize;
+}
+
+/// `client % num_workers`, the default strategy
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ModuloShard;
+
+impl ShardStrategy for ModuloShard {
+    fn worker_for(&self, client: ClientId, num_workers: usize) -> usize {
+        // ClientId is u16 or u32 (`wide-client-ids`), both widen losslessly to usize; a pool
+        // is never empty, but a zero divisor must not panic
+        (client as usize) % num_workers.max(1)
+    }
+}
@@ -0,0 +1,167 @@
+//! Client ledgers behind per-client locks, so a caller can move funds between clients of
+//! different workers (`Engine::transfer`) while the workers keep settling.
+//!
+//! Each worker still owns one shard, picked by the same `ShardStrategy` as routing, and is
+//! the only thread that adds or removes clients in it; for every transaction it takes the
+//! shard's read lock and then the client's own lock, both uncontended unless a transfer
+//! touches that client. A transfer takes the read locks of the two shards, then the two
+//! client locks in client id order so transfers in opposite directions can't deadlock.
+//! Nothing else in either shard waits on it, and no lock spans the whole engine.
+
+use crate::ledger::{ApplyOutcome, ClientLedger, FastHashMap, apply_transfer};
+use crate::{ClientId, EngineConfig, EngineError, RejectReason};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard};
+
+type Ledgers = FastHashMap<ClientId, Mutex<ClientLedger>>;
+
+/// One worker's clients; clones share the same ledgers
+#[derive(Clone, Default)]
+pub(crate) struct LedgerShard(Arc<RwLock<Ledgers>>);
+
+impl LedgerShard {
+    pub(crate) fn insert(&self, ledger: ClientLedger) {
+        let client = ledger.account().client;
+        self.0.write().unwrap().insert(client, Mutex::new(ledger));
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.read().unwrap().len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn contains(&self, client: ClientId) -> bool {
+        self.0.read().unwrap().contains_key(&client)
+    }
+
+    /// Hold the shard's read lock, so clients can be locked one at a time through it
+    /// Only the owning worker adds or removes clients, so its own view can't go stale
+    pub(crate) fn read(&self) -> ShardGuard<'_> {
+        ShardGuard(self.0.read().unwrap())
+    }
+
+    /// `f` of every ledger, in no particular order
+    pub(crate) fn map<T>(&self, mut f: impl FnMut(&ClientLedger) -> T) -> Vec<T> {
+        let ledgers = self.0.read().unwrap();
+        ledgers
+            .values()
+            .map(|ledger| f(&ledger.lock().unwrap()))
+            .collect()
+    }
+
+    /// Copies of the ledgers, sorted by client id
+    pub(crate) fn sorted(&self) -> Vec<ClientLedger> {
+        let mut shard = self.map(ClientLedger::clone);
+        shard.sort_unstable_by_key(|ledger| ledger.account().client);
+        shard
+    }
+
+    /// Move every ledger out, leaving the shard empty
+    pub(crate) fn take(&self) -> Vec<ClientLedger> {
+        let ledgers = std::mem::take(&mut *self.0.write().unwrap());
+        ledgers
+            .into_values()
+            .map(|ledger| ledger.into_inner().unwrap())
+            .collect()
+    }
+
+    /// Move every ledger out sorted by client id, leaving the shard empty
+    /// Runs on the worker thread so sorting happens in parallel across workers
+    pub(crate) fn take_sorted(&self) -> Vec<ClientLedger> {
+        let mut shard = self.take();
+        shard.sort_unstable_by_key(|ledger| ledger.account().client);
+        shard
+    }
+}
+
+/// A shard's read lock
+pub(crate) struct ShardGuard<'a>(RwLockReadGuard<'a, Ledgers>);
+
+impl ShardGuard<'_> {
+    /// The client's ledger, locked; `None` if the client has none
+    pub(crate) fn lock(&self, client: ClientId) -> Option<MutexGuard<'_, ClientLedger>> {
+        Some(self.0.get(&client)?.lock().unwrap())
+    }
+}
+
+/// Every worker's shard, indexed by worker id
+#[derive(Clone)]
+pub(crate) struct SharedLedgers {
+    shards: Vec<LedgerShard>,
+}
+
+impl SharedLedgers {
+    pub(crate) fn new(num_workers: usize) -> Self {
+        Self {
+            shards: (0..num_workers).map(|_| LedgerShard::default()).collect(),
+        }
+    }
+
+    pub(crate) fn shard(&self, worker_id: usize) -> LedgerShard {
+        self.shards[worker_id].clone()
+    }
+
+    /// Move `amount` from `from` to `to`, each given with the worker that owns it
+    /// Clients without a ledger in their shard are rejected as `UnknownClient`
+    pub(crate) fn transfer(
+        &self,
+        (from_worker, from): (usize, ClientId),
+        (to_worker, to): (usize, ClientId),
+        amount: f64,
+        config: &EngineConfig,
+    ) -> Result<ApplyOutcome, EngineError> {
+        if from == to {
+            return Err(EngineError::InvalidInput(format!(
+                "a transfer needs two different clients, got {} twice",
+                from
+            )));
+        }
+
+        // Shards are read-locked in worker id order and clients locked in client id order:
+        // a worker waiting to add a client blocks new readers of its shard, so two transfers
+        // taking shards in opposite orders could each wait on the other's worker. A shard
+        // holding both clients is read-locked once, for the same reason
+        let first = from_worker.min(to_worker);
+        let low = self.shards[first].0.read().map_err(poisoned)?;
+        let high = match from_worker == to_worker {
+            true => None,
+            false => Some(
+                self.shards[from_worker.max(to_worker)]
+                    .0
+                    .read()
+                    .map_err(poisoned)?,
+            ),
+        };
+        let shard_of = |worker| match &high {
+            Some(high) if worker != first => high,
+            _ => &low,
+        };
+        let (Some(source), Some(destination)) = (
+            shard_of(from_worker).get(&from),
+            shard_of(to_worker).get(&to),
+        ) else {
+            return Ok(ApplyOutcome::Rejected(RejectReason::UnknownClient));
+        };
+
+        let (mut source, mut destination) = if from < to {
+            let source = source.lock().map_err(poisoned)?;
+            (source, destination.lock().map_err(poisoned)?)
+        } else {
+            let destination = destination.lock().map_err(poisoned)?;
+            (source.lock().map_err(poisoned)?, destination)
+        };
+        Ok(apply_transfer(
+            &mut source,
+            &mut destination,
+            amount,
+            config,
+        ))
+    }
+}
+
+/// A lock left poisoned by a worker that panicked while holding it
+fn poisoned<T>(_: PoisonError<T>) -> EngineError {
+    EngineError::Worker("A worker panicked while holding a client ledger".to_string())
+}
@@ -0,0 +1,711 @@
+//! Engine state snapshots (`--snapshot` and `--resume`).
+//!
+//! A snapshot holds every client's balances and lock together with the deposits and
+//! withdrawals later disputes can refer to, so a run can pick up where an earlier one stopped.
+//! The file starts with a header, all integers little-endian:
+//!
+//! | Size | Field |
+//! |---|---|
+//! | 8 | magic `PAYSNAP\0` |
+//! | 2 | format version, `u16` |
+//! | 1 + n | engine version that wrote the file: length `u8`, then UTF-8 |
+//! | 8 | number of clients, `u64` |
+//! | 8 | number of transaction records, `u64` |
+//!
+//! followed by one entry per client:
+//!
+//! | Size | Field |
+//! |---|---|
+//! | 4 | client, `u32` |
+//! | 24 | available, held, total, `f64` each |
+//! | 1 | locked, 0 or 1 |
+//! | 1 | lock reason: 0 none, 1 chargeback, 2 dispute threshold |
+//! | 1 | closed, 0 or 1 |
+//! | 40 | applied deposits, withdrawals, disputes, resolves and chargebacks, `u64` each |
+//! | 8 | sequence number of the client's latest transaction, `u64` |
+//! | 4 | number of records, `u32` |
+//!
+//! each followed by its records:
+//!
+//! | Size | Field |
+//! |---|---|
+//! | 4 | tx, `u32` |
+//! | 8 | amount, `f64` |
+//! | 1 | dispute state: 0 none, 1 open, 2 resolved, 3 charged back |
+//! | 8 | amount held by the latest dispute, `f64` |
+//! | 1 | 1 for a deposit, 0 for a withdrawal |
+//! | 1 | 1 for a deposit that went to held on a locked account |
+//! | 8 | the client's sequence number of the transaction, `u64` |
+//!
+//! and finally the positions a consumer (`consume`) had reached in its source when the
+//! snapshot was taken, empty for a file run:
+//!
+//! | Size | Field |
+//! |---|---|
+//! | 4 | number of source offsets, `u32` |
+//! | 4 | partition, `i32`, per offset |
+//! | 8 | next offset to read, `i64`, per offset |
+//!
+//! The format version changes with any change to this layout. Files of the current version
+//! load as is, older versions go through an upgrade, and newer ones are refused.
+//!
+//! Format 1 had no lock reason and stored a `disputed` flag per record, with only `tx`,
+//! `amount`, `disputed` and the deposit flag. It is upgraded on load: a disputed record
+//! becomes an open dispute holding its full amount, any other record an undisputed one. A
+//! resolved dispute looked the same as no dispute in format 1, so it can be disputed again.
+//!
+//! Formats 1 and 2 had no transaction counts, which load as zero, and formats before 4 had no
+//! source offsets. Formats before 5 had no sequence numbers: every record loads as the
+//! client's latest transaction, so `max_dispute_age` counts from the resume. Formats before 6
+//! had no closed flag, and every account loads as open.
+
+use crate::account::TransactionCounts;
+use crate::ledger::TransactionRecord;
+use crate::{
+    ClientAccount, ClientId, ClientLedger, DisputeState, EngineError, LockReason, TransactionType,
+};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+
+/// First bytes of every snapshot file
+pub const SNAPSHOT_MAGIC: [u8; 8] = *b"PAYSNAP\0";
+
+/// Format version written by this engine
+pub const SNAPSHOT_FORMAT_VERSION: u16 = 6;
+
+/// Oldest format version that can still be upgraded on load
+pub const MIN_SNAPSHOT_FORMAT_VERSION: u16 = 1;
+
+/// Most records reserved up front for one client; a corrupt count then fails as truncated
+/// once the records run out instead of asking for gigabytes before the first one is read
+const MAX_RECORDS_RESERVED: u32 = 4096;
+
+/// Where a consumer stands in one partition of its source: the next offset it will read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceOffset {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+/// Header at the start of a snapshot file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub format_version: u16,
+    /// Version of the engine that wrote the file
+    pub engine_version: String,
+    pub clients: u64,
+    /// Deposit and withdrawal records over all clients
+    pub transactions: u64,
+}
+
+impl fmt::Display for SnapshotHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "format version: {}", self.format_version)?;
+        if self.format_version < SNAPSHOT_FORMAT_VERSION {
+            write!(f, " (upgraded to {} on load)", SNAPSHOT_FORMAT_VERSION)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "engine version: {}", self.engine_version)?;
+        writeln!(f, "clients: {}", self.clients)?;
+        write!(f, "transactions: {}", self.transactions)
+    }
+}
+
+/// Read and check the header of the snapshot at `path`
+pub fn inspect_snapshot(path: &str) -> Result<SnapshotHeader, EngineError> {
+    read_header(&mut BufReader::new(File::open(path)?))
+}
+
+/// Write `ledgers` and the source `offsets` they include as a snapshot of the current format
+/// The snapshot goes to a file beside `path`, synced and then renamed over it, so a crash or
+/// a full disk mid-write leaves the previous snapshot intact
+pub(crate) fn write_snapshot<'a>(
+    path: &str,
+    ledgers: impl Iterator<Item = &'a ClientLedger> + Clone,
+    offsets: &[SourceOffset],
+) -> Result<(), EngineError> {
+    let temp = format!("{}.tmp", path);
+    let written = File::create(&temp)
+        .map_err(EngineError::from)
+        .and_then(|file| {
+            let mut out = BufWriter::new(file);
+            write_contents(&mut out, ledgers, offsets)?;
+            out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+            Ok(())
+        });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+    std::fs::rename(&temp, path)?;
+    Ok(())
+}
+
+// `ClientId` is `u16` unless `wide-client-ids` is enabled
+#[allow(clippy::useless_conversion)]
+fn write_contents<'a>(
+    out: &mut impl Write,
+    ledgers: impl Iterator<Item = &'a ClientLedger> + Clone,
+    offsets: &[SourceOffset],
+) -> Result<(), EngineError> {
+    let header = SnapshotHeader {
+        format_version: SNAPSHOT_FORMAT_VERSION,
+        engine_version: env!("CARGO_PKG_VERSION").to_string(),
+        clients: ledgers.clone().count() as u64,
+        transactions: ledgers
+            .clone()
+            .map(|ledger| ledger.history().len() as u64)
+            .sum(),
+    };
+
+    out.write_all(&SNAPSHOT_MAGIC)?;
+    out.write_all(&header.format_version.to_le_bytes())?;
+    out.write_all(&[header.engine_version.len() as u8])?;
+    out.write_all(header.engine_version.as_bytes())?;
+    out.write_all(&header.clients.to_le_bytes())?;
+    out.write_all(&header.transactions.to_le_bytes())?;
+
+    for ledger in ledgers {
+        let account = ledger.account();
+        let history = ledger.history();
+        out.write_all(&u32::from(account.client).to_le_bytes())?;
+        for balance in [account.available, account.held, account.total] {
+            out.write_all(&balance.to_le_bytes())?;
+        }
+        out.write_all(&[
+            account.locked as u8,
+            lock_reason_code(account.lock_reason),
+            account.closed as u8,
+        ])?;
+        let counts = account.counts;
+        for count in [
+            counts.deposits,
+            counts.withdrawals,
+            counts.disputes,
+            counts.resolves,
+            counts.chargebacks,
+        ] {
+            out.write_all(&count.to_le_bytes())?;
+        }
+        out.write_all(&ledger.sequence().to_le_bytes())?;
+        out.write_all(&(history.len() as u32).to_le_bytes())?;
+        for (tx, record) in history {
+            out.write_all(&tx.to_le_bytes())?;
+            out.write_all(&record.amount.to_le_bytes())?;
+            out.write_all(&[dispute_code(record.dispute)])?;
+            out.write_all(&record.dispute_held.to_le_bytes())?;
+            out.write_all(&[record.is_deposit() as u8, record.frozen as u8])?;
+            out.write_all(&record.seq.to_le_bytes())?;
+        }
+    }
+    out.write_all(&(offsets.len() as u32).to_le_bytes())?;
+    for position in offsets {
+        out.write_all(&position.partition.to_le_bytes())?;
+        out.write_all(&position.offset.to_le_bytes())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Load every client ledger in the snapshot at `path`, upgrading older formats
+pub(crate) fn read_snapshot(path: &str) -> Result<Vec<ClientLedger>, EngineError> {
+    read_checkpoint(path).map(|(ledgers, _)| ledgers)
+}
+
+/// Load every client ledger in the snapshot at `path` along with its source offsets
+pub(crate) fn read_checkpoint(
+    path: &str,
+) -> Result<(Vec<ClientLedger>, Vec<SourceOffset>), EngineError> {
+    read_contents(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_contents(
+    input: &mut impl Read,
+) -> Result<(Vec<ClientLedger>, Vec<SourceOffset>), EngineError> {
+    let header = read_header(input)?;
+    let mut ledgers = Vec::new();
+    let mut transactions = 0u64;
+    for _ in 0..header.clients {
+        let (ledger, records) = match header.format_version {
+            1 => read_client_v1(input)?,
+            version => read_client(input, version)?,
+        };
+        transactions += records;
+        ledgers.push(ledger);
+    }
+
+    if transactions != header.transactions {
+        return Err(invalid(format!(
+            "header counts {} transactions but the file holds {}",
+            header.transactions, transactions
+        )));
+    }
+
+    let mut offsets = Vec::new();
+    if header.format_version >= 4 {
+        let count = u32::from_le_bytes(read_array(input)?);
+        for _ in 0..count {
+            offsets.push(SourceOffset {
+                partition: i32::from_le_bytes(read_array(input)?),
+                offset: i64::from_le_bytes(read_array(input)?),
+            });
+        }
+    }
+    if read_u8(input).is_ok() {
+        return Err(invalid(
+            "unexpected data at the end of the file".to_string(),
+        ));
+    }
+    Ok((ledgers, offsets))
+}
+
+fn read_header(input: &mut impl Read) -> Result<SnapshotHeader, EngineError> {
+    let mut magic = [0u8; 8];
+    read_exact(input, &mut magic)?;
+    if magic != SNAPSHOT_MAGIC {
+        return Err(invalid("not a snapshot file (bad magic bytes)".to_string()));
+    }
+
+    let format_version = u16::from_le_bytes(read_array(input)?);
+    let mut engine_version = vec![0u8; read_u8(input)? as usize];
+    read_exact(input, &mut engine_version)?;
+    let engine_version = String::from_utf8(engine_version)
+        .map_err(|_| invalid("engine version is not UTF-8".to_string()))?;
+
+    if format_version > SNAPSHOT_FORMAT_VERSION {
+        return Err(invalid(format!(
+            "format version {} was written by engine {} and is newer than this engine ({}) \
+             supports (up to {})",
+            format_version,
+            engine_version,
+            env!("CARGO_PKG_VERSION"),
+            SNAPSHOT_FORMAT_VERSION
+        )));
+    }
+    if format_version < MIN_SNAPSHOT_FORMAT_VERSION {
+        return Err(invalid(format!(
+            "unknown format version {}",
+            format_version
+        )));
+    }
+
+    Ok(SnapshotHeader {
+        format_version,
+        engine_version,
+        clients: u64::from_le_bytes(read_array(input)?),
+        transactions: u64::from_le_bytes(read_array(input)?),
+    })
+}
+
+/// A client entry of format 2 or later, with its number of records
+fn read_client(
+    input: &mut impl Read,
+    format_version: u16,
+) -> Result<(ClientLedger, u64), EngineError> {
+    let mut account = read_account(input)?;
+    let lock_reason = read_u8(input)?;
+    account.lock_reason = lock_reason_of(lock_reason)
+        .ok_or_else(|| invalid(format!("unknown lock reason {}", lock_reason)))?;
+    if format_version >= 6 {
+        account.closed = read_u8(input)? != 0;
+    }
+    if format_version >= 3 {
+        let [deposits, withdrawals, disputes, resolves, chargebacks] =
+            [(); 5].map(|()| read_array(input).map(u64::from_le_bytes));
+        account.counts = TransactionCounts {
+            deposits: deposits?,
+            withdrawals: withdrawals?,
+            disputes: disputes?,
+            resolves: resolves?,
+            chargebacks: chargebacks?,
+        };
+    }
+    let seq = if format_version >= 5 {
+        u64::from_le_bytes(read_array(input)?)
+    } else {
+        0
+    };
+
+    let count = u32::from_le_bytes(read_array(input)?);
+    let mut history = Vec::with_capacity(count.min(MAX_RECORDS_RESERVED) as usize);
+    for _ in 0..count {
+        let tx = u32::from_le_bytes(read_array(input)?);
+        let amount = f64::from_le_bytes(read_array(input)?);
+        let code = read_u8(input)?;
+        let dispute = dispute_state_of(code)
+            .ok_or_else(|| invalid(format!("unknown dispute state {}", code)))?;
+        let dispute_held = f64::from_le_bytes(read_array(input)?);
+        let [is_deposit, frozen] = read_array(input)?;
+        let record_seq = if format_version >= 5 {
+            u64::from_le_bytes(read_array(input)?)
+        } else {
+            seq
+        };
+        // Dispute ages count back from the client's sequence number
+        if record_seq > seq {
+            return Err(invalid(format!(
+                "tx {} has sequence number {}, past its client's {}",
+                tx, record_seq, seq
+            )));
+        }
+        history.push((
+            tx,
+            TransactionRecord {
+                amount,
+                dispute,
+                dispute_held,
+                kind: record_kind(is_deposit),
+                frozen: frozen != 0,
+                currency: None,
+                seq: record_seq,
+            },
+        ));
+    }
+    Ok((
+        ClientLedger::from_history(account, seq, history),
+        u64::from(count),
+    ))
+}
+
+pub(crate) fn record_kind(is_deposit: u8) -> TransactionType {
+    if is_deposit != 0 {
+        TransactionType::Deposit
+    } else {
+        TransactionType::Withdrawal
+    }
+}
+
+/// A client entry of format 1, upgraded to the current records
+fn read_client_v1(input: &mut impl Read) -> Result<(ClientLedger, u64), EngineError> {
+    let account = read_account(input)?;
+    let count = u32::from_le_bytes(read_array(input)?);
+    let mut history = Vec::with_capacity(count.min(MAX_RECORDS_RESERVED) as usize);
+    for _ in 0..count {
+        let tx = u32::from_le_bytes(read_array(input)?);
+        let amount = f64::from_le_bytes(read_array(input)?);
+        let [disputed, is_deposit] = read_array(input)?;
+        let (dispute, dispute_held) = if disputed != 0 {
+            (DisputeState::Open, amount)
+        } else {
+            (DisputeState::None, 0.0)
+        };
+        history.push((
+            tx,
+            TransactionRecord {
+                amount,
+                dispute,
+                dispute_held,
+                kind: record_kind(is_deposit),
+                frozen: false,
+                currency: None,
+                seq: 0,
+            },
+        ));
+    }
+    Ok((
+        ClientLedger::from_history(account, 0, history),
+        u64::from(count),
+    ))
+}
+
+/// Client id, balances and lock flag, the start of a client entry in every format
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+fn read_account(input: &mut impl Read) -> Result<ClientAccount, EngineError> {
+    let client = u32::from_le_bytes(read_array(input)?);
+    let client = ClientId::try_from(client).map_err(|_| {
+        invalid(format!(
+            "client {} does not fit this build's client ids (see the `wide-client-ids` feature)",
+            client
+        ))
+    })?;
+    let [available, held, total] = [(); 3].map(|()| read_array(input).map(f64::from_le_bytes));
+    Ok(ClientAccount {
+        available: available?,
+        held: held?,
+        total: total?,
+        locked: read_u8(input)? != 0,
+        ..ClientAccount::new(client)
+    })
+}
+
+pub(crate) fn lock_reason_code(reason: Option<LockReason>) -> u8 {
+    match reason {
+        None => 0,
+        Some(LockReason::Chargeback) => 1,
+        Some(LockReason::DisputeThreshold) => 2,
+    }
+}
+
+/// The lock reason written as `code`, or `None` for an unknown code
+pub(crate) fn lock_reason_of(code: u8) -> Option<Option<LockReason>> {
+    match code {
+        0 => Some(None),
+        1 => Some(Some(LockReason::Chargeback)),
+        2 => Some(Some(LockReason::DisputeThreshold)),
+        _ => None,
+    }
+}
+
+pub(crate) fn dispute_code(state: DisputeState) -> u8 {
+    match state {
+        DisputeState::None => 0,
+        DisputeState::Open => 1,
+        DisputeState::Resolved => 2,
+        DisputeState::ChargedBack => 3,
+    }
+}
+
+/// The dispute state written as `code`, or `None` for an unknown code
+pub(crate) fn dispute_state_of(code: u8) -> Option<DisputeState> {
+    match code {
+        0 => Some(DisputeState::None),
+        1 => Some(DisputeState::Open),
+        2 => Some(DisputeState::Resolved),
+        3 => Some(DisputeState::ChargedBack),
+        _ => None,
+    }
+}
+
+fn read_exact(input: &mut impl Read, buf: &mut [u8]) -> Result<(), EngineError> {
+    input.read_exact(buf).map_err(|e| match e.kind() {
+        ErrorKind::UnexpectedEof => invalid("file is truncated".to_string()),
+        _ => EngineError::Io(e),
+    })
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> Result<[u8; N], EngineError> {
+    let mut buf = [0u8; N];
+    read_exact(input, &mut buf)?;
+    Ok(buf)
+}
+
+fn read_u8(input: &mut impl Read) -> Result<u8, EngineError> {
+    read_array::<1>(input).map(|[byte]| byte)
+}
+
+fn invalid(reason: String) -> EngineError {
+    EngineError::InvalidSnapshot(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::apply_transaction;
+    use crate::{Transaction, TransactionType};
+
+    fn read_ledgers(input: &mut impl Read) -> Result<Vec<ClientLedger>, EngineError> {
+        read_contents(input).map(|(ledgers, _)| ledgers)
+    }
+
+    fn header_bytes(format_version: u16, clients: u64, transactions: u64) -> Vec<u8> {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend_from_slice(&format_version.to_le_bytes());
+        bytes.push(5);
+        bytes.extend_from_slice(b"0.0.9");
+        bytes.extend_from_slice(&clients.to_le_bytes());
+        bytes.extend_from_slice(&transactions.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut ledger = ClientLedger::new(3);
+        for (tx_type, tx, amount) in [
+            (TransactionType::Deposit, 1, Some(10.5)),
+            (TransactionType::Deposit, 2, Some(2.0)),
+            (TransactionType::Dispute, 1, None),
+            (TransactionType::Chargeback, 1, None),
+            (TransactionType::Dispute, 2, None),
+            (TransactionType::Close, 3, None),
+        ] {
+            let transaction = Transaction {
+                tx_type,
+                client: 3,
+                tx,
+                amount,
+                currency: None,
+                timestamp: None,
+            };
+            apply_transaction(&mut ledger, &transaction);
+        }
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("state.snap");
+        let path = path.to_str().unwrap();
+        let offsets = [SourceOffset {
+            partition: 2,
+            offset: 42,
+        }];
+        write_snapshot(path, [&ledger].into_iter(), &offsets).unwrap();
+
+        let header = inspect_snapshot(path).unwrap();
+        assert_eq!(header.format_version, SNAPSHOT_FORMAT_VERSION);
+        assert_eq!((header.clients, header.transactions), (1, 2));
+
+        let (loaded, loaded_offsets) = read_checkpoint(path).unwrap();
+        assert_eq!(loaded_offsets, offsets);
+        assert!(loaded[0].account().closed);
+        assert_eq!(loaded[0].account(), ledger.account());
+        assert_eq!(loaded[0].history(), ledger.history());
+        assert_eq!(loaded[0].open_disputes(), 1);
+        assert_eq!(loaded[0].sequence(), 6);
+    }
+
+    #[test]
+    fn test_failed_write_keeps_previous_snapshot() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("state.snap");
+        let path = path.to_str().unwrap();
+        let ledger = ClientLedger::new(1);
+        write_snapshot(path, [&ledger].into_iter(), &[]).unwrap();
+        assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+
+        // A directory where the temporary file goes makes the next write fail
+        std::fs::create_dir(format!("{}.tmp", path)).unwrap();
+        let second = ClientLedger::new(2);
+        assert!(write_snapshot(path, [&ledger, &second].into_iter(), &[]).is_err());
+        assert_eq!(inspect_snapshot(path).unwrap().clients, 1);
+    }
+
+    #[test]
+    fn test_format_1_upgraded_on_load() {
+        let mut bytes = header_bytes(1, 1, 2);
+        bytes.extend_from_slice(&7u32.to_le_bytes());
+        for balance in [5.0f64, 10.0, 15.0] {
+            bytes.extend_from_slice(&balance.to_le_bytes());
+        }
+        bytes.push(0);
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        for (tx, amount, disputed) in [(1u32, 10.0f64, 1u8), (2, 5.0, 0)] {
+            bytes.extend_from_slice(&tx.to_le_bytes());
+            bytes.extend_from_slice(&amount.to_le_bytes());
+            bytes.extend_from_slice(&[disputed, 1]);
+        }
+
+        let header = read_header(&mut bytes.as_slice()).unwrap();
+        assert!(header.to_string().contains(&format!(
+            "format version: 1 (upgraded to {}",
+            SNAPSHOT_FORMAT_VERSION
+        )));
+
+        let ledgers = read_ledgers(&mut bytes.as_slice()).unwrap();
+        let ledger = &ledgers[0];
+        assert_eq!(ledger.account().held, 10.0);
+        assert_eq!(ledger.dispute_state(1), Some(DisputeState::Open));
+        assert_eq!(ledger.dispute_state(2), Some(DisputeState::None));
+        assert_eq!(ledger.open_disputes(), 1);
+
+        // The upgraded dispute resolves like one opened by this engine
+        let mut ledger = ledger.clone();
+        let resolve = Transaction {
+            tx_type: TransactionType::Resolve,
+            client: 7,
+            tx: 1,
+            amount: None,
+            currency: None,
+            timestamp: None,
+        };
+        apply_transaction(&mut ledger, &resolve);
+        assert_eq!(
+            (ledger.account().available, ledger.account().held),
+            (15.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_format_2_loads_without_counts() {
+        let mut bytes = header_bytes(2, 1, 0);
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        for balance in [0.0f64, 0.0, 0.0] {
+            bytes.extend_from_slice(&balance.to_le_bytes());
+        }
+        bytes.extend_from_slice(&[1, 1]);
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+
+        let ledgers = read_ledgers(&mut bytes.as_slice()).unwrap();
+        let account = ledgers[0].account();
+        assert_eq!(account.lock_reason, Some(LockReason::Chargeback));
+        assert_eq!(account.counts, TransactionCounts::default());
+    }
+
+    #[test]
+    fn test_corrupted_header_rejected() {
+        let mut valid = header_bytes(SNAPSHOT_FORMAT_VERSION, 0, 0);
+        valid.extend_from_slice(&0u32.to_le_bytes());
+        assert!(read_ledgers(&mut valid.as_slice()).unwrap().is_empty());
+
+        let mut bad_magic = valid.clone();
+        bad_magic[0] = b'X';
+        let mut future = valid.clone();
+        future[8..10].copy_from_slice(&(SNAPSHOT_FORMAT_VERSION + 1).to_le_bytes());
+        let mut zero = valid.clone();
+        zero[8..10].copy_from_slice(&0u16.to_le_bytes());
+        let truncated = &valid[..valid.len() - 3];
+        let mut overcounted = header_bytes(SNAPSHOT_FORMAT_VERSION, 1, 0);
+        overcounted.extend_from_slice(&1u32.to_le_bytes());
+
+        for (bytes, expected) in [
+            (bad_magic.as_slice(), "bad magic"),
+            (future.as_slice(), "newer than this engine"),
+            (zero.as_slice(), "unknown format version 0"),
+            (truncated, "truncated"),
+            (overcounted.as_slice(), "truncated"),
+        ] {
+            match read_ledgers(&mut &bytes[..]) {
+                Err(EngineError::InvalidSnapshot(reason)) => {
+                    assert!(reason.contains(expected), "{}", reason)
+                }
+                other => panic!("expected {:?}, got {:?}", expected, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_record_after_client_sequence_rejected() {
+        let mut bytes = header_bytes(SNAPSHOT_FORMAT_VERSION, 1, 1);
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&[0; 24 + 3 + 40]);
+        bytes.extend_from_slice(&2u64.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&7u32.to_le_bytes());
+        bytes.extend_from_slice(&1.0f64.to_le_bytes());
+        bytes.push(0);
+        bytes.extend_from_slice(&0.0f64.to_le_bytes());
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&3u64.to_le_bytes());
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+
+        match read_ledgers(&mut bytes.as_slice()) {
+            Err(EngineError::InvalidSnapshot(reason)) => {
+                assert!(
+                    reason.contains("sequence number 3, past its client's 2"),
+                    "{}",
+                    reason
+                )
+            }
+            other => panic!("expected an invalid snapshot, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_huge_record_count_rejected() {
+        let mut current = header_bytes(SNAPSHOT_FORMAT_VERSION, 1, u64::from(u32::MAX));
+        current.extend_from_slice(&1u32.to_le_bytes());
+        current.extend_from_slice(&[0; 24 + 3 + 40 + 8]);
+        current.extend_from_slice(&u32::MAX.to_le_bytes());
+        let mut v1 = header_bytes(1, 1, u64::from(u32::MAX));
+        v1.extend_from_slice(&1u32.to_le_bytes());
+        v1.extend_from_slice(&[0; 24 + 1]);
+        v1.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        for bytes in [current, v1] {
+            match read_ledgers(&mut bytes.as_slice()) {
+                Err(EngineError::InvalidSnapshot(reason)) => {
+                    assert!(reason.contains("truncated"), "{}", reason)
+                }
+                other => panic!("expected a truncated snapshot, got {:?}", other),
+            }
+        }
+    }
+}
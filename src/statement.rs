@@ -0,0 +1,320 @@
+use crate::account::{format_decimal, round_to_four_decimals};
+use crate::input::open_transaction_input;
+use crate::ledger::{ApplyOutcome, ClientLedger, IgnoreReason, apply_normalized};
+use crate::processor::{check_row_limit, csv_transactions, read_seeds, rows_to_read};
+use crate::reorder::ReorderBuffer;
+use crate::{
+    ClientAccount, ClientId, EngineConfig, EngineError, InputFormat, RejectReason, Transaction,
+};
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+/// How `ClientStatement::write` lays out a statement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StatementFormat {
+    /// Aligned columns for reading in a terminal
+    #[default]
+    Table,
+    Csv,
+    Json,
+}
+
+impl fmt::Display for StatementFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            StatementFormat::Table => "table",
+            StatementFormat::Csv => "csv",
+            StatementFormat::Json => "json",
+        })
+    }
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "table" => Ok(StatementFormat::Table),
+            "csv" => Ok(StatementFormat::Csv),
+            "json" => Ok(StatementFormat::Json),
+            _ => Err(format!("Unknown ledger format: {}", s)),
+        }
+    }
+}
+
+/// One transaction in a `ClientStatement` and the balances right after it
+#[derive(Debug, Clone)]
+pub struct StatementEntry {
+    pub transaction: Transaction,
+    pub outcome: ApplyOutcome,
+    /// The client's account after the transaction; unchanged unless `outcome` is `Applied`
+    pub account: ClientAccount,
+}
+
+/// Every input row affecting one client, as produced by `client_statement`
+#[derive(Debug, Clone)]
+pub struct ClientStatement {
+    pub client: ClientId,
+    /// In the order the engine applied them
+    pub entries: Vec<StatementEntry>,
+    /// Final account, or `None` if the client never had one
+    pub account: Option<ClientAccount>,
+}
+
+/// Stream the input and apply the rows of `client` alone, recording each outcome
+///
+/// Dispute actions filed under another client id but naming one of this client's transactions
+/// are listed too: the engine applies them to the other client, where they are ignored, so
+/// they never change these balances. Only the one client's ledger is held in memory.
+/// Input other than CSV, string client ids and multi-currency mode are refused.
+pub fn client_statement(
+    path: &str,
+    client: ClientId,
+    config: &EngineConfig,
+) -> Result<ClientStatement, EngineError> {
+    config.validate()?;
+    if config.string_client_ids || config.multi_currency || config.input_format != InputFormat::Csv
+    {
+        return Err(EngineError::InvalidConfig(
+            "a client ledger takes CSV input with numeric client ids in a single currency"
+                .to_string(),
+        ));
+    }
+
+    let mut ledger = read_seeds(config)?
+        .into_iter()
+        .find(|ledger| ledger.account().client == client);
+    let mut entries = Vec::new();
+    let mut reorder = ReorderBuffer::default();
+    let mut late = 0;
+
+    let reader = open_transaction_input(path, config)?;
+    let mut rows = 0u64;
+    for transaction in csv_transactions(reader, config).take(rows_to_read(config)) {
+        let transaction = transaction?;
+        rows += 1;
+        check_row_limit(rows, config)?;
+
+        if transaction.client != client {
+            let names_ours = transaction.is_dispute_action()
+                && ledger
+                    .as_ref()
+                    .is_some_and(|ledger| ledger.transaction_amount(transaction.tx).is_some());
+            if names_ours {
+                entries.push(StatementEntry {
+                    transaction,
+                    outcome: ApplyOutcome::Ignored(IgnoreReason::UnknownTransaction),
+                    account: current_account(&ledger, client),
+                });
+            }
+            continue;
+        }
+
+        match config.reorder_window {
+            Some(window) => reorder.push(transaction, window, &mut late),
+            None => reorder.due.push(transaction),
+        }
+        for transaction in std::mem::take(&mut reorder.due) {
+            entries.push(apply(&mut ledger, transaction, config));
+        }
+    }
+    reorder.release_all(&mut late);
+    for transaction in std::mem::take(&mut reorder.due) {
+        entries.push(apply(&mut ledger, transaction, config));
+    }
+
+    Ok(ClientStatement {
+        client,
+        entries,
+        account: ledger.map(|ledger| ledger.account().clone()),
+    })
+}
+
+/// Apply one of the client's transactions, opening its ledger as the engine would
+fn apply(
+    ledger: &mut Option<ClientLedger>,
+    mut transaction: Transaction,
+    config: &EngineConfig,
+) -> StatementEntry {
+    let client = transaction.client;
+    let normalized = transaction.normalize_in_place();
+    let normalized = normalized.as_ref().map(|()| &transaction);
+    let outcome = match ledger {
+        Some(ledger) => apply_normalized(ledger, normalized, config),
+        None if config.account_creation.creates_account(&transaction) => {
+            apply_normalized(ledger.insert(ClientLedger::new(client)), normalized, config)
+        }
+        None => ApplyOutcome::Rejected(RejectReason::UnknownClient),
+    };
+    StatementEntry {
+        transaction,
+        outcome,
+        account: current_account(ledger, client),
+    }
+}
+
+fn current_account(ledger: &Option<ClientLedger>, client: ClientId) -> ClientAccount {
+    ledger.as_ref().map_or_else(
+        || ClientAccount::new(client),
+        |ledger| ledger.account().clone(),
+    )
+}
+
+/// A statement entry as written by the CSV and JSON formats
+#[derive(Serialize)]
+struct Row {
+    #[serde(rename = "type")]
+    tx_type: &'static str,
+    client: ClientId,
+    tx: u32,
+    amount: Option<f64>,
+    /// `applied`, `ignored` or `rejected`
+    outcome: &'static str,
+    /// Why the transaction was ignored or rejected
+    reason: Option<String>,
+    #[serde(serialize_with = "round_to_four_decimals")]
+    available: f64,
+    #[serde(serialize_with = "round_to_four_decimals")]
+    held: f64,
+    #[serde(serialize_with = "round_to_four_decimals")]
+    total: f64,
+    locked: bool,
+}
+
+impl From<&StatementEntry> for Row {
+    fn from(entry: &StatementEntry) -> Self {
+        let (outcome, reason) = match &entry.outcome {
+            ApplyOutcome::Applied => ("applied", None),
+            ApplyOutcome::Ignored(reason) => ("ignored", Some(reason.to_string())),
+            ApplyOutcome::Rejected(reason) => ("rejected", Some(reason.to_string())),
+        };
+        let transaction = &entry.transaction;
+        Self {
+            tx_type: transaction.tx_type.name(),
+            client: transaction.client,
+            tx: transaction.tx,
+            amount: transaction.amount,
+            outcome,
+            reason,
+            available: entry.account.available,
+            held: entry.account.held,
+            total: entry.account.total,
+            locked: entry.account.locked,
+        }
+    }
+}
+
+impl ClientStatement {
+    /// Write the statement to `output`
+    ///
+    /// The table and CSV formats end with a blank line and the final account in the default
+    /// output format; JSON is a single object with `client`, `entries` and `account` keys.
+    pub fn write<W: Write>(
+        &self,
+        format: StatementFormat,
+        mut output: W,
+    ) -> Result<(), EngineError> {
+        match format {
+            StatementFormat::Table => self.write_table(&mut output)?,
+            StatementFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut output);
+                if self.entries.is_empty() {
+                    writer.write_record(ROW_COLUMNS)?;
+                }
+                for entry in &self.entries {
+                    writer.serialize(Row::from(entry))?;
+                }
+                writer.flush()?;
+            }
+            StatementFormat::Json => {
+                #[derive(Serialize)]
+                struct Json<'a> {
+                    client: ClientId,
+                    entries: Vec<Row>,
+                    account: Option<&'a ClientAccount>,
+                }
+                let json = Json {
+                    client: self.client,
+                    entries: self.entries.iter().map(Row::from).collect(),
+                    account: self.account.as_ref(),
+                };
+                serde_json::to_writer_pretty(&mut output, &json).map_err(io::Error::from)?;
+                writeln!(output)?;
+                return Ok(());
+            }
+        }
+
+        writeln!(output)?;
+        writeln!(output, "client,available,held,total,locked")?;
+        if let Some(account) = &self.account {
+            writeln!(output, "{}", account.to_csv_row())?;
+        }
+        Ok(())
+    }
+
+    fn write_table<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut lines = vec![TABLE_COLUMNS.map(str::to_string).to_vec()];
+        for entry in &self.entries {
+            let row = Row::from(entry);
+            let outcome = match row.reason {
+                Some(reason) => format!("{}: {}", row.outcome, reason),
+                None => row.outcome.to_string(),
+            };
+            lines.push(vec![
+                row.tx_type.to_string(),
+                row.client.to_string(),
+                row.tx.to_string(),
+                row.amount.map(format_decimal).unwrap_or_default(),
+                outcome,
+                format_decimal(row.available),
+                format_decimal(row.held),
+                format_decimal(row.total),
+                row.locked.to_string(),
+            ]);
+        }
+
+        let mut widths = [0; TABLE_COLUMNS.len()];
+        for line in &lines {
+            for (width, field) in widths.iter_mut().zip(line) {
+                *width = (*width).max(field.len());
+            }
+        }
+        for line in &lines {
+            let padded: Vec<String> = line
+                .iter()
+                .zip(widths)
+                .map(|(field, width)| format!("{:<width$}", field))
+                .collect();
+            writeln!(output, "{}", padded.join("  ").trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+/// The table shows the reason alongside the outcome
+const TABLE_COLUMNS: [&str; 9] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "outcome",
+    "available",
+    "held",
+    "total",
+    "locked",
+];
+
+const ROW_COLUMNS: [&str; 10] = [
+    "type",
+    "client",
+    "tx",
+    "amount",
+    "outcome",
+    "reason",
+    "available",
+    "held",
+    "total",
+    "locked",
+];
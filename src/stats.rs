@@ -0,0 +1,255 @@
+use crate::{ClientId, RejectReason, TransactionType};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How much of a run's work landed on one worker, for judging shard skew
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WorkerLoad {
+    /// Transactions routed to this worker, not counting seeded accounts
+    pub rows: u64,
+    /// Distinct clients owned by this worker at the end of the run
+    pub clients: usize,
+    /// Time spent handling messages and commands
+    pub busy: Duration,
+    /// Time spent blocked waiting for the next message or while paused
+    pub idle: Duration,
+    /// Sends to this worker that found its channel full and had to wait
+    pub blocked_sends: u64,
+    /// Time the router spent waiting on those sends
+    pub blocked: Duration,
+    /// Most messages seen queued for this worker, sampled as batches arrive
+    pub max_queue_depth: usize,
+    /// Deposits and withdrawals kept for disputes at the end of the run; only counted with
+    /// `EngineConfig::profile`
+    pub records: usize,
+}
+
+/// An account locked by a dispute threshold, reported for risk review
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputeAlert {
+    pub client: ClientId,
+    /// The dispute that crossed the threshold
+    pub tx: u32,
+    /// Disputes open once it was applied
+    pub open_disputes: usize,
+    /// Their combined amount
+    pub disputed_amount: f64,
+}
+
+/// A client whose held funds went above `EngineConfig::held_threshold`, for AML review
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeldAlert {
+    pub client: ClientId,
+    /// The transaction that took held funds over the threshold
+    pub tx: u32,
+    /// Held funds once it was applied
+    pub held: f64,
+}
+
+/// Wall-clock time spent in each phase of a run, for throughput reporting
+/// Reading the clock a few times per batch rather than per row keeps the timers always on.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct PhaseTimes {
+    /// Reading, parsing and routing the input, until the last row was handed to a worker
+    pub route: Duration,
+    /// Time workers spent applying batches of transactions, summed over workers; overlaps
+    /// `route`, and transactions sent one at a time (`batch_size` 1) are not timed
+    pub settle: Duration,
+    /// Writing the output accounts; zero when the run returns them instead
+    pub write: Duration,
+    /// The whole run, from opening the input to the end of the last phase
+    pub elapsed: Duration,
+}
+
+/// Counters collected over a single engine run
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct EngineStats {
+    /// Transactions read from the input, including any skipped by the client filters
+    pub rows: u64,
+    /// Transactions dropped before routing by `include_clients` / `exclude_clients`
+    pub filtered: u64,
+    /// Routed transactions keyed by `TransactionType::name`
+    pub transaction_types: BTreeMap<&'static str, u64>,
+    /// Distinct client identifiers interned in string client id mode
+    pub interned_clients: usize,
+    /// Approximate heap bytes held by the client intern table
+    pub intern_table_bytes: usize,
+    /// Transactions that changed a ledger
+    pub applied: u64,
+    /// Deposits and withdrawals skipped as already applied (`EngineConfig::idempotent`)
+    pub already_applied: u64,
+    /// Disputes, resolves and chargebacks naming a tx id the client has no deposit or
+    /// withdrawal for, ignored as `IgnoreReason::UnknownTransaction`
+    pub orphan_disputes: u64,
+    /// Rejected transactions keyed by `RejectReason::code`
+    pub rejected: BTreeMap<&'static str, u64>,
+    /// Load of each worker, indexed by worker id
+    pub per_worker: Vec<WorkerLoad>,
+    /// Accounts locked by `max_open_disputes` / `max_disputed_amount`, in no particular order
+    pub alerts: Vec<DisputeAlert>,
+    /// Clients whose held funds went above `EngineConfig::held_threshold`, each listed once
+    /// in no particular order
+    pub suspicious: Vec<HeldAlert>,
+    /// Clients with a transaction rejected as `RejectReason::Overflow`, each listed once in
+    /// no particular order
+    pub overflowed: Vec<ClientId>,
+    /// Account events discarded because `EngineConfig::events` was full or disconnected
+    pub dropped_events: u64,
+    /// Timestamped transactions applied after a later one of the same client, having
+    /// arrived further out of order than `EngineConfig::reorder_window` could fix
+    pub late: u64,
+    /// Reading stopped at `EngineConfig::max_rows` under `RowLimitPolicy::Truncate`; the input
+    /// may have had more rows
+    pub row_limit_reached: bool,
+    /// Reading stopped at `EngineConfig::timeout` and, with `allow_partial`, the run finished
+    /// with the rows read before it
+    pub timed_out: bool,
+    /// Bytes read from the input; the file length for Parquet input
+    pub input_bytes: u64,
+    /// How long each phase of the run took
+    pub phases: PhaseTimes,
+    /// Statistics of the `EngineConfig::overlay` file alone; the other fields count the
+    /// main input
+    pub overlay: Option<Box<EngineStats>>,
+}
+
+/// Share of the router's blocked time one worker must account for to count as a hotspot
+const SKEW_BLOCKED_SHARE: f64 = 0.8;
+
+/// Width of the longest bar of `EngineStats::client_histogram`
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Blocked time below which the router is not considered held up at all
+const SKEW_MIN_BLOCKED: Duration = Duration::from_millis(10);
+
+impl EngineStats {
+    /// The worker the router spent most of its blocked time waiting on, with its share, when
+    /// that share is large enough to suggest skewed routing (e.g. one very hot client)
+    pub fn blocked_skew(&self) -> Option<(usize, f64)> {
+        let total: Duration = self.per_worker.iter().map(|load| load.blocked).sum();
+        if self.per_worker.len() < 2 || total < SKEW_MIN_BLOCKED {
+            return None;
+        }
+        let (worker_id, load) = self
+            .per_worker
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, load)| load.blocked)?;
+        let share = load.blocked.as_secs_f64() / total.as_secs_f64();
+        (share >= SKEW_BLOCKED_SHARE).then_some((worker_id, share))
+    }
+
+    /// Deposits and withdrawals kept for disputes across all workers at the end of the run;
+    /// only counted with `EngineConfig::profile`
+    pub fn stored_records(&self) -> usize {
+        self.per_worker.iter().map(|load| load.records).sum()
+    }
+
+    /// Histogram of how many clients each worker owns, one line per worker with a bar scaled
+    /// to the busiest, followed by the busiest worker's count against the mean
+    pub fn client_histogram(&self) -> String {
+        let counts: Vec<usize> = self.per_worker.iter().map(|load| load.clients).collect();
+        let max = counts.iter().copied().max().unwrap_or(0);
+        let total: usize = counts.iter().sum();
+        let mut histogram = String::from("Clients per worker:\n");
+        for (worker_id, &clients) in counts.iter().enumerate() {
+            let bar = (clients * HISTOGRAM_WIDTH).div_ceil(max.max(1));
+            histogram.push_str(&format!(
+                "  {:>6} {:>8} {}\n",
+                worker_id,
+                clients,
+                "#".repeat(bar)
+            ));
+        }
+        if total > 0 {
+            let mean = total as f64 / counts.len() as f64;
+            histogram.push_str(&format!(
+                "  busiest worker has {} clients, {:.2}x the mean of {:.1}\n",
+                max,
+                max as f64 / mean,
+                mean
+            ));
+        }
+        histogram
+    }
+
+    /// Rows read per second of the whole run
+    pub fn rows_per_second(&self) -> f64 {
+        per_second(self.rows, self.phases.elapsed)
+    }
+
+    /// Input bytes read per second of the whole run
+    pub fn input_bytes_per_second(&self) -> f64 {
+        per_second(self.input_bytes, self.phases.elapsed)
+    }
+
+    /// Clients in `suspicious`, sorted by id
+    pub fn suspicious_clients(&self) -> Vec<ClientId> {
+        let mut clients: Vec<_> = self.suspicious.iter().map(|alert| alert.client).collect();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// Count one rejection
+    pub fn record_rejection(&mut self, reason: &RejectReason) {
+        *self.rejected.entry(reason.code()).or_default() += 1;
+    }
+
+    /// Count one routed transaction of the given type
+    pub fn record_transaction(&mut self, tx_type: &TransactionType) {
+        *self.transaction_types.entry(tx_type.name()).or_default() += 1;
+    }
+
+    /// Number of transactions rejected for the given `RejectReason::code`
+    pub fn rejected_count(&self, code: &str) -> u64 {
+        self.rejected.get(code).copied().unwrap_or(0)
+    }
+
+    /// Add another set of counters, e.g. from a worker, into this one
+    pub fn merge(&mut self, other: &EngineStats) {
+        self.rows += other.rows;
+        self.filtered += other.filtered;
+        self.applied += other.applied;
+        self.already_applied += other.already_applied;
+        self.orphan_disputes += other.orphan_disputes;
+        self.dropped_events += other.dropped_events;
+        self.late += other.late;
+        self.row_limit_reached |= other.row_limit_reached;
+        self.timed_out |= other.timed_out;
+        self.input_bytes += other.input_bytes;
+        self.phases.route += other.phases.route;
+        self.phases.settle += other.phases.settle;
+        self.phases.write += other.phases.write;
+        self.phases.elapsed = self.phases.elapsed.max(other.phases.elapsed);
+        for (name, count) in &other.transaction_types {
+            *self.transaction_types.entry(name).or_default() += count;
+        }
+        for (code, count) in &other.rejected {
+            *self.rejected.entry(code).or_default() += count;
+        }
+        self.per_worker.extend(other.per_worker.iter().cloned());
+        self.alerts.extend(other.alerts.iter().cloned());
+        self.suspicious.extend(other.suspicious.iter().cloned());
+        self.overflowed.extend(other.overflowed.iter().copied());
+    }
+}
+
+/// `count` per second of `elapsed`, or 0 for a phase too short to measure
+pub(crate) fn per_second(count: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    count as f64 / elapsed.as_secs_f64()
+}
+
+/// Peak resident set size of this process in bytes, read from `VmHWM` in `/proc/self/status`
+/// `None` where that file doesn't exist or has no such line, i.e. outside Linux
+#[cfg(feature = "peak-rss")]
+pub fn peak_rss_bytes() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmHWM:"))?;
+    let kib: u64 = line.trim().strip_suffix("kB")?.trim().parse().ok()?;
+    Some(kib * 1024)
+}
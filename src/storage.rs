@@ -0,0 +1,384 @@
+//! Client ledgers kept in a store that outlives the run (`EngineConfig::storage`).
+//!
+//! With `StorageBackend::Memory`, the default, ledgers live only in the workers' shards. With
+//! a store, every worker still settles its clients in its shard, but writes each change
+//! through to the store: the account after every transaction, and the record of the deposit
+//! or withdrawal it applied or disputed. The writes of one worker message, a single
+//! transaction or a batch, go to the store as one atomic commit, so after a crash the store
+//! holds every change of some prefix of each worker's input and nothing of the rest.
+//!
+//! Once a commit lands, the records of the clients it touched leave memory; a later
+//! transaction referring to one reads it back with `LedgerStore::get_record`. Accounts stay,
+//! and a client the run hasn't touched yet is read with `LedgerStore::get_account` the first
+//! time a transaction names it. At shutdown each worker loads the accounts it owns that the
+//! run never touched, so the output covers every client in the store, and flushes the store
+//! to disk before its accounts go out.
+//!
+//! A store is keyed by client id alone, so workers, which own disjoint clients, never write
+//! the same keys, and a store can be reopened with a different number of workers.
+
+#[cfg(feature = "sled-storage")]
+mod sled;
+
+use crate::ledger::{FastHashSet, TransactionRecord};
+use crate::processor::worker_for;
+use crate::shared::LedgerShard;
+use crate::{ClientAccount, ClientId, ClientLedger, EngineConfig, EngineError, StorageBackend};
+
+/// A client's ledger as a store keeps it, without its records
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct StoredAccount {
+    pub(crate) account: ClientAccount,
+    /// `ClientLedger::sequence`
+    pub(crate) seq: u64,
+    /// Number of disputes open on the account and the sum of their amounts
+    pub(crate) open_disputes: u64,
+    pub(crate) disputed_amount: f64,
+}
+
+/// Storage for client accounts and the deposit and withdrawal records disputes refer to
+///
+/// Puts are buffered until `commit`, which writes them as one atomic batch; reads only see
+/// committed data. Workers never read a key they have put but not committed, since the
+/// account and records in question are still in memory then. `Sync` because the Python
+/// `Engine` class holds a `WorkerState`, and pyo3 classes must be shareable between threads.
+pub(crate) trait LedgerStore: Send + Sync {
+    fn get_account(&self, client: ClientId) -> Result<Option<StoredAccount>, EngineError>;
+    fn put_account(&mut self, account: &StoredAccount) -> Result<(), EngineError>;
+    fn get_record(
+        &self,
+        client: ClientId,
+        tx: u32,
+    ) -> Result<Option<TransactionRecord>, EngineError>;
+    fn put_record(
+        &mut self,
+        client: ClientId,
+        tx: u32,
+        record: &TransactionRecord,
+    ) -> Result<(), EngineError>;
+    /// Every committed account, in no particular order
+    fn accounts(&self) -> Result<Vec<StoredAccount>, EngineError>;
+    /// Write the puts since the last commit as one atomic batch
+    fn commit(&mut self) -> Result<(), EngineError>;
+    /// Commit, then wait until everything committed is on disk
+    fn flush(&mut self) -> Result<(), EngineError>;
+}
+
+/// One worker's handle on the store
+pub(crate) struct WorkerStore {
+    store: Box<dyn LedgerStore>,
+    worker_id: usize,
+    num_workers: usize,
+    /// Clients changed since the last commit, whose records leave memory once it lands
+    dirty: FastHashSet<ClientId>,
+    /// The first store operation that failed; later ones are skipped and the run fails at the
+    /// end
+    error: Option<EngineError>,
+}
+
+impl WorkerStore {
+    // Only a sled store, or the tests' `MemoryStore`, makes one
+    #[cfg_attr(not(any(feature = "sled-storage", test)), allow(dead_code))]
+    fn new(store: Box<dyn LedgerStore>, worker_id: usize, num_workers: usize) -> Self {
+        Self {
+            store,
+            worker_id,
+            num_workers,
+            dirty: FastHashSet::default(),
+            error: None,
+        }
+    }
+
+    /// Run `f` on the store, keeping its error as the first; skipped once there is one
+    fn attempt<T>(
+        &mut self,
+        f: impl FnOnce(&mut dyn LedgerStore) -> Result<T, EngineError>,
+    ) -> Option<T> {
+        if self.error.is_some() {
+            return None;
+        }
+        match f(self.store.as_mut()) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+
+    /// Bring the client's account, and its record of `tx`, from the store into `shard` unless
+    /// they are already there
+    pub(crate) fn load(&mut self, shard: &LedgerShard, client: ClientId, tx: u32) {
+        if !shard.contains(client) {
+            match self.attempt(|store| store.get_account(client)) {
+                Some(Some(stored)) => shard.insert(ClientLedger::from_stored(stored)),
+                _ => return,
+            }
+        }
+        let ledgers = shard.read();
+        let Some(mut ledger) = ledgers.lock(client) else {
+            return;
+        };
+        if ledger.record(tx).is_none()
+            && let Some(Some(record)) = self.attempt(|store| store.get_record(client, tx))
+        {
+            ledger.restore_record(tx, record);
+        }
+    }
+
+    /// Put the ledger's account, and its record of `tx` if it has one, in the next commit
+    pub(crate) fn save(&mut self, ledger: &ClientLedger, tx: u32) {
+        let client = ledger.account().client;
+        self.dirty.insert(client);
+        self.attempt(|store| store.put_account(&ledger.to_stored()));
+        if let Some(record) = ledger.record(tx) {
+            self.attempt(|store| store.put_record(client, tx, record));
+        }
+    }
+
+    /// Commit what was saved since the last commit, then drop the records of the clients it
+    /// touched from memory
+    pub(crate) fn commit(&mut self, shard: &LedgerShard) {
+        if self.dirty.is_empty() || self.attempt(|store| store.commit()).is_none() {
+            return;
+        }
+        let ledgers = shard.read();
+        for client in self.dirty.drain() {
+            if let Some(mut ledger) = ledgers.lock(client) {
+                ledger.evict_records();
+            }
+        }
+    }
+
+    /// Commit, load every account of this worker's clients the run didn't touch into `shard`,
+    /// and flush the store, once the worker has applied all its input
+    pub(crate) fn finish(&mut self, shard: &LedgerShard, config: &EngineConfig) {
+        self.commit(shard);
+        let (worker_id, num_workers) = (self.worker_id, self.num_workers);
+        let accounts = self.attempt(|store| store.accounts()).unwrap_or_default();
+        for stored in accounts {
+            let client = stored.account.client;
+            if !config.processes_client(client) || shard.contains(client) {
+                continue;
+            }
+            match worker_for(client, num_workers, config) {
+                Ok(owner) if owner == worker_id => {
+                    shard.insert(ClientLedger::from_stored(stored));
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.error.get_or_insert(e);
+                }
+            }
+        }
+        self.attempt(|store| store.flush());
+    }
+
+    /// The first store operation that failed, if any
+    pub(crate) fn into_result(self) -> Result<(), EngineError> {
+        self.error.map_or(Ok(()), Err)
+    }
+}
+
+/// One handle per worker on the `EngineConfig::storage` store, if the run has one
+pub(crate) fn worker_stores(
+    num_workers: usize,
+    config: &EngineConfig,
+) -> Result<Option<Vec<WorkerStore>>, EngineError> {
+    match &config.storage {
+        StorageBackend::Memory => Ok(None),
+        #[cfg(feature = "sled-storage")]
+        StorageBackend::Sled(path) => {
+            let db = self::sled::SledStore::open(path)?;
+            Ok(Some(
+                (0..num_workers)
+                    .map(|worker_id| WorkerStore::new(Box::new(db.clone()), worker_id, num_workers))
+                    .collect(),
+            ))
+        }
+        #[cfg(not(feature = "sled-storage"))]
+        StorageBackend::Sled(_) => {
+            let _ = num_workers;
+            Err(EngineError::InvalidConfig(
+                "a sled store needs the `sled-storage` feature".to_string(),
+            ))
+        }
+    }
+}
+
+/// Store kept in memory and shared by its clones, for the worker tests
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct MemoryStore {
+    committed: std::sync::Arc<std::sync::Mutex<MemoryContents>>,
+    pending: MemoryContents,
+}
+
+#[cfg(test)]
+#[derive(Clone, Default)]
+struct MemoryContents {
+    accounts: crate::ledger::FastHashMap<ClientId, StoredAccount>,
+    records: crate::ledger::FastHashMap<(ClientId, u32), TransactionRecord>,
+}
+
+#[cfg(test)]
+impl LedgerStore for MemoryStore {
+    fn get_account(&self, client: ClientId) -> Result<Option<StoredAccount>, EngineError> {
+        Ok(self
+            .committed
+            .lock()
+            .unwrap()
+            .accounts
+            .get(&client)
+            .cloned())
+    }
+
+    fn put_account(&mut self, account: &StoredAccount) -> Result<(), EngineError> {
+        self.pending
+            .accounts
+            .insert(account.account.client, account.clone());
+        Ok(())
+    }
+
+    fn get_record(
+        &self,
+        client: ClientId,
+        tx: u32,
+    ) -> Result<Option<TransactionRecord>, EngineError> {
+        Ok(self
+            .committed
+            .lock()
+            .unwrap()
+            .records
+            .get(&(client, tx))
+            .cloned())
+    }
+
+    fn put_record(
+        &mut self,
+        client: ClientId,
+        tx: u32,
+        record: &TransactionRecord,
+    ) -> Result<(), EngineError> {
+        self.pending.records.insert((client, tx), record.clone());
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<StoredAccount>, EngineError> {
+        Ok(self
+            .committed
+            .lock()
+            .unwrap()
+            .accounts
+            .values()
+            .cloned()
+            .collect())
+    }
+
+    fn commit(&mut self) -> Result<(), EngineError> {
+        let pending = std::mem::take(&mut self.pending);
+        let mut committed = self.committed.lock().unwrap();
+        committed.accounts.extend(pending.accounts);
+        committed.records.extend(pending.records);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), EngineError> {
+        self.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processor::{WorkerState, apply_to_client};
+    use crate::{ApplyOutcome, Transaction, TransactionType};
+
+    fn worker(store: &MemoryStore) -> WorkerState {
+        let mut worker = WorkerState::default();
+        worker.store = Some(WorkerStore::new(Box::new(store.clone()), 0, 1));
+        worker
+    }
+
+    fn apply(worker: &mut WorkerState, tx_type: TransactionType, tx: u32, amount: Option<f64>) {
+        let transaction = Transaction {
+            tx_type,
+            client: 1,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        };
+        let config = EngineConfig::default();
+        assert_eq!(
+            apply_to_client(worker, transaction, &config),
+            ApplyOutcome::Applied
+        );
+    }
+
+    fn commit(worker: &mut WorkerState) {
+        worker.store.as_mut().unwrap().commit(&worker.client_states);
+    }
+
+    fn ledger(worker: &WorkerState) -> ClientLedger {
+        worker.client_states.sorted().remove(0)
+    }
+
+    #[test]
+    fn test_records_leave_memory_once_committed_and_come_back_when_disputed() {
+        let store = MemoryStore::default();
+        let mut first = worker(&store);
+        apply(&mut first, TransactionType::Deposit, 1, Some(10.0));
+        apply(&mut first, TransactionType::Deposit, 2, Some(5.0));
+        assert_eq!(ledger(&first).record_count(), 2);
+        commit(&mut first);
+        assert_eq!(ledger(&first).record_count(), 0);
+
+        apply(&mut first, TransactionType::Dispute, 1, None);
+        assert_eq!(ledger(&first).record_count(), 1);
+        assert_eq!(ledger(&first).account().held(), 10.0);
+        commit(&mut first);
+        assert!(ledger(&first).violations().is_empty());
+
+        // A second run starts from the store, with nothing in memory
+        let mut second = worker(&store);
+        apply(&mut second, TransactionType::Resolve, 1, None);
+        let resumed = ledger(&second);
+        assert_eq!(resumed.account().held(), 0.0);
+        assert_eq!(resumed.account().available(), 15.0);
+        assert_eq!(resumed.open_disputes(), 0);
+        assert!(resumed.violations().is_empty());
+        assert_eq!(
+            store.get_record(1, 1).unwrap().unwrap().dispute,
+            crate::DisputeState::Open
+        );
+        commit(&mut second);
+        assert_eq!(
+            store.get_record(1, 1).unwrap().unwrap().dispute,
+            crate::DisputeState::Resolved
+        );
+    }
+
+    #[test]
+    fn test_finish_loads_untouched_accounts() {
+        let config = EngineConfig::default();
+        let store = MemoryStore::default();
+        let mut first = worker(&store);
+        apply(&mut first, TransactionType::Deposit, 1, Some(3.0));
+        first
+            .store
+            .as_mut()
+            .unwrap()
+            .finish(&first.client_states, &config);
+
+        let mut second = worker(&store);
+        assert!(second.client_states.is_empty());
+        second
+            .store
+            .as_mut()
+            .unwrap()
+            .finish(&second.client_states, &config);
+        assert_eq!(ledger(&second).account().total(), 3.0);
+        assert!(second.store.take().unwrap().into_result().is_ok());
+    }
+}
@@ -0,0 +1,426 @@
+//! `LedgerStore` on a sled database (`StorageBackend::Sled`).
+//!
+//! Keys start with a tag byte, then the client id and for records the tx id, all `u32`
+//! big-endian so a client's keys sort together:
+//!
+//! | Key | Value |
+//! |---|---|
+//! | `F` | format version, `u16` |
+//! | `A`, client | account |
+//! | `R`, client, tx | record |
+//!
+//! Values are little-endian, like snapshot entries. An account:
+//!
+//! | Size | Field |
+//! |---|---|
+//! | 24 | available, held, total, `f64` each |
+//! | 1 | locked, 0 or 1 |
+//! | 1 | lock reason: 0 none, 1 chargeback, 2 dispute threshold |
+//! | 1 | closed, 0 or 1 |
+//! | 40 | applied deposits, withdrawals, disputes, resolves and chargebacks, `u64` each |
+//! | 8 | sequence number of the client's latest transaction, `u64` |
+//! | 8 | number of open disputes, `u64` |
+//! | 16 | sum of the open disputes' amounts, for the ledger and the account, `f64` each |
+//!
+//! A record:
+//!
+//! | Size | Field |
+//! |---|---|
+//! | 8 | amount, `f64` |
+//! | 1 | dispute state: 0 none, 1 open, 2 resolved, 3 charged back |
+//! | 8 | amount held by the latest dispute, `f64` |
+//! | 1 | 1 for a deposit, 0 for a withdrawal |
+//! | 1 | 1 for a deposit that went to held on a locked account |
+//! | 8 | the client's sequence number of the transaction, `u64` |
+//!
+//! A store of another format version is refused.
+
+use super::{LedgerStore, StoredAccount};
+use crate::account::TransactionCounts;
+use crate::ledger::TransactionRecord;
+use crate::snapshot::{
+    dispute_code, dispute_state_of, lock_reason_code, lock_reason_of, record_kind,
+};
+use crate::{ClientAccount, ClientId, EngineError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FORMAT_KEY: &[u8] = b"F";
+const ACCOUNT_TAG: u8 = b'A';
+const RECORD_TAG: u8 = b'R';
+
+/// Layout of the keys and values; bumped with any change to them
+const STORE_FORMAT_VERSION: u16 = 3;
+
+/// How long `open` waits for the lock of a database the process has just closed, which sled
+/// releases from a background thread a moment after the last handle goes
+const LOCK_WAIT: Duration = Duration::from_secs(1);
+
+/// One worker's handle on a sled database; clones share the database but not the puts
+/// waiting for `commit`
+pub(crate) struct SledStore {
+    db: ::sled::Db,
+    batch: ::sled::Batch,
+}
+
+impl Clone for SledStore {
+    fn clone(&self) -> Self {
+        Self {
+            db: self.db.clone(),
+            batch: ::sled::Batch::default(),
+        }
+    }
+}
+
+impl SledStore {
+    /// Open the database at `path`, creating it if needed
+    pub(crate) fn open(path: &str) -> Result<Self, EngineError> {
+        let db = open_db(path)?;
+        match db.get(FORMAT_KEY).map_err(store_error)? {
+            Some(version) if version.as_ref() == STORE_FORMAT_VERSION.to_le_bytes() => {}
+            Some(version) => {
+                return Err(EngineError::Store(format!(
+                    "{} has store format {:?}, this engine reads format {}",
+                    path,
+                    version.as_ref(),
+                    STORE_FORMAT_VERSION
+                )));
+            }
+            None => {
+                db.insert(FORMAT_KEY, &STORE_FORMAT_VERSION.to_le_bytes())
+                    .map_err(store_error)?;
+            }
+        }
+        Ok(Self {
+            db,
+            batch: ::sled::Batch::default(),
+        })
+    }
+}
+
+impl LedgerStore for SledStore {
+    fn get_account(&self, client: ClientId) -> Result<Option<StoredAccount>, EngineError> {
+        self.db
+            .get(account_key(client))
+            .map_err(store_error)?
+            .map(|value| decode_account(client, &value))
+            .transpose()
+    }
+
+    fn put_account(&mut self, account: &StoredAccount) -> Result<(), EngineError> {
+        self.batch.insert(
+            &account_key(account.account.client),
+            encode_account(account),
+        );
+        Ok(())
+    }
+
+    fn get_record(
+        &self,
+        client: ClientId,
+        tx: u32,
+    ) -> Result<Option<TransactionRecord>, EngineError> {
+        self.db
+            .get(record_key(client, tx))
+            .map_err(store_error)?
+            .map(|value| decode_record(&value))
+            .transpose()
+    }
+
+    fn put_record(
+        &mut self,
+        client: ClientId,
+        tx: u32,
+        record: &TransactionRecord,
+    ) -> Result<(), EngineError> {
+        self.batch
+            .insert(&record_key(client, tx), encode_record(record));
+        Ok(())
+    }
+
+    fn accounts(&self) -> Result<Vec<StoredAccount>, EngineError> {
+        self.db
+            .scan_prefix([ACCOUNT_TAG])
+            .map(|entry| {
+                let (key, value) = entry.map_err(store_error)?;
+                decode_account(key_client(&key)?, &value)
+            })
+            .collect()
+    }
+
+    fn commit(&mut self) -> Result<(), EngineError> {
+        self.db
+            .apply_batch(std::mem::take(&mut self.batch))
+            .map_err(store_error)
+    }
+
+    fn flush(&mut self) -> Result<(), EngineError> {
+        self.commit()?;
+        self.db.flush().map_err(store_error)?;
+        Ok(())
+    }
+}
+
+// `ClientId` is `u16` unless `wide-client-ids` is enabled
+#[allow(clippy::useless_conversion)]
+fn account_key(client: ClientId) -> [u8; 5] {
+    let mut key = [ACCOUNT_TAG; 5];
+    key[1..].copy_from_slice(&u32::from(client).to_be_bytes());
+    key
+}
+
+#[allow(clippy::useless_conversion)]
+fn record_key(client: ClientId, tx: u32) -> [u8; 9] {
+    let mut key = [RECORD_TAG; 9];
+    key[1..5].copy_from_slice(&u32::from(client).to_be_bytes());
+    key[5..].copy_from_slice(&tx.to_be_bytes());
+    key
+}
+
+/// The client of an account key
+#[allow(clippy::useless_conversion, clippy::unnecessary_fallible_conversions)]
+fn key_client(key: &[u8]) -> Result<ClientId, EngineError> {
+    let client = key
+        .get(1..5)
+        .and_then(|bytes| bytes.try_into().ok())
+        .map(u32::from_be_bytes)
+        .ok_or_else(|| corrupt(format!("malformed account key {:?}", key)))?;
+    ClientId::try_from(client).map_err(|_| {
+        corrupt(format!(
+            "client {} does not fit this build's client ids (see the `wide-client-ids` feature)",
+            client
+        ))
+    })
+}
+
+fn encode_account(stored: &StoredAccount) -> Vec<u8> {
+    let account = &stored.account;
+    let counts = account.counts;
+    let mut value = Vec::with_capacity(107);
+    for balance in [account.available, account.held, account.total] {
+        value.extend_from_slice(&balance.to_le_bytes());
+    }
+    value.extend_from_slice(&[
+        account.locked as u8,
+        lock_reason_code(account.lock_reason),
+        account.closed as u8,
+    ]);
+    for count in [
+        counts.deposits,
+        counts.withdrawals,
+        counts.disputes,
+        counts.resolves,
+        counts.chargebacks,
+        stored.seq,
+        stored.open_disputes,
+    ] {
+        value.extend_from_slice(&count.to_le_bytes());
+    }
+    for amount in [stored.disputed_amount, account.open_disputed_amount] {
+        value.extend_from_slice(&amount.to_le_bytes());
+    }
+    value
+}
+
+fn decode_account(client: ClientId, value: &[u8]) -> Result<StoredAccount, EngineError> {
+    let mut fields = Fields(value);
+    let [available, held, total] = [fields.f64()?, fields.f64()?, fields.f64()?];
+    let [locked, lock_reason, closed] = fields.take()?;
+    let lock_reason = lock_reason_of(lock_reason)
+        .ok_or_else(|| corrupt(format!("unknown lock reason {}", lock_reason)))?;
+    let counts = TransactionCounts {
+        deposits: fields.u64()?,
+        withdrawals: fields.u64()?,
+        disputes: fields.u64()?,
+        resolves: fields.u64()?,
+        chargebacks: fields.u64()?,
+    };
+    let seq = fields.u64()?;
+    let open_disputes = fields.u64()?;
+    let disputed_amount = fields.f64()?;
+    let open_disputed_amount = fields.f64()?;
+    fields.end()?;
+    Ok(StoredAccount {
+        account: ClientAccount {
+            available,
+            held,
+            total,
+            locked: locked != 0,
+            lock_reason,
+            closed: closed != 0,
+            counts,
+            open_disputed_amount,
+            ..ClientAccount::new(client)
+        },
+        seq,
+        open_disputes,
+        disputed_amount,
+    })
+}
+
+fn encode_record(record: &TransactionRecord) -> Vec<u8> {
+    let mut value = Vec::with_capacity(27);
+    value.extend_from_slice(&record.amount.to_le_bytes());
+    value.push(dispute_code(record.dispute));
+    value.extend_from_slice(&record.dispute_held.to_le_bytes());
+    value.extend_from_slice(&[record.is_deposit() as u8, record.frozen as u8]);
+    value.extend_from_slice(&record.seq.to_le_bytes());
+    value
+}
+
+fn decode_record(value: &[u8]) -> Result<TransactionRecord, EngineError> {
+    let mut fields = Fields(value);
+    let amount = fields.f64()?;
+    let [code] = fields.take()?;
+    let dispute =
+        dispute_state_of(code).ok_or_else(|| corrupt(format!("unknown dispute state {}", code)))?;
+    let dispute_held = fields.f64()?;
+    let [is_deposit, frozen] = fields.take()?;
+    let seq = fields.u64()?;
+    fields.end()?;
+    Ok(TransactionRecord {
+        amount,
+        dispute,
+        dispute_held,
+        kind: record_kind(is_deposit),
+        frozen: frozen != 0,
+        currency: None,
+        seq,
+    })
+}
+
+/// Fixed-size fields read off the front of a value
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], EngineError> {
+        let (field, rest) = self
+            .0
+            .split_first_chunk::<N>()
+            .ok_or_else(|| corrupt("value is truncated".to_string()))?;
+        self.0 = rest;
+        Ok(*field)
+    }
+
+    fn u64(&mut self) -> Result<u64, EngineError> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    fn f64(&mut self) -> Result<f64, EngineError> {
+        self.take().map(f64::from_le_bytes)
+    }
+
+    fn end(&self) -> Result<(), EngineError> {
+        if self.0.is_empty() {
+            Ok(())
+        } else {
+            Err(corrupt(format!(
+                "{} bytes past the end of a value",
+                self.0.len()
+            )))
+        }
+    }
+}
+
+/// `sled::open`, retried for up to `LOCK_WAIT` while the database is locked
+fn open_db(path: &str) -> Result<::sled::Db, EngineError> {
+    let deadline = Instant::now() + LOCK_WAIT;
+    loop {
+        match ::sled::open(path) {
+            // sled reports a held lock as an `Other` I/O error, told apart only by its message
+            Err(::sled::Error::Io(e))
+                if e.to_string().starts_with("could not acquire lock")
+                    && Instant::now() < deadline =>
+            {
+                thread::sleep(Duration::from_millis(10));
+            }
+            result => return result.map_err(store_error),
+        }
+    }
+}
+
+fn store_error(e: ::sled::Error) -> EngineError {
+    EngineError::Store(e.to_string())
+}
+
+fn corrupt(reason: String) -> EngineError {
+    EngineError::Store(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::LockReason;
+    use crate::{DisputeState, TransactionType};
+
+    fn stored(client: ClientId) -> StoredAccount {
+        StoredAccount {
+            account: ClientAccount {
+                available: 1.5,
+                held: 2.25,
+                total: 3.75,
+                locked: true,
+                lock_reason: Some(LockReason::Chargeback),
+                closed: true,
+                counts: TransactionCounts {
+                    deposits: 3,
+                    withdrawals: 1,
+                    disputes: 2,
+                    resolves: 0,
+                    chargebacks: 1,
+                },
+                open_disputed_amount: 2.25,
+                ..ClientAccount::new(client)
+            },
+            seq: 7,
+            open_disputes: 1,
+            disputed_amount: 2.25,
+        }
+    }
+
+    fn record() -> TransactionRecord {
+        TransactionRecord {
+            amount: 2.25,
+            dispute: DisputeState::Open,
+            dispute_held: 2.0,
+            kind: TransactionType::Withdrawal,
+            frozen: false,
+            currency: None,
+            seq: 4,
+        }
+    }
+
+    #[test]
+    fn test_values_round_trip() {
+        let account = stored(9);
+        assert_eq!(
+            decode_account(9, &encode_account(&account)).unwrap(),
+            account
+        );
+        assert_eq!(decode_record(&encode_record(&record())).unwrap(), record());
+
+        let value = encode_record(&record());
+        assert!(decode_record(&value[..value.len() - 1]).is_err());
+        assert!(decode_account(9, &value).is_err());
+    }
+
+    #[test]
+    fn test_only_committed_puts_survive_reopen() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("store");
+        let path = path.to_str().unwrap();
+        {
+            let mut store = SledStore::open(path).unwrap();
+            store.put_account(&stored(1)).unwrap();
+            store.put_record(1, 4, &record()).unwrap();
+            store.flush().unwrap();
+            store.put_account(&stored(2)).unwrap();
+            assert_eq!(store.get_account(2).unwrap(), None);
+        }
+        let store = SledStore::open(path).unwrap();
+        assert_eq!(store.get_account(1).unwrap(), Some(stored(1)));
+        assert_eq!(store.get_record(1, 4).unwrap(), Some(record()));
+        assert_eq!(store.get_record(1, 5).unwrap(), None);
+        assert_eq!(store.accounts().unwrap(), vec![stored(1)]);
+    }
+}
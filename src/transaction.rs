@@ -1,25 +1,260 @@
-use serde::Deserialize;
+use crate::account::to_minor_units;
+use crate::{Currency, RejectReason};
+use serde::{Deserialize, Deserializer, de};
+use std::fmt;
 
-#[derive(Debug, Deserialize, PartialEq, Clone)]
-#[serde(rename_all = "lowercase")]
+/// Client identifier: `u16` by default, `u32` with the `wide-client-ids` feature
+#[cfg(not(feature = "wide-client-ids"))]
+pub type ClientId = u16;
+
+/// Client identifier: `u16` by default, `u32` with the `wide-client-ids` feature
+#[cfg(feature = "wide-client-ids")]
+pub type ClientId = u32;
+
+/// Type of a transaction, read from its name in any case and with surrounding whitespace
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum TransactionType {
     Deposit,
     Withdrawal,
     Dispute,
     Resolve,
     Chargeback,
+    /// Closes the account: later deposits and withdrawals are rejected, while disputes,
+    /// resolves and chargebacks still apply. Separate from the fraud lock
+    Close,
+}
+
+/// Alternate input column names accepted in place of the canonical ones, as
+/// `(canonical, alias)`; the serde aliases on `Transaction` must match
+pub(crate) const COLUMN_ALIASES: [(&str, &str); 4] = [
+    ("type", "transaction_type"),
+    ("client", "client_id"),
+    ("tx", "transaction_id"),
+    ("amount", "value"),
+];
+
+/// Whether the input column `name` is the canonical `column` or its alias
+pub(crate) fn is_column(name: &str, column: &str) -> bool {
+    name == column
+        || COLUMN_ALIASES
+            .iter()
+            .any(|&(canonical, alias)| canonical == column && alias == name)
 }
 
+sealed_fields! {
+/// One input row; columns may also be named as in `COLUMN_ALIASES`, e.g.
+/// `transaction_type,client_id,transaction_id,value`
+/// Built outside the crate with `Transaction::try_new`, which refuses amounts the engine
+/// would not apply; rows read from input are checked by the engine instead
 #[derive(Debug, Deserialize, Clone)]
 pub struct Transaction {
-    #[serde(rename = "type")]
-    pub tx_type: TransactionType,
-    pub client: u16,
-    pub tx: u32,
-    pub amount: Option<f64>,
+    #[serde(rename = "type", alias = "transaction_type")]
+    tx_type: TransactionType,
+    #[serde(alias = "client_id")]
+    client: ClientId,
+    #[serde(alias = "transaction_id")]
+    tx: u32,
+    /// Empty for dispute actions; the column may be left out altogether, e.g. in a
+    /// dispute-only feed with just `type,client,tx`
+    #[serde(default, alias = "value")]
+    amount: Option<f64>,
+    /// Currency of a deposit or withdrawal in multi-currency mode; dispute actions take the
+    /// currency of the transaction they refer to
+    #[serde(default)]
+    currency: Option<Currency>,
+    /// When the transaction happened, from the input's optional `timestamp` column, as an
+    /// integer in any unit the input uses consistently (e.g. Unix milliseconds). Only read
+    /// with `EngineConfig::reorder_window`, which applies each client's transactions in
+    /// timestamp order
+    #[serde(default)]
+    timestamp: Option<u64>,
+}
+}
+
+/// Why `Transaction::try_new` refused to build a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxError {
+    /// A deposit or withdrawal without an amount
+    MissingAmount,
+    /// A dispute action or closure given an amount; they take the amount of the transaction
+    /// they refer to
+    UnexpectedAmount,
+    /// An amount that is not a finite number above zero
+    InvalidAmount,
+    /// An amount that rounds to 0.0000
+    AmountBelowMinimum,
+}
+
+impl fmt::Display for TxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TxError::MissingAmount => write!(f, "a deposit or withdrawal needs an amount"),
+            TxError::UnexpectedAmount => {
+                write!(f, "only deposits and withdrawals carry an amount")
+            }
+            TxError::InvalidAmount => write!(f, "the amount must be a finite number above zero"),
+            TxError::AmountBelowMinimum => write!(f, "the amount rounds to 0.0000"),
+        }
+    }
+}
+
+impl std::error::Error for TxError {}
+
+impl TransactionType {
+    /// Every type, in declaration order
+    pub const ALL: [TransactionType; 6] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+        TransactionType::Close,
+    ];
+
+    /// The type named `name`, ignoring case and surrounding whitespace
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim();
+        Self::ALL
+            .into_iter()
+            .find(|tx_type| tx_type.name().eq_ignore_ascii_case(name))
+    }
+
+    /// Name of the type as written in the input
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+            TransactionType::Close => "close",
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TransactionType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_str(TypeVisitor)
+    }
+}
+
+/// Matches the name in place, so decoding a row doesn't allocate a `String` for it
+struct TypeVisitor;
+
+impl de::Visitor<'_> for TypeVisitor {
+    type Value = TransactionType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a transaction type")
+    }
+
+    fn visit_str<E: de::Error>(self, name: &str) -> Result<Self::Value, E> {
+        TransactionType::from_name(name).ok_or_else(|| {
+            E::unknown_variant(
+                name,
+                &[
+                    "deposit",
+                    "withdrawal",
+                    "dispute",
+                    "resolve",
+                    "chargeback",
+                    "close",
+                ],
+            )
+        })
+    }
 }
 
 impl Transaction {
+    /// A transaction the engine would apply: deposits and withdrawals need a finite amount
+    /// of at least 0.0001, other types none. Add a currency or timestamp with
+    /// `with_currency` and `with_timestamp`
+    pub fn try_new(
+        tx_type: TransactionType,
+        client: ClientId,
+        tx: u32,
+        amount: Option<f64>,
+    ) -> Result<Self, TxError> {
+        let transaction = Transaction {
+            tx_type,
+            client,
+            tx,
+            amount,
+            currency: None,
+            timestamp: None,
+        };
+        match amount {
+            None if transaction.requires_amount() => Err(TxError::MissingAmount),
+            Some(_) if !transaction.requires_amount() => Err(TxError::UnexpectedAmount),
+            Some(amount) if !amount.is_finite() || amount <= 0.0 => Err(TxError::InvalidAmount),
+            Some(_) if transaction.is_below_minimum() => Err(TxError::AmountBelowMinimum),
+            _ => Ok(transaction),
+        }
+    }
+
+    /// The same transaction in `currency`, for multi-currency mode
+    pub fn with_currency(mut self, currency: Currency) -> Self {
+        self.currency = Some(currency);
+        self
+    }
+
+    /// The same transaction stamped with `timestamp`, for `EngineConfig::reorder_window`
+    pub fn with_timestamp(mut self, timestamp: u64) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn tx_type(&self) -> TransactionType {
+        self.tx_type
+    }
+
+    pub fn client(&self) -> ClientId {
+        self.client
+    }
+
+    pub fn tx(&self) -> u32 {
+        self.tx
+    }
+
+    /// Amount of a deposit or withdrawal; `None` for other types
+    pub fn amount(&self) -> Option<f64> {
+        self.amount
+    }
+
+    pub fn currency(&self) -> Option<Currency> {
+        self.currency
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    /// Canonical form of this transaction, or why it can't be applied
+    /// Text is already cleaned up as the row is read: the CSV reader trims and unquotes
+    /// fields, the type matches in any case, and an amount may carry a leading `+`. Here a
+    /// dispute action or closure loses any amount it carried, and a deposit or withdrawal is
+    /// rejected without an amount (`MissingAmount`) or with one that rounds to 0.0000
+    /// (`AmountBelowMinimum`). Amounts that aren't positive are left for `is_valid`, which
+    /// the ledger ignores rather than rejects.
+    pub fn normalize(mut self) -> Result<Transaction, RejectReason> {
+        self.normalize_in_place().map(|()| self)
+    }
+
+    /// `normalize` for a transaction the caller owns; a rejected transaction is left as it was
+    pub(crate) fn normalize_in_place(&mut self) -> Result<(), RejectReason> {
+        if !self.requires_amount() {
+            self.amount = None;
+            return Ok(());
+        }
+        match self.amount {
+            None => Err(RejectReason::MissingAmount),
+            Some(amount) if amount > 0.0 && self.is_below_minimum() => {
+                Err(RejectReason::AmountBelowMinimum)
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
     /// Returns true if this transaction type requires an amount
     pub fn requires_amount(&self) -> bool {
         matches!(
@@ -36,14 +271,25 @@ impl Transaction {
         )
     }
 
-    /// Validates that the transaction has required fields
+    /// Validates that the transaction has required fields, with a finite, positive amount
+    /// where one is needed; `try_new` refuses the same amounts as `TxError::InvalidAmount`
     pub fn is_valid(&self) -> bool {
         if self.requires_amount() {
-            self.amount.is_some() && self.amount.unwrap() > 0.0
+            self.amount
+                .is_some_and(|amount| amount.is_finite() && amount > 0.0)
         } else {
             true
         }
     }
+
+    /// Returns true if this deposit or withdrawal has an amount that rounds to zero at
+    /// 4 decimal places, which would record a transaction that moves nothing
+    pub fn is_below_minimum(&self) -> bool {
+        self.requires_amount()
+            && self
+                .amount
+                .is_some_and(|amount| to_minor_units(amount) == 0)
+    }
 }
 
 #[cfg(test)]
@@ -57,6 +303,8 @@ mod tests {
             client: 1,
             tx: 1,
             amount: Some(100.0),
+            currency: None,
+            timestamp: None,
         };
         assert!(deposit.requires_amount());
 
@@ -65,6 +313,8 @@ mod tests {
             client: 1,
             tx: 1,
             amount: None,
+            currency: None,
+            timestamp: None,
         };
         assert!(!dispute.requires_amount());
     }
@@ -76,15 +326,111 @@ mod tests {
             client: 1,
             tx: 1,
             amount: Some(100.0),
+            currency: None,
+            timestamp: None,
         };
         assert!(valid.is_valid());
 
-        let invalid = Transaction {
-            tx_type: TransactionType::Deposit,
+        for amount in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            let invalid = Transaction {
+                tx_type: TransactionType::Deposit,
+                client: 1,
+                tx: 1,
+                amount: Some(amount),
+                currency: None,
+                timestamp: None,
+            };
+            assert!(!invalid.is_valid(), "{}", amount);
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            TransactionType::from_name(" Deposit "),
+            Some(TransactionType::Deposit)
+        );
+        assert_eq!(
+            TransactionType::from_name("CHARGEBACK"),
+            Some(TransactionType::Chargeback)
+        );
+        assert_eq!(TransactionType::from_name("refund"), None);
+    }
+
+    #[test]
+    fn test_deserialize_type() {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(" Withdrawal \nrefund\n".as_bytes());
+        let mut rows = reader.deserialize::<(TransactionType,)>();
+
+        assert_eq!(
+            rows.next().unwrap().unwrap(),
+            (TransactionType::Withdrawal,)
+        );
+        let error = rows.next().unwrap().unwrap_err().to_string();
+        assert!(error.contains("unknown variant `refund`"), "{}", error);
+    }
+
+    #[test]
+    fn test_normalize() {
+        let transaction = |tx_type, amount| Transaction {
+            tx_type,
             client: 1,
             tx: 1,
-            amount: Some(0.0),
+            amount,
+            currency: None,
+            timestamp: None,
         };
-        assert!(!invalid.is_valid());
+
+        let dispute = transaction(TransactionType::Dispute, Some(5.0)).normalize();
+        assert_eq!(dispute.unwrap().amount, None);
+        assert_eq!(
+            transaction(TransactionType::Deposit, None)
+                .normalize()
+                .unwrap_err(),
+            RejectReason::MissingAmount
+        );
+        assert_eq!(
+            transaction(TransactionType::Withdrawal, Some(0.00001))
+                .normalize()
+                .unwrap_err(),
+            RejectReason::AmountBelowMinimum
+        );
+        let negative = transaction(TransactionType::Deposit, Some(-1.0)).normalize();
+        assert!(!negative.unwrap().is_valid());
+    }
+
+    #[test]
+    fn test_try_new() {
+        let deposit = Transaction::try_new(TransactionType::Deposit, 1, 7, Some(2.5)).unwrap();
+        assert_eq!(
+            (deposit.tx_type(), deposit.client(), deposit.tx()),
+            (TransactionType::Deposit, 1, 7)
+        );
+        assert_eq!(deposit.amount(), Some(2.5));
+        let stamped = deposit.with_timestamp(40);
+        assert_eq!(stamped.timestamp(), Some(40));
+        assert!(Transaction::try_new(TransactionType::Dispute, 1, 7, None).is_ok());
+
+        let error = |tx_type, amount| Transaction::try_new(tx_type, 1, 1, amount).unwrap_err();
+        assert_eq!(
+            error(TransactionType::Withdrawal, None),
+            TxError::MissingAmount
+        );
+        assert_eq!(
+            error(TransactionType::Chargeback, Some(1.0)),
+            TxError::UnexpectedAmount
+        );
+        for amount in [-1.0, 0.0, f64::NAN, f64::INFINITY] {
+            assert_eq!(
+                error(TransactionType::Deposit, Some(amount)),
+                TxError::InvalidAmount
+            );
+        }
+        assert_eq!(
+            error(TransactionType::Deposit, Some(0.00001)),
+            TxError::AmountBelowMinimum
+        );
     }
 }
@@ -0,0 +1,172 @@
+//! End-of-run consistency checks (`--verify`).
+//!
+//! Once every transaction has been applied, each client's balances are checked against the
+//! invariants the settlement rules keep. Balances are compared in minor units, so f64 residue
+//! below 0.0001 is not a violation. Held funds and locks a run started with, from
+//! `initial_balances` or a snapshot, are taken as given.
+//!
+//! `check_written_output` is the separate output check (`--verify-output`): the CSV just
+//! written is parsed again and each row compared with the account it came from.
+
+use crate::account::{format_decimal, to_minor_units};
+use crate::{
+    AccountColumn, AmountScale, ClientAccount, ClientId, ClientLedger, Currency, EngineConfig,
+    EngineError,
+};
+use std::fmt;
+
+/// An invariant a settled account breaks, with the balances involved
+#[derive(Debug, Clone, PartialEq)]
+pub enum Invariant {
+    /// `total` is not `available + held`
+    TotalMismatch {
+        available: f64,
+        held: f64,
+        total: f64,
+    },
+    /// `held` differs from what the open disputes and frozen deposits account for
+    HeldMismatch { held: f64, expected: f64 },
+    /// `held` is below zero
+    NegativeHeld { held: f64 },
+    /// The account is locked with no `LockReason`, and was not locked at the start
+    MissingLockCause,
+}
+
+impl fmt::Display for Invariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Invariant::TotalMismatch {
+                available,
+                held,
+                total,
+            } => write!(
+                f,
+                "total {} is not available {} + held {}",
+                format_decimal(*total),
+                format_decimal(*available),
+                format_decimal(*held)
+            ),
+            Invariant::HeldMismatch { held, expected } => write!(
+                f,
+                "held {} but open disputes and frozen deposits hold {}",
+                format_decimal(*held),
+                format_decimal(*expected)
+            ),
+            Invariant::NegativeHeld { held } => {
+                write!(f, "held {} is negative", format_decimal(*held))
+            }
+            Invariant::MissingLockCause => write!(f, "locked without a recorded cause"),
+        }
+    }
+}
+
+/// One invariant broken by one account
+#[derive(Debug, Clone, PartialEq)]
+pub struct Violation {
+    pub client: ClientId,
+    /// The currency of the account in multi-currency mode; `None` otherwise
+    pub currency: Option<Currency>,
+    pub invariant: Invariant,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "client {}", self.client)?;
+        if let Some(currency) = self.currency {
+            write!(f, " ({})", currency)?;
+        }
+        write!(f, ": {}", self.invariant)
+    }
+}
+
+/// Every violation in `ledgers`, in iteration order
+pub fn verify_ledgers<'a>(ledgers: impl Iterator<Item = &'a ClientLedger>) -> Vec<Violation> {
+    ledgers.flat_map(ClientLedger::violations).collect()
+}
+
+/// Parse `written`, the output CSV, and check it holds `expected` in order, in
+/// `config.output_columns()`
+/// Amounts are parsed in `config.amount_scale` and compared with the account's balance
+/// rounded to 4 decimal places; every other field must read back as the text it stands for.
+pub(crate) fn check_written_output(
+    written: &[u8],
+    expected: &[ClientAccount],
+    config: &EngineConfig,
+    client_names: Option<&[String]>,
+) -> Result<(), EngineError> {
+    let columns = config.output_columns();
+    let mut reader = csv::Reader::from_reader(written);
+    let header: Vec<&str> = columns.iter().map(AccountColumn::name).collect();
+    if reader.headers()?.iter().ne(header.iter().copied()) {
+        return Err(EngineError::OutputMismatch(format!(
+            "header is not {}",
+            header.join(",")
+        )));
+    }
+
+    let mut rows = 0;
+    for (record, account) in reader.records().zip(expected) {
+        let record = record?;
+        rows += 1;
+        if record.len() != columns.len() {
+            return Err(EngineError::OutputMismatch(format!(
+                "row {} has {} fields for {} columns",
+                rows,
+                record.len(),
+                columns.len()
+            )));
+        }
+        for (&column, field) in columns.iter().zip(&record) {
+            if !reads_back(column, field, account, config.amount_scale, client_names) {
+                return Err(EngineError::OutputMismatch(format!(
+                    "row {} (client {}) has {} `{}`, expected {}",
+                    rows,
+                    account.client,
+                    column.name(),
+                    field,
+                    account.field(column, config.amount_scale)
+                )));
+            }
+        }
+    }
+    let written_rows = rows + reader.records().count();
+    if written_rows != expected.len() {
+        return Err(EngineError::OutputMismatch(format!(
+            "{} rows written for {} accounts",
+            written_rows,
+            expected.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Whether `field`, written in `column`, parses back to `account`'s value
+fn reads_back(
+    column: AccountColumn,
+    field: &str,
+    account: &ClientAccount,
+    amount_scale: AmountScale,
+    client_names: Option<&[String]>,
+) -> bool {
+    let amount = match column {
+        AccountColumn::Available => account.available,
+        AccountColumn::Held => account.held,
+        AccountColumn::Total => account.total,
+        AccountColumn::OpenDisputedAmount => account.open_disputed_amount,
+        AccountColumn::Client => {
+            return match client_names {
+                Some(names) => {
+                    names.get(account.client as usize).map(String::as_str) == Some(field)
+                }
+                None => field.parse() == Ok(account.client),
+            };
+        }
+        AccountColumn::Locked => return field.parse() == Ok(account.locked),
+        _ => return field == account.field(column, amount_scale),
+    };
+    let minor_units = match amount_scale {
+        AmountScale::Decimal => field.parse().ok().map(to_minor_units),
+        AmountScale::MinorUnits => field.parse().ok(),
+    };
+    minor_units == Some(to_minor_units(amount))
+}
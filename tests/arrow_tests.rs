@@ -0,0 +1,226 @@
+// tests/arrow_tests.rs
+
+//! Arrow record batch ingestion tests.
+//! Run with: cargo test --features arrow --test arrow_tests
+
+#![cfg(feature = "arrow")]
+
+use arrow_array::builder::{
+    Decimal128Builder, Float64Builder, StringBuilder, StringDictionaryBuilder, UInt16Builder,
+    UInt32Builder,
+};
+use arrow_array::types::Int8Type;
+use arrow_array::{ArrayRef, RecordBatch};
+use payments_engine::{
+    ClientAccount, Engine, EngineConfig, EngineError, PaymentEngine, apply_record_batch,
+    route_record_batches,
+};
+use std::sync::Arc;
+
+type Row = (&'static str, u16, u32, Option<f64>);
+
+const ROWS: [Row; 6] = [
+    ("deposit", 1, 1, Some(10.0)),
+    ("deposit", 2, 2, Some(5.5)),
+    ("withdrawal", 1, 3, Some(2.25)),
+    ("dispute", 2, 2, None),
+    ("withdrawal", 2, 4, Some(1.0)),
+    ("dispute", 1, 99, None),
+];
+
+fn client_and_tx(rows: &[Row]) -> (ArrayRef, ArrayRef) {
+    let mut client = UInt16Builder::new();
+    let mut tx = UInt32Builder::new();
+    for (_, c, t, _) in rows {
+        client.append_value(*c);
+        tx.append_value(*t);
+    }
+    (Arc::new(client.finish()), Arc::new(tx.finish()))
+}
+
+fn float_amounts(rows: &[Row]) -> ArrayRef {
+    let mut amount = Float64Builder::new();
+    for (.., a) in rows {
+        amount.append_option(*a);
+    }
+    Arc::new(amount.finish())
+}
+
+/// A batch with a plain `Utf8` type column and a nullable `Float64` amount column
+fn utf8_batch(rows: &[Row]) -> RecordBatch {
+    let mut tx_type = StringBuilder::new();
+    for (t, ..) in rows {
+        tx_type.append_value(t);
+    }
+    let (client, tx) = client_and_tx(rows);
+    RecordBatch::try_from_iter([
+        ("type", Arc::new(tx_type.finish()) as ArrayRef),
+        ("client", client),
+        ("tx", tx),
+        ("amount", float_amounts(rows)),
+    ])
+    .unwrap()
+}
+
+/// The same rows with a dictionary-encoded type column and `Decimal128(10, 4)` amounts
+fn dictionary_batch(rows: &[Row]) -> RecordBatch {
+    let mut tx_type = StringDictionaryBuilder::<Int8Type>::new();
+    let mut amount = Decimal128Builder::new();
+    for (t, .., a) in rows {
+        tx_type.append_value(t);
+        amount.append_option(a.map(|a| (a * 10_000.0).round() as i128));
+    }
+    let (client, tx) = client_and_tx(rows);
+    RecordBatch::try_from_iter([
+        ("type", Arc::new(tx_type.finish()) as ArrayRef),
+        ("client", client),
+        ("tx", tx),
+        (
+            "amount",
+            Arc::new(amount.finish().with_precision_and_scale(10, 4).unwrap()) as ArrayRef,
+        ),
+    ])
+    .unwrap()
+}
+
+fn assert_expected_accounts(accounts: &[ClientAccount]) {
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[0].client(), 1);
+    assert_eq!(accounts[0].available(), 7.75);
+    assert_eq!(accounts[1].client(), 2);
+    assert_eq!(accounts[1].available(), 0.0);
+    assert_eq!(accounts[1].held(), 5.5);
+}
+
+#[test]
+fn test_apply_utf8_batch_with_null_amounts() {
+    let mut engine = PaymentEngine::new(&EngineConfig::default()).unwrap();
+    let outcome = apply_record_batch(&mut engine, &utf8_batch(&ROWS)).unwrap();
+
+    assert_eq!(outcome.rows, 6);
+    assert_eq!(outcome.applied, 4);
+    assert_eq!(outcome.ignored, 1);
+    assert_eq!(outcome.rejected.get("insufficient_funds"), Some(&1));
+    assert_expected_accounts(&engine.accounts());
+}
+
+#[test]
+fn test_apply_dictionary_batch_with_decimal_amounts() {
+    let mut engine = PaymentEngine::new(&EngineConfig::default()).unwrap();
+    let outcome = apply_record_batch(&mut engine, &dictionary_batch(&ROWS)).unwrap();
+
+    assert_eq!(outcome.applied, 4);
+    assert_expected_accounts(&engine.accounts());
+}
+
+#[test]
+fn test_batch_without_amount_column() {
+    let mut engine = PaymentEngine::new(&EngineConfig::default()).unwrap();
+    apply_record_batch(&mut engine, &utf8_batch(&ROWS[..2])).unwrap();
+
+    let disputes = utf8_batch(&[("dispute", 1, 1, None)]);
+    let disputes = disputes.project(&[0, 1, 2]).unwrap();
+    let outcome = apply_record_batch(&mut engine, &disputes).unwrap();
+
+    assert_eq!(outcome.applied, 1);
+    assert_eq!(engine.accounts()[0].held(), 10.0);
+}
+
+#[test]
+fn test_batch_with_aliased_column_names() {
+    let batch = utf8_batch(&ROWS);
+    let aliased = RecordBatch::try_from_iter(
+        ["transaction_type", "client_id", "transaction_id", "value"]
+            .into_iter()
+            .zip(batch.columns().iter().cloned()),
+    )
+    .unwrap();
+    let mut engine = PaymentEngine::new(&EngineConfig::default()).unwrap();
+    let outcome = apply_record_batch(&mut engine, &aliased).unwrap();
+
+    assert_eq!(outcome.applied, 4);
+    assert_expected_accounts(&engine.accounts());
+}
+
+#[test]
+fn test_schema_errors_apply_nothing() {
+    let mut engine = PaymentEngine::new(&EngineConfig::default()).unwrap();
+
+    let no_tx = utf8_batch(&ROWS).project(&[0, 1, 3]).unwrap();
+    assert!(matches!(
+        apply_record_batch(&mut engine, &no_tx),
+        Err(EngineError::InvalidInput(message)) if message.contains("`tx`")
+    ));
+
+    let mut client = UInt16Builder::new();
+    client.append_value(1);
+    client.append_null();
+    let batch = utf8_batch(&ROWS[..2]);
+    let null_client = RecordBatch::try_from_iter([
+        ("type", batch.column(0).clone()),
+        ("client", Arc::new(client.finish()) as ArrayRef),
+        ("tx", batch.column(2).clone()),
+    ])
+    .unwrap();
+    assert!(matches!(
+        apply_record_batch(&mut engine, &null_client),
+        Err(EngineError::InvalidInput(message)) if message.contains("nulls")
+    ));
+
+    let mut tx_type = StringDictionaryBuilder::<Int8Type>::new();
+    tx_type.append_value("refund");
+    let (client, tx) = client_and_tx(&ROWS[..1]);
+    let unknown_type = RecordBatch::try_from_iter([
+        ("type", Arc::new(tx_type.finish()) as ArrayRef),
+        ("client", client),
+        ("tx", tx),
+    ])
+    .unwrap();
+    assert!(matches!(
+        apply_record_batch(&mut engine, &unknown_type),
+        Err(EngineError::InvalidInput(message)) if message.contains("refund")
+    ));
+
+    assert!(engine.accounts().is_empty());
+}
+
+#[test]
+fn test_bad_row_fails_the_whole_batch() {
+    let rows = [ROWS[0], ("refund", 1, 7, Some(1.0)), ROWS[1]];
+    let mut engine = PaymentEngine::new(&EngineConfig::default()).unwrap();
+    assert!(matches!(
+        apply_record_batch(&mut engine, &utf8_batch(&rows)),
+        Err(EngineError::InvalidInput(message)) if message.contains("`refund` in row 1")
+    ));
+    assert!(engine.accounts().is_empty());
+
+    let config = EngineConfig {
+        workers: Some(2),
+        ..EngineConfig::default()
+    };
+    let mut engine = Engine::new(&config).unwrap();
+    let batches = [utf8_batch(&ROWS[1..2]), utf8_batch(&rows)];
+    assert!(route_record_batches(&engine, &batches).is_err());
+
+    // Only the batch before the bad one reached the workers
+    let accounts = engine.drain_and_reset().unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[&2].available(), 5.5);
+}
+
+#[test]
+fn test_route_record_batches_to_worker_pool() {
+    let config = EngineConfig {
+        workers: Some(2),
+        ..EngineConfig::default()
+    };
+    let mut engine = Engine::new(&config).unwrap();
+    let batches = [utf8_batch(&ROWS[..3]), dictionary_batch(&ROWS[3..])];
+
+    assert_eq!(route_record_batches(&engine, &batches).unwrap(), 6);
+
+    let accounts = engine.drain_and_reset().unwrap();
+    let mut accounts: Vec<_> = accounts.into_values().collect();
+    accounts.sort_by_key(|account| account.client());
+    assert_expected_accounts(&accounts);
+}
@@ -0,0 +1,86 @@
+// tests/async_tests.rs
+
+//! Async API tests on a tokio runtime.
+//! Run with: cargo test --features async --test async_tests
+
+#![cfg(feature = "async")]
+
+use payments_engine::{
+    EngineConfig, EngineError, process_file, process_reader_async, process_reader_async_with_config,
+};
+use tokio::io::AsyncWriteExt;
+
+const FIXTURES: [&str; 4] = [
+    "tests/inputs/test_complex_scenario.csv",
+    "tests/inputs/test_dispute_heavy.csv",
+    "tests/inputs/test_locked_account.csv",
+    "tests/inputs/test_precision.csv",
+];
+
+/// Async reader that receives `bytes` a few at a time from a writer task, so reads end at
+/// arbitrary points within lines
+fn trickle(bytes: Vec<u8>) -> tokio::io::DuplexStream {
+    let (mut writer, reader) = tokio::io::duplex(64);
+    tokio::spawn(async move {
+        for chunk in bytes.chunks(37) {
+            writer.write_all(chunk).await.unwrap();
+        }
+    });
+    reader
+}
+
+#[tokio::test]
+async fn test_async_reader_matches_sync() {
+    for fixture in FIXTURES {
+        let expected = process_file(fixture, &EngineConfig::default()).unwrap();
+        let bytes = std::fs::read(fixture).unwrap();
+
+        let accounts = process_reader_async(trickle(bytes.clone())).await.unwrap();
+        assert_eq!(accounts, expected, "{}", fixture);
+
+        for workers in [1, 3] {
+            let config = EngineConfig {
+                workers: Some(workers),
+                batch_size: 2,
+                channel_capacity: Some(1),
+                ..Default::default()
+            };
+            let accounts = process_reader_async_with_config(bytes.as_slice(), &config)
+                .await
+                .unwrap();
+            assert_eq!(accounts, expected, "{} with {} workers", fixture, workers);
+        }
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_async_large_input_on_multi_thread_runtime() {
+    // Well past one parse block, with dispute actions following their deposits
+    let mut csv = String::from("type, client, tx, amount\n");
+    for tx in 0..20_000u32 {
+        let client = tx % 97;
+        if tx % 10 == 9 && tx >= 970 {
+            csv.push_str(&format!("dispute, {}, {},\n", client, tx - 970));
+        } else {
+            csv.push_str(&format!("deposit, {}, {}, {}.5\n", client, tx, tx % 13));
+        }
+    }
+    let dir = tempfile::TempDir::new().unwrap();
+    let path = dir.path().join("large.csv");
+    std::fs::write(&path, &csv).unwrap();
+
+    let expected = process_file(path.to_str().unwrap(), &EngineConfig::default()).unwrap();
+    let accounts = process_reader_async(trickle(csv.into_bytes()))
+        .await
+        .unwrap();
+    assert_eq!(accounts, expected);
+}
+
+#[tokio::test]
+async fn test_async_parse_error_reports_input_line() {
+    let csv = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,-2,1.0\n";
+    match process_reader_async(csv.as_bytes()).await {
+        Err(EngineError::SignedId { line, .. }) => assert_eq!(line, 3),
+        other => panic!("expected SignedId, got {:?}", other),
+    }
+}
@@ -0,0 +1,13 @@
+// tests/compile_fail.rs
+
+//! Compile-fail tests: embedders can't build transactions or change balances past the engine's
+//! checks. The `legacy-fields` feature reopens the fields, so these only run without it.
+//! Refresh the expected errors with: TRYBUILD=overwrite cargo test --test compile_fail
+
+#![cfg(not(feature = "legacy-fields"))]
+
+#[test]
+fn test_sealed_fields() {
+    let cases = trybuild::TestCases::new();
+    cases.compile_fail("tests/compile_fail/*.rs");
+}
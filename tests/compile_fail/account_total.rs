@@ -0,0 +1,7 @@
+// `total` can't be set apart from `available + held`
+use payments_engine::ClientAccount;
+
+fn main() {
+    let mut account = ClientAccount::new(1);
+    account.total = 100.0;
+}
@@ -0,0 +1,7 @@
+// A checked transaction's amount can't be changed afterwards
+use payments_engine::{Transaction, TransactionType};
+
+fn main() {
+    let mut deposit = Transaction::try_new(TransactionType::Deposit, 1, 1, Some(10.0)).unwrap();
+    deposit.amount = Some(-10.0);
+}
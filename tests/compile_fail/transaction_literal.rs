@@ -0,0 +1,13 @@
+// A negative deposit can't be built by hand; `Transaction::try_new` refuses it
+use payments_engine::{Transaction, TransactionType};
+
+fn main() {
+    let _deposit = Transaction {
+        tx_type: TransactionType::Deposit,
+        client: 1,
+        tx: 1,
+        amount: Some(-10.0),
+        currency: None,
+        timestamp: None,
+    };
+}
@@ -0,0 +1,181 @@
+// tests/consume_tests.rs
+
+//! Consume mode against an in-memory `MessageSource` standing in for a Kafka topic.
+
+use payments_engine::snapshot::inspect_snapshot;
+use payments_engine::{
+    ConsumeConfig, EngineConfig, EngineError, MessageSource, PayloadFormat, SourceMessage,
+    SourceOffset, consume,
+};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tempfile::TempDir;
+
+/// Hands out queued messages and records each commit along with the snapshot's client count
+/// at that moment, to show the checkpoint was written first
+struct MockSource {
+    messages: VecDeque<SourceMessage>,
+    snapshot: String,
+    commits: Vec<(Vec<SourceOffset>, u64)>,
+}
+
+impl MockSource {
+    fn new(snapshot: &str, messages: &[(i32, i64, &str)]) -> Self {
+        Self {
+            messages: messages
+                .iter()
+                .map(|&(partition, offset, payload)| SourceMessage {
+                    partition,
+                    offset,
+                    payload: payload.as_bytes().to_vec(),
+                })
+                .collect(),
+            snapshot: snapshot.to_string(),
+            commits: Vec::new(),
+        }
+    }
+}
+
+impl MessageSource for MockSource {
+    fn poll(&mut self, _timeout: Duration) -> Result<Option<SourceMessage>, EngineError> {
+        Ok(self.messages.pop_front())
+    }
+
+    fn commit(&mut self, offsets: &[SourceOffset]) -> Result<(), EngineError> {
+        let clients = inspect_snapshot(&self.snapshot)?.clients;
+        self.commits.push((offsets.to_vec(), clients));
+        Ok(())
+    }
+}
+
+fn setup() -> (TempDir, String, EngineConfig) {
+    let dir = TempDir::new().unwrap();
+    let snapshot = dir.path().join("state.snap").to_str().unwrap().to_string();
+    let config = EngineConfig {
+        workers: Some(2),
+        snapshot: Some(snapshot.clone()),
+        ..Default::default()
+    };
+    (dir, snapshot, config)
+}
+
+fn consume_config(payload: PayloadFormat, checkpoint_every: u64) -> ConsumeConfig {
+    ConsumeConfig {
+        topic: "transactions".to_string(),
+        payload,
+        checkpoint_every,
+        idle_timeout: Some(Duration::ZERO),
+        ..Default::default()
+    }
+}
+
+fn offset(partition: i32, offset: i64) -> SourceOffset {
+    SourceOffset { partition, offset }
+}
+
+const DEPOSITS: [(i32, i64, &str); 4] = [
+    (
+        0,
+        0,
+        r#"{"type":"deposit","client":1,"tx":1,"amount":10.0}"#,
+    ),
+    (1, 0, r#"{"type":"deposit","client":2,"tx":2,"amount":5.0}"#),
+    (
+        0,
+        1,
+        r#"{"type":"withdrawal","client":1,"tx":3,"amount":4.0}"#,
+    ),
+    (1, 1, r#"{"type":"dispute","client":2,"tx":2}"#),
+];
+
+#[test]
+fn test_json_payloads_commit_after_checkpoint() {
+    let (_dir, snapshot, config) = setup();
+    let mut source = MockSource::new(&snapshot, &DEPOSITS);
+
+    let accounts = consume(
+        &mut source,
+        &consume_config(PayloadFormat::Json, 2),
+        &config,
+    )
+    .unwrap();
+
+    assert_eq!(accounts[&1].available(), 6.0);
+    assert_eq!(accounts[&2].held(), 5.0);
+    // Each commit follows a snapshot holding what it covers
+    assert_eq!(
+        source.commits,
+        vec![
+            (vec![offset(0, 1), offset(1, 1)], 2),
+            (vec![offset(0, 2), offset(1, 2)], 2),
+        ]
+    );
+}
+
+#[test]
+fn test_restart_skips_messages_covered_by_the_checkpoint() {
+    let (_dir, snapshot, config) = setup();
+    let mut first = MockSource::new(&snapshot, &DEPOSITS[..3]);
+    consume(
+        &mut first,
+        &consume_config(PayloadFormat::Json, 10),
+        &config,
+    )
+    .unwrap();
+
+    // The last commit was lost, so the source redelivers everything after the first message
+    let mut second = MockSource::new(&snapshot, &DEPOSITS[1..]);
+    let accounts = consume(
+        &mut second,
+        &consume_config(PayloadFormat::Json, 10),
+        &config,
+    )
+    .unwrap();
+
+    assert_eq!(accounts[&1].available(), 6.0);
+    assert_eq!(accounts[&2].available(), 0.0);
+    assert_eq!(accounts[&2].held(), 5.0);
+    assert_eq!(second.commits, vec![(vec![offset(0, 2), offset(1, 2)], 2)]);
+}
+
+#[test]
+fn test_csv_payloads_with_a_client_split_across_partitions() {
+    let (_dir, snapshot, config) = setup();
+    // Client 1 is not partitioned by client here; per-client routing still keeps its order
+    let mut source = MockSource::new(
+        &snapshot,
+        &[
+            (0, 0, "deposit,1,1,10.0"),
+            (1, 0, "withdrawal,1,2,3.5"),
+            (2, 0, "not a transaction"),
+            (0, 1, "dispute,1,1,"),
+        ],
+    );
+
+    let accounts = consume(
+        &mut source,
+        &consume_config(PayloadFormat::Csv, 100),
+        &config,
+    )
+    .unwrap();
+
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[&1].available(), -3.5);
+    assert_eq!(accounts[&1].held(), 10.0);
+    // The bad payload is skipped but its offset still committed
+    assert_eq!(
+        source.commits,
+        vec![(vec![offset(0, 2), offset(1, 1), offset(2, 1)], 1)]
+    );
+}
+
+#[test]
+fn test_consume_requires_snapshot() {
+    let mut source = MockSource::new("", &[]);
+    let result = consume(
+        &mut source,
+        &consume_config(PayloadFormat::Json, 1),
+        &EngineConfig::default(),
+    );
+    assert!(matches!(result, Err(EngineError::InvalidConfig(_))));
+}
@@ -0,0 +1,3194 @@
+// tests/engine_tests.rs
+
+//! End-to-end tests through the library API, asserting on the resulting accounts.
+
+use payments_engine::{
+    AccountCreationPolicy, AmountScale, ApplyOutcome, ClientAccount, ClientId, DisputeAlert,
+    DisputeFundsPolicy, EmptyOutputPolicy, Engine, EngineConfig, EngineError, HeldAlert,
+    InputFormat, LockReason, LockedDepositPolicy, ModuloShard, NumberFormat, RedactKey,
+    RejectHandler, RejectReason, RowLimitPolicy, ShardStrategy, Transaction, TransactionCounts,
+    TransactionType, WithdrawalDisputeModel, binary, dry_run, inspect, process_csv_bytes,
+    process_csv_bytes_with_config, process_file, process_file_with_report, process_transactions,
+    redact, replay_journal, start_engine_iter, start_engine_iter_with_config,
+    start_engine_with_config,
+};
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+fn create_test_csv(content: &str) -> (TempDir, String) {
+    create_named_csv("test.csv", content)
+}
+
+fn create_named_csv(name: &str, content: &str) -> (TempDir, String) {
+    let dir = TempDir::new().unwrap();
+    let file_path = dir.path().join(name);
+    let mut file = File::create(&file_path).unwrap();
+    write!(file, "{}", content).unwrap();
+    (dir, file_path.to_str().unwrap().to_string())
+}
+
+/// Deposits, withdrawals and disputes spread over 500 clients
+fn generate_mixed_csv(rows: u32) -> String {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for i in 0..rows {
+        let client = i % 500;
+        match i % 20 {
+            0 => csv.push_str(&format!("withdrawal,{},{},{}.5\n", client, i, i % 40)),
+            7 => csv.push_str(&format!("dispute,{},{},\n", client, i - 7)),
+            _ => csv.push_str(&format!("deposit,{},{},{}.1234\n", client, i, i % 100)),
+        }
+    }
+    csv
+}
+
+#[test]
+fn test_small_read_buffer_matches_default() {
+    let csv = generate_mixed_csv(200_000);
+    assert!(
+        csv.len() > 4 * 1024 * 1024,
+        "fixture should span several MB"
+    );
+    let (_dir, path) = create_test_csv(&csv);
+
+    let default = process_file(&path, &EngineConfig::default()).unwrap();
+    let small = process_file(
+        &path,
+        &EngineConfig {
+            read_buffer_size: 4 * 1024,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(default.len(), 500);
+    assert_eq!(default, small);
+}
+
+#[test]
+fn test_read_buffer_bounds_rejected() {
+    let (_dir, path) = create_test_csv("type,client,tx,amount\ndeposit,1,1,1.0");
+
+    for read_buffer_size in [0, 2 * 1024 * 1024 * 1024] {
+        let config = EngineConfig {
+            read_buffer_size,
+            ..Default::default()
+        };
+        let result = process_file(&path, &config);
+        assert!(matches!(result, Err(EngineError::InvalidConfig(_))));
+    }
+}
+
+#[test]
+fn test_zero_batch_size_and_channel_capacity_rejected() {
+    let (_dir, path) = create_test_csv("type,client,tx,amount\ndeposit,1,1,1.0");
+
+    let zero_batch = EngineConfig {
+        batch_size: 0,
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_file(&path, &zero_batch),
+        Err(EngineError::InvalidConfig(_))
+    ));
+
+    let zero_capacity = EngineConfig {
+        channel_capacity: Some(0),
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_file(&path, &zero_capacity),
+        Err(EngineError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_batched_sending_matches_per_row() {
+    // 50 interleaved clients, each depositing and then disputing, resolving or charging back
+    // that deposit 50 rows later, so batch boundaries fall between dependent transactions
+    let transactions: Vec<Transaction> = (0..20_000u32)
+        .map(|i| {
+            let client = (i % 50) as ClientId;
+            match (i / 50) % 4 {
+                0 => tx(TransactionType::Deposit, client, i, Some(10.0)),
+                1 => tx(TransactionType::Dispute, client, i - 50, None),
+                2 if client.is_multiple_of(2) => {
+                    tx(TransactionType::Resolve, client, i - 100, None)
+                }
+                3 if !client.is_multiple_of(2) => {
+                    tx(TransactionType::Chargeback, client, i - 150, None)
+                }
+                _ => tx(TransactionType::Withdrawal, client, i, Some(4.0)),
+            }
+        })
+        .collect();
+    let per_row = EngineConfig {
+        batch_size: 1,
+        ..Default::default()
+    };
+    let expected = process_transactions(transactions.clone(), &per_row).unwrap();
+    assert!(expected.values().any(|account| account.locked()));
+
+    for batch_size in [2, 7, 256, 100_000] {
+        for workers in [1, 4] {
+            let config = EngineConfig {
+                batch_size,
+                workers: Some(workers),
+                ..Default::default()
+            };
+            assert_eq!(
+                process_transactions(transactions.clone(), &config).unwrap(),
+                expected,
+                "batch size {}, {} workers",
+                batch_size,
+                workers
+            );
+        }
+    }
+}
+
+#[test]
+fn test_bounded_batched_channels_match_default() {
+    let (_dir, path) = create_test_csv(&generate_mixed_csv(20_000));
+
+    let default = process_file(&path, &EngineConfig::default()).unwrap();
+    let tuned = process_file(
+        &path,
+        &EngineConfig {
+            channel_capacity: Some(4),
+            batch_size: 64,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(default, tuned);
+}
+
+fn tx(tx_type: TransactionType, client: ClientId, tx: u32, amount: Option<f64>) -> Transaction {
+    Transaction::try_new(tx_type, client, tx, amount).unwrap()
+}
+
+#[test]
+fn test_process_transactions_from_vec() {
+    let transactions = vec![
+        tx(TransactionType::Deposit, 1, 1, Some(100.0)),
+        tx(TransactionType::Deposit, 2, 2, Some(40.0)),
+        tx(TransactionType::Withdrawal, 1, 3, Some(30.0)),
+        tx(TransactionType::Dispute, 2, 2, None),
+        tx(TransactionType::Deposit, 3, 4, Some(5.0)),
+        tx(TransactionType::Dispute, 3, 4, None),
+        tx(TransactionType::Chargeback, 3, 4, None),
+    ];
+    let config = EngineConfig {
+        workers: Some(3),
+        ..Default::default()
+    };
+
+    let accounts = process_transactions(transactions, &config).unwrap();
+
+    assert_eq!(accounts.len(), 3);
+    assert_eq!(accounts[&1].available(), 70.0);
+    assert_eq!(accounts[&2].available(), 0.0);
+    assert_eq!(accounts[&2].held(), 40.0);
+    assert_eq!(accounts[&3].total(), 0.0);
+    assert!(accounts[&3].locked());
+}
+
+#[test]
+fn test_engine_drain_and_reset_starts_next_period_from_zero() {
+    let config = EngineConfig {
+        workers: Some(2),
+        ..Default::default()
+    };
+    let mut engine = Engine::new(&config).unwrap();
+
+    for transaction in [
+        tx(TransactionType::Deposit, 1, 1, Some(100.0)),
+        tx(TransactionType::Deposit, 2, 2, Some(40.0)),
+        tx(TransactionType::Withdrawal, 1, 3, Some(30.0)),
+        tx(TransactionType::Dispute, 2, 2, None),
+    ] {
+        engine.submit(transaction).unwrap();
+    }
+    let first = engine.drain_and_reset().unwrap();
+    assert_eq!(first.len(), 2);
+    assert_eq!(first[&1].available(), 70.0);
+    assert_eq!(first[&2].held(), 40.0);
+
+    for transaction in [
+        tx(TransactionType::Deposit, 1, 4, Some(5.0)),
+        // tx 2 went with the first period, so there is nothing left to resolve
+        tx(TransactionType::Resolve, 2, 2, None),
+        tx(TransactionType::Deposit, 3, 5, Some(1.0)),
+    ] {
+        engine.submit(transaction).unwrap();
+    }
+    let second = engine.drain_and_reset().unwrap();
+    assert_eq!(second.len(), 3);
+    assert_eq!(second[&1].available(), 5.0);
+    assert_eq!(second[&1].total(), 5.0);
+    assert_eq!(second[&2].available(), 0.0);
+    assert_eq!(second[&2].held(), 0.0);
+    assert_eq!(second[&3].total(), 1.0);
+
+    assert!(engine.drain_and_reset().unwrap().is_empty());
+}
+
+#[test]
+fn test_engine_processed_count_polled_from_another_thread() {
+    const TRANSACTIONS: u64 = 10_000;
+    let config = EngineConfig {
+        workers: Some(4),
+        ..Default::default()
+    };
+    let mut engine = Engine::new(&config).unwrap();
+
+    thread::scope(|scope| {
+        let monitor = scope.spawn(|| {
+            let deadline = Instant::now() + Duration::from_secs(30);
+            let mut last = 0;
+            while last < TRANSACTIONS && Instant::now() < deadline {
+                let count = engine.processed_count();
+                assert!(count >= last, "count went back from {} to {}", last, count);
+                last = count;
+                thread::yield_now();
+            }
+            last
+        });
+        for id in 0..TRANSACTIONS as u32 {
+            let client = (id % 50) as ClientId;
+            engine
+                .submit(tx(TransactionType::Deposit, client, id, Some(1.0)))
+                .unwrap();
+        }
+        assert_eq!(monitor.join().unwrap(), TRANSACTIONS);
+    });
+
+    // Counting carries on across periods
+    engine.drain_and_reset().unwrap();
+    engine
+        .submit(tx(TransactionType::Deposit, 1, 10_000, Some(1.0)))
+        .unwrap();
+    engine.drain_and_reset().unwrap();
+    assert_eq!(engine.processed_count(), TRANSACTIONS + 1);
+}
+
+#[test]
+fn test_engine_concurrent_transfers_across_workers_conserve_funds() {
+    const CLIENTS: u32 = 8;
+    const TRANSFERS: u32 = 2_000;
+    let config = EngineConfig {
+        workers: Some(4),
+        ..Default::default()
+    };
+    let mut engine = Engine::new(&config).unwrap();
+    for client in 1..=CLIENTS {
+        engine
+            .submit(tx(
+                TransactionType::Deposit,
+                client as ClientId,
+                client,
+                Some(1_000.0),
+            ))
+            .unwrap();
+    }
+    // Transfers only move funds between existing accounts
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while engine.processed_count() < u64::from(CLIENTS) && Instant::now() < deadline {
+        thread::yield_now();
+    }
+
+    thread::scope(|scope| {
+        let engine = &engine;
+        for thread_id in 0..4 {
+            scope.spawn(move || {
+                for i in 0..TRANSFERS {
+                    // Neighbouring ids sit on different workers; odd threads send the other
+                    // way, so transfers lock the same pairs in both directions
+                    let from = (i + thread_id) % CLIENTS + 1;
+                    let to = from % CLIENTS + 1;
+                    let (from, to) = if thread_id % 2 == 0 {
+                        (from, to)
+                    } else {
+                        (to, from)
+                    };
+                    let outcome = engine
+                        .transfer(from as ClientId, to as ClientId, 0.75)
+                        .unwrap();
+                    assert!(
+                        matches!(
+                            outcome,
+                            ApplyOutcome::Applied
+                                | ApplyOutcome::Rejected(RejectReason::InsufficientFunds { .. })
+                        ),
+                        "{:?}",
+                        outcome
+                    );
+                }
+            });
+        }
+        // The workers keep settling deposits to the same clients meanwhile
+        scope.spawn(move || {
+            for i in 0..TRANSFERS {
+                let client = (i % CLIENTS + 1) as ClientId;
+                engine
+                    .submit(tx(TransactionType::Deposit, client, 100 + i, Some(0.5)))
+                    .unwrap();
+            }
+        });
+    });
+
+    // Both clients on one worker, an unknown client and the same client twice
+    assert_eq!(engine.transfer(1, 5, 0.25).unwrap(), ApplyOutcome::Applied);
+    assert_eq!(
+        engine.transfer(1, 99, 0.25).unwrap(),
+        ApplyOutcome::Rejected(RejectReason::UnknownClient)
+    );
+    assert!(matches!(
+        engine.transfer(2, 2, 0.25),
+        Err(EngineError::InvalidInput(_))
+    ));
+
+    let accounts = engine.drain_and_reset().unwrap();
+    let total: f64 = accounts.values().map(ClientAccount::total).sum();
+    assert_eq!(
+        total,
+        f64::from(CLIENTS) * 1_000.0 + f64::from(TRANSFERS) * 0.5
+    );
+    for account in accounts.values() {
+        assert_eq!(account.total(), account.available() + account.held());
+        assert!(account.available() >= 0.0);
+    }
+}
+
+#[test]
+fn test_process_transactions_matches_file() {
+    let csv = generate_mixed_csv(5_000);
+    let (_dir, path) = create_test_csv(&csv);
+
+    let transactions: Vec<Transaction> = csv::ReaderBuilder::new()
+        .from_reader(csv.as_bytes())
+        .deserialize()
+        .map(Result::unwrap)
+        .collect();
+
+    let from_file = process_file(&path, &EngineConfig::default()).unwrap();
+    let from_vec = process_transactions(transactions, &EngineConfig::default()).unwrap();
+
+    assert_eq!(from_file, from_vec);
+}
+
+const UUID_FIXTURE: &str = "tests/string_inputs/test_uuid_clients.csv";
+
+#[test]
+fn test_string_client_ids_interned() {
+    let config = EngineConfig {
+        string_client_ids: true,
+        workers: Some(2),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(UUID_FIXTURE, &config).unwrap();
+    let names = report.client_names.unwrap();
+
+    // Identifiers padded with whitespace are the same client once trimmed
+    assert_eq!(
+        names,
+        [
+            "3f2b8c1e-9a4d-4e7b-b1c2-0d5e6f7a8b9c",
+            "a1b2c3d4-e5f6-4a7b-8c9d-0e1f2a3b4c5d",
+            "0c0ffee0-0000-4000-8000-000000000001",
+        ]
+    );
+    assert_eq!(report.accounts[&0].available(), 60.0);
+    assert_eq!(report.accounts[&1].available(), 20.5);
+    assert_eq!(report.accounts[&1].held(), 5.0);
+    assert_eq!(report.accounts[&2].total(), 7.25);
+    assert_eq!(report.stats.rows, 6);
+    assert_eq!(report.stats.interned_clients, 3);
+    assert!(report.stats.intern_table_bytes > 0);
+}
+
+#[test]
+fn test_string_client_ids_in_output() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--string-client-ids", UUID_FIXTURE])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         3f2b8c1e-9a4d-4e7b-b1c2-0d5e6f7a8b9c,60.0,0.0,60.0,false\n\
+         a1b2c3d4-e5f6-4a7b-8c9d-0e1f2a3b4c5d,20.5,5.0,25.5,false\n\
+         0c0ffee0-0000-4000-8000-000000000001,7.25,0.0,7.25,false\n"
+    );
+}
+
+#[test]
+fn test_csv_error_message_prefixed_once() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,1.0\n\
+         deposit,1,2,1.0,9\n",
+    );
+    let message = "CSV error: record 2 (line: 3, byte: 38): found record with 5 fields, \
+                   but the previous record has 4 fields";
+    assert_eq!(
+        process_file(&path, &EngineConfig::default())
+            .unwrap_err()
+            .to_string(),
+        message
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&format!("Error processing file: {}\n", message)),
+        "{}",
+        stderr
+    );
+
+    // csv leaves its I/O errors unprefixed, e.g. an HTTP body that fails mid-stream
+    let read_failure = csv::Error::from(std::io::Error::other("connection reset"));
+    assert_eq!(
+        EngineError::from(read_failure).to_string(),
+        "CSV error: connection reset"
+    );
+}
+
+/// Client 2 only ever withdraws, client 3 disputes a transaction it never made
+/// and client 4 opens with a deposit
+const UNKNOWN_CLIENTS: &str = "type,client,tx,amount\n\
+                               deposit,1,1,50.0\n\
+                               withdrawal,2,2,10.0\n\
+                               dispute,3,1,\n\
+                               deposit,4,3,20.0\n";
+
+const INITIAL_BALANCES: &str = "client,available,held,total,locked\n\
+                                1,5.0,0.0,5.0,false\n\
+                                2,30.0,0.0,30.0,false\n";
+
+#[test]
+fn test_implicit_account_creation() {
+    let (_dir, path) = create_test_csv(UNKNOWN_CLIENTS);
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+
+    // Every client seen gets an account, even without a successful deposit
+    assert_eq!(report.accounts.len(), 4);
+    assert_eq!(report.accounts[&2].total(), 0.0);
+    assert_eq!(report.accounts[&3].total(), 0.0);
+    assert_eq!(report.stats.rejected_count("unknown_client"), 0);
+    assert_eq!(report.stats.rejected_count("insufficient_funds"), 1);
+}
+
+#[test]
+fn test_deposits_only_account_creation() {
+    let (_dir, path) = create_test_csv(UNKNOWN_CLIENTS);
+    let config = EngineConfig {
+        account_creation: AccountCreationPolicy::DepositsOnly,
+        workers: Some(2),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(&path, &config).unwrap();
+
+    let mut clients: Vec<ClientId> = report.accounts.keys().copied().collect();
+    clients.sort_unstable();
+    assert_eq!(clients, [1, 4]);
+    assert_eq!(report.accounts[&4].available(), 20.0);
+    assert_eq!(report.stats.rejected_count("unknown_client"), 2);
+}
+
+#[test]
+fn test_seeded_only_account_creation() {
+    let (_dir, path) = create_test_csv(UNKNOWN_CLIENTS);
+    let (_seed_dir, seeds) = create_named_csv("balances.csv", INITIAL_BALANCES);
+
+    let rejected = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&rejected);
+    let config = EngineConfig {
+        account_creation: AccountCreationPolicy::SeededOnly,
+        initial_balances: Some(seeds),
+        on_reject: Some(RejectHandler::new(move |transaction, reason| {
+            sink.lock()
+                .unwrap()
+                .push((transaction.client(), reason.clone()));
+        })),
+        workers: Some(2),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(&path, &config).unwrap();
+
+    assert_eq!(report.accounts.len(), 2);
+    assert_eq!(report.accounts[&1].available(), 55.0);
+    assert_eq!(report.accounts[&2].available(), 20.0);
+    assert_eq!(report.accounts[&2].total(), 20.0);
+    assert_eq!(report.stats.rejected_count("unknown_client"), 2);
+
+    let mut rejected = rejected.lock().unwrap().clone();
+    rejected.sort_by_key(|(client, _)| *client);
+    assert_eq!(
+        rejected,
+        [
+            (3, RejectReason::UnknownClient),
+            (4, RejectReason::UnknownClient)
+        ]
+    );
+}
+
+#[test]
+fn test_seeded_only_requires_initial_balances() {
+    let (_dir, path) = create_test_csv(UNKNOWN_CLIENTS);
+    let config = EngineConfig {
+        account_creation: AccountCreationPolicy::SeededOnly,
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        process_file(&path, &config),
+        Err(EngineError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_insufficient_funds_reported_with_shortfall() {
+    let transactions = vec![
+        tx(TransactionType::Deposit, 1, 1, Some(10.0)),
+        tx(TransactionType::Withdrawal, 1, 2, Some(25.5)),
+        tx(TransactionType::Withdrawal, 1, 3, Some(4.0)),
+    ];
+
+    let rejected = Arc::new(Mutex::new(Vec::new()));
+    let sink = Arc::clone(&rejected);
+    let config = EngineConfig {
+        on_reject: Some(RejectHandler::new(move |transaction, reason| {
+            sink.lock()
+                .unwrap()
+                .push((transaction.tx(), reason.clone()));
+        })),
+        ..Default::default()
+    };
+
+    let accounts = process_transactions(transactions, &config).unwrap();
+
+    // The over-withdrawal is dropped, the later one still applies
+    assert_eq!(accounts[&1].available(), 6.0);
+    assert_eq!(
+        *rejected.lock().unwrap(),
+        [(2, RejectReason::InsufficientFunds { shortfall: 15.5 })]
+    );
+}
+
+/// Client 1 is locked by a chargeback before receiving another deposit
+fn deposit_after_lock() -> Vec<Transaction> {
+    vec![
+        tx(TransactionType::Deposit, 1, 1, Some(10.0)),
+        tx(TransactionType::Deposit, 1, 2, Some(5.0)),
+        tx(TransactionType::Dispute, 1, 2, None),
+        tx(TransactionType::Chargeback, 1, 2, None),
+        tx(TransactionType::Deposit, 1, 3, Some(7.0)),
+    ]
+}
+
+#[test]
+fn test_locked_deposit_policies() {
+    for (policy, available, held, total) in [
+        (LockedDepositPolicy::Reject, 10.0, 0.0, 10.0),
+        (LockedDepositPolicy::ToHeld, 10.0, 7.0, 17.0),
+        (LockedDepositPolicy::ToAvailable, 17.0, 0.0, 17.0),
+    ] {
+        let config = EngineConfig {
+            locked_deposits: policy,
+            ..Default::default()
+        };
+
+        let accounts = process_transactions(deposit_after_lock(), &config).unwrap();
+
+        let account = &accounts[&1];
+        assert!(account.locked());
+        assert_eq!(
+            (account.available(), account.held(), account.total()),
+            (available, held, total),
+            "{:?}",
+            policy
+        );
+    }
+}
+
+#[test]
+fn test_locked_deposit_rejection_recorded() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,5.0\n\
+         dispute,1,2,\n\
+         chargeback,1,2,\n\
+         deposit,1,3,7.0\n",
+    );
+
+    let rejecting = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+    assert_eq!(rejecting.locked_deposits, LockedDepositPolicy::Reject);
+    assert_eq!(rejecting.stats.rejected_count("account_locked"), 1);
+
+    let config = EngineConfig {
+        locked_deposits: LockedDepositPolicy::ToHeld,
+        ..Default::default()
+    };
+    let holding = process_file_with_report(&path, &config).unwrap();
+    assert_eq!(holding.locked_deposits, LockedDepositPolicy::ToHeld);
+    assert_eq!(holding.stats.rejected_count("account_locked"), 0);
+}
+
+#[test]
+fn test_locked_deposit_policy_printed_only_when_verbose() {
+    let (_dir, path) = create_test_csv("type,client,tx,amount\ndeposit,1,1,1.0\n");
+    let stderr = |verbose: bool| {
+        let mut command = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"));
+        if verbose {
+            command.arg("--verbose");
+        }
+        let output = command.arg(&path).output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stderr).unwrap()
+    };
+
+    assert!(!stderr(false).contains("Locked deposit policy"));
+    let verbose = stderr(true);
+    assert!(
+        verbose.contains("Locked deposit policy: reject"),
+        "{}",
+        verbose
+    );
+}
+
+#[test]
+fn test_dispute_of_deposit_held_on_locked_account() {
+    let mut transactions = deposit_after_lock();
+    transactions.extend([
+        tx(TransactionType::Dispute, 1, 3, None),
+        tx(TransactionType::Resolve, 1, 3, None),
+        tx(TransactionType::Dispute, 1, 3, None),
+        tx(TransactionType::Chargeback, 1, 3, None),
+    ]);
+    let config = EngineConfig {
+        locked_deposits: LockedDepositPolicy::ToHeld,
+        allow_redispute: true,
+        ..Default::default()
+    };
+
+    let accounts = process_transactions(transactions, &config).unwrap();
+
+    // The frozen deposit never reaches available; its chargeback removes it from held
+    let account = &accounts[&1];
+    assert_eq!(account.available(), 10.0);
+    assert_eq!(account.held(), 0.0);
+    assert_eq!(account.total(), 10.0);
+}
+
+#[test]
+fn test_comma_thousands_semicolon_delimited() {
+    let (_dir, path) = create_test_csv(
+        "type;client;tx;amount\n\
+         deposit;1;1;1,234.5678\n\
+         deposit;2;2;12,345,678.5\n\
+         withdrawal;2;3;2,000,000.25\n",
+    );
+    let config = EngineConfig {
+        delimiter: b';',
+        number_format: NumberFormat::CommaThousands,
+        ..Default::default()
+    };
+
+    let accounts = process_file(&path, &config).unwrap();
+
+    assert_eq!(accounts[&1].available(), 1234.5678);
+    assert_eq!(accounts[&2].available(), 10_345_678.25);
+}
+
+#[test]
+fn test_european_number_format() {
+    let (_dir, path) = create_test_csv(
+        "type;client;tx;amount\n\
+         deposit;1;1;1 234,5678\n\
+         deposit;1;2;1.000,5\n\
+         withdrawal;1;3;0,0678\n",
+    );
+    let config = EngineConfig {
+        delimiter: b';',
+        number_format: NumberFormat::European,
+        ..Default::default()
+    };
+
+    let accounts = process_file(&path, &config).unwrap();
+
+    assert_eq!(accounts[&1].available(), 2235.0);
+}
+
+#[test]
+fn test_comma_number_format_needs_other_delimiter() {
+    let (_dir, path) = create_test_csv("type,client,tx,amount\ndeposit,1,1,1.0");
+    let config = EngineConfig {
+        number_format: NumberFormat::European,
+        ..Default::default()
+    };
+
+    assert!(matches!(
+        process_file(&path, &config),
+        Err(EngineError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_explain_client_trace() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,3.0\n\
+         withdrawal,1,3,15.0\n\
+         dispute,1,1,\n\
+         withdrawal,2,5,1.0\n\
+         chargeback,1,1,\n\
+         deposit,1,4,1.25\n",
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--explain", "1", "--workers", "2", &path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "Explain client 1 (5 transactions):\n  \
+             deposit tx 1 amount 10.0 -> available 10.0 held 0.0 total 10.0\n  \
+             withdrawal tx 3 amount 15.0 -> rejected: insufficient funds (short by 5.0)\n  \
+             dispute tx 1 -> available 0.0 held 10.0 total 10.0\n  \
+             chargeback tx 1 -> available 0.0 held 0.0 total 0.0 locked\n  \
+             deposit tx 4 amount 1.25 -> rejected: account locked\n"
+        ),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_client_ledger_lists_only_that_client() {
+    let input = "tests/inputs/test_client_ledger.csv";
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["ledger", "--client", "42", "--format", "csv", input])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // The dispute filed under client 7 names client 42's tx 3, so it is listed as well
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "type,client,tx,amount,outcome,reason,available,held,total,locked\n\
+         deposit,42,1,100.0,applied,,100.0,0.0,100.0,false\n\
+         withdrawal,42,3,30.5,applied,,69.5,0.0,69.5,false\n\
+         dispute,42,1,,applied,,-30.5,100.0,69.5,false\n\
+         dispute,7,3,,ignored,unknown transaction,-30.5,100.0,69.5,false\n\
+         withdrawal,42,6,200.0,rejected,insufficient funds (short by 230.5),-30.5,100.0,69.5,false\n\
+         resolve,42,1,,applied,,69.5,0.0,69.5,false\n\
+         dispute,42,99,,ignored,unknown transaction,69.5,0.0,69.5,false\n\
+         deposit,42,7,0.25,applied,,69.75,0.0,69.75,false\n\
+         \n\
+         client,available,held,total,locked\n\
+         42,69.75,0.0,69.75,false\n"
+    );
+
+    // The final account matches a full run
+    let accounts = process_file(input, &EngineConfig::default()).unwrap();
+    assert_eq!(accounts[&42].to_csv_row(), "42,69.75,0.0,69.75,false");
+}
+
+#[test]
+fn test_diff_reports_the_differing_field() {
+    let (_left_dir, left) = create_test_csv(
+        "client,available,held,total,locked\n\
+         1,10.0,0.0,10.0,false\n\
+         2,5.5,1.0,6.5,false\n",
+    );
+    // Same clients in another order; client 1 is off by less than the tolerance
+    let (_right_dir, right) = create_test_csv(
+        "client,available,held,total,locked\n\
+         2,5.5,1.0,6.75,false\n\
+         1,10.00005,0.0,10.0,false\n",
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--diff", "--tolerance", "0.001", &left, &right])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client 2: total 6.5 != 6.75\n1 differences\n"
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--diff", &left, &left])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+}
+
+#[test]
+fn test_slow_worker_does_not_stall_routing() {
+    // Worker 0 owns client 0 and stalls on its first rejection until worker 1 rejects the last
+    // transaction of the input; if the router waited on worker 0's full channel, worker 1 would
+    // never receive it
+    let released = Arc::new((Mutex::new(false), Condvar::new()));
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let handler = {
+        let released = Arc::clone(&released);
+        let timed_out = Arc::clone(&timed_out);
+        RejectHandler::new(move |transaction, _| {
+            let (lock, condvar) = &*released;
+            if transaction.client() == 1 {
+                *lock.lock().unwrap() = true;
+                condvar.notify_all();
+            } else if !timed_out.load(Ordering::SeqCst) {
+                let guard = lock.lock().unwrap();
+                let (_guard, wait) = condvar
+                    .wait_timeout_while(guard, Duration::from_secs(5), |released| !*released)
+                    .unwrap();
+                timed_out.store(wait.timed_out(), Ordering::SeqCst);
+            }
+        })
+    };
+
+    let mut transactions: Vec<Transaction> = (0..50)
+        .map(|i| tx(TransactionType::Withdrawal, 0, i, Some(1.0)))
+        .collect();
+    transactions.push(tx(TransactionType::Deposit, 1, 100, Some(5.0)));
+    transactions.push(tx(TransactionType::Withdrawal, 1, 101, Some(10.0)));
+
+    let config = EngineConfig {
+        workers: Some(2),
+        channel_capacity: Some(1),
+        on_reject: Some(handler),
+        ..Default::default()
+    };
+    let accounts = process_transactions(transactions, &config).unwrap();
+
+    assert!(!timed_out.load(Ordering::SeqCst));
+    assert_eq!(accounts[&0].total(), 0.0);
+    assert_eq!(accounts[&1].available(), 5.0);
+}
+
+#[test]
+fn test_slow_workers_with_spill_match_default() {
+    let csv = generate_mixed_csv(20_000);
+    let (_dir, path) = create_test_csv(&csv);
+
+    // Rejections on every other worker sleep, so those workers lag far behind the router
+    let config = EngineConfig {
+        workers: Some(4),
+        channel_capacity: Some(2),
+        spill_limit: 8,
+        batch_size: 4,
+        on_reject: Some(RejectHandler::new(|transaction, _| {
+            if transaction.client() % 2 == 0 {
+                std::thread::sleep(Duration::from_micros(200));
+            }
+        })),
+        ..Default::default()
+    };
+
+    let default = process_file(&path, &EngineConfig::default()).unwrap();
+    let spilled = process_file(&path, &config).unwrap();
+
+    assert_eq!(default, spilled);
+}
+
+#[test]
+fn test_manifest_counts_rows_by_type() {
+    use sha2::{Digest, Sha256};
+
+    let fixture = "tests/inputs/test_complex_scenario.csv";
+    let dir = TempDir::new().unwrap();
+    let manifest_path = dir.path().join("manifest.json");
+    let config = EngineConfig {
+        workers: Some(2),
+        manifest: Some(manifest_path.to_str().unwrap().to_string()),
+        ..EngineConfig::default()
+    };
+    process_file(fixture, &config).unwrap();
+
+    let manifest: serde_json::Value =
+        serde_json::from_reader(File::open(&manifest_path).unwrap()).unwrap();
+    assert_eq!(manifest["input"], fixture);
+    assert_eq!(manifest["rows"], 11);
+    assert_eq!(
+        manifest["transaction_types"],
+        serde_json::json!({
+            "chargeback": 1,
+            "deposit": 5,
+            "dispute": 2,
+            "resolve": 1,
+            "withdrawal": 2,
+        })
+    );
+    assert_eq!(manifest["workers"], 2);
+
+    let expected: String = Sha256::digest(std::fs::read(fixture).unwrap())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    assert_eq!(manifest["sha256"], expected);
+
+    let timestamp = manifest["timestamp"].as_str().unwrap();
+    assert_eq!(timestamp.len(), "1970-01-01T00:00:00Z".len());
+    assert!(timestamp.ends_with('Z'));
+}
+
+#[test]
+fn test_provenance_hashes_input_and_leaves_default_output_unchanged() {
+    use sha2::{Digest, Sha256};
+
+    let fixture = "tests/inputs/test_complex_scenario.csv";
+    let expected_sha256: String = Sha256::digest(std::fs::read(fixture).unwrap())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+
+    let config = EngineConfig {
+        provenance: true,
+        dispute_funds: DisputeFundsPolicy::PartialHold,
+        ..EngineConfig::default()
+    };
+    let report = process_file_with_report(fixture, &config).unwrap();
+    let provenance = report.provenance.unwrap();
+    assert_eq!(provenance.input, fixture);
+    assert_eq!(provenance.sha256, expected_sha256);
+    assert_eq!(provenance.engine_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(provenance.config["dispute_funds"], "PartialHold");
+    assert_eq!(provenance.config["max_open_disputes"], "");
+    let again = process_file_with_report(fixture, &config).unwrap();
+    assert_ne!(again.provenance.unwrap().run_id, provenance.run_id);
+    assert!(
+        process_file_with_report(fixture, &EngineConfig::default())
+            .unwrap()
+            .provenance
+            .is_none()
+    );
+
+    let run = |extra: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .args(extra)
+            .arg(fixture)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let expected =
+        std::fs::read_to_string("tests/expected_outputs/test_complex_scenario_output.csv").unwrap();
+    assert_eq!(run(&[]), expected);
+
+    let with_header = run(&["--provenance-header"]);
+    let (block, csv) = with_header.split_at(with_header.find("\nclient,").unwrap() + 1);
+    assert_eq!(csv, expected);
+    let lines: Vec<&str> = block.lines().collect();
+    assert_eq!(lines.len(), 5);
+    assert!(lines.iter().all(|line| line.starts_with("# ")));
+    assert!(lines[0].starts_with("# run_id: "));
+    assert_eq!(lines[1], format!("# input: {}", fixture));
+    assert_eq!(lines[2], format!("# sha256: {}", expected_sha256));
+    assert!(lines[4].contains("dispute_funds=AllowNegative"));
+}
+
+#[test]
+fn test_dispute_of_spent_deposit_under_each_funds_policy() {
+    // Deposit 100, withdraw 80, dispute the deposit, then charge it back
+    let fixture = "tests/inputs/test_dispute_spent_deposit.csv";
+    let run = |dispute_funds| {
+        let config = EngineConfig {
+            dispute_funds,
+            ..EngineConfig::default()
+        };
+        let report = process_file_with_report(fixture, &config).unwrap();
+        let account = &report.accounts[&1];
+        (
+            (
+                account.available(),
+                account.held(),
+                account.total(),
+                account.locked(),
+            ),
+            report.stats.rejected_count("dispute_unfunded"),
+        )
+    };
+
+    // The full deposit is held and charged back, leaving the client 80 short
+    assert_eq!(
+        run(DisputeFundsPolicy::AllowNegative),
+        ((-80.0, 0.0, -80.0, true), 0)
+    );
+    // The dispute is rejected, so the chargeback has nothing to act on
+    assert_eq!(
+        run(DisputeFundsPolicy::RejectIfInsufficient),
+        ((20.0, 0.0, 20.0, false), 1)
+    );
+    // Only the remaining 20 is held, and only that is charged back
+    assert_eq!(
+        run(DisputeFundsPolicy::PartialHold),
+        ((0.0, 0.0, 0.0, true), 0)
+    );
+}
+
+#[test]
+fn test_parallel_run_matches_ordered() {
+    // There is no cross-client transaction type yet; the dispute-heavy fixture is the most
+    // interleaved input available, and cross-client fixtures should be added here as they appear
+    let fixture = "tests/inputs/test_dispute_heavy.csv";
+    let ordered = process_file_with_report(
+        fixture,
+        &EngineConfig {
+            ordered: true,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    for workers in [2, 3, 8] {
+        let parallel = process_file_with_report(
+            fixture,
+            &EngineConfig {
+                workers: Some(workers),
+                channel_capacity: Some(2),
+                batch_size: 16,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(parallel.accounts, ordered.accounts, "{} workers", workers);
+        assert_eq!(parallel.stats.rejected, ordered.stats.rejected);
+    }
+}
+
+#[test]
+fn test_single_worker_and_zero_workers_clamped() {
+    let fixture = "tests/inputs/test_dispute_heavy.csv";
+    let expected = process_file(fixture, &EngineConfig::default()).unwrap();
+
+    for workers in [1, 0] {
+        let config = EngineConfig {
+            workers: Some(workers),
+            ..Default::default()
+        };
+        assert_eq!(config.worker_count(), 1);
+        let report = process_file_with_report(fixture, &config).unwrap();
+        assert_eq!(report.accounts, expected, "{} workers", workers);
+        assert_eq!(report.stats.per_worker.len(), 1);
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--workers", "0", fixture])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+}
+
+#[test]
+fn test_ordered_rejects_multiple_workers() {
+    let config = EngineConfig {
+        ordered: true,
+        workers: Some(4),
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_file("tests/inputs/transactions.csv", &config),
+        Err(EngineError::InvalidConfig(_))
+    ));
+    assert_eq!(
+        EngineConfig {
+            ordered: true,
+            ..Default::default()
+        }
+        .worker_count(),
+        1
+    );
+}
+
+#[test]
+fn test_directory_of_csvs_processed_in_name_order() {
+    let dir = TempDir::new().unwrap();
+    let write = |name: &str, content: &str| {
+        std::fs::write(dir.path().join(name), content).unwrap();
+    };
+    // Written out of order; the second file has no trailing newline
+    write(
+        "02-tuesday.csv",
+        "type,client,tx,amount\nwithdrawal,1,3,4.0\ndispute,2,2,",
+    );
+    write(
+        "01-monday.csv",
+        "type,client,tx,amount\ndeposit,1,1,10.0\ndeposit,2,2,5.0\n",
+    );
+    write(
+        "03-wednesday.csv",
+        "type,client,tx,amount\nchargeback,2,2,\n",
+    );
+    write("notes.txt", "not,a,transaction\n");
+
+    let accounts = process_file(dir.path().to_str().unwrap(), &EngineConfig::default()).unwrap();
+
+    assert_eq!(accounts.len(), 2);
+    assert_eq!(accounts[&1].available(), 6.0);
+    assert_eq!(accounts[&2].total(), 0.0);
+    assert!(accounts[&2].locked());
+
+    write("04-thursday.csv", "type,client,tx\ndispute,1,1\n");
+    match process_file(dir.path().to_str().unwrap(), &EngineConfig::default()) {
+        Err(e) => assert!(
+            e.to_string().contains("has header `type,client,tx`"),
+            "{}",
+            e
+        ),
+        Ok(_) => panic!("mismatched headers should fail the run"),
+    }
+}
+
+#[test]
+fn test_directory_and_empty_paths_rejected() {
+    let dir = TempDir::new().unwrap();
+    let dir_path = dir.path().to_str().unwrap();
+    let config = EngineConfig::default();
+
+    // Only the transaction input may be a directory
+    let (_input_dir, input) = create_test_csv("type,client,tx,amount\n");
+    let seeded = EngineConfig {
+        initial_balances: Some(dir_path.to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_file(&input, &seeded),
+        Err(EngineError::IsDirectory(path)) if path == dir_path
+    ));
+    assert!(matches!(
+        process_file("", &config),
+        Err(EngineError::EmptyPath)
+    ));
+
+    let missing = dir.path().join("missing.csv");
+    let missing = missing.to_str().unwrap();
+    assert!(matches!(
+        process_file(missing, &config),
+        Err(EngineError::NotFound(path)) if path == missing
+    ));
+}
+
+#[test]
+fn test_deterministic_output_across_worker_counts() {
+    let run = |workers: &str| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .args([
+                "--deterministic",
+                "--workers",
+                workers,
+                "tests/inputs/test_dispute_heavy.csv",
+            ])
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        (output.stdout, output.stderr)
+    };
+
+    let (stdout, stderr) = run("1");
+    assert!(!stdout.is_empty());
+    for workers in ["3", "7"] {
+        let (other_stdout, other_stderr) = run(workers);
+        assert!(
+            other_stdout == stdout,
+            "stdout differs at {} workers",
+            workers
+        );
+        assert_eq!(
+            String::from_utf8(other_stderr).unwrap(),
+            String::from_utf8(stderr.clone()).unwrap(),
+            "stderr differs at {} workers",
+            workers
+        );
+    }
+}
+
+#[test]
+fn test_out_of_range_shard_strategy_rejected() {
+    #[derive(Debug)]
+    struct PastTheEnd;
+
+    impl ShardStrategy for PastTheEnd {
+        fn worker_for(&self, _client: ClientId, num_workers: usize) -> usize {
+            num_workers
+        }
+    }
+
+    let config = EngineConfig {
+        workers: Some(2),
+        shard_strategy: Arc::new(PastTheEnd),
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_file("tests/inputs/transactions.csv", &config),
+        Err(EngineError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_binary_input_matches_csv() {
+    for fixture in [
+        "tests/inputs/test_complex_scenario.csv",
+        "tests/inputs/test_dispute_heavy.csv",
+        "tests/inputs/test_precision.csv",
+    ] {
+        let transactions: Vec<Transaction> = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(fixture)
+            .unwrap()
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let (_dir, path) = create_named_csv("input.bin", "");
+        std::fs::write(&path, binary::encode_transactions(&transactions).unwrap()).unwrap();
+
+        let from_binary = process_file(
+            &path,
+            &EngineConfig {
+                input_format: InputFormat::Binary,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let from_csv = process_file(fixture, &EngineConfig::default()).unwrap();
+        assert_eq!(from_binary, from_csv, "{}", fixture);
+    }
+}
+
+#[test]
+fn test_hot_client_load_lands_on_one_worker() {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 1..=1000 {
+        csv.push_str(&format!("deposit,7,{},1.0\n", tx));
+    }
+    // A handful of cold clients so the other workers are not entirely empty
+    for client in 0..3 {
+        csv.push_str(&format!("deposit,{},{},1.0\n", client, 2000 + client));
+    }
+    let (_dir, path) = create_test_csv(&csv);
+
+    let report = process_file_with_report(
+        &path,
+        &EngineConfig {
+            workers: Some(4),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let loads = &report.stats.per_worker;
+    assert_eq!(loads.len(), 4);
+    assert_eq!(loads.iter().map(|load| load.rows).sum::<u64>(), 1003);
+    assert_eq!(loads.iter().map(|load| load.clients).sum::<usize>(), 4);
+
+    let hot = &loads[7 % 4];
+    assert!(hot.rows >= 1000, "hot worker saw {} rows", hot.rows);
+    assert!(hot.rows as f64 / 1003.0 > 0.99);
+}
+
+#[test]
+fn test_hot_client_triggers_routing_skew_warning() {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 1..=20_000 {
+        // Client 1 belongs to worker 1; every 1000th row goes to client 2 on worker 0
+        let client = if tx % 1000 == 0 { 2 } else { 1 };
+        csv.push_str(&format!("deposit,{},{},1.0\n", client, tx));
+    }
+    let (_dir, path) = create_test_csv(&csv);
+
+    // One-slot channels and no spill buffer make the router wait on every full channel
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args([
+            "--workers",
+            "2",
+            "--channel-capacity",
+            "1",
+            "--spill-limit",
+            "0",
+            "--batch-size",
+            "1",
+            "--verbose",
+            &path,
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("blocked_ms"), "{}", stderr);
+    assert!(
+        stderr.contains("of the time routing was blocked went to worker 1"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_account_stream_matches_process_file() {
+    let fixture = "tests/inputs/test_dispute_heavy.csv";
+    let expected = process_file(fixture, &EngineConfig::default()).unwrap();
+
+    let streamed: Vec<_> = start_engine_iter(fixture).unwrap().collect();
+    assert_eq!(streamed.len(), expected.len());
+    assert!(
+        streamed
+            .windows(2)
+            .all(|pair| pair[0].client() < pair[1].client())
+    );
+    for account in &streamed {
+        assert_eq!(Some(account), expected.get(&account.client()));
+    }
+}
+
+#[test]
+fn test_account_stream_dropped_early_releases_workers() {
+    // Far more clients than the per-worker output buffer, so workers block sending accounts
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 0..20_000u32 {
+        csv.push_str(&format!("deposit,{},{},1.0\n", tx % 10_000, tx));
+    }
+    let (_dir, path) = create_test_csv(&csv);
+
+    // Every worker thread holds a clone of the config, and with it the shard strategy
+    let strategy = Arc::new(ModuloShard);
+    let config = EngineConfig {
+        workers: Some(4),
+        shard_strategy: strategy.clone(),
+        ..Default::default()
+    };
+
+    let mut stream = start_engine_iter_with_config(&path, &config).unwrap();
+    let first: Vec<_> = stream.by_ref().take(3).collect();
+    assert_eq!(
+        first
+            .iter()
+            .map(|account| account.client())
+            .collect::<Vec<_>>(),
+        [0, 1, 2]
+    );
+    drop(stream);
+
+    // Only the test and its config still hold the strategy once every worker has exited
+    assert_eq!(Arc::strong_count(&strategy), 2);
+}
+
+#[test]
+fn test_selftest_command_passes() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg("--selftest")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success(), "{}", stdout);
+    assert!(stdout.contains("PASS test_dispute_chargeback"));
+    assert!(stdout.trim_end().ends_with("0 failed"));
+}
+
+#[test]
+fn test_client_filters_limit_output() {
+    let fixture = "tests/inputs/test_8_clients.csv";
+    let all = process_file(fixture, &EngineConfig::default()).unwrap();
+    assert_eq!(all.len(), 8);
+
+    let report = process_file_with_report(
+        fixture,
+        &EngineConfig {
+            include_clients: Some([1, 3].into()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut clients: Vec<_> = report.accounts.keys().copied().collect();
+    clients.sort_unstable();
+    assert_eq!(clients, [1, 3]);
+    for client in clients {
+        assert_eq!(report.accounts[&client], all[&client]);
+    }
+    assert!(report.stats.filtered > 0);
+
+    let excluded = process_file(
+        fixture,
+        &EngineConfig {
+            exclude_clients: [0, 2, 4, 5, 6, 7].into(),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(excluded, report.accounts);
+
+    let both = EngineConfig {
+        include_clients: Some([1, 3, 5].into()),
+        exclude_clients: [5].into(),
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_file(fixture, &both),
+        Err(EngineError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_client_filter_flags_match_an_extracted_file() {
+    let run = |args: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .args(args)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+    let (extracted, _) = run(&["tests/inputs/test_8_clients_client_2.csv"]);
+
+    let (included, stderr) = run(&["--include-clients", "2", "tests/inputs/test_8_clients.csv"]);
+    assert_eq!(included, extracted);
+    assert!(stderr.contains("Skipped 24 transactions of filtered-out clients"));
+
+    let (dir, _) = create_test_csv("");
+    let ids = dir.path().join("excluded.txt");
+    std::fs::write(&ids, "0\n1\n3\n\n4\n5\n6\n7\n").unwrap();
+    let (excluded, _) = run(&[
+        "--exclude-clients",
+        ids.to_str().unwrap(),
+        "tests/inputs/test_8_clients.csv",
+    ]);
+    assert_eq!(excluded, extracted);
+
+    let both = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--include-clients", "1,2", "--exclude-clients", "1"])
+        .arg("tests/inputs/test_8_clients.csv")
+        .output()
+        .unwrap();
+    assert!(!both.status.success());
+}
+
+#[test]
+fn test_empty_output_policy_on_all_rejected_input() {
+    // Withdrawals cannot open accounts under deposits-only creation, so nothing survives
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         withdrawal,1,1,10.0\n\
+         withdrawal,2,2,5.0\n",
+    );
+    let config = EngineConfig {
+        account_creation: AccountCreationPolicy::DepositsOnly,
+        ..Default::default()
+    };
+    assert!(process_file(&path, &config).unwrap().is_empty());
+
+    let warn = EngineConfig {
+        empty_output: EmptyOutputPolicy::Warn,
+        ..config.clone()
+    };
+    assert!(process_file(&path, &warn).unwrap().is_empty());
+
+    let error = EngineConfig {
+        empty_output: EmptyOutputPolicy::Error,
+        ..config
+    };
+    assert!(matches!(
+        process_file(&path, &error),
+        Err(EngineError::EmptyOutput { rows: 2 })
+    ));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args([
+            "--account-creation",
+            "deposits-only",
+            "--empty-output",
+            "warn",
+            &path,
+        ])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Warning: All 2 input rows were processed but no accounts were produced")
+    );
+}
+
+#[test]
+fn test_dispute_threshold_alert_and_lock_reason() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,20.0\n\
+         deposit,1,3,30.0\n\
+         deposit,2,4,50.0\n\
+         dispute,1,1,\n\
+         dispute,1,2,\n\
+         dispute,1,3,\n\
+         deposit,1,5,5.0\n\
+         dispute,2,4,\n\
+         chargeback,2,4,\n",
+    );
+    let config = EngineConfig {
+        max_open_disputes: Some(3),
+        workers: Some(2),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(&path, &config).unwrap();
+    assert_eq!(
+        report.stats.alerts,
+        [DisputeAlert {
+            client: 1,
+            tx: 3,
+            open_disputes: 3,
+            disputed_amount: 60.0,
+        }]
+    );
+    assert_eq!(report.stats.rejected_count("account_locked"), 1);
+    assert_eq!(
+        report.accounts[&1].lock_reason(),
+        Some(LockReason::DisputeThreshold)
+    );
+    assert_eq!(
+        report.accounts[&2].lock_reason(),
+        Some(LockReason::Chargeback)
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args([
+            "--max-open-disputes",
+            "3",
+            "--columns",
+            "client,locked,lock_reason",
+            &path,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,locked,lock_reason\n1,true,dispute-threshold\n2,true,chargeback\n"
+    );
+}
+
+#[test]
+fn test_clients_holding_funds_above_threshold_flagged() {
+    // Client 1 goes above 50 held with its second dispute and is listed once, although it
+    // crosses again after resolving; client 2 only reaches the threshold
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,30.0\n\
+         deposit,1,2,25.0\n\
+         deposit,2,3,50.0\n\
+         deposit,3,4,80.0\n\
+         dispute,1,1,\n\
+         dispute,2,3,\n\
+         dispute,1,2,\n\
+         resolve,1,1,\n\
+         resolve,1,2,\n\
+         dispute,1,1,\n\
+         dispute,1,2,\n",
+    );
+    let config = EngineConfig {
+        held_threshold: Some(50.0),
+        allow_redispute: true,
+        workers: Some(2),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(&path, &config).unwrap();
+    assert_eq!(
+        report.stats.suspicious,
+        [HeldAlert {
+            client: 1,
+            tx: 2,
+            held: 55.0,
+        }]
+    );
+    assert_eq!(report.stats.suspicious_clients(), [1]);
+    assert_eq!(report.accounts[&1].held(), 55.0);
+    assert!(!report.accounts[&1].locked());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--held-threshold", "50", &path])
+        .output()
+        .unwrap();
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Suspicious clients, held funds above 50.0: 1\n")
+    );
+}
+
+#[test]
+fn test_late_dispute_rejected_outside_window() {
+    // Client 2's transactions in between don't age client 1's deposits
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,20.0\n\
+         deposit,2,3,1.0\n\
+         deposit,2,4,1.0\n\
+         deposit,2,5,1.0\n\
+         deposit,1,6,5.0\n\
+         dispute,1,2,\n\
+         dispute,1,1,\n",
+    );
+    let config = EngineConfig {
+        max_dispute_age: Some(3),
+        workers: Some(2),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(&path, &config).unwrap();
+    // tx 2 is disputed 3 transactions on and held; tx 1 is 4 on and rejected
+    assert_eq!(report.accounts[&1].held(), 20.0);
+    assert_eq!(report.accounts[&1].available(), 15.0);
+    assert_eq!(report.stats.rejected_count("dispute_expired"), 1);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--max-dispute-age", "0", &path])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("max_dispute_age must be at least 1")
+    );
+}
+
+#[test]
+fn test_out_of_order_timestamps_applied_in_order() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount,timestamp\n\
+         withdrawal,1,2,5.0,200\n\
+         deposit,2,3,7.0,150\n\
+         deposit,1,1,10.0,100\n\
+         dispute,2,3,,400\n\
+         withdrawal,2,4,7.0,300\n\
+         deposit,1,5,1.0,50\n",
+    );
+    let config = EngineConfig {
+        reorder_window: Some(1),
+        workers: Some(2),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(&path, &config).unwrap();
+    assert_eq!(report.stats.rejected_count("insufficient_funds"), 0);
+    assert_eq!(report.accounts[&1].available(), 6.0);
+    // Client 2 withdrew everything before disputing the deposit
+    assert_eq!(report.accounts[&2].available(), -7.0);
+    assert_eq!(report.accounts[&2].held(), 7.0);
+    // Client 1's deposit at 50 came after its deposit at 100 had been released
+    assert_eq!(report.stats.late, 1);
+
+    // In file order the first withdrawal finds no funds
+    let in_file_order = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+    assert_eq!(in_file_order.stats.rejected_count("insufficient_funds"), 2);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--reorder-window", "8", &path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,6.0,0.0,6.0,false\n2,-7.0,7.0,0.0,false\n"
+    );
+}
+
+#[test]
+fn test_id_fields_with_leading_zeros_and_signs() {
+    let (_dir, path) = create_test_csv("type,client,tx,amount\ndeposit,001,0007,5.0\n");
+    let accounts = process_file(&path, &EngineConfig::default()).unwrap();
+    assert_eq!(accounts[&1].available(), 5.0);
+
+    for (row, column, value) in [
+        ("deposit,1,+5,5.0", "tx", "+5"),
+        ("deposit,1,-1,5.0", "tx", "-1"),
+        ("deposit,-1,1,5.0", "client", "-1"),
+    ] {
+        let (_dir, path) = create_test_csv(&format!(
+            "type,client,tx,amount\ndeposit,1,1,1.0\n{}\n",
+            row
+        ));
+        match process_file(&path, &EngineConfig::default()) {
+            Err(EngineError::SignedId {
+                column: got_column,
+                value: got_value,
+                line,
+            }) => {
+                assert_eq!((got_column, got_value.as_str(), line), (column, value, 3));
+            }
+            other => panic!("{}: expected SignedId, got {:?}", row, other),
+        }
+    }
+}
+
+#[test]
+fn test_alternate_column_names() {
+    let rows = "deposit,1,1,10.0\n\
+                deposit,2,2,5.0\n\
+                withdrawal,1,3,2.5\n\
+                dispute,2,2,\n";
+    let (_dir, canonical) = create_test_csv(&format!("type,client,tx,amount\n{}", rows));
+    let (_dir, aliased) = create_test_csv(&format!(
+        " transaction_type, client_id, transaction_id, value\n{}",
+        rows
+    ));
+
+    let expected = process_file(&canonical, &EngineConfig::default()).unwrap();
+    let accounts = process_file(&aliased, &EngineConfig::default()).unwrap();
+    assert_eq!(accounts, expected);
+    assert_eq!(accounts[&1].available(), 7.5);
+    assert_eq!(accounts[&2].held(), 5.0);
+
+    // Checks that look columns up by name find the aliases too
+    let (_dir, signed) =
+        create_test_csv("transaction_type,client_id,transaction_id,value\ndeposit,-1,1,1.0\n");
+    assert!(matches!(
+        process_file(&signed, &EngineConfig::default()),
+        Err(EngineError::SignedId {
+            column: "client",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn test_multi_currency_balances_per_currency() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount,currency\n\
+         deposit,1,1,100.0,USD\n\
+         deposit,1,2,50.0,EUR\n\
+         withdrawal,1,3,30.0,USD\n\
+         dispute,1,2,,\n\
+         deposit,2,4,7.5,EUR\n\
+         deposit,2,5,1.0,\n",
+    );
+    let config = EngineConfig {
+        multi_currency: true,
+        ..Default::default()
+    };
+    let report = process_file_with_report(&path, &config).unwrap();
+    let rows: Vec<_> = report
+        .currency_accounts
+        .iter()
+        .map(|account| {
+            (
+                account.client(),
+                account.currency().unwrap().to_string(),
+                account.available(),
+                account.held(),
+                account.total(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        rows,
+        [
+            (1, "EUR".to_string(), 0.0, 50.0, 50.0),
+            (1, "USD".to_string(), 70.0, 0.0, 70.0),
+            (2, "EUR".to_string(), 7.5, 0.0, 7.5),
+        ]
+    );
+    assert_eq!(
+        report.stats.rejected[&RejectReason::MissingCurrency.code()],
+        1
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--multi-currency", &path])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,currency,available,held,total,locked\n\
+         1,EUR,0.0,50.0,50.0,false\n\
+         1,USD,70.0,0.0,70.0,false\n\
+         2,EUR,7.5,0.0,7.5,false\n"
+    );
+}
+
+#[test]
+fn test_currency_column_ignored_without_multi_currency() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount,currency\n\
+         deposit,1,1,100.0,USD\n\
+         deposit,1,2,50.0,not a code\n",
+    );
+    let accounts = process_file(&path, &EngineConfig::default()).unwrap();
+    assert_eq!(accounts[&1].available(), 150.0);
+
+    let config = EngineConfig {
+        multi_currency: true,
+        max_disputed_amount: Some(100.0),
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_file(&path, &config),
+        Err(EngineError::InvalidConfig(_))
+    ));
+}
+
+#[cfg(feature = "affinity")]
+#[test]
+fn test_pinned_workers_give_the_same_accounts() {
+    let fixture = "tests/inputs/test_dispute_heavy.csv";
+    let unpinned = process_file(fixture, &EngineConfig::default()).unwrap();
+
+    // More workers than this machine may have cores only skips pinning
+    for (workers, pin_router) in [(1, false), (1, true), (2, true), (256, false)] {
+        let config = EngineConfig {
+            workers: Some(workers),
+            pin_workers: true,
+            pin_router,
+            ..Default::default()
+        };
+        assert_eq!(process_file(fixture, &config).unwrap(), unpinned);
+    }
+
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+    let pinned = run(&["--pin-workers", "--pin-router", "--workers", "1", fixture]);
+    assert!(pinned.status.success());
+    assert_eq!(pinned.stdout, run(&[fixture]).stdout);
+}
+
+#[cfg(not(feature = "affinity"))]
+#[test]
+fn test_pin_workers_needs_affinity_feature() {
+    let config = EngineConfig {
+        pin_workers: true,
+        ..Default::default()
+    };
+    let result = process_file("tests/inputs/test_dispute_heavy.csv", &config);
+    assert!(matches!(result, Err(EngineError::InvalidConfig(_))));
+}
+
+#[test]
+fn test_inspect_reports_columns_and_delimiter() {
+    let schema = inspect("tests/inputs/transactions.csv", &EngineConfig::default()).unwrap();
+    assert_eq!(schema.delimiter, b',');
+    assert_eq!(schema.columns, ["type", "client", "tx", "amount"]);
+    assert!(schema.is_clean());
+
+    let (_dir, path) = create_test_csv(
+        "transaction_type;client_id;tx;value;note\n\
+         deposit;1;1;1.5;a\n\
+         withdrawal;1;2;0.12345;b\n\
+         dispute;1;1;;c\n",
+    );
+    let schema = inspect(&path, &EngineConfig::default()).unwrap();
+    assert_eq!(schema.delimiter, b';');
+    assert_eq!(
+        schema.recognized,
+        [
+            ("transaction_type".to_string(), "type"),
+            ("client_id".to_string(), "client"),
+            ("tx".to_string(), "tx"),
+            ("value".to_string(), "amount"),
+        ]
+    );
+    assert_eq!(schema.unknown, ["note"]);
+    assert!(schema.missing.is_empty());
+    assert_eq!((schema.sample_rows, schema.amounts), (3, 2));
+    assert_eq!(schema.irregular_amounts, ["0.12345"]);
+
+    let (_dir, path) = create_test_csv("kind|client|amount\ndeposit|1|2.0\n");
+    let schema = inspect(&path, &EngineConfig::default()).unwrap();
+    assert_eq!(schema.delimiter, b'|');
+    assert_eq!(schema.missing, ["type", "tx"]);
+    assert!(!schema.is_clean());
+}
+
+#[test]
+fn test_journal_replays_to_identical_accounts() {
+    let dir = TempDir::new().unwrap();
+    for fixture in [
+        "tests/inputs/test_dispute_heavy.csv",
+        "tests/inputs/test_complex_scenario.csv",
+        "tests/inputs/test_precision.csv",
+    ] {
+        let journal = dir.path().join("run.journal");
+        let journal = journal.to_str().unwrap().to_string();
+        let config = EngineConfig {
+            journal: Some(journal.clone()),
+            workers: Some(3),
+            ..Default::default()
+        };
+
+        let report = process_file_with_report(fixture, &config).unwrap();
+        let replayed = replay_journal(&journal, &EngineConfig::default()).unwrap();
+        assert_eq!(replayed, report.accounts, "{}", fixture);
+
+        // Only applied transactions are journaled
+        let rows = std::fs::read_to_string(&journal).unwrap().lines().count() as u64;
+        assert_eq!(rows, report.stats.applied + 1, "{}", fixture);
+    }
+}
+
+#[test]
+fn test_journal_parts_written_as_transactions_apply() {
+    let dir = TempDir::new().unwrap();
+    let journal = dir.path().join("run.journal");
+    let journal = journal.to_str().unwrap().to_string();
+    let config = EngineConfig {
+        journal: Some(journal.clone()),
+        workers: Some(2),
+        ..Default::default()
+    };
+    let part = |worker| format!("{}.{}", journal, worker);
+
+    process_file("tests/inputs/test_dispute_heavy.csv", &config).unwrap();
+    assert!(!std::path::Path::new(&part(0)).exists());
+    assert!(!std::path::Path::new(&part(1)).exists());
+
+    // A run that fails part way keeps each worker's part, a journal of what it had applied
+    // before the failure stopped it
+    let (_input, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,1.0\n\
+         deposit,2,2,2.0\n\
+         deposit,1,3,1.0,9\n",
+    );
+    std::fs::remove_file(&journal).unwrap();
+    assert!(process_file(&path, &config).is_err());
+    assert!(!std::path::Path::new(&journal).exists());
+    for worker in 0..2 {
+        let replayed = replay_journal(&part(worker), &EngineConfig::default()).unwrap();
+        assert!(replayed.values().all(|account| account.total() > 0.0));
+    }
+}
+
+#[test]
+fn test_resume_from_snapshot_matches_single_run() {
+    let first = "type,client,tx,amount\n\
+                 deposit,1,1,10.0\n\
+                 deposit,2,2,20.0\n\
+                 withdrawal,2,3,5.0\n\
+                 dispute,2,2,\n";
+    let second = "type,client,tx,amount\n\
+                  dispute,1,1,\n\
+                  resolve,2,2,\n\
+                  chargeback,1,1,\n\
+                  deposit,3,4,1.5\n";
+    let (dir, first_path) = create_test_csv(first);
+    let (_dir, second_path) = create_test_csv(second);
+    let (_dir, whole_path) = create_test_csv(&format!(
+        "{}{}",
+        first,
+        second.trim_start_matches("type,client,tx,amount\n")
+    ));
+    let snapshot = dir.path().join("state.snap");
+    let snapshot = snapshot.to_str().unwrap().to_string();
+
+    process_file(
+        &first_path,
+        &EngineConfig {
+            snapshot: Some(snapshot.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let resumed = process_file(
+        &second_path,
+        &EngineConfig {
+            resume_from: Some(snapshot.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let whole = process_file(&whole_path, &EngineConfig::default()).unwrap();
+    assert_eq!(resumed, whole);
+    assert!(resumed[&1].locked());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["inspect-snapshot", &snapshot])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "format version: 6\nengine version: {}\nclients: 2\ntransactions: 3\n",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["inspect-snapshot", &first_path])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("bad magic")
+    );
+}
+
+#[test]
+fn test_verify_passes_clean_run_and_reports_inconsistent_seed() {
+    let run = |args: &[&str]| {
+        std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .args(args)
+            .output()
+            .unwrap()
+    };
+
+    for fixture in [
+        "tests/inputs/test_dispute_heavy.csv",
+        "tests/inputs/test_withdrawal_with_dispute.csv",
+    ] {
+        let output = run(&["--verify", "--locked-deposits", "to-held", fixture]);
+        assert!(output.status.success(), "{}", fixture);
+        assert!(
+            !String::from_utf8(output.stderr)
+                .unwrap()
+                .contains("Verification")
+        );
+    }
+
+    // A seeded total that doesn't add up is kept as loaded, so verification catches it
+    let (_dir, path) = create_test_csv("type,client,tx,amount\ndeposit,1,1,1.0\n");
+    let (_seed_dir, seeds) = create_named_csv(
+        "balances.csv",
+        "client,available,held,total,locked\n\
+         1,10.0,0.0,10.0,false\n\
+         3,10.0,1.0,12.0,false\n",
+    );
+    let output = run(&["--verify", "--initial-balances", &seeds, &path]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .contains("3,10.0,1.0,12.0,false")
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(
+            "Verification failed for client 3: total 12.0 is not available 10.0 + held 1.0"
+        )
+    );
+    assert!(!stderr.contains("client 1"));
+}
+
+#[test]
+fn test_idempotent_replay_against_saved_state_matches_single_run() {
+    let (dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,20.0\n\
+         withdrawal,2,3,5.0\n\
+         dispute,2,2,\n\
+         resolve,2,2,\n\
+         deposit,3,4,7.5\n\
+         dispute,3,4,\n\
+         chargeback,3,4,\n\
+         deposit,1,5,2.5\n",
+    );
+    let snapshot = dir.path().join("state.snap");
+    let snapshot = snapshot.to_str().unwrap();
+    let run = |extra: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .args(["--idempotent", "--snapshot", snapshot])
+            .args(extra)
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        (
+            String::from_utf8(output.stdout).unwrap(),
+            String::from_utf8(output.stderr).unwrap(),
+        )
+    };
+
+    let (single, _) = run(&[]);
+    let (replayed, stderr) = run(&["--resume", snapshot, "--verbose"]);
+    assert_eq!(replayed, single);
+    assert!(
+        stderr.contains("Skipped 5 deposits and withdrawals already applied"),
+        "{}",
+        stderr
+    );
+    // The replay saved the same state again, so replaying once more still changes nothing
+    let (replayed, _) = run(&["--resume", snapshot]);
+    assert_eq!(replayed, single);
+
+    // Without the option the replayed deposits and withdrawals count as rejected duplicates
+    let report = process_file_with_report(
+        &path,
+        &EngineConfig {
+            resume_from: Some(snapshot.to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    assert_eq!(report.accounts[&1].available(), 12.5);
+    assert_eq!(report.stats.rejected_count("duplicate_tx"), 5);
+}
+
+#[test]
+fn test_dry_run_leaves_snapshot_untouched() {
+    let (dir, base_path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,20.0\n\
+         withdrawal,2,3,15.0\n",
+    );
+    let snapshot = dir.path().join("state.snap");
+    let snapshot = snapshot.to_str().unwrap().to_string();
+    process_file(
+        &base_path,
+        &EngineConfig {
+            snapshot: Some(snapshot.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let before = std::fs::read(&snapshot).unwrap();
+
+    // Charging back client 2's deposit leaves it at -15; client 1 overdraws
+    let (_dir, correction) = create_test_csv(
+        "type,client,tx,amount\n\
+         dispute,2,2,\n\
+         chargeback,2,2,\n\
+         withdrawal,1,4,50.0\n\
+         deposit,3,5,1.0\n",
+    );
+    let config = EngineConfig {
+        resume_from: Some(snapshot.clone()),
+        snapshot: Some(snapshot.clone()),
+        dry_run: true,
+        ..Default::default()
+    };
+    let report = dry_run(&correction, &config).unwrap();
+    assert_eq!((report.rows, report.applied), (4, 3));
+    assert_eq!(report.rejected[&"insufficient_funds"], 1);
+    assert_eq!(report.newly_locked, [2]);
+    assert_eq!(report.negative.len(), 1);
+    assert_eq!(report.negative[0].available(), -15.0);
+    assert!(!report.is_clean());
+    assert_eq!(std::fs::read(&snapshot).unwrap(), before);
+
+    // The library entry point leaves previews to `dry_run` rather than running them for real
+    assert!(matches!(
+        start_engine_with_config(&correction, &config),
+        Err(EngineError::InvalidConfig(_))
+    ));
+    assert_eq!(std::fs::read(&snapshot).unwrap(), before);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args([
+            "--resume",
+            &snapshot,
+            "--snapshot",
+            &snapshot,
+            "--dry-run",
+            &correction,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("Dry run:"), "{}", stdout);
+    assert!(!stdout.contains("client,available"));
+    assert!(stdout.contains("  client 2: available -15.0 held 0.0 total -15.0"));
+    assert_eq!(std::fs::read(&snapshot).unwrap(), before);
+}
+
+#[test]
+fn test_nothing_moves_after_terminal_chargeback() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,100.0\n\
+         deposit,1,2,50.0\n\
+         dispute,1,1,\n\
+         chargeback,1,1,\n\
+         resolve,1,1,\n\
+         dispute,1,1,\n\
+         resolve,1,1,\n\
+         chargeback,1,1,\n",
+    );
+
+    for allow_redispute in [false, true] {
+        let config = EngineConfig {
+            explain: Some(1),
+            allow_redispute,
+            ..Default::default()
+        };
+        let report = process_file_with_report(&path, &config).unwrap();
+        let account = &report.accounts[&1];
+        assert_eq!(
+            (
+                account.available(),
+                account.held(),
+                account.total(),
+                account.locked()
+            ),
+            (50.0, 0.0, 50.0, true)
+        );
+        assert_eq!(account.lock_reason(), Some(LockReason::Chargeback));
+        assert!(report.stats.rejected.is_empty());
+
+        // Every step after the chargeback leaves the account exactly as the chargeback did
+        let after_chargeback: Vec<_> = report.explain[3..]
+            .iter()
+            .map(|step| step.outcome.as_ref().unwrap())
+            .collect();
+        assert_eq!(after_chargeback.len(), 5);
+        assert!(after_chargeback.iter().all(|step| *step == account));
+    }
+}
+
+#[test]
+fn test_max_bytes_refuses_large_file_before_reading() {
+    let csv = generate_mixed_csv(100);
+    let size = csv.len() as u64;
+    let (_dir, path) = create_test_csv(&csv);
+
+    let at_limit = EngineConfig {
+        max_bytes: Some(size),
+        ..Default::default()
+    };
+    assert!(process_file(&path, &at_limit).is_ok());
+
+    let config = EngineConfig {
+        max_bytes: Some(size - 1),
+        ..Default::default()
+    };
+    let err = process_file(&path, &config).unwrap_err();
+    assert!(
+        matches!(err, EngineError::InputTooLarge { size: s, max_bytes } if s == size && max_bytes == size - 1),
+        "{:?}",
+        err
+    );
+    assert!(err.to_string().contains("max_bytes"));
+}
+
+#[test]
+fn test_max_rows_stops_at_first_row_over_limit() {
+    let (_dir, path) = create_test_csv(&generate_mixed_csv(100));
+
+    let at_limit = EngineConfig {
+        max_rows: Some(100),
+        ..Default::default()
+    };
+    assert!(process_file(&path, &at_limit).is_ok());
+
+    for batch_size in [1, 16] {
+        let config = EngineConfig {
+            max_rows: Some(40),
+            row_limit: RowLimitPolicy::Error,
+            batch_size,
+            ..Default::default()
+        };
+        let err = process_file(&path, &config).unwrap_err();
+        assert!(
+            matches!(
+                err,
+                EngineError::TooManyRows {
+                    row: 41,
+                    max_rows: 40
+                }
+            ),
+            "{:?}",
+            err
+        );
+        assert_eq!(
+            err.to_string(),
+            "Input exceeds the max_rows limit of 40 rows: stopped at data row 41"
+        );
+    }
+}
+
+#[test]
+fn test_max_rows_truncates_at_batch_boundary() {
+    let csv = generate_mixed_csv(100);
+    let (_dir, path) = create_test_csv(&csv);
+    // The first 32 rows alone, i.e. the header and 32 lines
+    let head: String = csv.split_inclusive('\n').take(33).collect();
+    let (_head_dir, head_path) = create_test_csv(&head);
+    let expected = process_file(&head_path, &EngineConfig::default()).unwrap();
+
+    // 32 rows fill exactly two batches of 16, and exactly 32 one-row messages
+    for batch_size in [1, 16] {
+        let config = EngineConfig {
+            max_rows: Some(32),
+            batch_size,
+            workers: Some(3),
+            ..Default::default()
+        };
+        let report = process_file_with_report(&path, &config).unwrap();
+        assert_eq!(report.stats.rows, 32);
+        assert!(report.stats.row_limit_reached);
+        assert_eq!(report.accounts, expected, "batch size {}", batch_size);
+    }
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--max-rows", "32", "--batch-size", "16", &path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Warning: stopped reading the input at the max_rows limit of 32 rows"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_stop_after_row_gives_balances_at_that_row() {
+    // Row 4, the second withdrawal, is the first to change client 1 after row 3
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,2,2,4.0\n\
+         withdrawal,1,3,3.0\n\
+         withdrawal,1,4,2.5\n\
+         dispute,2,2,\n",
+    );
+    let run = |rows: u64| {
+        let config = EngineConfig {
+            stop_after_row: Some(rows),
+            batch_size: 2,
+            workers: Some(2),
+            ..Default::default()
+        };
+        process_file_with_report(&path, &config).unwrap()
+    };
+
+    let after_3 = run(3);
+    assert_eq!(after_3.stats.rows, 3);
+    assert!(!after_3.stats.row_limit_reached);
+    assert_eq!(after_3.accounts[&1].available(), 7.0);
+    assert_eq!(after_3.accounts[&2].available(), 4.0);
+
+    let after_4 = run(4);
+    assert_eq!(after_4.stats.rows, 4);
+    assert_eq!(after_4.accounts[&1].available(), 4.5);
+    assert_eq!(after_4.accounts[&2].held(), 0.0);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--stop-after-row", "4", &path])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,4.5,0.0,4.5,false\n2,4.0,0.0,4.0,false\n"
+    );
+    assert!(
+        !String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Warning")
+    );
+}
+
+/// Runs the binary on a FIFO that gets a header and one deposit, then stays open without
+/// being written to until the run exits
+#[cfg(unix)]
+fn run_on_stalled_pipe(args: &[&str]) -> std::process::Output {
+    let dir = TempDir::new().unwrap();
+    let fifo = dir.path().join("input.csv");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    let child = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(args)
+        .arg(&fifo)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Opening blocks until the engine opens the other end
+    let mut writer = std::fs::OpenOptions::new().write(true).open(&fifo).unwrap();
+    writer
+        .write_all(b"type,client,tx,amount\ndeposit,1,1,2.5\n")
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    drop(writer);
+    output
+}
+
+#[cfg(unix)]
+#[test]
+fn test_timeout_stops_a_stalled_input() {
+    let started = std::time::Instant::now();
+    let output = run_on_stalled_pipe(&["--timeout", "1", "--workers", "2"]);
+    assert!(started.elapsed() < Duration::from_secs(30));
+    assert_eq!(output.status.code(), Some(124));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr
+            .contains("Error processing file: Input not read to the end within the timeout of 1s"),
+        "{}",
+        stderr
+    );
+
+    // With --allow-partial the row read in time is applied and written
+    let output = run_on_stalled_pipe(&["--timeout", "1", "--allow-partial"]);
+    assert_eq!(output.status.code(), Some(124));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n1,2.5,0.0,2.5,false\n"
+    );
+}
+
+#[test]
+fn test_disputes_of_non_transfers_counted_as_orphans() {
+    // Only the client's applied deposits and withdrawals are disputable: a dispute action
+    // naming a rejected withdrawal, another client's deposit or an unseen tx id is a no-op
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         dispute,1,1,\n\
+         resolve,1,1,\n\
+         withdrawal,1,2,50.0\n\
+         dispute,1,2,\n\
+         dispute,2,1,\n\
+         chargeback,1,7,\n",
+    );
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+
+    assert_eq!(report.stats.orphan_disputes, 3);
+    assert_eq!(report.stats.applied, 3);
+    assert_eq!(report.accounts[&1].available(), 10.0);
+    assert_eq!(report.accounts[&1].held(), 0.0);
+    assert!(!report.accounts[&1].locked());
+}
+
+#[test]
+fn test_dispute_after_duplicate_deposit_holds_first_record() {
+    // The second deposit reuses tx 1; it must neither move funds nor replace the record the
+    // dispute holds
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,100.0\n\
+         deposit,1,1,50.0\n\
+         dispute,1,1,\n",
+    );
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+
+    assert_eq!(report.stats.rejected_count("duplicate_tx"), 1);
+    assert_eq!(report.accounts[&1].held(), 100.0);
+    assert_eq!(report.accounts[&1].available(), 0.0);
+    assert_eq!(report.accounts[&1].total(), 100.0);
+}
+
+#[test]
+fn test_profile_counts_stored_records() {
+    let fixture = "tests/inputs/test_precision.csv";
+    let transfers = std::fs::read_to_string(fixture)
+        .unwrap()
+        .lines()
+        .filter(|line| line.starts_with("deposit") || line.starts_with("withdrawal"))
+        .count();
+    let config = EngineConfig {
+        workers: Some(3),
+        profile: true,
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(fixture, &config).unwrap();
+    assert!(report.stats.rejected.is_empty());
+    assert_eq!(report.stats.stored_records(), transfers);
+
+    // Without profiling nothing is counted
+    let report = process_file_with_report(fixture, &EngineConfig::default()).unwrap();
+    assert_eq!(report.stats.stored_records(), 0);
+}
+
+#[test]
+fn test_phase_timers_cover_the_run() {
+    let fixture = "tests/inputs/test_dispute_heavy.csv";
+    let input = std::fs::read_to_string(fixture).unwrap();
+    // One worker, so its settle time can't add up to more than the run
+    let config = EngineConfig {
+        workers: Some(1),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(fixture, &config).unwrap();
+    let stats = &report.stats;
+    let phases = stats.phases;
+    assert_eq!(stats.rows, input.lines().count() as u64 - 1);
+    assert_eq!(stats.input_bytes, input.len() as u64);
+    assert!(!phases.route.is_zero());
+    assert!(!phases.settle.is_zero());
+    assert!(phases.elapsed >= phases.route);
+    assert!(phases.elapsed >= phases.settle);
+    // Accounts are returned rather than written
+    assert!(phases.write.is_zero());
+    assert!(stats.rows_per_second() > 0.0);
+    assert!(stats.input_bytes_per_second() > stats.rows_per_second());
+}
+
+#[test]
+fn test_dispute_only_feed_without_amount_column() {
+    let dir = TempDir::new().unwrap();
+    let deposits = dir.path().join("deposits.csv");
+    std::fs::write(
+        &deposits,
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,5.0\n\
+         deposit,2,3,7.5\n",
+    )
+    .unwrap();
+    let disputes = dir.path().join("disputes.csv");
+    std::fs::write(
+        &disputes,
+        "type,client,tx\n\
+         dispute,1,1\n\
+         dispute,1,2\n\
+         resolve,1,2\n\
+         dispute,2,3\n\
+         chargeback,2,3\n",
+    )
+    .unwrap();
+    let snapshot = dir.path().join("state.snap");
+    let snapshot = snapshot.to_str().unwrap().to_string();
+
+    process_file(
+        deposits.to_str().unwrap(),
+        &EngineConfig {
+            snapshot: Some(snapshot.clone()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let accounts = process_file(
+        disputes.to_str().unwrap(),
+        &EngineConfig {
+            resume_from: Some(snapshot),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+
+    assert_eq!(accounts[&1].available(), 5.0);
+    assert_eq!(accounts[&1].held(), 10.0);
+    assert_eq!(accounts[&2].total(), 0.0);
+    assert!(accounts[&2].locked());
+
+    // The in-memory path reads the same header
+    let output = process_csv_bytes(b"type,client,tx\ndispute,1,1\n").unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "client,available,held,total,locked\n1,0.0,0.0,0.0,false\n"
+    );
+}
+
+#[test]
+fn test_deposit_without_amount_column_rejected() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx\n\
+         deposit,1,1\n\
+         withdrawal,1,2\n\
+         dispute,1,1\n",
+    );
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+
+    assert_eq!(report.stats.rejected_count("missing_amount"), 2);
+    assert_eq!(report.stats.orphan_disputes, 1);
+    assert_eq!(report.stats.applied, 0);
+    assert_eq!(report.accounts[&1].total(), 0.0);
+}
+
+#[test]
+fn test_overflowing_deposits_rejected_and_client_flagged() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,500000000000.0\n\
+         deposit,1,2,500000000000.0\n\
+         deposit,1,3,922337203685477.5807\n\
+         deposit,2,4,1.5\n\
+         deposit,1,5,0.25\n",
+    );
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+
+    assert_eq!(report.stats.rejected_count("overflow"), 2);
+    assert_eq!(report.stats.overflowed, vec![1]);
+    assert_eq!(report.accounts[&1].total(), 500000000000.25);
+    assert_eq!(report.accounts[&2].total(), 1.5);
+}
+
+#[test]
+fn test_infinite_amount_ignored_as_invalid_not_overflow() {
+    // 1e400 parses as f64::INFINITY
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,1e400\n\
+         deposit,1,2,2.0\n",
+    );
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+
+    assert_eq!(report.stats.rejected_count("overflow"), 0);
+    assert!(report.stats.overflowed.is_empty());
+    assert_eq!(report.accounts[&1].total(), 2.0);
+}
+
+#[test]
+fn test_extended_output_counts_transaction_types() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,100.0\n\
+         deposit,1,2,50.0\n\
+         withdrawal,1,3,30.0\n\
+         withdrawal,1,4,500.0\n\
+         dispute,1,1,\n\
+         resolve,1,1,\n\
+         dispute,1,2,\n\
+         dispute,1,99,\n\
+         chargeback,1,2,\n\
+         deposit,2,5,1.0\n",
+    );
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+    // The overdraft and the dispute of an unknown tx change nothing, so they aren't counted
+    assert_eq!(
+        report.accounts[&1].counts(),
+        TransactionCounts {
+            deposits: 2,
+            withdrawals: 1,
+            disputes: 2,
+            resolves: 1,
+            chargebacks: 1,
+        }
+    );
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--extended-output", &path])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked,deposits,withdrawals,disputes,resolves,chargebacks,open_disputed_amount\n\
+         1,70.0,0.0,70.0,true,2,1,2,1,1,0.0\n\
+         2,1.0,0.0,1.0,false,1,0,0,0,0,0.0\n"
+    );
+}
+
+#[test]
+fn test_open_disputed_amount_differs_from_held() {
+    // Client 1's withdrawal dispute can only hold the 10 left; client 2's deposit dispute
+    // holds all of it; client 3's dispute was resolved
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,100.0\n\
+         withdrawal,1,2,30.0\n\
+         withdrawal,1,3,60.0\n\
+         dispute,1,2,\n\
+         deposit,2,4,50.0\n\
+         dispute,2,4,\n\
+         deposit,3,5,20.0\n\
+         dispute,3,5,\n\
+         resolve,3,5,\n",
+    );
+    let config = EngineConfig {
+        withdrawal_disputes: WithdrawalDisputeModel::HoldFromAvailable,
+        dispute_funds: DisputeFundsPolicy::PartialHold,
+        ..Default::default()
+    };
+    let accounts = process_file_with_report(&path, &config).unwrap().accounts;
+    let held_and_disputed = |client| {
+        let account = &accounts[&client];
+        (account.held(), account.open_disputed_amount())
+    };
+    assert_eq!(held_and_disputed(1), (10.0, 30.0));
+    assert_eq!(held_and_disputed(2), (50.0, 50.0));
+    assert_eq!(held_and_disputed(3), (0.0, 0.0));
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args([
+            "--withdrawal-disputes",
+            "hold-from-available",
+            "--dispute-funds",
+            "partial-hold",
+            "--columns",
+            "client,held,open_disputed_amount",
+            &path,
+        ])
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,held,open_disputed_amount\n1,10.0,30.0\n2,50.0,50.0\n3,0.0,0.0\n"
+    );
+
+    // The default output is unchanged
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(
+        String::from_utf8(output.stdout)
+            .unwrap()
+            .starts_with("client,available,held,total,locked\n")
+    );
+}
+
+#[test]
+fn test_process_csv_bytes_matches_expected_outputs() {
+    let mut checked = 0;
+    for entry in std::fs::read_dir("tests/inputs").unwrap() {
+        let input = entry.unwrap().path();
+        let name = input.file_stem().unwrap().to_str().unwrap().to_string();
+        let expected = format!("tests/expected_outputs/{}_output.csv", name);
+        let Ok(expected) = std::fs::read_to_string(&expected) else {
+            continue;
+        };
+
+        let output = process_csv_bytes(&std::fs::read(&input).unwrap()).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), expected, "{}", name);
+        checked += 1;
+    }
+    assert!(checked > 0);
+
+    let error = process_csv_bytes(b"type,client,tx,amount\ndeposit,-1,1,5.0\n").unwrap_err();
+    assert!(error.contains("Invalid client `-1` on line 2"), "{}", error);
+
+    let snapshot = EngineConfig {
+        snapshot: Some("state.snap".to_string()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        process_csv_bytes_with_config(b"type,client,tx,amount\n", &snapshot),
+        Err(EngineError::InvalidConfig(_))
+    ));
+}
+
+#[test]
+fn test_stream_shards_writes_the_same_rows() {
+    let path = "tests/inputs/test_dispute_heavy.csv";
+    let run = |extra: &[&str]| {
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .args(["--workers", "4"])
+            .args(extra)
+            .arg(path)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap()
+    };
+    let merged = run(&[]);
+    let streamed = run(&["--stream-shards"]);
+
+    // The header comes first, then every row of the merged output, in worker blocks
+    let sorted_rows = |output: &str| {
+        let (header, rows) = output.split_once('\n').unwrap();
+        let mut rows: Vec<String> = rows.lines().map(str::to_string).collect();
+        rows.sort_by_key(|row| row.split(',').next().unwrap().parse::<u32>().unwrap());
+        (header.to_string(), rows)
+    };
+    assert_eq!(sorted_rows(&streamed), sorted_rows(&merged));
+
+    for (option, config) in [
+        (
+            "deterministic",
+            EngineConfig {
+                stream_shards: true,
+                deterministic: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "string client ids",
+            EngineConfig {
+                stream_shards: true,
+                string_client_ids: true,
+                ..Default::default()
+            },
+        ),
+    ] {
+        assert!(
+            matches!(config.validate(), Err(EngineError::InvalidConfig(_))),
+            "{}",
+            option
+        );
+    }
+}
+
+#[test]
+fn test_read_only_refuses_writes_and_leaves_snapshot_untouched() {
+    let (dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,100.0\n\
+         deposit,2,2,50.0\n",
+    );
+    let snapshot = dir.path().join("state.snap");
+    let snapshot = snapshot.to_str().unwrap();
+    process_file(
+        &path,
+        &EngineConfig {
+            snapshot: Some(snapshot.to_string()),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let contents = std::fs::read(snapshot).unwrap();
+    let modified = std::fs::metadata(snapshot).unwrap().modified().unwrap();
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args([
+            "--read-only",
+            "--snapshot",
+            snapshot,
+            "--journal",
+            "run.journal",
+            &path,
+        ])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("snapshot, journal"), "{}", stderr);
+    assert!(output.stdout.is_empty());
+
+    let (_more_dir, more) = create_test_csv(
+        "type,client,tx,amount\n\
+         withdrawal,1,3,40.0\n\
+         dispute,2,2,\n",
+    );
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--read-only", "--resume", snapshot, &more])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,60.0,0.0,60.0,false\n\
+         2,0.0,50.0,50.0,false\n"
+    );
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Read-only run")
+    );
+
+    assert_eq!(std::fs::read(snapshot).unwrap(), contents);
+    assert_eq!(
+        std::fs::metadata(snapshot).unwrap().modified().unwrap(),
+        modified
+    );
+}
+
+#[test]
+fn test_messy_rows_are_normalized() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         \x20Deposit ,1,1, 10.0\n\
+         WITHDRAWAL,1,2,+5.0\n\
+         \"deposit\",1,3,\"3.0\"\n\
+         Dispute,1,3,99.0\n\
+         deposit,1,4,\n\
+         deposit,1,5,0.00001\n",
+    );
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+
+    let account = &report.accounts[&1];
+    assert_eq!(account.available(), 5.0);
+    assert_eq!(account.held(), 3.0);
+    assert_eq!(account.total(), 8.0);
+    assert_eq!(report.stats.rejected_count("missing_amount"), 1);
+    assert_eq!(report.stats.rejected_count("amount_below_minimum"), 1);
+}
+
+#[test]
+fn test_overlay_resolve_closes_a_dispute_from_the_input() {
+    let (dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,50.0\n\
+         deposit,2,2,20.0\n\
+         dispute,1,1,\n",
+    );
+    let overlay = dir.path().join("corrections.csv");
+    std::fs::write(
+        &overlay,
+        "type,client,tx,amount\nresolve,1,1,\ndeposit,2,2,20.0\n",
+    )
+    .unwrap();
+    let overlay_output = dir.path().join("corrected.csv");
+    let journal = dir.path().join("run.journal");
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg("--overlay")
+        .arg(&overlay)
+        .arg("--overlay-output")
+        .arg(&overlay_output)
+        .arg("--journal")
+        .arg(&journal)
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    // Only client 1 differs: the overlay resolved its dispute and re-sent client 2's deposit
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,available,held,total,locked\n\
+         1,0.0,50.0,50.0,false\n\
+         2,20.0,0.0,20.0,false\n"
+    );
+    assert_eq!(
+        std::fs::read_to_string(&overlay_output).unwrap(),
+        "client,available,held,total,locked\n\
+         1,50.0,0.0,50.0,false\n\
+         2,20.0,0.0,20.0,false\n"
+    );
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Overlay: 2 rows, 1 applied"));
+    assert!(stderr.contains("Overlay rejected 1 transactions: duplicate_tx"));
+    let journal = std::fs::read_to_string(&journal).unwrap();
+    assert!(journal.starts_with("type,client,tx,amount,source\n"));
+    assert!(journal.ends_with("resolve,1,1,,overlay\n"));
+
+    let config = EngineConfig {
+        overlay: Some(overlay.to_str().unwrap().to_string()),
+        ..Default::default()
+    };
+    let report = process_file_with_report(&path, &config).unwrap();
+    assert_eq!(report.accounts[&1].held(), 0.0);
+    assert_eq!(report.stats.rows, 3);
+    assert_eq!(
+        report.stats.overlay.unwrap().rejected_count("duplicate_tx"),
+        1
+    );
+}
+
+#[test]
+fn test_verify_output_round_trips_rounded_amounts() {
+    let fixture = "tests/inputs/test_precision.csv";
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--verify-output", fixture])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        std::fs::read_to_string("tests/expected_outputs/test_precision_output.csv").unwrap()
+    );
+
+    // Minor units and the extended columns read back too
+    let input = std::fs::read(fixture).unwrap();
+    let config = EngineConfig {
+        verify_output: true,
+        amount_scale: AmountScale::MinorUnits,
+        extended_output: true,
+        ..Default::default()
+    };
+    let written = process_csv_bytes_with_config(&input, &config).unwrap();
+    assert!(
+        String::from_utf8(written)
+            .unwrap()
+            .ends_with("1,21234,12345,33579,false,2,1,1,0,0,12345\n")
+    );
+}
+
+#[test]
+fn test_clients_per_worker_histogram() {
+    let mut csv = String::from("type,client,tx,amount\n");
+    for (tx, client) in [0, 1, 2, 4, 5, 8, 9, 12].into_iter().enumerate() {
+        csv.push_str(&format!("deposit,{},{},1.0\n", client, tx + 1));
+    }
+    let (_dir, path) = create_test_csv(&csv);
+    let config = EngineConfig {
+        workers: Some(4),
+        ..Default::default()
+    };
+
+    let report = process_file_with_report(&path, &config).unwrap();
+    let counts: Vec<usize> = report
+        .stats
+        .per_worker
+        .iter()
+        .map(|load| load.clients)
+        .collect();
+    assert_eq!(counts, [4, 3, 1, 0]);
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--workers", "4", "--clients-per-worker", &path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&report.stats.client_histogram()));
+    assert!(stderr.contains("       2        1 ##########\n"));
+    assert!(stderr.contains("busiest worker has 4 clients, 2.00x the mean of 2.0"));
+}
+
+#[test]
+fn test_redact_is_reproducible_and_keeps_locks() {
+    let fixture = "tests/inputs/test_8_clients.csv";
+    let dir = TempDir::new().unwrap();
+    let first = dir.path().join("first.csv");
+    let second = dir.path().join("second.csv");
+    let key = RedactKey::new("fixture key");
+    let config = EngineConfig::default();
+
+    let report = redact(fixture, first.to_str().unwrap(), &key, &config).unwrap();
+    assert!(report.is_clean(), "{}", report);
+    assert_eq!(report.rows, 28);
+    redact(fixture, second.to_str().unwrap(), &key, &config).unwrap();
+    let redacted = std::fs::read_to_string(&first).unwrap();
+    assert_eq!(redacted, std::fs::read_to_string(&second).unwrap());
+    assert_ne!(redacted, std::fs::read_to_string(fixture).unwrap());
+
+    let original = process_file(fixture, &config).unwrap();
+    let copy = process_file(first.to_str().unwrap(), &config).unwrap();
+    let locked = |accounts: &std::collections::HashMap<ClientId, ClientAccount>| {
+        let mut locked: Vec<ClientId> = accounts
+            .values()
+            .filter(|account| account.locked())
+            .map(|account| account.client())
+            .collect();
+        locked.sort_unstable();
+        locked
+    };
+    let mut expected: Vec<ClientId> = locked(&original)
+        .into_iter()
+        .map(|client| key.client(client))
+        .collect();
+    expected.sort_unstable();
+    assert_eq!(expected.len(), 2);
+    assert_eq!(locked(&copy), expected);
+
+    // Balances scale by the client's factor
+    let client = key.client(1);
+    assert_eq!(
+        copy[&client].total(),
+        original[&1].total() * key.factor(1) as f64
+    );
+}
+
+#[test]
+fn test_hot_client_helpers_match_the_default_path() {
+    // Client 1 makes up most rows, with withdrawals past its funds, disputes and a chargeback
+    // Client 5 shares its worker, so the worker's rows come from the helpers and the router
+    let mut csv = String::from("type,client,tx,amount\n");
+    for tx in 1..=20_000u32 {
+        let row = match tx {
+            19_997 => "dispute,1,19995,".to_string(),
+            19_998 => "chargeback,1,19995,".to_string(),
+            _ if tx % 4 == 0 => format!("deposit,{},{},2.5", 2 + tx % 50, tx),
+            _ if tx % 24 == 2 => format!("withdrawal,5,{},1.0", tx),
+            _ if tx % 8 == 2 => format!("deposit,5,{},0.75", tx),
+            _ if tx % 1000 == 999 => format!("dispute,1,{},", tx - 4),
+            _ if tx % 1000 == 3 && tx > 1000 => format!("resolve,1,{},", tx - 8),
+            _ if tx % 7 == 0 => format!("withdrawal,1,{},{}.5", tx, tx % 40),
+            _ => format!("deposit,1,{},1.{:04}", tx, tx % 10_000),
+        };
+        csv.push_str(&row);
+        csv.push('\n');
+    }
+    let (dir, path) = create_test_csv(&csv);
+    let run = |helpers: usize, name: &str| {
+        let journal = dir.path().join(name).to_str().unwrap().to_string();
+        let config = EngineConfig {
+            workers: Some(4),
+            batch_size: 16,
+            hot_client_helpers: helpers,
+            journal: Some(journal.clone()),
+            ..Default::default()
+        };
+        let report = process_file_with_report(&path, &config).unwrap();
+        (report, std::fs::read_to_string(journal).unwrap())
+    };
+
+    let (default, default_journal) = run(0, "default.csv");
+    let (helped, helped_journal) = run(3, "helped.csv");
+    assert!(default.accounts[&1].locked());
+    assert!(default.stats.rejected_count("insufficient_funds") > 0);
+    assert_eq!(helped.accounts, default.accounts);
+    assert_eq!(helped.stats.rows, default.stats.rows);
+    assert_eq!(helped.stats.rejected, default.stats.rejected);
+    // Every transaction applied in the same order
+    assert_eq!(helped_journal, default_journal);
+
+    // A row of the hot client that doesn't parse fails the run the same way
+    assert!(csv.contains("deposit,1,15005,1.5005\n"));
+    let broken = csv.replacen("deposit,1,15005,1.5005", "deposit,1,15005,lots", 1);
+    let (_broken_dir, broken_path) = create_test_csv(&broken);
+    let error = |helpers| {
+        let config = EngineConfig {
+            hot_client_helpers: helpers,
+            ..Default::default()
+        };
+        process_file(&broken_path, &config).unwrap_err().to_string()
+    };
+    assert_eq!(error(3), error(0));
+}
+
+#[test]
+fn test_closed_account_rejects_deposits_and_resolves_pending_dispute() {
+    let (_dir, path) = create_test_csv(
+        "type,client,tx,amount\n\
+         deposit,1,1,10.0\n\
+         deposit,1,2,5.0\n\
+         dispute,1,2,\n\
+         close,1,3,\n\
+         deposit,1,4,7.0\n\
+         resolve,1,2,\n\
+         withdrawal,1,5,1.0\n\
+         deposit,2,6,3.0\n",
+    );
+
+    let report = process_file_with_report(&path, &EngineConfig::default()).unwrap();
+    let closed = &report.accounts[&1];
+    assert!(closed.closed() && !closed.locked());
+    assert_eq!(
+        (closed.available(), closed.held(), closed.total()),
+        (15.0, 0.0, 15.0)
+    );
+    assert_eq!(report.stats.rejected_count("account_closed"), 2);
+    assert!(!report.accounts[&2].closed());
+
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--columns", "client,total,locked,closed", &path])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "client,total,locked,closed\n1,15.0,false,true\n2,3.0,false,false\n"
+    );
+}
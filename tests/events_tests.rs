@@ -0,0 +1,126 @@
+// tests/events_tests.rs
+
+//! Account events published to `EngineConfig::events` while a run settles.
+
+use payments_engine::{
+    AccountEvent, ClientId, EngineConfig, LockReason, Transaction, TransactionType,
+    process_file_with_report, process_transactions,
+};
+use tempfile::TempDir;
+
+fn tx(tx_type: TransactionType, client: ClientId, tx: u32, amount: Option<f64>) -> Transaction {
+    Transaction::try_new(tx_type, client, tx, amount).unwrap()
+}
+
+#[test]
+fn test_dispute_then_chargeback_event_sequence() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let config = EngineConfig {
+        workers: Some(2),
+        events: Some(sender),
+        ..Default::default()
+    };
+    let transactions = vec![
+        tx(TransactionType::Deposit, 1, 1, Some(100.0)),
+        tx(TransactionType::Withdrawal, 1, 2, Some(60.0)),
+        // Holds the full 100.0 out of 40.0 available
+        tx(TransactionType::Dispute, 1, 1, None),
+        tx(TransactionType::Chargeback, 1, 1, None),
+        // Rejected on the locked account, so no event
+        tx(TransactionType::Deposit, 1, 3, Some(5.0)),
+        // Ignored, so no event
+        tx(TransactionType::Dispute, 1, 99, None),
+    ];
+
+    let accounts = process_transactions(transactions, &config).unwrap();
+    drop(config);
+
+    assert!(accounts[&1].locked());
+    assert_eq!(
+        receiver.iter().collect::<Vec<_>>(),
+        vec![
+            AccountEvent::DisputeOpened {
+                client: 1,
+                tx: 1,
+                amount: 100.0
+            },
+            AccountEvent::WentNegative {
+                client: 1,
+                tx: 1,
+                currency: None,
+                available: -60.0
+            },
+            AccountEvent::ChargebackApplied {
+                client: 1,
+                tx: 1,
+                amount: 100.0
+            },
+            AccountEvent::Locked {
+                client: 1,
+                tx: 1,
+                reason: LockReason::Chargeback
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_dispute_threshold_lock_is_published() {
+    let (sender, receiver) = crossbeam_channel::unbounded();
+    let config = EngineConfig {
+        max_open_disputes: Some(2),
+        events: Some(sender),
+        ..Default::default()
+    };
+    let transactions = vec![
+        tx(TransactionType::Deposit, 7, 1, Some(1.0)),
+        tx(TransactionType::Deposit, 7, 2, Some(2.0)),
+        tx(TransactionType::Dispute, 7, 1, None),
+        tx(TransactionType::Dispute, 7, 2, None),
+    ];
+
+    process_transactions(transactions, &config).unwrap();
+    drop(config);
+
+    let events: Vec<_> = receiver.iter().collect();
+    assert_eq!(events.len(), 3);
+    assert_eq!(
+        events[2],
+        AccountEvent::Locked {
+            client: 7,
+            tx: 2,
+            reason: LockReason::DisputeThreshold
+        }
+    );
+}
+
+#[test]
+fn test_full_channel_drops_events_without_blocking() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("disputes.csv");
+    let mut csv = String::from("type,client,tx,amount\n");
+    for id in 1..=10 {
+        csv.push_str(&format!("deposit,1,{id},1.0\ndispute,1,{id},\n"));
+    }
+    std::fs::write(&path, csv).unwrap();
+
+    // Nothing reads the channel during the run, so only the first event fits
+    let (sender, receiver) = crossbeam_channel::bounded(1);
+    let config = EngineConfig {
+        events: Some(sender),
+        ..Default::default()
+    };
+    let report = process_file_with_report(path.to_str().unwrap(), &config).unwrap();
+    drop(config);
+
+    assert_eq!(report.accounts[&1].held(), 10.0);
+    assert_eq!(report.stats.dropped_events, 9);
+    assert_eq!(
+        receiver.iter().collect::<Vec<_>>(),
+        vec![AccountEvent::DisputeOpened {
+            client: 1,
+            tx: 1,
+            amount: 1.0
+        }]
+    );
+}
@@ -0,0 +1,41 @@
+/* Minimal C caller of the FFI layer; tests/ffi_tests.rs checks that it compiles against
+ * include/payments_engine.h. Link it against the library built with
+ *   cargo rustc --release --lib --features ffi --crate-type staticlib */
+
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "payments_engine.h"
+
+int main(void) {
+    Engine *engine = pe_engine_new("{\"account_creation\": \"deposits-only\"}");
+    if (engine == NULL) {
+        return 1;
+    }
+
+    int status = pe_engine_apply(engine, PE_DEPOSIT, 1, 1, 10.0);
+    if (status == PE_OK) {
+        status = pe_engine_apply(engine, PE_WITHDRAWAL, 1, 2, 25.0);
+    }
+    if (status == PE_REJECTED_INSUFFICIENT_FUNDS) {
+        fprintf(stderr, "withdrawal refused\n");
+    }
+
+    PeAccount account;
+    if (pe_engine_account(engine, 1, &account) == PE_OK) {
+        printf("client %u available %.4f locked %d\n", account.client, account.available,
+               account.locked);
+    }
+
+    ptrdiff_t needed = pe_engine_finish_csv(engine, NULL, 0);
+    if (needed >= 0) {
+        char *csv = malloc((size_t)needed + 1);
+        if (csv != NULL && pe_engine_finish_csv(engine, csv, (size_t)needed + 1) == needed) {
+            fputs(csv, stdout);
+        }
+        free(csv);
+    }
+
+    pe_engine_free(engine);
+    return 0;
+}
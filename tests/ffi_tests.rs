@@ -0,0 +1,215 @@
+// tests/ffi_tests.rs
+
+//! C ABI tests, calling the `extern "C"` functions the way a C caller would.
+//! Run with: cargo test --features ffi --test ffi_tests
+
+#![cfg(feature = "ffi")]
+
+use payments_engine::ffi::*;
+use std::ffi::{CString, c_char};
+use std::path::Path;
+use std::process::Command;
+use std::ptr;
+
+fn new_engine(json: Option<&str>) -> *mut Engine {
+    let json = json.map(|json| CString::new(json).unwrap());
+    unsafe { pe_engine_new(json.as_ref().map_or(ptr::null(), |json| json.as_ptr())) }
+}
+
+fn finish_csv(engine: *const Engine) -> String {
+    let needed = unsafe { pe_engine_finish_csv(engine, ptr::null_mut(), 0) };
+    assert!(needed >= 0, "status {}", needed);
+    let mut buf = vec![0u8; needed as usize + 1];
+    let written = unsafe { pe_engine_finish_csv(engine, buf.as_mut_ptr().cast(), buf.len()) };
+    assert_eq!(written, needed);
+    assert_eq!(buf.pop(), Some(0));
+    String::from_utf8(buf).unwrap()
+}
+
+fn type_code(name: &str) -> u8 {
+    match name {
+        "deposit" => PE_DEPOSIT,
+        "withdrawal" => PE_WITHDRAWAL,
+        "dispute" => PE_DISPUTE,
+        "resolve" => PE_RESOLVE,
+        "chargeback" => PE_CHARGEBACK,
+        other => panic!("unknown type {}", other),
+    }
+}
+
+#[test]
+fn test_statuses_follow_the_ledger() {
+    let engine = new_engine(None);
+    assert!(!engine.is_null());
+
+    unsafe {
+        assert_eq!(pe_engine_apply(engine, PE_DEPOSIT, 1, 1, 100.0), PE_OK);
+        assert_eq!(
+            pe_engine_apply(engine, PE_WITHDRAWAL, 1, 2, 150.0),
+            PE_REJECTED_INSUFFICIENT_FUNDS
+        );
+        assert_eq!(pe_engine_apply(engine, PE_DISPUTE, 1, 99, 0.0), PE_IGNORED);
+        assert_eq!(pe_engine_apply(engine, PE_DISPUTE, 1, 1, 0.0), PE_OK);
+
+        let mut account = PeAccount::default();
+        assert_eq!(pe_engine_account(engine, 1, &mut account), PE_OK);
+        assert_eq!(
+            account,
+            PeAccount {
+                client: 1,
+                available: 0.0,
+                held: 100.0,
+                total: 100.0,
+                locked: false,
+            }
+        );
+
+        assert_eq!(pe_engine_apply(engine, PE_CHARGEBACK, 1, 1, 0.0), PE_OK);
+        assert_eq!(
+            pe_engine_apply(engine, PE_DEPOSIT, 1, 3, 5.0),
+            PE_REJECTED_ACCOUNT_LOCKED
+        );
+        assert_eq!(pe_engine_account(engine, 1, &mut account), PE_OK);
+        assert!(account.locked);
+        assert_eq!(pe_engine_account(engine, 2, &mut account), PE_ERR_NOT_FOUND);
+
+        pe_engine_free(engine);
+    }
+}
+
+#[test]
+fn test_config_json_sets_policies() {
+    let engine = new_engine(Some(r#"{"account_creation": "deposits-only"}"#));
+    assert!(!engine.is_null());
+    unsafe {
+        assert_eq!(
+            pe_engine_apply(engine, PE_WITHDRAWAL, 7, 1, 1.0),
+            PE_REJECTED_UNKNOWN_CLIENT
+        );
+        pe_engine_free(engine);
+    }
+
+    let engine = new_engine(Some(r#"{"idempotent": true}"#));
+    unsafe {
+        assert_eq!(pe_engine_apply(engine, PE_DEPOSIT, 1, 1, 1.0), PE_OK);
+        assert_eq!(
+            pe_engine_apply(engine, PE_DEPOSIT, 1, 1, 1.0),
+            PE_ALREADY_APPLIED
+        );
+        pe_engine_free(engine);
+    }
+
+    for json in [
+        "not json",
+        r#"{"no_such_option": true}"#,
+        r#"{"dispute_funds": "sometimes"}"#,
+        r#"{"max_disputed_amount": -1.0}"#,
+    ] {
+        assert!(new_engine(Some(json)).is_null(), "{}", json);
+    }
+}
+
+#[test]
+fn test_invalid_arguments_are_reported() {
+    let engine = new_engine(None);
+    let mut account = PeAccount::default();
+    unsafe {
+        assert_eq!(
+            pe_engine_apply(ptr::null_mut(), PE_DEPOSIT, 1, 1, 1.0),
+            PE_ERR_NULL
+        );
+        assert_eq!(
+            pe_engine_apply(engine, 9, 1, 1, 1.0),
+            PE_ERR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            pe_engine_apply(engine, PE_DEPOSIT, 1, 1, f64::NAN),
+            PE_ERR_INVALID_ARGUMENT
+        );
+        // The amount of a dispute is ignored, even when it isn't a number
+        assert_eq!(
+            pe_engine_apply(engine, PE_DISPUTE, 1, 1, f64::NAN),
+            PE_IGNORED
+        );
+        assert_eq!(pe_engine_account(ptr::null(), 1, &mut account), PE_ERR_NULL);
+        assert_eq!(pe_engine_account(engine, 1, ptr::null_mut()), PE_ERR_NULL);
+        assert_eq!(
+            pe_engine_finish_csv(ptr::null(), ptr::null_mut(), 0),
+            PE_ERR_NULL as isize
+        );
+        pe_engine_free(engine);
+        pe_engine_free(ptr::null_mut());
+    }
+}
+
+#[test]
+fn test_finish_csv_matches_file_output() {
+    let fixture = "test_complex_scenario";
+    let engine = new_engine(None);
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(format!("tests/inputs/{}.csv", fixture))
+        .unwrap();
+    for record in reader.records() {
+        let record = record.unwrap();
+        let amount = record
+            .get(3)
+            .filter(|a| !a.is_empty())
+            .map_or(0.0, |a| a.parse().unwrap());
+        unsafe {
+            pe_engine_apply(
+                engine,
+                type_code(&record[0]),
+                record[1].parse().unwrap(),
+                record[2].parse().unwrap(),
+                amount,
+            )
+        };
+    }
+
+    let expected =
+        std::fs::read_to_string(format!("tests/expected_outputs/{}_output.csv", fixture)).unwrap();
+    assert_eq!(finish_csv(engine), expected);
+    // Rendering leaves the engine usable
+    assert_eq!(
+        unsafe { pe_engine_apply(engine, PE_DEPOSIT, 2, 100, 1.0) },
+        PE_OK
+    );
+    assert_ne!(finish_csv(engine), expected);
+    unsafe { pe_engine_free(engine) };
+}
+
+#[test]
+fn test_finish_csv_truncates_like_snprintf() {
+    let engine = new_engine(None);
+    unsafe { pe_engine_apply(engine, PE_DEPOSIT, 1, 1, 2.5) };
+    let full = finish_csv(engine);
+
+    let mut buf = [0x7f as c_char; 8];
+    let needed = unsafe { pe_engine_finish_csv(engine, buf.as_mut_ptr(), buf.len()) };
+    assert_eq!(needed as usize, full.len());
+    let written: Vec<u8> = buf[..7].iter().map(|&b| b as u8).collect();
+    assert_eq!(written, full.as_bytes()[..7]);
+    assert_eq!(buf[7], 0);
+    unsafe { pe_engine_free(engine) };
+}
+
+#[test]
+fn test_header_compiles_with_c_caller() {
+    let Ok(status) = Command::new("cc")
+        .args([
+            "-std=c99",
+            "-Wall",
+            "-Wextra",
+            "-Werror",
+            "-fsyntax-only",
+            "-Iinclude",
+        ])
+        .arg(Path::new("tests/ffi/smoke.c"))
+        .status()
+    else {
+        eprintln!("skipping: no C compiler on PATH");
+        return;
+    };
+    assert!(status.success());
+}
@@ -0,0 +1,107 @@
+// tests/http_input_tests.rs
+
+//! HTTP(S) input tests against a local tiny_http server.
+//! Run with: cargo test --features http-input --test http_input_tests
+
+#![cfg(feature = "http-input")]
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use payments_engine::{EngineConfig, EngineError, process_file};
+use std::io::{Cursor, Write};
+use std::thread;
+use tiny_http::{Header, Response, Server};
+
+const FIXTURE: &str = "tests/inputs/test_dispute_chargeback.csv";
+
+/// Serve the given responses, one per incoming request, and return the server URL
+fn serve(responses: Vec<Response<Cursor<Vec<u8>>>>) -> String {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let port = server.server_addr().to_ip().unwrap().port();
+
+    thread::spawn(move || {
+        for response in responses {
+            let request = server.recv().unwrap();
+            let _ = request.respond(response);
+        }
+    });
+
+    format!("http://127.0.0.1:{}/transactions.csv", port)
+}
+
+fn fixture_response() -> Response<Cursor<Vec<u8>>> {
+    Response::from_data(std::fs::read(FIXTURE).unwrap())
+}
+
+fn config_with_retries(http_retries: u32) -> EngineConfig {
+    EngineConfig {
+        http_retries,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn test_url_matches_local_file() {
+    let url = serve(vec![fixture_response()]);
+
+    let remote = process_file(&url, &EngineConfig::default()).unwrap();
+    let local = process_file(FIXTURE, &EngineConfig::default()).unwrap();
+
+    let account = &remote[&1];
+    assert_eq!(account.available(), 100.0);
+    assert!(account.locked());
+    assert_eq!(remote.len(), local.len());
+    assert_eq!(account.total(), local[&1].total());
+}
+
+#[test]
+fn test_gzip_content_encoding() {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(&std::fs::read(FIXTURE).unwrap()).unwrap();
+    let body = encoder.finish().unwrap();
+
+    let response = Response::from_data(body)
+        .with_header(Header::from_bytes("Content-Encoding", "gzip").unwrap());
+    let url = serve(vec![response]);
+
+    let accounts = process_file(&url, &EngineConfig::default()).unwrap();
+    assert_eq!(accounts[&1].available(), 100.0);
+    assert!(accounts[&1].locked());
+}
+
+#[test]
+fn test_not_found_reports_status() {
+    let url = serve(vec![Response::from_data(Vec::new()).with_status_code(404)]);
+
+    let result = process_file(&url, &EngineConfig::default());
+    assert!(matches!(result, Err(EngineError::Http { status: 404, .. })));
+}
+
+#[test]
+fn test_redirect_is_not_followed() {
+    let response = Response::from_data(Vec::new())
+        .with_status_code(302)
+        .with_header(Header::from_bytes("Location", "/elsewhere.csv").unwrap());
+    let url = serve(vec![response]);
+
+    let result = process_file(&url, &EngineConfig::default());
+    assert!(matches!(result, Err(EngineError::Http { status: 302, .. })));
+}
+
+#[test]
+fn test_transient_failure_is_retried() {
+    let unavailable = Response::from_data(Vec::new()).with_status_code(503);
+    let url = serve(vec![unavailable, fixture_response()]);
+
+    let accounts = process_file(&url, &config_with_retries(1)).unwrap();
+    assert_eq!(accounts[&1].available(), 100.0);
+}
+
+#[test]
+fn test_retries_exhausted_reports_status() {
+    let unavailable = Response::from_data(Vec::new()).with_status_code(503);
+    let url = serve(vec![unavailable]);
+
+    let result = process_file(&url, &config_with_retries(0));
+    assert!(matches!(result, Err(EngineError::Http { status: 503, .. })));
+}
@@ -0,0 +1,152 @@
+// tests/parquet_tests.rs
+
+//! Parquet input tests, comparing each run with the same rows read from CSV.
+//! Run with: cargo test --features parquet --test parquet_tests
+
+#![cfg(feature = "parquet")]
+
+use arrow_array::builder::{Decimal128Builder, Float64Builder, Int64Builder, StringBuilder};
+use arrow_array::{ArrayRef, RecordBatch};
+use parquet::arrow::ArrowWriter;
+use parquet::file::properties::WriterProperties;
+use payments_engine::{EngineConfig, EngineError, InputFormat, process_file};
+use std::fs::File;
+use std::process::Command;
+use std::sync::Arc;
+use tempfile::TempDir;
+
+const FIXTURES: [&str; 3] = [
+    "tests/inputs/test_complex_scenario.csv",
+    "tests/inputs/test_dispute_heavy.csv",
+    "tests/inputs/test_precision.csv",
+];
+
+/// Rows per row group, small enough that every fixture spans several
+const ROW_GROUP_ROWS: usize = 100;
+
+#[derive(Clone, Copy)]
+enum Amounts {
+    Float64,
+    /// `Decimal128(18, 4)`, holding four-decimal amounts exactly
+    Decimal,
+}
+
+/// Write the rows of a CSV fixture to `dir` as Parquet, with Int64 ids as pandas writes them
+fn write_parquet(dir: &TempDir, csv_path: &str, amounts: Amounts) -> String {
+    let mut tx_type = StringBuilder::new();
+    let mut client = Int64Builder::new();
+    let mut tx = Int64Builder::new();
+    let mut float_amount = Float64Builder::new();
+    let mut decimal_amount = Decimal128Builder::new();
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_path(csv_path)
+        .unwrap();
+    for record in reader.records() {
+        let record = record.unwrap();
+        tx_type.append_value(&record[0]);
+        client.append_value(record[1].parse().unwrap());
+        tx.append_value(record[2].parse().unwrap());
+        let amount: Option<f64> = record
+            .get(3)
+            .filter(|a| !a.is_empty())
+            .map(|a| a.parse().unwrap());
+        float_amount.append_option(amount);
+        decimal_amount.append_option(amount.map(|a| (a * 10_000.0).round() as i128));
+    }
+
+    let amount: ArrayRef = match amounts {
+        Amounts::Float64 => Arc::new(float_amount.finish()),
+        Amounts::Decimal => Arc::new(
+            decimal_amount
+                .finish()
+                .with_precision_and_scale(18, 4)
+                .unwrap(),
+        ),
+    };
+    let batch = RecordBatch::try_from_iter([
+        ("type", Arc::new(tx_type.finish()) as ArrayRef),
+        ("client", Arc::new(client.finish()) as ArrayRef),
+        ("tx", Arc::new(tx.finish()) as ArrayRef),
+        ("amount", amount),
+    ])
+    .unwrap();
+
+    let path = dir.path().join("transactions.parquet");
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(ROW_GROUP_ROWS)
+        .build();
+    let mut writer = ArrowWriter::try_new(
+        File::create(&path).unwrap(),
+        batch.schema(),
+        Some(properties),
+    )
+    .unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn parquet_config() -> EngineConfig {
+    EngineConfig {
+        input_format: InputFormat::Parquet,
+        ..EngineConfig::default()
+    }
+}
+
+#[test]
+fn test_parquet_matches_csv() {
+    for fixture in FIXTURES {
+        for amounts in [Amounts::Float64, Amounts::Decimal] {
+            let dir = TempDir::new().unwrap();
+            let parquet = write_parquet(&dir, fixture, amounts);
+
+            let expected = process_file(fixture, &EngineConfig::default()).unwrap();
+            let actual = process_file(&parquet, &parquet_config()).unwrap();
+            assert_eq!(actual, expected, "{}", fixture);
+        }
+    }
+}
+
+#[test]
+fn test_cli_detects_parquet_by_extension() {
+    let fixture = FIXTURES[0];
+    let dir = TempDir::new().unwrap();
+    let parquet = write_parquet(&dir, fixture, Amounts::Decimal);
+
+    let run = |path: &str| {
+        let output = Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+            .arg(path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        output.stdout
+    };
+    assert_eq!(run(&parquet), run(fixture));
+}
+
+#[test]
+fn test_parquet_without_type_column_is_invalid_input() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("no_type.parquet");
+    let mut client = Int64Builder::new();
+    client.append_value(1);
+    let batch =
+        RecordBatch::try_from_iter([("client", Arc::new(client.finish()) as ArrayRef)]).unwrap();
+    let mut writer =
+        ArrowWriter::try_new(File::create(&path).unwrap(), batch.schema(), None).unwrap();
+    writer.write(&batch).unwrap();
+    writer.close().unwrap();
+
+    let result = process_file(path.to_str().unwrap(), &parquet_config());
+    assert!(matches!(
+        result,
+        Err(EngineError::InvalidInput(message)) if message.contains("`type`")
+    ));
+
+    // A CSV file given as Parquet fails to decode rather than being misread
+    let result = process_file(FIXTURES[0], &parquet_config());
+    assert!(matches!(result, Err(EngineError::Parquet(_))));
+}
@@ -0,0 +1,109 @@
+// tests/python_tests.rs
+
+//! Python binding tests, importing the module into an embedded interpreter and asserting
+//! from Python.
+//! Run with: cargo test --features python --test python_tests
+
+#![cfg(feature = "python")]
+
+use payments_engine::python::payments_engine;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::ffi::CString;
+use std::sync::Once;
+
+/// Register the module with the interpreter, which can only happen before it starts
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        pyo3::append_to_inittab!(payments_engine);
+        Python::initialize();
+    });
+}
+
+/// Run `code` with `payments_engine` imported and `path` bound to `fixture`
+fn run_python(fixture: &str, code: &str) {
+    init();
+    Python::attach(|py| {
+        let locals = PyDict::new(py);
+        locals.set_item("path", fixture).unwrap();
+        let code = CString::new(format!("import payments_engine\n{}", code)).unwrap();
+        py.run(&code, None, Some(&locals))
+            .map_err(|e| e.display(py))
+            .unwrap();
+    });
+}
+
+#[test]
+fn test_process_returns_records() {
+    run_python(
+        "tests/inputs/test_complex_scenario.csv",
+        r#"
+accounts = payments_engine.process(path, workers=2)
+assert accounts == [
+    {"client": 1, "available": 800.0, "held": 0.0, "total": 800.0, "locked": True},
+    {"client": 2, "available": 450.0, "held": 0.0, "total": 450.0, "locked": False},
+], accounts
+"#,
+    );
+}
+
+#[test]
+fn test_errors_become_python_exceptions() {
+    run_python(
+        "tests/inputs/does_not_exist.csv",
+        r#"
+try:
+    payments_engine.process(path)
+    raise AssertionError("missing file was processed")
+except FileNotFoundError as e:
+    assert "not found" in str(e), e
+
+for options in [{"dispute_funds": "sometimes"}, {"max_dispute_age": 0}, {"no_such_option": 1}]:
+    try:
+        payments_engine.process("tests/inputs/test_complex_scenario.csv", **options)
+        raise AssertionError(options)
+    except ValueError:
+        pass
+"#,
+    );
+}
+
+#[test]
+fn test_engine_applies_incrementally() {
+    run_python(
+        "",
+        r#"
+from decimal import Decimal
+
+engine = payments_engine.Engine(account_creation="deposits-only")
+assert engine.apply({"type": "deposit", "client": 1, "tx": 1, "amount": 10.00004})
+assert engine.apply({"type": "deposit", "client": 1, "tx": 2, "amount": Decimal("2.5")})
+assert not engine.apply({"type": "dispute", "client": 1, "tx": 99})
+
+try:
+    engine.apply({"type": "withdrawal", "client": 1, "tx": 3, "amount": 20.0})
+    raise AssertionError("overdraft was applied")
+except payments_engine.TransactionRejected as e:
+    assert e.code == "insufficient_funds", e.code
+    assert isinstance(e, payments_engine.PaymentsEngineError)
+    assert "short by 7.5" in str(e), e
+
+try:
+    engine.apply({"type": "withdrawal", "client": 2, "tx": 4, "amount": 1.0})
+    raise AssertionError("withdrawal created an account")
+except payments_engine.TransactionRejected as e:
+    assert e.code == "unknown_client", e.code
+
+try:
+    engine.apply({"type": "refund", "client": 1, "tx": 5})
+    raise AssertionError("unknown type was applied")
+except ValueError:
+    pass
+
+assert engine.accounts() == [
+    {"client": 1, "available": 12.5, "held": 0.0, "total": 12.5, "locked": False},
+], engine.accounts()
+"#,
+    );
+}
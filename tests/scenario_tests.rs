@@ -1,7 +1,14 @@
+use payments_engine::{
+    AccountCreationPolicy, DisputeFundsPolicy, EngineConfig, LockedDepositPolicy,
+    process_csv_bytes_with_config,
+};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+/// Set to 1 to rewrite `tests/expected_outputs` with `bless_expected_outputs`
+const BLESS_VAR: &str = "PAYMENTS_ENGINE_BLESS";
+
 #[test]
 fn test_all_input_scenarios() {
     let inputs_dir = PathBuf::from("tests/inputs");
@@ -15,7 +22,7 @@ fn test_all_input_scenarios() {
     // Build first
     println!("Building project...");
     let build = Command::new("cargo")
-        .args(&["build", "--release", "--quiet"])
+        .args(["build", "--release", "--quiet"])
         .status()
         .expect("Failed to build");
     assert!(build.success(), "Build failed");
@@ -29,7 +36,7 @@ fn test_all_input_scenarios() {
         let entry = entry.expect("Invalid entry");
         let input_path = entry.path();
 
-        if input_path.extension().map_or(true, |ext| ext != "csv") {
+        if input_path.extension().is_none_or(|ext| ext != "csv") {
             continue;
         }
 
@@ -48,7 +55,7 @@ fn test_all_input_scenarios() {
 
         // Run engine
         let output = Command::new("cargo")
-            .args(&[
+            .args([
                 "run",
                 "--release",
                 "--quiet",
@@ -129,3 +136,139 @@ fn normalize_csv(content: &str) -> String {
 
     result
 }
+
+/// Rules the expected outputs are generated under, spelled out rather than taken from
+/// `EngineConfig::default()` so a changed default shows up as a diff when blessing
+fn fixture_config() -> EngineConfig {
+    EngineConfig {
+        account_creation: AccountCreationPolicy::Implicit,
+        locked_deposits: LockedDepositPolicy::Reject,
+        dispute_funds: DisputeFundsPolicy::AllowNegative,
+        allow_redispute: false,
+        ..Default::default()
+    }
+}
+
+/// How one regenerated expected output compares with the file it replaces
+#[derive(Debug, PartialEq)]
+enum FixtureChange {
+    Unchanged,
+    Added,
+    /// Rows only in the old file, then rows only in the new one
+    Changed(Vec<String>, Vec<String>),
+}
+
+/// Run every input through the in-process engine and write its output to `out_dir`, named as
+/// in `tests/expected_outputs`; returns each file name with how it differs from the version
+/// previously in `out_dir`
+fn regenerate(
+    inputs_dir: &Path,
+    out_dir: &Path,
+    config: &EngineConfig,
+) -> Vec<(String, FixtureChange)> {
+    let mut inputs: Vec<PathBuf> = fs::read_dir(inputs_dir)
+        .expect("Can't read inputs dir")
+        .map(|entry| entry.expect("Invalid entry").path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "csv"))
+        .collect();
+    inputs.sort();
+
+    let mut changes = Vec::new();
+    for input_path in inputs {
+        let base_name = input_path.file_stem().unwrap().to_str().unwrap();
+        let name = format!("{}_output.csv", base_name);
+        let out_path = out_dir.join(&name);
+
+        let input = fs::read(&input_path).unwrap();
+        let output = process_csv_bytes_with_config(&input, config)
+            .unwrap_or_else(|e| panic!("{} failed: {}", input_path.display(), e));
+        let output = String::from_utf8(output).unwrap();
+
+        let change = match fs::read_to_string(&out_path) {
+            Err(_) => FixtureChange::Added,
+            Ok(old) if old == output => FixtureChange::Unchanged,
+            Ok(old) => FixtureChange::Changed(
+                old.lines()
+                    .filter(|line| !output.lines().any(|new| new == *line))
+                    .map(str::to_string)
+                    .collect(),
+                output
+                    .lines()
+                    .filter(|line| !old.lines().any(|prev| prev == *line))
+                    .map(str::to_string)
+                    .collect(),
+            ),
+        };
+        fs::write(&out_path, output).unwrap();
+        changes.push((name, change));
+    }
+    changes
+}
+
+/// Rewrite `tests/expected_outputs` from the current engine and print what changed, for
+/// review alongside a deliberate change of semantics:
+/// `PAYMENTS_ENGINE_BLESS=1 cargo test --test scenario_tests bless -- --nocapture`
+/// Does nothing unless the variable is set, and refuses to run in CI.
+#[test]
+fn bless_expected_outputs() {
+    if std::env::var(BLESS_VAR).as_deref() != Ok("1") {
+        return;
+    }
+    assert!(
+        std::env::var_os("CI").is_none(),
+        "{} is set in CI; expected outputs are only blessed locally",
+        BLESS_VAR
+    );
+
+    let changes = regenerate(
+        Path::new("tests/inputs"),
+        Path::new("tests/expected_outputs"),
+        &fixture_config(),
+    );
+    let mut unchanged = 0;
+    for (name, change) in &changes {
+        match change {
+            FixtureChange::Unchanged => unchanged += 1,
+            FixtureChange::Added => println!("added   {}", name),
+            FixtureChange::Changed(removed, added) => {
+                println!("changed {}", name);
+                for line in removed {
+                    println!("  - {}", line);
+                }
+                for line in added {
+                    println!("  + {}", line);
+                }
+            }
+        }
+    }
+    println!(
+        "{} of {} expected outputs unchanged",
+        unchanged,
+        changes.len()
+    );
+}
+
+#[test]
+fn test_regeneration_reproduces_committed_fixtures() {
+    let dir = tempfile::TempDir::new().unwrap();
+    for entry in fs::read_dir("tests/expected_outputs").unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, dir.path().join(path.file_name().unwrap())).unwrap();
+    }
+
+    let changes = regenerate(
+        Path::new("tests/inputs"),
+        dir.path(),
+        &EngineConfig::default(),
+    );
+    assert!(!changes.is_empty());
+    for (name, change) in changes {
+        assert_eq!(change, FixtureChange::Unchanged, "{}", name);
+        assert_eq!(
+            fs::read(dir.path().join(&name)).unwrap(),
+            fs::read(Path::new("tests/expected_outputs").join(&name)).unwrap(),
+            "{}",
+            name
+        );
+    }
+}
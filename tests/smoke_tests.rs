@@ -38,6 +38,7 @@ fn test_invalid_transaction_type() {
 }
 
 #[test]
+#[cfg(not(feature = "wide-client-ids"))]
 fn test_client_id_overflow() {
     let csv = "type,client,tx,amount\n\
                deposit,99999,1,100.0";
@@ -47,6 +48,20 @@ fn test_client_id_overflow() {
     assert!(result.is_err(), "Should reject client ID > u16::MAX");
 }
 
+#[test]
+#[cfg(feature = "wide-client-ids")]
+fn test_wide_client_id_accepted() {
+    let csv = "type,client,tx,amount\n\
+               deposit,99999,1,100.0";
+
+    let (_dir, path) = create_test_csv(csv);
+    let result = start_engine(&path);
+    assert!(
+        result.is_ok(),
+        "Should accept client ID > u16::MAX in wide mode"
+    );
+}
+
 #[test]
 fn test_empty_file() {
     let csv = "type,client,tx,amount";
@@ -73,7 +88,7 @@ fn test_large_dataset() {
 
     // Generate 100K transactions across 1000 clients
     for i in 0..100_000 {
-        let client = (i % 1000) as u16;
+        let client = i % 1000;
         let tx_type = if i % 10 == 0 { "withdrawal" } else { "deposit" };
         csv.push_str(&format!(
             "{},{},{},{}.0\n",
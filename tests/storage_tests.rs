@@ -0,0 +1,193 @@
+// tests/storage_tests.rs
+
+//! The sled store (`StorageBackend::Sled`) against in-memory runs of the same input.
+//! Run with: cargo test --features sled-storage --test storage_tests
+
+#![cfg(feature = "sled-storage")]
+
+use payments_engine::{
+    ClientAccount, ClientId, EngineConfig, EngineError, StorageBackend, process_file,
+};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const DISPUTE_FIXTURES: &[&str] = &[
+    "tests/inputs/test_dispute_chargeback.csv",
+    "tests/inputs/test_dispute_resolve.csv",
+    "tests/inputs/test_dispute_heavy.csv",
+    "tests/inputs/test_dispute_spent_deposit.csv",
+    "tests/inputs/test_invalid_disputes.csv",
+    "tests/inputs/test_multiple_disputes.csv",
+    "tests/inputs/test_withdrawal_with_dispute.csv",
+    "tests/inputs/test_complex_scenario.csv",
+];
+
+fn run(path: &str, storage: StorageBackend, workers: usize) -> HashMap<ClientId, ClientAccount> {
+    let config = EngineConfig {
+        storage,
+        workers: Some(workers),
+        ..Default::default()
+    };
+    process_file(path, &config).unwrap()
+}
+
+fn sled(dir: &TempDir) -> StorageBackend {
+    StorageBackend::Sled(dir.path().join("store").to_str().unwrap().to_string())
+}
+
+/// The input at `path` cut in two after half its rows, each half with the header
+fn split(path: &str, dir: &TempDir) -> (String, String) {
+    let content = fs::read_to_string(path).unwrap();
+    let mut lines = content.lines();
+    let header = lines.next().unwrap();
+    let rows: Vec<&str> = lines.collect();
+    let (first, second) = rows.split_at(rows.len() / 2);
+    let write = |name: &str, rows: &[&str]| {
+        let path = dir.path().join(name);
+        fs::write(&path, format!("{}\n{}\n", header, rows.join("\n"))).unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    (write("first.csv", first), write("second.csv", second))
+}
+
+#[test]
+fn test_dispute_scenarios_match_memory() {
+    for fixture in DISPUTE_FIXTURES {
+        for workers in [1, 3] {
+            let dir = TempDir::new().unwrap();
+            assert_eq!(
+                run(fixture, sled(&dir), workers),
+                run(fixture, StorageBackend::Memory, workers),
+                "{} with {} workers",
+                fixture,
+                workers
+            );
+        }
+    }
+}
+
+#[test]
+fn test_split_runs_continue_from_the_store() {
+    for fixture in DISPUTE_FIXTURES {
+        let dir = TempDir::new().unwrap();
+        let (first, second) = split(fixture, &dir);
+        run(&first, sled(&dir), 2);
+        // A different worker count reads the same store
+        assert_eq!(
+            run(&second, sled(&dir), 3),
+            run(fixture, StorageBackend::Memory, 2),
+            "{}",
+            fixture
+        );
+    }
+}
+
+#[test]
+fn test_store_refuses_snapshot_options() {
+    let dir = TempDir::new().unwrap();
+    let config = EngineConfig {
+        storage: sled(&dir),
+        resume_from: Some("state.snap".to_string()),
+        multi_currency: true,
+        ..Default::default()
+    };
+    match process_file(DISPUTE_FIXTURES[0], &config) {
+        Err(EngineError::InvalidConfig(message)) => {
+            assert!(
+                message.ends_with("resume_from, multi_currency"),
+                "{}",
+                message
+            )
+        }
+        other => panic!("expected the options to be refused, got {:?}", other),
+    }
+}
+
+/// Deposits with disputes, resolves and the odd chargeback over 500 clients, and no
+/// withdrawals, so a client's outcome doesn't depend on when its funds arrive
+fn crash_input(rows: u32) -> String {
+    let client = |i: u32| i % 500 + 1;
+    let mut input = String::from("type,client,tx,amount\n");
+    for i in 0..rows {
+        writeln!(input, "deposit,{},{},{}.5", client(i), i + 1, i % 97 + 1).unwrap();
+        if i % 7 == 3 {
+            writeln!(input, "dispute,{},{},", client(i - 1), i).unwrap();
+        }
+        if i % 1400 == 12 {
+            writeln!(input, "chargeback,{},{},", client(i - 3), i - 2).unwrap();
+        } else if i % 14 == 5 || i % 14 == 12 {
+            writeln!(input, "resolve,{},{},", client(i - 3), i - 2).unwrap();
+        }
+    }
+    input
+}
+
+#[test]
+fn test_killed_run_leaves_a_consistent_prefix() {
+    let dir = TempDir::new().unwrap();
+    let input = dir.path().join("input.csv");
+    fs::write(&input, crash_input(100_000)).unwrap();
+    let input = input.to_str().unwrap();
+    let empty = dir.path().join("empty.csv");
+    fs::write(&empty, "type,client,tx,amount\n").unwrap();
+    let store = dir.path().join("store");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .arg("--store")
+        .arg(&store)
+        .args(["--workers", "4", input])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    // Kill it part way through: the store appears as the run starts, and getting through the
+    // input takes seconds
+    let started = Instant::now();
+    while !store.exists() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(10));
+    }
+    thread::sleep(Duration::from_millis(300));
+    assert!(
+        child.try_wait().unwrap().is_none(),
+        "the run ended before it could be killed"
+    );
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    let storage = StorageBackend::Sled(store.to_str().unwrap().to_string());
+    let recovered = run(empty.to_str().unwrap(), storage.clone(), 2);
+    for account in recovered.values() {
+        assert!(
+            (account.available() + account.held() - account.total()).abs() < 1e-6,
+            "{:?}",
+            account
+        );
+        assert!(account.held() >= 0.0, "{:?}", account);
+    }
+
+    // Every committed change is in the store exactly once, so going through the whole input
+    // again, skipping what it already applied, ends where an uninterrupted run does
+    let config = EngineConfig {
+        storage,
+        idempotent: true,
+        workers: Some(3),
+        ..Default::default()
+    };
+    let resumed = process_file(input, &config).unwrap();
+    let uninterrupted = run(input, StorageBackend::Memory, 4);
+    assert_eq!(resumed.len(), uninterrupted.len());
+    for (client, account) in &uninterrupted {
+        let resumed = &resumed[client];
+        assert_eq!(
+            (resumed.available(), resumed.held(), resumed.locked()),
+            (account.available(), account.held(), account.locked()),
+            "client {}",
+            client
+        );
+    }
+}
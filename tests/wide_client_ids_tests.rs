@@ -0,0 +1,44 @@
+// tests/wide_client_ids_tests.rs
+
+//! Client ids above u16::MAX with the `wide-client-ids` feature.
+//! Run with: cargo test --features wide-client-ids --test wide_client_ids_tests
+
+#![cfg(feature = "wide-client-ids")]
+
+use payments_engine::{EngineConfig, process_file};
+
+const FIXTURE: &str = "tests/wide_inputs/test_wide_client_ids.csv";
+
+#[test]
+fn test_wide_client_ids_processed() {
+    let config = EngineConfig {
+        workers: Some(3),
+        ..Default::default()
+    };
+    let accounts = process_file(FIXTURE, &config).unwrap();
+
+    assert_eq!(accounts.len(), 5);
+    assert_eq!(accounts[&70001].available(), 200.0);
+    assert_eq!(accounts[&65536].held(), 40.0);
+    assert_eq!(accounts[&u32::MAX].total(), 12.0);
+    assert!(accounts[&70002].locked());
+    assert!(!accounts[&1].locked());
+}
+
+#[test]
+fn test_wide_client_ids_output_sorted() {
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_payments_engine"))
+        .args(["--workers", "3", FIXTURE])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let clients: Vec<&str> = stdout
+        .lines()
+        .skip(1)
+        .map(|line| line.split(',').next().unwrap())
+        .collect();
+
+    assert_eq!(clients, ["1", "65536", "70001", "70002", "4294967295"]);
+}